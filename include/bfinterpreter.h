@@ -0,0 +1,42 @@
+/* C interface of bfinterpreter, built with `cargo build --features ffi`. */
+#ifndef BFINTERPRETER_H
+#define BFINTERPRETER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct bf_handle bf_handle;
+
+/* Returns the next input byte, or a negative value at the end of input. */
+typedef int (*bf_input_callback)(void *user);
+
+/* Receives a byte of output. */
+typedef void (*bf_output_callback)(unsigned char byte, void *user);
+
+/* Compiles a program. Always returns a handle to release with bf_free; if
+ * compiling failed, bf_error describes why and bf_run fails. */
+bf_handle *bf_compile(const char *source);
+
+/* Runs the program from the start on a fresh tape. Returns 0 on success and
+ * 1 on error. */
+int bf_run(bf_handle *handle);
+
+/* Reads input from callback instead of stdin; NULL goes back to stdin. */
+void bf_set_input_callback(bf_handle *handle, bf_input_callback callback, void *user);
+
+/* Writes output to callback instead of stdout; NULL goes back to stdout. */
+void bf_set_output_callback(bf_handle *handle, bf_output_callback callback, void *user);
+
+/* The error of the last failed bf_compile or bf_run, or NULL. It stays valid
+ * until the handle is next run or freed. */
+const char *bf_error(const bf_handle *handle);
+
+/* Releases a handle. NULL is ignored. */
+void bf_free(bf_handle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
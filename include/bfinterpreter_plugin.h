@@ -0,0 +1,92 @@
+/* Plugin interface of bfinterpreter, built with `cargo build --features plugins`
+ * and loaded with `--plugin=FILE`. A plugin is a shared library exporting
+ * bf_plugin_init, which registers what it provides through the host. */
+#ifndef BFINTERPRETER_PLUGIN_H
+#define BFINTERPRETER_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Changes whenever anything in this file does. */
+#define BF_PLUGIN_ABI_VERSION 3
+
+/* Operations of bf_instruction, the same as in bytecode files. */
+#define BF_OP_ADD 0              /* adds arg to the current cell */
+#define BF_OP_CLEAR 1            /* sets the current cell to zero */
+#define BF_OP_MOVE 2             /* moves the pointer by arg cells */
+#define BF_OP_INPUT 3
+#define BF_OP_OUTPUT 4
+#define BF_OP_JUMP_IF_ZERO 5     /* a loop's '[' */
+#define BF_OP_JUMP_IF_NOT_ZERO 6 /* a loop's ']' */
+#define BF_OP_FORK 7             /* Brainfork's 'Y' */
+#define BF_OP_SET 8              /* sets the current cell to arg */
+#define BF_OP_ADD_AT 9           /* adds arg to the cell at cells from the current one */
+#define BF_OP_SET_AT 10          /* sets the cell at cells from the current one to arg */
+
+/* An instruction of the optimized program. The arg of jumps is ignored when
+ * a pass hands instructions back; the host pairs them up again. */
+typedef struct bf_instruction {
+    uint8_t op;
+    int64_t arg;
+    /* Cell the instruction uses, relative to the current one, for
+     * BF_OP_ADD_AT and BF_OP_SET_AT; 0 otherwise. */
+    int64_t at;
+    /* Byte offset in the source the instruction was generated from. */
+    size_t offset;
+} bf_instruction;
+
+/* Receives a Brainfuck command ('+', '-', '<', '>', ',', '.', '[', ']' or
+ * 'Y') read from offset in the source. */
+typedef void (*bf_emit_command)(void *ctx, size_t offset, char command);
+
+/* Reads source of a dialect, emitting its commands in order. Returns 0 on
+ * success and anything else if the source is not valid in the dialect. */
+typedef int (*bf_lex_fn)(const char *source, size_t len, bf_emit_command emit, void *ctx);
+
+/* Receives an instruction of the rewritten program. */
+typedef void (*bf_emit_instruction)(void *ctx, bf_instruction instruction);
+
+/* Rewrites a program, emitting the new one in order. Returns 0 on success;
+ * on failure the program is left as it was. */
+typedef int (*bf_pass_fn)(const bf_instruction *program, size_t len, bf_emit_instruction emit, void *ctx);
+
+/* Opens a sink for the program's output. argument is the text after ':' in
+ * `--output-sink=NAME:ARGUMENT`, or "" without one. Returns NULL on failure. */
+typedef void *(*bf_sink_open_fn)(const char *argument);
+
+/* Writes output to a sink. Returns 0 on success. */
+typedef int (*bf_sink_write_fn)(void *sink, const uint8_t *bytes, size_t len);
+
+/* Closes a sink once the program has finished. */
+typedef void (*bf_sink_close_fn)(void *sink);
+
+/* Handed to bf_plugin_init. Names are copied, so they only need to stay
+ * valid during the call; functions have to stay valid for the whole run. */
+typedef struct bf_host {
+    uint32_t abi_version;
+    void *registry;
+    /* Adds a dialect, selected with `--dialect=NAME`. */
+    void (*register_dialect)(void *registry, const char *name, bf_lex_fn lex);
+    /* Adds an optimizer pass, run after the built-in ones with `--pass=NAME`. */
+    void (*register_pass)(void *registry, const char *name, bf_pass_fn pass);
+    /* Adds an output sink, selected with `--output-sink=NAME[:ARGUMENT]`. */
+    void (*register_sink)(void *registry,
+                          const char *name,
+                          bf_sink_open_fn open,
+                          bf_sink_write_fn write,
+                          bf_sink_close_fn close);
+} bf_host;
+
+/* Exported by every plugin. Returns 0 on success; a plugin built for another
+ * abi_version should return anything else. */
+int bf_plugin_init(const bf_host *host);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,188 @@
+//! Facts about a program that hold for every input, found without running
+//! it: loops that never end once entered, cells whose values are never
+//! used, how far the pointer can move and whether input is read.
+
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use ir::{Instruction, Program};
+
+/// What `analyze` found. Cells are numbered from the one the pointer starts
+/// at, so cells left of it are negative.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Analysis {
+    /// The `JumpIfZero` of every loop that runs forever once entered: one
+    /// without nested loops or input into the cell it tests, which returns
+    /// the pointer to that cell and leaves it nonzero.
+    pub endless_loops: Vec<usize>,
+    /// Cells the program may write but whose values are never printed or
+    /// tested by a loop, unless the pointer becomes unknown before a read.
+    pub unread_cells: Option<Vec<isize>>,
+    /// The cells the pointer can reach, unless it becomes unknown.
+    pub pointer_range: Option<RangeInclusive<isize>>,
+    /// The `JumpIfZero` of the first loop that moves the pointer by an
+    /// amount that depends on the data, after which the pointer is unknown.
+    pub unknown_from: Option<usize>,
+    pub reads_input: bool,
+}
+
+/// What one iteration of a loop does to the cell it tests.
+#[derive(Clone, Copy)]
+enum Effect {
+    Add(u8),
+    Set(u8),
+}
+
+impl Effect {
+    fn then(self, other: Effect) -> Effect {
+        match (self, other) {
+            (_, Effect::Set(n)) => Effect::Set(n),
+            (Effect::Add(a), Effect::Add(b)) => Effect::Add(a.wrapping_add(b)),
+            (Effect::Set(a), Effect::Add(b)) => Effect::Set(a.wrapping_add(b)),
+        }
+    }
+}
+
+/// A loop being walked through by `balance`.
+struct Frame {
+    start: usize,
+    /// Where the pointer is, relative to the tested cell.
+    pointer: isize,
+    /// Whether every nested loop so far returns the pointer to where it was.
+    balanced: bool,
+    /// What the body so far does to the tested cell, while that is known.
+    tested: Option<Effect>,
+}
+
+/// Which loops leave the pointer where it was on every iteration, by the
+/// index of their `JumpIfZero`, and which never end once entered.
+fn balance(instructions: &[Instruction]) -> (Vec<bool>, Vec<usize>) {
+    let mut balanced = vec![false; instructions.len()];
+    let mut endless = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+
+    for (pc, &instruction) in instructions.iter().enumerate() {
+        let frame = match instruction {
+            Instruction::JumpIfZero(_) => {
+                stack.push(Frame {
+                    start: pc,
+                    pointer: 0,
+                    balanced: true,
+                    tested: Some(Effect::Add(0)),
+                });
+                continue;
+            }
+            Instruction::JumpIfNotZero(_) => {
+                let done = match stack.pop() {
+                    Some(done) => done,
+                    None => continue,
+                };
+                let is_balanced = done.balanced && done.pointer == 0;
+                balanced[done.start] = is_balanced;
+                let leaves_nonzero = match done.tested {
+                    Some(Effect::Add(n)) => n == 0,
+                    Some(Effect::Set(n)) => n != 0,
+                    None => false,
+                };
+                if is_balanced && leaves_nonzero {
+                    endless.push(done.start);
+                }
+                if let Some(outer) = stack.last_mut() {
+                    outer.balanced &= is_balanced;
+                    outer.tested = None;
+                }
+                continue;
+            }
+            _ => match stack.last_mut() {
+                Some(frame) => frame,
+                None => continue,
+            },
+        };
+
+        let (at, effect) = match instruction {
+            Instruction::Move(n) => {
+                frame.pointer += n;
+                continue;
+            }
+            Instruction::Add(n) => (0, Some(Effect::Add(n))),
+            Instruction::AddAt(at, n) => (at, Some(Effect::Add(n))),
+            Instruction::Clear => (0, Some(Effect::Set(0))),
+            Instruction::Set(n) => (0, Some(Effect::Set(n))),
+            Instruction::SetAt(at, n) => (at, Some(Effect::Set(n))),
+            Instruction::Input => (0, None),
+            // The new thread runs the rest of the body on a zeroed cell.
+            Instruction::Fork => {
+                frame.tested = None;
+                continue;
+            }
+            Instruction::Output | Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_) => continue,
+        };
+        if frame.pointer + at == 0 {
+            frame.tested = match (frame.tested, effect) {
+                (Some(tested), Some(effect)) => Some(tested.then(effect)),
+                _ => None,
+            };
+        }
+    }
+    (balanced, endless)
+}
+
+/// Finds what can be known about `program` without running it. Loops are
+/// walked through once, which covers every iteration as long as they
+/// return the pointer to where it was.
+pub fn analyze(program: &Program) -> Analysis {
+    let instructions = &program.instructions;
+    let (balanced, endless_loops) = balance(instructions);
+
+    let mut pointer = Some(0isize);
+    let mut unknown_from = None;
+    let (mut lowest, mut highest) = (0, 0);
+    let mut written = BTreeSet::new();
+    let mut read = BTreeSet::new();
+    let mut read_unknown = false;
+
+    for (pc, &instruction) in instructions.iter().enumerate() {
+        if let Instruction::JumpIfZero(_) = instruction {
+            if pointer.is_some() && !balanced[pc] {
+                unknown_from = Some(pc);
+                pointer = None;
+            }
+        }
+        let at = match pointer {
+            Some(at) => at,
+            None => {
+                read_unknown |= matches!(instruction,
+                                         Instruction::Output | Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_));
+                continue;
+            }
+        };
+
+        let (cell, reads) = match instruction {
+            Instruction::Move(n) => {
+                pointer = Some(at + n);
+                lowest = lowest.min(at + n);
+                highest = highest.max(at + n);
+                continue;
+            }
+            Instruction::AddAt(offset, _) | Instruction::SetAt(offset, _) => (at + offset, false),
+            Instruction::Add(_) | Instruction::Clear | Instruction::Set(_) | Instruction::Input => (at, false),
+            Instruction::Output | Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_) => (at, true),
+            Instruction::Fork => continue,
+        };
+        lowest = lowest.min(cell);
+        highest = highest.max(cell);
+        if reads {
+            read.insert(cell);
+        } else {
+            written.insert(cell);
+        }
+    }
+
+    Analysis {
+        endless_loops,
+        unread_cells: if read_unknown { None } else { Some(written.difference(&read).cloned().collect()) },
+        pointer_range: if unknown_from.is_none() { Some(lowest..=highest) } else { None },
+        unknown_from,
+        reads_input: instructions.contains(&Instruction::Input),
+    }
+}
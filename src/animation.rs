@@ -0,0 +1,249 @@
+//! Animations of a run for the `render` command: the tape is recorded every
+//! few steps and written out as a single HTML page that plays the frames
+//! back, with the command being run marked in the source and the output
+//! printed so far, for teaching.
+
+use harness::SharedBuffer;
+use interp::{step_threads, State};
+use tape::Tape;
+
+/// Steps between frames unless told otherwise.
+pub const DEFAULT_EVERY: u64 = 1;
+/// Frames recorded at most unless told otherwise, which keeps pages small
+/// enough for a browser to open.
+pub const DEFAULT_MAX_FRAMES: usize = 2000;
+/// Cells from the left of the tape shown in each frame unless told
+/// otherwise.
+pub const DEFAULT_CELLS: usize = 64;
+
+/// What to record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Steps between frames.
+    pub every: u64,
+    /// Once this many frames are recorded, the run is stopped.
+    pub max_frames: usize,
+    /// Cells kept of each frame's tape.
+    pub cells: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            every: DEFAULT_EVERY,
+            max_frames: DEFAULT_MAX_FRAMES,
+            cells: DEFAULT_CELLS,
+        }
+    }
+}
+
+/// The machine at one point of the run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Frame {
+    /// Instructions executed so far.
+    pub step: u64,
+    pub pointer: usize,
+    /// The leftmost cells of the tape, as many as `Settings::cells`.
+    pub cells: Vec<u8>,
+    /// Bytes of output printed so far.
+    pub output: usize,
+    /// The source of the instruction run next, as byte offsets, or `None`
+    /// once the program has ended.
+    pub span: Option<(usize, usize)>,
+}
+
+/// The frames of a run, and how it ended.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Recording {
+    pub frames: Vec<Frame>,
+    /// Everything the program printed while it was recorded.
+    pub output: Vec<u8>,
+    /// Why the recording ends.
+    pub end: String,
+}
+
+/// Runs `state` until it ends or `settings.max_frames` frames are
+/// recorded, recording a frame every `settings.every` steps as well as the
+/// first and the last. Output is captured instead of written.
+pub fn record(state: &mut State, settings: &Settings) -> Recording {
+    let output = SharedBuffer::default();
+    state.output = Some(Box::new(output.clone()));
+    let every = settings.every.max(1);
+    let mut frames = vec![frame(state, &output, settings.cells)];
+
+    let end = loop {
+        if frames.len() >= settings.max_frames.max(2) {
+            break format!("Stopped after {} frames.", frames.len());
+        }
+        match step_threads(state) {
+            Ok(true) => (),
+            Ok(false) => break format!("The program ended after {} steps.", state.stats.instructions),
+            Err(e) => break format!("The program failed: {}", e),
+        }
+        if state.stats.instructions.is_multiple_of(every) {
+            frames.push(frame(state, &output, settings.cells));
+        }
+    };
+    // Output held back by the interpreter only appears once it is flushed.
+    let _ = state.flush();
+    let last = frame(state, &output, settings.cells);
+    if frames.last().map(|frame| frame.step) != Some(last.step) {
+        frames.push(last);
+    }
+
+    Recording {
+        frames,
+        output: output.take(),
+        end,
+    }
+}
+
+fn frame(state: &State, output: &SharedBuffer, cells: usize) -> Frame {
+    let tape = state.tape();
+    Frame {
+        step: state.stats.instructions,
+        pointer: state.pointer(),
+        cells: (0..tape.len().min(cells)).map(|cell| tape.get(cell)).collect(),
+        output: output.len(),
+        span: state.program().span_of(state.pc()).map(|span| (span.start, span.end)),
+    }
+}
+
+impl Recording {
+    /// A page that plays the recording of a run of `source` with no other
+    /// files needed, titled `title`.
+    pub fn to_html(&self, title: &str, source: &str) -> String {
+        // Spans are in bytes, but JavaScript counts UTF-16 units.
+        let mut units = vec![0; source.len() + 1];
+        let mut unit = 0;
+        for (i, c) in source.char_indices() {
+            units[i..i + c.len_utf8()].fill(unit);
+            unit += c.len_utf16();
+        }
+        units[source.len()] = unit;
+        let at = |offset: usize| units[offset.min(source.len())];
+        let frames: Vec<Frame> = self.frames
+            .iter()
+            .map(|frame| Frame { span: frame.span.map(|(start, end)| (at(start), at(end))), ..frame.clone() })
+            .collect();
+
+        let data = ::serde_json::json!({
+            "source": source,
+            "recording": Recording { frames, output: self.output.clone(), end: self.end.clone() },
+        });
+        // A `</script>` in the source or output would end the script early.
+        let data = data.to_string().replace("</", "<\\/");
+        PAGE.replace("{title}", &html_escape(title)).replace("{data}", &data)
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body { font-family: sans-serif; margin: 2em; color: #222; }
+#controls { margin-bottom: 1em; }
+#controls input[type=range] { width: 30em; vertical-align: middle; }
+#tape { display: flex; flex-wrap: wrap; gap: 2px; margin: 1em 0; }
+.cell { width: 3em; padding: 0.3em 0; text-align: center; font-family: monospace; border: 1px solid #bbb; }
+.cell small { display: block; color: #888; font-size: 0.7em; }
+.cell.zero { color: #bbb; }
+.cell.pointer { background: #ffd54f; border-color: #f57f17; }
+pre { background: #f5f5f5; padding: 0.5em; white-space: pre-wrap; word-break: break-all; }
+mark { background: #ffd54f; }
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<div id="controls">
+<button id="play">Play</button>
+<input id="frame" type="range" min="0" value="0">
+<label>Speed <select id="speed"><option value="500">slow</option><option value="100" selected>normal</option><option value="20">fast</option></select></label>
+<span id="step"></span>
+</div>
+<div id="tape"></div>
+<h2>Source</h2>
+<pre id="source"></pre>
+<h2>Output</h2>
+<pre id="output"></pre>
+<p id="end"></p>
+<script>
+const data = {data};
+const frames = data.recording.frames;
+const slider = document.getElementById("frame");
+const play = document.getElementById("play");
+const speed = document.getElementById("speed");
+const decoder = new TextDecoder();
+let timer = null;
+slider.max = frames.length - 1;
+
+function escape(text) {
+  return text.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;");
+}
+
+function show(i) {
+  const frame = frames[i];
+  document.getElementById("step").textContent = "step " + frame.step + ", frame " + (i + 1) + " of " + frames.length;
+  const tape = document.getElementById("tape");
+  tape.innerHTML = "";
+  const cells = Math.max(frame.cells.length, frame.pointer + 1);
+  for (let cell = 0; cell < cells; cell++) {
+    const value = cell < frame.cells.length ? frame.cells[cell] : 0;
+    const div = document.createElement("div");
+    div.className = "cell" + (value === 0 ? " zero" : "") + (cell === frame.pointer ? " pointer" : "");
+    div.innerHTML = value + "<small>" + cell + "</small>";
+    tape.appendChild(div);
+  }
+  const source = data.source;
+  const span = frame.span;
+  document.getElementById("source").innerHTML = span
+    ? escape(source.slice(0, span[0])) + "<mark>" + escape(source.slice(span[0], span[1])) + "</mark>" + escape(source.slice(span[1]))
+    : escape(source);
+  document.getElementById("output").textContent = decoder.decode(new Uint8Array(data.recording.output.slice(0, frame.output)));
+  document.getElementById("end").textContent = i === frames.length - 1 ? data.recording.end : "";
+}
+
+function stop() {
+  clearInterval(timer);
+  timer = null;
+  play.textContent = "Play";
+}
+
+play.onclick = function () {
+  if (timer !== null) {
+    stop();
+    return;
+  }
+  if (+slider.value === frames.length - 1) {
+    slider.value = 0;
+  }
+  play.textContent = "Pause";
+  timer = setInterval(function () {
+    if (+slider.value >= frames.length - 1) {
+      stop();
+      return;
+    }
+    slider.value = +slider.value + 1;
+    show(+slider.value);
+  }, +speed.value);
+};
+speed.onchange = function () {
+  if (timer !== null) {
+    stop();
+    play.onclick();
+  }
+};
+slider.oninput = function () {
+  show(+slider.value);
+};
+show(0);
+</script>
+</body>
+</html>
+"#;
@@ -0,0 +1,161 @@
+//! Running a program inside an async runtime such as tokio, with `,` reading
+//! from an `AsyncRead` and `.` writing to an `AsyncWrite`, so that a program
+//! waiting for input or for its reader does not block the runtime's thread.
+//!
+//! A `State` is not `Send`, so neither is the future of `run`; spawn it with
+//! `tokio::task::spawn_local` or await it in place.
+
+use std::future::Future;
+use std::io::{Cursor, ErrorKind};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use harness::SharedBuffer;
+use interp::{step_threads, RunOutcome, State};
+
+/// Instructions executed before the future gives other tasks a turn.
+const BUDGET: u32 = 10_000;
+
+/// Runs the program of `state` like `interp::run`, but reading `input` and
+/// writing `output` without blocking. The input and output set on `state`
+/// are replaced.
+pub fn run<R, W>(state: &mut State, input: R, output: W) -> Run<'_, R, W>
+    where R: AsyncRead + Unpin,
+          W: AsyncWrite + Unpin
+{
+    let written = SharedBuffer::default();
+    state.input = None;
+    state.output = Some(Box::new(written.clone()));
+    Run {
+        state,
+        input,
+        output,
+        read: Vec::new(),
+        input_ended: false,
+        written,
+        pending: Vec::new(),
+        sent: 0,
+        result: None,
+    }
+}
+
+/// The future returned by `run`.
+pub struct Run<'a, R, W> {
+    state: &'a mut State,
+    input: R,
+    output: W,
+    /// Bytes read from `input` that the program has not read yet.
+    read: Vec<u8>,
+    input_ended: bool,
+    /// Receives what the program writes.
+    written: SharedBuffer,
+    /// Output being written to `output`, of which `sent` bytes are done.
+    pending: Vec<u8>,
+    sent: usize,
+    /// How the program ended, once it has; the output may still have to be
+    /// written.
+    result: Option<Result<RunOutcome, String>>,
+}
+
+impl<'a, R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Run<'a, R, W> {
+    /// Reads up to the end of the next line of input, or of the input.
+    fn poll_line(&mut self, cx: &mut Context) -> Poll<Result<Vec<u8>, String>> {
+        loop {
+            if let Some(end) = self.read.iter().position(|&byte| byte == b'\n') {
+                return Poll::Ready(Ok(self.read.drain(..=end).collect()));
+            }
+            if self.input_ended {
+                return Poll::Ready(Ok(mem::take(&mut self.read)));
+            }
+            let mut buf = [0; 1024];
+            let mut buf = ReadBuf::new(&mut buf);
+            match Pin::new(&mut self.input).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => self.input_ended = true,
+                Poll::Ready(Ok(())) => self.read.extend_from_slice(buf.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(format!("Unable to read input: {}", e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Writes all of `pending`. A closed output ends the program, as it does
+    /// for `interp::run`.
+    fn poll_send(&mut self, cx: &mut Context) -> Poll<Result<(), Result<RunOutcome, String>>> {
+        while self.sent < self.pending.len() {
+            match Pin::new(&mut self.output).poll_write(cx, &self.pending[self.sent..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(Err("Could not write output.".to_owned()))),
+                Poll::Ready(Ok(n)) => self.sent += n,
+                Poll::Ready(Err(ref e)) if e.kind() == ErrorKind::BrokenPipe => {
+                    return Poll::Ready(Err(Ok(RunOutcome::OutputClosed)));
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Err(format!("Could not write output: {}", e)))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Executes instructions until the program writes output, ends or has
+    /// used up its turn.
+    fn poll_steps(&mut self, cx: &mut Context) -> Poll<()> {
+        for _ in 0..BUDGET {
+            if self.state.wants_line() {
+                match self.poll_line(cx) {
+                    Poll::Ready(Ok(line)) => self.state.input = Some(Box::new(Cursor::new(line))),
+                    Poll::Ready(Err(e)) => {
+                        let _ = self.state.flush();
+                        self.result = Some(Err(e));
+                        return Poll::Ready(());
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let stepped = step_threads(self.state);
+            if stepped != Ok(true) {
+                let flushed = self.state.flush();
+                self.result = Some(stepped.and(flushed).map(|_| self.state.outcome()));
+                return Poll::Ready(());
+            }
+            self.pending = self.written.take();
+            if !self.pending.is_empty() {
+                self.sent = 0;
+                return Poll::Ready(());
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Future for Run<'a, R, W> {
+    type Output = Result<RunOutcome, String>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.poll_send(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(result)) => return Poll::Ready(result),
+                Poll::Pending => return Poll::Pending,
+            }
+            this.pending = this.written.take();
+            this.sent = 0;
+            if !this.pending.is_empty() {
+                continue;
+            }
+            if this.result.is_some() {
+                return match Pin::new(&mut this.output).poll_flush(cx) {
+                    Poll::Ready(Ok(())) => Poll::Ready(this.result.take().unwrap_or(Ok(RunOutcome::Finished))),
+                    Poll::Ready(Err(e)) => Poll::Ready(Err(format!("Could not write output: {}", e))),
+                    Poll::Pending => Poll::Pending,
+                };
+            }
+            if this.poll_steps(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
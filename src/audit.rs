@@ -0,0 +1,68 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use interp::RunOutcome;
+
+/// One line of the audit log, describing a single program execution.
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch at which the run finished.
+    pub timestamp: u64,
+    /// FNV-1a hash of the program source, prefixed with the algorithm name.
+    pub program_hash: String,
+    pub program_size: usize,
+    pub input_bytes: u64,
+    pub max_memory: Option<usize>,
+    /// `"ok"`, `"output_closed"`, `"interrupted"` or `"error"`.
+    pub outcome: &'static str,
+    pub error: Option<String>,
+    pub duration_ms: f64,
+}
+
+impl Record {
+    pub fn new(source: &str,
+               input_bytes: u64,
+               max_memory: Option<usize>,
+               result: &Result<RunOutcome, String>,
+               duration: Duration)
+               -> Self {
+        Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            program_hash: format!("fnv1a64:{:016x}", fnv1a(source.as_bytes())),
+            program_size: source.len(),
+            input_bytes,
+            max_memory,
+            outcome: match *result {
+                Ok(RunOutcome::Finished) => "ok",
+                Ok(RunOutcome::OutputClosed) => "output_closed",
+                Ok(RunOutcome::Interrupted) => "interrupted",
+                Err(_) => "error",
+            },
+            error: result.clone().err(),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Appends `record` as a single JSON line to the log at `path`.
+pub fn append(path: &str, record: &Record) -> Result<(), String> {
+    let line = ::serde_json::to_string(record).map_err(|e| e.to_string())?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Could not open audit log '{}': {}", path, e))?;
+
+    // A single write keeps lines intact when several processes share a log.
+    file.write_all(format!("{}\n", line).as_bytes())
+        .map_err(|e| format!("Could not write audit log '{}': {}", path, e))
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
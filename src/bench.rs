@@ -0,0 +1,93 @@
+//! Timing repeated runs of a program, for the `bench` command.
+
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use harness::SharedBuffer;
+use interp::{RunOutcome, State};
+use ir::Program;
+use tape::Backend;
+
+/// Wall times of a number of runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub mean: Duration,
+    pub median: Duration,
+    /// Population standard deviation.
+    pub stddev: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Summary {
+    /// Summarizes `times`, which must not be empty.
+    pub fn of(times: &[Duration]) -> Summary {
+        let mut sorted = times.to_vec();
+        sorted.sort();
+        let n = sorted.len();
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2
+        };
+        let mean = sorted.iter().map(Duration::as_secs_f64).sum::<f64>() / n as f64;
+        let variance = sorted.iter()
+            .map(|time| (time.as_secs_f64() - mean).powi(2))
+            .sum::<f64>() / n as f64;
+        Summary {
+            mean: Duration::from_secs_f64(mean),
+            median,
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+            min: sorted[0],
+            max: sorted[n - 1],
+        }
+    }
+}
+
+/// The result of `measure`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    pub summary: Summary,
+    /// Instructions a single run executed.
+    pub instructions: u64,
+    /// What the program printed, which is the same on every run.
+    pub output: Vec<u8>,
+}
+
+impl Measurement {
+    /// Instructions executed per second of the mean run.
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.summary.mean.as_secs_f64().max(1e-9)
+    }
+}
+
+/// Runs `program` `iterations` times with `run`, each time on a fresh state
+/// with a tape of the kind `tape`, reading `input` and printing into a
+/// buffer, and times the runs. Setting up the states is not timed.
+pub fn measure<F>(program: &Program, input: &[u8], iterations: usize, tape: Backend, mut run: F) -> Result<Measurement, String>
+    where F: FnMut(&mut State) -> Result<RunOutcome, String>
+{
+    if iterations == 0 {
+        return Err("At least one iteration is needed.".to_owned());
+    }
+    let mut times = Vec::with_capacity(iterations);
+    let mut instructions = 0;
+    let mut output = Vec::new();
+    for i in 0..iterations {
+        let buffer = SharedBuffer::default();
+        let mut state = State::new(program.clone());
+        state.use_tape(tape)?;
+        state.input = Some(Box::new(Cursor::new(input.to_vec())));
+        state.output = Some(Box::new(buffer.clone()));
+
+        let start = Instant::now();
+        run(&mut state)?;
+        times.push(start.elapsed());
+
+        if i == 0 {
+            instructions = state.stats.instructions;
+            output = buffer.take();
+        }
+    }
+    Ok(Measurement { summary: Summary::of(&times), instructions, output })
+}
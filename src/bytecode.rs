@@ -0,0 +1,239 @@
+//! A compact binary form of an optimized program, written by `compile
+//! --target=bytecode` and loaded instead of parsing the source again.
+//!
+//! A file starts with `MAGIC`, the format version and the optimization level
+//! as little-endian `u32`s, and the number of instructions as a `u64`. Each
+//! instruction is an opcode byte, its operand if it has one, and the `u64`
+//! source offset it was generated from.
+
+use ir::{Instruction, OptLevel, Program};
+
+/// The first bytes of every bytecode file.
+pub const MAGIC: &[u8; 4] = b"BFC\0";
+
+/// Version of the format written by `encode`.
+pub const VERSION: u32 = 2;
+
+/// Extension of bytecode files, and of the cache next to a source file.
+pub const EXTENSION: &str = "bfc";
+
+const ADD: u8 = 0;
+const CLEAR: u8 = 1;
+const MOVE: u8 = 2;
+const INPUT: u8 = 3;
+const OUTPUT: u8 = 4;
+const JUMP_IF_ZERO: u8 = 5;
+const JUMP_IF_NOT_ZERO: u8 = 6;
+const FORK: u8 = 7;
+const SET: u8 = 8;
+const ADD_AT: u8 = 9;
+const SET_AT: u8 = 10;
+
+/// A program loaded from bytecode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bytecode {
+    pub program: Program,
+    /// The level the program was optimized at.
+    pub opt_level: OptLevel,
+}
+
+/// Whether `bytes` look like bytecode rather than source.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serializes `program`, which was optimized at `opt_level`.
+pub fn encode(program: &Program, opt_level: OptLevel) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&opt_level.get().to_le_bytes());
+    out.extend_from_slice(&(program.instructions.len() as u64).to_le_bytes());
+
+    for (i, (&instruction, &offset)) in program.instructions.iter().zip(&program.offsets).enumerate() {
+        match instruction {
+            Instruction::Add(n) => out.extend_from_slice(&[ADD, n]),
+            Instruction::Clear => out.push(CLEAR),
+            Instruction::Set(n) => out.extend_from_slice(&[SET, n]),
+            Instruction::AddAt(at, n) | Instruction::SetAt(at, n) => {
+                out.push(if let Instruction::AddAt(..) = instruction { ADD_AT } else { SET_AT });
+                out.extend_from_slice(&(at as i64).to_le_bytes());
+                out.push(n);
+            }
+            Instruction::Move(n) => {
+                out.push(MOVE);
+                out.extend_from_slice(&(n as i64).to_le_bytes());
+            }
+            Instruction::Input => out.push(INPUT),
+            Instruction::Output => out.push(OUTPUT),
+            Instruction::JumpIfZero(target) => {
+                out.push(JUMP_IF_ZERO);
+                out.extend_from_slice(&(target as u64).to_le_bytes());
+            }
+            Instruction::JumpIfNotZero(target) => {
+                out.push(JUMP_IF_NOT_ZERO);
+                out.extend_from_slice(&(target as u64).to_le_bytes());
+            }
+            Instruction::Fork => out.push(FORK),
+        }
+        out.extend_from_slice(&(offset as u64).to_le_bytes());
+        out.extend_from_slice(&(program.end(i) as u64).to_le_bytes());
+    }
+
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("Could not load bytecode: file is truncated.".to_owned());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        let n = self.u64()?;
+        if n > usize::MAX as u64 {
+            return Err("Could not load bytecode: value out of range.".to_owned());
+        }
+        Ok(n as usize)
+    }
+}
+
+/// Deserializes a program written by `encode`, checking that its jumps
+/// match up.
+pub fn decode(bytes: &[u8]) -> Result<Bytecode, String> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("Could not load bytecode: not a bytecode file.".to_owned());
+    }
+    let version = reader.u32()?;
+    if version != 1 && version != VERSION {
+        return Err(format!("Could not load bytecode: unsupported version {}.", version));
+    }
+    let opt_level = OptLevel::new(reader.u32()?)
+        .ok_or_else(|| "Could not load bytecode: unknown optimization level.".to_owned())?;
+    let count = reader.usize()?;
+
+    let mut program = Program::default();
+    for _ in 0..count {
+        let instruction = match reader.u8()? {
+            ADD => Instruction::Add(reader.u8()?),
+            CLEAR => Instruction::Clear,
+            SET => Instruction::Set(reader.u8()?),
+            ADD_AT => Instruction::AddAt(reader.u64()? as i64 as isize, reader.u8()?),
+            SET_AT => Instruction::SetAt(reader.u64()? as i64 as isize, reader.u8()?),
+            MOVE => Instruction::Move(reader.u64()? as i64 as isize),
+            INPUT => Instruction::Input,
+            OUTPUT => Instruction::Output,
+            JUMP_IF_ZERO => Instruction::JumpIfZero(reader.usize()?),
+            JUMP_IF_NOT_ZERO => Instruction::JumpIfNotZero(reader.usize()?),
+            FORK => Instruction::Fork,
+            op => return Err(format!("Could not load bytecode: unknown opcode {}.", op)),
+        };
+        let offset = reader.usize()?;
+        program.instructions.push(instruction);
+        program.offsets.push(offset);
+        program.ends.push(if version == 1 { offset + 1 } else { reader.usize()? });
+    }
+    if !reader.bytes.is_empty() {
+        return Err("Could not load bytecode: trailing data.".to_owned());
+    }
+
+    check_jumps(&program)?;
+    Ok(Bytecode { program, opt_level })
+}
+
+/// Checks that every jump points just past its partner, as `ir::lower`
+/// generates them.
+fn check_jumps(program: &Program) -> Result<(), String> {
+    let instructions = &program.instructions;
+    let mut opens = Vec::new();
+    for (i, &instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::JumpIfZero(_) => opens.push(i),
+            Instruction::JumpIfNotZero(target) => {
+                let open = opens.pop().ok_or_else(|| "Could not load bytecode: unbalanced jumps.".to_owned())?;
+                if target != open + 1 || instructions[open] != Instruction::JumpIfZero(i + 1) {
+                    return Err("Could not load bytecode: inconsistent jump targets.".to_owned());
+                }
+            }
+            _ => (),
+        }
+    }
+    if !opens.is_empty() {
+        return Err("Could not load bytecode: unbalanced jumps.".to_owned());
+    }
+    Ok(())
+}
+
+/// Lists the instructions of `bytecode` with their index and source offset.
+/// Loops are numbered, and each jump names its loop and where it goes.
+pub fn disassemble(bytecode: &Bytecode) -> String {
+    let program = &bytecode.program;
+    let mut out = format!("; bytecode version {}, opt level {}, {} instructions\n",
+                          VERSION,
+                          bytecode.opt_level,
+                          program.instructions.len());
+    out.push_str(";  index  offset  instruction\n");
+
+    let mut loops = 0;
+    let mut open = Vec::new();
+    for (i, (&instruction, &offset)) in program.instructions.iter().zip(&program.offsets).enumerate() {
+        let comment = match instruction {
+            Instruction::JumpIfZero(target) => {
+                open.push(loops);
+                loops += 1;
+                format!("; loop {} exits to {}", loops - 1, target)
+            }
+            Instruction::JumpIfNotZero(target) => {
+                format!("; loop {} repeats from {}", open.pop().unwrap_or(0), target)
+            }
+            _ => String::new(),
+        };
+        let line = format!("{:>8}  {:>6}  {:<12}{}", i, offset, instruction.to_string(), comment);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir;
+    use parser;
+
+    #[test]
+    fn decode_reverses_encode() {
+        let tree = parser::parse(",[>++++[>+<-]<-]>>[-]+++.>[<]").unwrap();
+        for level in 0..=OptLevel::MAX.get() {
+            let opt_level = OptLevel::new(level).unwrap();
+            let program = ir::optimize(&ir::lower(&tree), opt_level);
+            let decoded = decode(&encode(&program, opt_level)).unwrap();
+            assert_eq!(decoded, Bytecode { program, opt_level });
+        }
+    }
+}
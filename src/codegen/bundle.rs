@@ -0,0 +1,137 @@
+//! A ready-to-build package of a program, written by `compile --bundle`:
+//! the C translation with a makefile and the Rust one as a Cargo project,
+//! each of which builds for this machine or for every platform in
+//! `PLATFORMS` at once.
+
+use super::{c, rust, CodegenOptions};
+use ir::{Instruction, Program};
+
+/// A platform the bundle cross-compiles for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Platform {
+    /// Suffix of the binary's name in `dist/`.
+    pub name: &'static str,
+    /// Target of `zig cc`, which the makefile uses as a cross compiler.
+    pub zig_target: &'static str,
+    pub rust_target: &'static str,
+    pub exe_suffix: &'static str,
+}
+
+pub const PLATFORMS: [Platform; 5] = [
+    Platform { name: "linux-x86_64", zig_target: "x86_64-linux-musl", rust_target: "x86_64-unknown-linux-musl", exe_suffix: "" },
+    Platform { name: "linux-aarch64", zig_target: "aarch64-linux-musl", rust_target: "aarch64-unknown-linux-musl", exe_suffix: "" },
+    Platform { name: "macos-x86_64", zig_target: "x86_64-macos", rust_target: "x86_64-apple-darwin", exe_suffix: "" },
+    Platform { name: "macos-aarch64", zig_target: "aarch64-macos", rust_target: "aarch64-apple-darwin", exe_suffix: "" },
+    Platform { name: "windows-x86_64", zig_target: "x86_64-windows-gnu", rust_target: "x86_64-pc-windows-gnu", exe_suffix: ".exe" },
+];
+
+/// Turns a file name into one usable as a binary, makefile and Cargo
+/// package name: lowercase letters, digits and `-`, starting with a letter.
+pub fn package_name(stem: &str) -> String {
+    let mut name = String::new();
+    for c in stem.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_end_matches('-');
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name.to_owned(),
+        Some(_) => format!("bf-{}", name),
+        None => "program".to_owned(),
+    }
+}
+
+/// The files of the bundle of `program`, as paths relative to the bundle's
+/// directory and their contents. `name` has to be a `package_name`.
+pub fn files(program: &Program, name: &str, options: &CodegenOptions) -> Result<Vec<(String, String)>, String> {
+    if program.instructions.contains(&Instruction::Fork) {
+        return Err("A bundle cannot hold Brainfork's 'Y'.".to_owned());
+    }
+    Ok(vec![("README.md".to_owned(), readme(name)),
+            (format!("c/{}.c", name), c::generate(program, options)),
+            ("c/Makefile".to_owned(), c_makefile(name)),
+            ("rust/Cargo.toml".to_owned(), cargo_toml(name)),
+            ("rust/src/main.rs".to_owned(), rust::generate(program, options)),
+            ("rust/Makefile".to_owned(), rust_makefile(name))])
+}
+
+fn readme(name: &str) -> String {
+    let mut out = format!("# {}\n\n\
+                           Generated by bfinterpreter. Both directories build the same program.\n\n\
+                           - `c/`: `make` builds `{}` for this machine with `$CC`; `make dist`\n  \
+                           builds every platform below into `c/dist/` with `zig cc`, or with the\n  \
+                           compilers given as e.g. `make dist CC_windows_x86_64=x86_64-w64-mingw32-gcc`.\n\
+                           - `rust/`: `cargo build --release` builds for this machine; `make dist`\n  \
+                           builds every platform below into `rust/dist/`, once their targets are\n  \
+                           installed with `rustup target add`.\n\n\
+                           | Platform | zig target | Rust target |\n\
+                           |----------|------------|-------------|\n",
+                          name,
+                          name);
+    for platform in &PLATFORMS {
+        out.push_str(&format!("| {} | {} | {} |\n", platform.name, platform.zig_target, platform.rust_target));
+    }
+    out
+}
+
+/// Make variables can't contain `-`, so platforms are spelled with `_` in
+/// them.
+fn variable(platform: &Platform) -> String {
+    platform.name.replace('-', "_")
+}
+
+fn binaries(name: &str) -> Vec<String> {
+    PLATFORMS.iter()
+        .map(|platform| format!("dist/{}-{}{}", name, platform.name, platform.exe_suffix))
+        .collect()
+}
+
+fn c_makefile(name: &str) -> String {
+    let mut out = format!("# Generated by bfinterpreter.\n\
+                           NAME = {}\n\
+                           CC ?= cc\n\
+                           CFLAGS ?= -O2\n\
+                           ZIG ?= zig\n\n",
+                          name);
+    for platform in &PLATFORMS {
+        out.push_str(&format!("CC_{} ?= $(ZIG) cc -target {}\n", variable(platform), platform.zig_target));
+    }
+    out.push_str(&format!("\n$(NAME): $(NAME).c\n\t$(CC) $(CFLAGS) -o $@ $<\n\ndist: {}\n", binaries(name).join(" ")));
+    for (platform, binary) in PLATFORMS.iter().zip(binaries(name)) {
+        out.push_str(&format!("\n{}: $(NAME).c\n\t@mkdir -p dist\n\t$(CC_{}) $(CFLAGS) -o $@ $<\n", binary, variable(platform)));
+    }
+    out.push_str("\nclean:\n\trm -rf $(NAME) dist\n\n.PHONY: dist clean\n");
+    out
+}
+
+fn cargo_toml(name: &str) -> String {
+    format!("# Generated by bfinterpreter.\n\
+             [package]\n\
+             name = \"{}\"\n\
+             version = \"0.1.0\"\n\
+             edition = \"2021\"\n\n\
+             [profile.release]\n\
+             panic = \"abort\"\n\n\
+             # A project of its own, even if unpacked inside another workspace.\n\
+             [workspace]\n",
+            name)
+}
+
+fn rust_makefile(name: &str) -> String {
+    let mut out = format!("# Generated by bfinterpreter.\nNAME = {}\nCARGO ?= cargo\n\ndist: {}\n", name, binaries(name).join(" "));
+    for (platform, binary) in PLATFORMS.iter().zip(binaries(name)) {
+        out.push_str(&format!("\n{}:\n\
+                               \t$(CARGO) build --release --target {}\n\
+                               \t@mkdir -p dist\n\
+                               \tcp target/{}/release/$(NAME){} $@\n",
+                              binary,
+                              platform.rust_target,
+                              platform.rust_target,
+                              platform.exe_suffix));
+    }
+    out.push_str("\nclean:\n\trm -rf target dist\n\n.PHONY: dist clean\n");
+    out
+}
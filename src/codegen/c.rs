@@ -0,0 +1,151 @@
+use super::CodegenOptions;
+use ir::{Instruction, Program};
+
+// The runtime mirrors the interpreter: the tape grows in both directions,
+// input is read a line at a time as either a number or a character, and
+// output bytes are written as they are. Helpers are only emitted
+// when the program uses them.
+
+const HEADER: &str = r#"/* Generated by bfinterpreter. */
+#include <ctype.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+"#;
+
+const PRELUDE: &str = r#"
+static cell *tape;
+static size_t len = 1, ptr = 0;
+
+static void die(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "%s\n", message);
+    exit(1);
+}
+"#;
+
+const SHIFT: &str = r#"
+static void grow(size_t front, size_t back) {
+    cell *grown = calloc(len + front + back, sizeof *tape);
+    if (!grown) die("Out of memory.");
+    memcpy(grown + front, tape, len * sizeof *tape);
+    free(tape);
+    tape = grown;
+    len += front + back;
+}
+
+static void shift(long n) {
+    if (n < 0 && (size_t)-n > ptr) {
+        size_t missing = (size_t)-n - ptr;
+        size_t front = missing > len ? missing : len;
+        grow(front, 0);
+        ptr += front;
+    } else if (n > 0 && ptr + (size_t)n >= len) {
+        size_t missing = ptr + (size_t)n - len + 1;
+        grow(0, missing > len ? missing : len);
+    }
+    ptr += (size_t)n;
+}
+"#;
+
+const AT: &str = r#"
+static cell *at(long n) {
+    if ((n < 0 && (size_t)-n > ptr) || (n > 0 && ptr + (size_t)n >= len)) {
+        shift(n);
+        shift(-n);
+    }
+    return &tape[ptr + (size_t)n];
+}
+"#;
+
+const INPUT: &str = r#"
+static unsigned char input(void) {
+    char *line = NULL;
+    size_t size = 0, used = 0, start = 0, value = 0, i;
+    int c;
+
+    fflush(stdout);
+    while ((c = getchar()) != EOF) {
+        if (used + 1 >= size) {
+            size = size ? size * 2 : 64;
+            line = realloc(line, size);
+            if (!line) die("Out of memory.");
+        }
+        line[used++] = (char)c;
+        if (c == '\n') break;
+    }
+    if (ferror(stdin)) die("Unable to read input.");
+
+    while (used > 0 && isspace((unsigned char)line[used - 1])) used--;
+    while (start < used && isspace((unsigned char)line[start])) start++;
+    if (start == used) die("Could not parse input.");
+
+    i = start + (line[start] == '+' && used - start > 1);
+    while (i < used && isdigit((unsigned char)line[i]) && value <= 255) {
+        value = value * 10 + (size_t)(line[i++] - '0');
+    }
+    c = (i == used && value <= 255) ? (int)value : (unsigned char)line[start];
+    free(line);
+    return (unsigned char)c;
+}
+"#;
+
+const OUTPUT: &str = r#"
+static void output(unsigned char c) {
+    putchar(c);
+}
+"#;
+
+const MAIN: &str = r#"
+int main(void) {
+    tape = calloc(len, sizeof *tape);
+    if (!tape) die("Out of memory.");
+
+"#;
+
+/// Translates `program` into a self-contained C program.
+/// The program must not fork, which `codegen::generate` checks.
+pub fn generate(program: &Program, options: &CodegenOptions) -> String {
+    let uses = |f: fn(&Instruction) -> bool| program.instructions.iter().any(f);
+    let cell = match options.cell_bits {
+        8 => "unsigned char".to_owned(),
+        bits => format!("uint{}_t", bits),
+    };
+    let mut out = String::from(HEADER);
+    out.push_str(&format!("typedef {} cell;\n", cell));
+    out.push_str(PRELUDE);
+    let offset = uses(|i| matches!(*i, Instruction::AddAt(..) | Instruction::SetAt(..)));
+    if offset || uses(|i| matches!(*i, Instruction::Move(_))) {
+        out.push_str(SHIFT);
+    }
+    if offset {
+        out.push_str(AT);
+    }
+    if uses(|i| *i == Instruction::Input) {
+        out.push_str(INPUT);
+    }
+    if uses(|i| *i == Instruction::Output) {
+        out.push_str(OUTPUT);
+    }
+    out.push_str(MAIN);
+    out.push_str(&super::structured(program, |instruction| match *instruction {
+        Instruction::Add(n) if (n as i8) < 0 => format!("tape[ptr] -= {};", (n as i8).unsigned_abs()),
+        Instruction::Add(n) => format!("tape[ptr] += {};", n),
+        Instruction::Clear => "tape[ptr] = 0;".to_owned(),
+        Instruction::Set(n) => format!("tape[ptr] = {};", n),
+        Instruction::AddAt(at, n) if (n as i8) < 0 => format!("*at({}) -= {};", at, (n as i8).unsigned_abs()),
+        Instruction::AddAt(at, n) => format!("*at({}) += {};", at, n),
+        Instruction::SetAt(at, n) => format!("*at({}) = {};", at, n),
+        Instruction::Move(n) => format!("shift({});", n),
+        Instruction::Input => "tape[ptr] = input();".to_owned(),
+        Instruction::Output if options.cell_bits > 8 => "output((unsigned char)tape[ptr]);".to_owned(),
+        Instruction::Output => "output(tape[ptr]);".to_owned(),
+        Instruction::JumpIfZero(_) => "while (tape[ptr]) {".to_owned(),
+        Instruction::JumpIfNotZero(_) => "}".to_owned(),
+        Instruction::Fork => unreachable!("Brainfork is rejected by codegen::generate."),
+    }));
+    out.push_str("\n    fflush(stdout);\n    return 0;\n}\n");
+    out
+}
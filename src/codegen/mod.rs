@@ -0,0 +1,160 @@
+//! Translations of the instruction stream into other languages.
+
+use bytecode;
+use ir::{Instruction, OptLevel, Program};
+
+pub mod bundle;
+pub mod c;
+pub mod rust;
+pub mod verify;
+pub mod wasm;
+
+/// A language programs can be compiled to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    C,
+    Rust,
+    Wasm,
+    /// The format `bytecode::decode` loads, see there.
+    Bytecode,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "c" => Some(Target::C),
+            "rust" => Some(Target::Rust),
+            "wasm" => Some(Target::Wasm),
+            "bytecode" => Some(Target::Bytecode),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::C => "c",
+            Target::Rust => "rust",
+            Target::Wasm => "wasm",
+            Target::Bytecode => "bytecode",
+        }
+    }
+}
+
+/// Cells the wasm target reserves unless told otherwise.
+pub const DEFAULT_TAPE_LEN: usize = 30_000;
+
+/// What a wasm module uses for input and output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Runtime {
+    /// Imports `env.input` and `env.output`, provided by the embedder.
+    #[default]
+    Env,
+    /// Reads stdin and writes stdout through WASI, as a command module.
+    Wasi,
+}
+
+/// How to generate code. The defaults match the interpreter; `set` changes
+/// one option and checks that the target supports it.
+#[derive(Clone, Debug)]
+pub struct CodegenOptions {
+    /// The level the program was optimized at, recorded in bytecode.
+    pub opt_level: OptLevel,
+    /// Cells of the fixed wasm tape; the other targets grow the tape.
+    pub tape_len: usize,
+    /// Width of a cell: 8, 16, 32 or 64 bits. Output is the low 8 bits.
+    pub cell_bits: u32,
+    pub runtime: Runtime,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        CodegenOptions {
+            opt_level: OptLevel::MAX,
+            tape_len: DEFAULT_TAPE_LEN,
+            cell_bits: 8,
+            runtime: Runtime::Env,
+        }
+    }
+}
+
+impl CodegenOptions {
+    /// Applies a `name=value` option for `target`:
+    ///
+    /// - `cell-type`: `u8`, `u16`, `u32` or `u64`, or the C names
+    ///   `unsigned char` and `uint8_t` to `uint64_t`. Wasm supports up to
+    ///   32 bits, bytecode only 8.
+    /// - `runtime`: `env` or `wasi`, for wasm.
+    pub fn set(&mut self, target: Target, option: &str) -> Result<(), String> {
+        let (name, value) = match option.find('=') {
+            Some(i) => (&option[..i], &option[i + 1..]),
+            None => return Err(format!("Codegen option '{}' must be NAME=VALUE.", option)),
+        };
+        match (name, target) {
+            ("cell-type", _) => {
+                let bits = match value {
+                    "u8" | "uint8_t" | "unsigned char" => 8,
+                    "u16" | "uint16_t" => 16,
+                    "u32" | "uint32_t" => 32,
+                    "u64" | "uint64_t" => 64,
+                    _ => return Err(format!("Unknown cell type '{}'.", value)),
+                };
+                let max = match target {
+                    Target::C | Target::Rust => 64,
+                    Target::Wasm => 32,
+                    Target::Bytecode => 8,
+                };
+                if bits > max {
+                    return Err(format!("The {} target supports cells of up to {} bits.", target.name(), max));
+                }
+                self.cell_bits = bits;
+            }
+            ("runtime", Target::Wasm) => {
+                self.runtime = match value {
+                    "env" => Runtime::Env,
+                    "wasi" => Runtime::Wasi,
+                    _ => return Err(format!("Unknown runtime '{}'.", value)),
+                };
+            }
+            _ => return Err(format!("The {} target has no option '{}'.", target.name(), name)),
+        }
+        Ok(())
+    }
+}
+
+/// Translates `program` for `target`. Only bytecode can hold the threads of
+/// Brainfork.
+pub fn generate(program: &Program, target: Target, options: &CodegenOptions) -> Result<Vec<u8>, String> {
+    if target != Target::Bytecode && program.instructions.contains(&Instruction::Fork) {
+        return Err(format!("The {} target does not support Brainfork's 'Y'.", target.name()));
+    }
+    Ok(match target {
+        Target::Bytecode => bytecode::encode(program, options.opt_level),
+        Target::C => c::generate(program, options).into_bytes(),
+        Target::Rust => rust::generate(program, options).into_bytes(),
+        Target::Wasm => wasm::generate(program, options),
+    })
+}
+
+/// Emits one line per instruction inside `main`, indented by loop depth.
+/// Loops become `while` blocks, so `line` must open a block for
+/// `JumpIfZero` and close it for `JumpIfNotZero`.
+fn structured<F>(program: &Program, line: F) -> String
+    where F: Fn(&Instruction) -> String
+{
+    let mut out = String::new();
+    let mut depth = 1;
+
+    for instruction in &program.instructions {
+        if let Instruction::JumpIfNotZero(_) = *instruction {
+            depth -= 1;
+        }
+        out.push_str(&"    ".repeat(depth));
+        out.push_str(&line(instruction));
+        out.push('\n');
+        if let Instruction::JumpIfZero(_) = *instruction {
+            depth += 1;
+        }
+    }
+
+    out
+}
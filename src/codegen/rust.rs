@@ -0,0 +1,118 @@
+use super::CodegenOptions;
+use ir::{Instruction, Program};
+
+// The runtime mirrors the interpreter: the tape grows in both directions,
+// input is read a line at a time as either a number or a character, and
+// output bytes are written as they are.
+
+const HEADER: &str = r#"// Generated by bfinterpreter.
+#![allow(dead_code)]
+
+use std::io::{self, BufRead, Write};
+use std::process;
+
+"#;
+
+const PRELUDE: &str = r#"
+struct Machine {
+    tape: Vec<Cell>,
+    ptr: usize,
+    out: io::BufWriter<io::Stdout>,
+}
+
+impl Machine {
+    fn fail(&mut self, message: &str) -> ! {
+        let _ = self.out.flush();
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+
+    fn shift(&mut self, n: isize) {
+        if n < 0 && (-n) as usize > self.ptr {
+            let missing = (-n) as usize - self.ptr;
+            self.tape.splice(0..0, std::iter::repeat(0).take(missing));
+            self.ptr = 0;
+        } else {
+            self.ptr = (self.ptr as isize + n) as usize;
+            if self.ptr >= self.tape.len() {
+                self.tape.resize(self.ptr + 1, 0);
+            }
+        }
+    }
+
+    fn at(&mut self, n: isize) -> &mut Cell {
+        let index = self.ptr as isize + n;
+        if index < 0 || index as usize >= self.tape.len() {
+            self.shift(n);
+            self.shift(-n);
+        }
+        let index = (self.ptr as isize + n) as usize;
+        &mut self.tape[index]
+    }
+
+    fn input(&mut self) -> u8 {
+        if self.out.flush().is_err() {
+            self.fail("Could not write output.");
+        }
+        let mut line = String::new();
+        if let Err(e) = io::stdin().lock().read_line(&mut line) {
+            self.fail(&format!("Unable to read input: {}", e));
+        }
+        let line = line.trim();
+        match line.parse::<u8>() {
+            Ok(value) => value,
+            Err(_) => match line.chars().next() {
+                Some(c) => c as u8,
+                None => self.fail("Could not parse input."),
+            },
+        }
+    }
+
+    fn output(&mut self, c: u8) {
+        if self.out.write_all(&[c]).is_err() {
+            self.fail("Could not write output.");
+        }
+    }
+}
+
+fn main() {
+    let mut m = Machine {
+        tape: vec![0],
+        ptr: 0,
+        out: io::BufWriter::new(io::stdout()),
+    };
+
+"#;
+
+/// Translates `program` into a standalone `main.rs` that only needs the
+/// standard library.
+/// The program must not fork, which `codegen::generate` checks.
+pub fn generate(program: &Program, options: &CodegenOptions) -> String {
+    let wide = options.cell_bits > 8;
+    let mut out = String::from(HEADER);
+    out.push_str(&format!("type Cell = u{};\n", options.cell_bits));
+    out.push_str(PRELUDE);
+    out.push_str(&super::structured(program, |instruction| match *instruction {
+        Instruction::Add(n) if (n as i8) < 0 => {
+            format!("m.tape[m.ptr] = m.tape[m.ptr].wrapping_sub({});", (n as i8).unsigned_abs())
+        }
+        Instruction::Add(n) => format!("m.tape[m.ptr] = m.tape[m.ptr].wrapping_add({});", n),
+        Instruction::Clear => "m.tape[m.ptr] = 0;".to_owned(),
+        Instruction::Set(n) => format!("m.tape[m.ptr] = {};", n),
+        Instruction::AddAt(at, n) if (n as i8) < 0 => {
+            format!("{{ let c = m.at({}); *c = c.wrapping_sub({}); }}", at, (n as i8).unsigned_abs())
+        }
+        Instruction::AddAt(at, n) => format!("{{ let c = m.at({}); *c = c.wrapping_add({}); }}", at, n),
+        Instruction::SetAt(at, n) => format!("*m.at({}) = {};", at, n),
+        Instruction::Move(n) => format!("m.shift({});", n),
+        Instruction::Input if wide => "m.tape[m.ptr] = Cell::from(m.input());".to_owned(),
+        Instruction::Input => "m.tape[m.ptr] = m.input();".to_owned(),
+        Instruction::Output if wide => "m.output(m.tape[m.ptr] as u8);".to_owned(),
+        Instruction::Output => "m.output(m.tape[m.ptr]);".to_owned(),
+        Instruction::JumpIfZero(_) => "while m.tape[m.ptr] != 0 {".to_owned(),
+        Instruction::JumpIfNotZero(_) => "}".to_owned(),
+        Instruction::Fork => unreachable!("Brainfork is rejected by codegen::generate."),
+    }));
+    out.push_str("\n    if m.out.flush().is_err() {\n        m.fail(\"Could not write output.\");\n    }\n}\n");
+    out
+}
@@ -0,0 +1,155 @@
+//! Checks generated code against the interpreter: builds it with the
+//! toolchain for its target, runs it on some input and compares what it
+//! prints with what the interpreter prints for the same program.
+
+use std::env;
+use std::fs;
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+use super::{generate, CodegenOptions, Runtime, Target};
+use bytecode;
+use harness::SharedBuffer;
+use interp::{run, State};
+use ir::Program;
+
+/// What a run printed to stdout, and whether it succeeded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub output: Vec<u8>,
+    pub success: bool,
+}
+
+/// The outcome of `verify`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The generated code behaved like the interpreter.
+    Match,
+    /// It did not; `expected` is the interpreter's run.
+    Mismatch { expected: Run, actual: Run },
+    /// There is no toolchain to build or run the target; says which was
+    /// looked for.
+    Unavailable(String),
+}
+
+// Runs a WASI command module with the WASI support built into Node.js.
+const NODE_WASI: &str = r#"const { WASI } = require('node:wasi');
+const fs = require('fs');
+const wasi = new WASI({ version: 'preview1', args: [], env: {}, returnOnExit: true });
+WebAssembly.instantiate(fs.readFileSync(process.argv[2]), wasi.getImportObject())
+  .then(({ instance }) => { process.exitCode = wasi.start(instance); });
+"#;
+
+/// Generates code for `program` with `options`, runs it on `input` and
+/// compares it with the interpreter. C is built with `$CC` or `cc`, Rust
+/// with `$RUSTC` or `rustc`, and wasm, which needs the WASI runtime, runs
+/// under `wasmtime` or `node`. Bytecode is decoded and run by the
+/// interpreter.
+pub fn verify(program: &Program, target: Target, options: &CodegenOptions, input: &[u8]) -> Result<Verdict, String> {
+    if target == Target::Wasm && options.runtime != Runtime::Wasi {
+        return Err("Verifying wasm needs '--codegen runtime=wasi'.".to_owned());
+    }
+    let expected = interpret(program.clone(), input);
+    let code = generate(program, target, options)?;
+
+    let actual = if target == Target::Bytecode {
+        interpret(bytecode::decode(&code)?.program, input)
+    } else {
+        let dir = env::temp_dir().join(format!("bfinterpreter-verify-{}", process::id()));
+        fs::create_dir_all(&dir).map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+        let result = build_and_run(&dir, target, &code, input);
+        let _ = fs::remove_dir_all(&dir);
+        match result? {
+            Some(run) => run,
+            None => return Ok(Verdict::Unavailable(toolchain(target))),
+        }
+    };
+
+    if actual == expected {
+        Ok(Verdict::Match)
+    } else {
+        Ok(Verdict::Mismatch { expected, actual })
+    }
+}
+
+fn interpret(program: Program, input: &[u8]) -> Run {
+    let output = SharedBuffer::default();
+    let mut state = State::new(program);
+    state.input = Some(Box::new(Cursor::new(input.to_vec())));
+    state.output = Some(Box::new(output.clone()));
+    let success = run(&mut state).is_ok();
+    Run { output: output.take(), success }
+}
+
+fn toolchain(target: Target) -> String {
+    match target {
+        Target::C => format!("no C compiler found ('{}')", compiler("CC", "cc")),
+        Target::Rust => format!("no Rust compiler found ('{}')", compiler("RUSTC", "rustc")),
+        _ => "neither 'wasmtime' nor 'node' found".to_owned(),
+    }
+}
+
+fn compiler(var: &str, default: &str) -> String {
+    env::var(var).unwrap_or_else(|_| default.to_owned())
+}
+
+/// Builds `code` in `dir` and runs it; `None` if a tool is missing.
+fn build_and_run(dir: &Path, target: Target, code: &[u8], input: &[u8]) -> Result<Option<Run>, String> {
+    let write = |name: &str, contents: &[u8]| -> Result<PathBuf, String> {
+        let path = dir.join(name);
+        fs::write(&path, contents).map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+        Ok(path)
+    };
+    let binary = dir.join("program");
+
+    let mut runs = match target {
+        Target::C | Target::Rust => {
+            let (source, mut build) = match target {
+                Target::C => (write("program.c", code)?, Command::new(compiler("CC", "cc"))),
+                _ => (write("program.rs", code)?, Command::new(compiler("RUSTC", "rustc"))),
+            };
+            build.arg("-O").arg("-o").arg(&binary).arg(&source);
+            match run_tool(&mut build, &[])? {
+                None => return Ok(None),
+                Some(ref build) if !build.success => {
+                    return Err(format!("Could not build the generated {} code.", target.name()));
+                }
+                Some(_) => (),
+            }
+            vec![Command::new(&binary)]
+        }
+        _ => {
+            let module = write("program.wasm", code)?;
+            let script = write("run.js", NODE_WASI.as_bytes())?;
+            let mut wasmtime = Command::new("wasmtime");
+            wasmtime.arg(&module);
+            let mut node = Command::new("node");
+            node.arg("--no-warnings").arg(&script).arg(&module);
+            vec![wasmtime, node]
+        }
+    };
+
+    for command in &mut runs {
+        if let Some(run) = run_tool(command, input)? {
+            return Ok(Some(run));
+        }
+    }
+    Ok(None)
+}
+
+/// Runs `command` with `input` on stdin; `None` if it does not exist.
+fn run_tool(command: &mut Command, input: &[u8]) -> Result<Option<Run>, String> {
+    let child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Could not run {:?}: {}", command.get_program(), e)),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The program may exit before reading all of it.
+        let _ = stdin.write_all(input);
+    }
+    let output = child.wait_with_output().map_err(|e| format!("Could not run {:?}: {}", command.get_program(), e))?;
+    Ok(Some(Run { output: output.stdout, success: output.status.success() }))
+}
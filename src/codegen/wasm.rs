@@ -0,0 +1,465 @@
+use super::{CodegenOptions, Runtime};
+use ir::{Instruction, Program};
+
+// With the env runtime the module imports `env.input() -> i32`, which
+// returns the value for `,`, and `env.output(i32)`, which receives each byte
+// written by `.`, and exports `run`. With the WASI runtime it reads and
+// writes stdin and stdout itself, the way the interpreter does, and exports
+// `_start`. Either way it exports its `memory`, which holds the tape: cells
+// of the configured width, starting at address 0 with the pointer. Moving
+// the pointer off either end of the tape, or using a cell off it, traps.
+
+const PAGE_SIZE: usize = 65536;
+
+const TYPE_INPUT: u32 = 0;
+const TYPE_OUTPUT: u32 = 1;
+const TYPE_RUN: u32 = 2;
+const TYPE_FD: u32 = 3;
+
+// Opcodes.
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const UNREACHABLE: u8 = 0x00;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1a;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I32_LOAD: u8 = 0x28;
+const I32_LOAD8_U: u8 = 0x2d;
+const I32_LOAD16_U: u8 = 0x2f;
+const I32_STORE: u8 = 0x36;
+const I32_STORE8: u8 = 0x3a;
+const I32_STORE16: u8 = 0x3b;
+const I32_CONST: u8 = 0x41;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I32_LT_U: u8 = 0x49;
+const I32_GT_U: u8 = 0x4b;
+const I32_GE_U: u8 = 0x4f;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_MUL: u8 = 0x6c;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const EMPTY: u8 = 0x40;
+const I32: u8 = 0x7f;
+
+const POINTER: u32 = 0;
+/// Address of the cell an `AddAt` or `SetAt` uses.
+const CELL: u32 = 1;
+
+const PARSE_ERROR: &[u8] = b"Could not parse input.\n";
+
+fn unsigned(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    unsigned(module, contents.len() as u64);
+    module.extend_from_slice(contents);
+}
+
+fn i32_const(code: &mut Vec<u8>, n: i64) {
+    code.push(I32_CONST);
+    signed(code, n);
+}
+
+fn call(code: &mut Vec<u8>, function: u32) {
+    code.push(CALL);
+    unsigned(code, function as u64);
+}
+
+/// Accesses cells of one width.
+struct Cells {
+    bytes: usize,
+    load: u8,
+    store: u8,
+}
+
+impl Cells {
+    fn new(bits: u32) -> Self {
+        match bits {
+            8 => Cells { bytes: 1, load: I32_LOAD8_U, store: I32_STORE8 },
+            16 => Cells { bytes: 2, load: I32_LOAD16_U, store: I32_STORE16 },
+            _ => Cells { bytes: 4, load: I32_LOAD, store: I32_STORE },
+        }
+    }
+
+    /// Pushes the value of the current cell.
+    fn load(&self, code: &mut Vec<u8>) {
+        code.extend_from_slice(&[LOCAL_GET, POINTER as u8, self.load, 0, 0]);
+    }
+
+    /// Stores the value on top of the stack, which was pushed after the
+    /// pointer, in the current cell.
+    fn store(&self, code: &mut Vec<u8>) {
+        code.extend_from_slice(&[self.store, 0, 0]);
+    }
+}
+
+/// Sets `CELL` to the address `offset` bytes past the pointer, trapping if
+/// that is off the tape.
+fn cell_at(code: &mut Vec<u8>, offset: i64, tape_bytes: usize) {
+    code.extend_from_slice(&[LOCAL_GET, POINTER as u8]);
+    i32_const(code, offset);
+    code.extend_from_slice(&[I32_ADD, LOCAL_TEE, CELL as u8]);
+    i32_const(code, tape_bytes as i64);
+    code.extend_from_slice(&[I32_GE_U, IF, EMPTY, UNREACHABLE, END]);
+}
+
+/// Function indices; imports come first.
+struct Functions {
+    input: u32,
+    output: u32,
+    run: u32,
+}
+
+/// Translates `program` into a WebAssembly module with `options.tape_len`
+/// cells of tape, rounded up to whole pages of linear memory.
+/// The program must not fork, which `codegen::generate` checks.
+pub fn generate(program: &Program, options: &CodegenOptions) -> Vec<u8> {
+    let cells = Cells::new(options.cell_bits);
+    let tape_bytes = options.tape_len * cells.bytes;
+    // The WASI runtime keeps its buffers and messages just past the tape.
+    let scratch = (tape_bytes + 3) & !3;
+    let functions = match options.runtime {
+        Runtime::Env => Functions { input: 0, output: 1, run: 2 },
+        Runtime::Wasi => Functions { input: 3, output: 4, run: 5 },
+    };
+
+    let mut code = Vec::new();
+    for instruction in &program.instructions {
+        match *instruction {
+            Instruction::Add(n) => {
+                code.extend_from_slice(&[LOCAL_GET, POINTER as u8]);
+                cells.load(&mut code);
+                i32_const(&mut code, n as i8 as i64);
+                code.push(I32_ADD);
+                cells.store(&mut code);
+            }
+            Instruction::Clear => {
+                code.extend_from_slice(&[LOCAL_GET, POINTER as u8, I32_CONST, 0]);
+                cells.store(&mut code);
+            }
+            Instruction::Set(n) => {
+                code.extend_from_slice(&[LOCAL_GET, POINTER as u8]);
+                i32_const(&mut code, n as i64);
+                cells.store(&mut code);
+            }
+            Instruction::AddAt(at, n) => {
+                cell_at(&mut code, at as i64 * cells.bytes as i64, tape_bytes);
+                code.extend_from_slice(&[LOCAL_GET, CELL as u8, LOCAL_GET, CELL as u8, cells.load, 0, 0]);
+                i32_const(&mut code, n as i8 as i64);
+                code.push(I32_ADD);
+                cells.store(&mut code);
+            }
+            Instruction::SetAt(at, n) => {
+                cell_at(&mut code, at as i64 * cells.bytes as i64, tape_bytes);
+                code.extend_from_slice(&[LOCAL_GET, CELL as u8]);
+                i32_const(&mut code, n as i64);
+                cells.store(&mut code);
+            }
+            Instruction::Move(n) => {
+                code.extend_from_slice(&[LOCAL_GET, POINTER as u8]);
+                i32_const(&mut code, n as i64 * cells.bytes as i64);
+                code.extend_from_slice(&[I32_ADD, LOCAL_TEE, POINTER as u8]);
+                i32_const(&mut code, tape_bytes as i64);
+                code.extend_from_slice(&[I32_GE_U, IF, EMPTY, UNREACHABLE, END]);
+            }
+            Instruction::Input => {
+                code.extend_from_slice(&[LOCAL_GET, POINTER as u8]);
+                call(&mut code, functions.input);
+                cells.store(&mut code);
+            }
+            Instruction::Output => {
+                cells.load(&mut code);
+                if cells.bytes > 1 {
+                    i32_const(&mut code, 0xff);
+                    code.push(I32_AND);
+                }
+                call(&mut code, functions.output);
+            }
+            Instruction::JumpIfZero(_) => {
+                code.extend_from_slice(&[BLOCK, EMPTY, LOOP, EMPTY]);
+                cells.load(&mut code);
+                code.extend_from_slice(&[I32_EQZ, BR_IF, 1]);
+            }
+            Instruction::JumpIfNotZero(_) => {
+                code.extend_from_slice(&[BR, 0, END, END]);
+            }
+            Instruction::Fork => unreachable!("Brainfork is rejected by codegen::generate."),
+        }
+    }
+    code.push(END);
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+    let mut types = Vec::new();
+    unsigned(&mut types, 4);
+    types.extend_from_slice(&[0x60, 0, 1, I32]);
+    types.extend_from_slice(&[0x60, 1, I32, 0]);
+    types.extend_from_slice(&[0x60, 0, 0]);
+    types.extend_from_slice(&[0x60, 4, I32, I32, I32, I32, 1, I32]);
+    section(&mut module, 1, &types);
+
+    let imports: &[(&str, &str, u32)] = match options.runtime {
+        Runtime::Env => &[("env", "input", TYPE_INPUT), ("env", "output", TYPE_OUTPUT)],
+        Runtime::Wasi => &[("wasi_snapshot_preview1", "fd_read", TYPE_FD),
+                           ("wasi_snapshot_preview1", "fd_write", TYPE_FD),
+                           ("wasi_snapshot_preview1", "proc_exit", TYPE_OUTPUT)],
+    };
+    let mut section_imports = Vec::new();
+    unsigned(&mut section_imports, imports.len() as u64);
+    for &(module_name, field, ty) in imports {
+        name(&mut section_imports, module_name);
+        name(&mut section_imports, field);
+        section_imports.push(0);
+        unsigned(&mut section_imports, ty as u64);
+    }
+    section(&mut module, 2, &section_imports);
+
+    let mut bodies = vec![function_body(2, &code)];
+    if options.runtime == Runtime::Wasi {
+        bodies.insert(0, function_body(6, &wasi_input(scratch as i64)));
+        bodies.insert(1, function_body(0, &wasi_output(scratch as i64)));
+    }
+
+    let mut section_functions = Vec::new();
+    unsigned(&mut section_functions, bodies.len() as u64);
+    if options.runtime == Runtime::Wasi {
+        unsigned(&mut section_functions, TYPE_INPUT as u64);
+        unsigned(&mut section_functions, TYPE_OUTPUT as u64);
+    }
+    unsigned(&mut section_functions, TYPE_RUN as u64);
+    section(&mut module, 3, &section_functions);
+
+    let memory_bytes = match options.runtime {
+        Runtime::Env => tape_bytes,
+        Runtime::Wasi => scratch + 32 + PARSE_ERROR.len(),
+    };
+    let pages = memory_bytes.div_ceil(PAGE_SIZE).max(1) as u64;
+    let mut memory = Vec::new();
+    unsigned(&mut memory, 1);
+    memory.push(1);
+    unsigned(&mut memory, pages);
+    unsigned(&mut memory, pages);
+    section(&mut module, 5, &memory);
+
+    let mut exports = Vec::new();
+    unsigned(&mut exports, 2);
+    name(&mut exports, if options.runtime == Runtime::Wasi { "_start" } else { "run" });
+    exports.push(0);
+    unsigned(&mut exports, functions.run as u64);
+    name(&mut exports, "memory");
+    exports.push(2);
+    unsigned(&mut exports, 0);
+    section(&mut module, 7, &exports);
+
+    let mut section_code = Vec::new();
+    unsigned(&mut section_code, bodies.len() as u64);
+    for body in &bodies {
+        unsigned(&mut section_code, body.len() as u64);
+        section_code.extend_from_slice(body);
+    }
+    section(&mut module, 10, &section_code);
+
+    if options.runtime == Runtime::Wasi {
+        let mut data = Vec::new();
+        unsigned(&mut data, 1);
+        data.push(0);
+        i32_const(&mut data, scratch as i64 + 32);
+        data.push(END);
+        unsigned(&mut data, PARSE_ERROR.len() as u64);
+        data.extend_from_slice(PARSE_ERROR);
+        section(&mut module, 11, &data);
+    }
+
+    module
+}
+
+/// A function body with `locals` locals of type i32.
+fn function_body(locals: u64, code: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    if locals == 0 {
+        unsigned(&mut body, 0);
+    } else {
+        unsigned(&mut body, 1);
+        unsigned(&mut body, locals);
+        body.push(I32);
+    }
+    body.extend_from_slice(code);
+    body
+}
+
+// Scratch memory used by the WASI runtime, relative to its start: an iovec,
+// the count of bytes read or written, a buffer of two bytes and the parse
+// error message.
+const IOVEC: i64 = 0;
+const COUNT: i64 = 8;
+const BUFFER: i64 = 16;
+const MESSAGE: i64 = 32;
+
+const FD_READ: u32 = 0;
+const FD_WRITE: u32 = 1;
+const PROC_EXIT: u32 = 2;
+
+/// Points the iovec at `len` bytes from `address`.
+fn set_iovec(code: &mut Vec<u8>, scratch: i64, address: i64, len: i64) {
+    i32_const(code, scratch + IOVEC);
+    i32_const(code, address);
+    code.extend_from_slice(&[I32_STORE, 2, 0]);
+    i32_const(code, scratch + IOVEC + 4);
+    i32_const(code, len);
+    code.extend_from_slice(&[I32_STORE, 2, 0]);
+}
+
+/// Calls `fd_read` or `fd_write` on `fd` with the iovec, dropping the result.
+fn fd_call(code: &mut Vec<u8>, scratch: i64, function: u32, fd: i64) {
+    i32_const(code, fd);
+    i32_const(code, scratch + IOVEC);
+    i32_const(code, 1);
+    i32_const(code, scratch + COUNT);
+    call(code, function);
+}
+
+/// Reads a line from stdin and returns the value for `,`, as the interpreter
+/// does: the number on the line if it is one from 0 to 255, otherwise its
+/// first character after leading whitespace. An empty line or the end of
+/// input print an error and exit.
+fn wasi_input(scratch: i64) -> Vec<u8> {
+    // Locals: the byte read, the number so far, the first character or -1,
+    // whether the line is still a number, the count of digits, and whether
+    // whitespace followed the first character.
+    let (c, value, first, number, digits, space) = (0u8, 1u8, 2u8, 3u8, 4u8, 5u8);
+    let mut code = Vec::new();
+    i32_const(&mut code, -1);
+    code.extend_from_slice(&[LOCAL_SET, first]);
+    i32_const(&mut code, 1);
+    code.extend_from_slice(&[LOCAL_SET, number]);
+    set_iovec(&mut code, scratch, scratch + BUFFER, 1);
+
+    code.extend_from_slice(&[BLOCK, EMPTY, LOOP, EMPTY]);
+    // c = the next byte, or -1 at the end of input.
+    fd_call(&mut code, scratch, FD_READ, 0);
+    code.push(DROP);
+    i32_const(&mut code, scratch + COUNT);
+    code.extend_from_slice(&[I32_LOAD, 2, 0, I32_EQZ, IF, I32]);
+    i32_const(&mut code, -1);
+    code.push(ELSE);
+    i32_const(&mut code, scratch + BUFFER);
+    code.extend_from_slice(&[I32_LOAD8_U, 0, 0, END, LOCAL_TEE, c]);
+    // The line ends at a newline or the end of input.
+    i32_const(&mut code, -1);
+    code.extend_from_slice(&[I32_EQ, LOCAL_GET, c]);
+    i32_const(&mut code, b'\n' as i64);
+    code.extend_from_slice(&[I32_EQ, I32_OR, BR_IF, 1]);
+    // Whitespace, the same that `isspace` and `str::trim` skip in ASCII.
+    code.extend_from_slice(&[LOCAL_GET, c]);
+    i32_const(&mut code, b' ' as i64);
+    code.extend_from_slice(&[I32_EQ, LOCAL_GET, c]);
+    i32_const(&mut code, 9);
+    code.push(I32_SUB);
+    i32_const(&mut code, 5);
+    code.extend_from_slice(&[I32_LT_U, I32_OR, IF, EMPTY]);
+    code.extend_from_slice(&[LOCAL_GET, first]);
+    i32_const(&mut code, -1);
+    code.extend_from_slice(&[I32_NE, IF, EMPTY]);
+    i32_const(&mut code, 1);
+    code.extend_from_slice(&[LOCAL_SET, space, END, BR, 1, END]);
+    // The first character; a leading `+` may start a number.
+    code.extend_from_slice(&[LOCAL_GET, first]);
+    i32_const(&mut code, -1);
+    code.extend_from_slice(&[I32_EQ, IF, EMPTY, LOCAL_GET, c, LOCAL_SET, first, LOCAL_GET, c]);
+    i32_const(&mut code, b'+' as i64);
+    code.extend_from_slice(&[I32_EQ, BR_IF, 1, ELSE]);
+    // Anything after whitespace means the line is not a number.
+    code.extend_from_slice(&[LOCAL_GET, space, IF, EMPTY]);
+    i32_const(&mut code, 0);
+    code.extend_from_slice(&[LOCAL_SET, number, END, END]);
+    // Digits add to the number, up to a value that is out of range anyway.
+    code.extend_from_slice(&[LOCAL_GET, c]);
+    i32_const(&mut code, b'0' as i64);
+    code.push(I32_SUB);
+    i32_const(&mut code, 10);
+    code.extend_from_slice(&[I32_LT_U, IF, EMPTY, LOCAL_GET, value]);
+    i32_const(&mut code, 10);
+    code.extend_from_slice(&[I32_MUL, LOCAL_GET, c]);
+    code.push(I32_ADD);
+    i32_const(&mut code, b'0' as i64);
+    code.extend_from_slice(&[I32_SUB, LOCAL_SET, value, LOCAL_GET, digits]);
+    i32_const(&mut code, 1);
+    code.extend_from_slice(&[I32_ADD, LOCAL_SET, digits, LOCAL_GET, value]);
+    i32_const(&mut code, 255);
+    code.extend_from_slice(&[I32_GT_U, IF, EMPTY]);
+    i32_const(&mut code, 256);
+    code.extend_from_slice(&[LOCAL_SET, value]);
+    i32_const(&mut code, 0);
+    code.extend_from_slice(&[LOCAL_SET, number, END, ELSE]);
+    i32_const(&mut code, 0);
+    code.extend_from_slice(&[LOCAL_SET, number, END]);
+    code.extend_from_slice(&[BR, 0, END, END]);
+
+    // Nothing but whitespace: fail like the interpreter.
+    code.extend_from_slice(&[LOCAL_GET, first]);
+    i32_const(&mut code, -1);
+    code.extend_from_slice(&[I32_EQ, IF, EMPTY]);
+    set_iovec(&mut code, scratch, scratch + MESSAGE, PARSE_ERROR.len() as i64);
+    fd_call(&mut code, scratch, FD_WRITE, 2);
+    code.push(DROP);
+    i32_const(&mut code, 1);
+    call(&mut code, PROC_EXIT);
+    code.extend_from_slice(&[UNREACHABLE, END]);
+
+    code.extend_from_slice(&[LOCAL_GET, number, LOCAL_GET, digits]);
+    i32_const(&mut code, 0);
+    code.extend_from_slice(&[I32_NE, I32_AND, IF, EMPTY]);
+    code.extend_from_slice(&[LOCAL_GET, value, RETURN, END]);
+    code.extend_from_slice(&[LOCAL_GET, first, END]);
+    code
+}
+
+/// Writes a byte to stdout.
+fn wasi_output(scratch: i64) -> Vec<u8> {
+    let c = 0u8;
+    let mut code = Vec::new();
+    i32_const(&mut code, scratch + BUFFER);
+    code.extend_from_slice(&[LOCAL_GET, c, I32_STORE8, 0, 0]);
+    set_iovec(&mut code, scratch, scratch + BUFFER, 1);
+    fd_call(&mut code, scratch, FD_WRITE, 1);
+    code.extend_from_slice(&[DROP, END]);
+    code
+}
@@ -0,0 +1,214 @@
+//! One entry point for library users: a `Config` collects the options of
+//! every stage, from the dialect a program is read in to the limits it runs
+//! under, and turns source into a `State` set up with them.
+
+use std::io::{BufRead, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dialect::Dialect;
+use interp::{run, RunOutcome, State};
+use ir::{self, OptLevel, Program};
+use newline::Newline;
+use observer::Observer;
+use output::OutputMode;
+use parser::{self, ParseOptions};
+use tape::Backend;
+
+/// Options of a run, set with the builder methods. The defaults are those
+/// of `State::new`: Brainfuck, no optimization, no limits and no input or
+/// output.
+#[derive(Default)]
+pub struct Config {
+    dialect: Dialect,
+    parse_options: ParseOptions,
+    opt_level: OptLevel,
+    max_steps: Option<u64>,
+    max_loop_iterations: Option<u64>,
+    timeout: Option<Duration>,
+    memory_limit: Option<usize>,
+    strict_tape: bool,
+    tape: Backend,
+    byte_input: bool,
+    eof_unchanged: bool,
+    newline: Newline,
+    output_mode: OutputMode,
+    stdio: bool,
+    input: Option<Box<dyn BufRead>>,
+    output: Option<Box<dyn Write>>,
+    interrupt: Option<Arc<AtomicBool>>,
+    observer: Option<Box<dyn Observer>>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// Reads programs in `dialect`, which is also how Brainfork's threads
+    /// are enabled.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Refuses programs with loops nested more than `depth` deep.
+    pub fn max_nesting(mut self, depth: usize) -> Self {
+        self.parse_options.max_nesting = Some(depth);
+        self
+    }
+
+    /// Refuses programs with more than `commands` commands.
+    pub fn max_program_size(mut self, commands: usize) -> Self {
+        self.parse_options.max_size = Some(commands);
+        self
+    }
+
+    /// Ignores a `]` that closes no loop instead of failing.
+    pub fn lenient(mut self) -> Self {
+        self.parse_options.lenient = true;
+        self
+    }
+
+    pub fn opt_level(mut self, level: OptLevel) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Stops a program after it has executed `steps` instructions.
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    /// Stops a program once any one loop has gone around more than
+    /// `iterations` times without being left.
+    pub fn max_loop_iterations(mut self, iterations: u64) -> Self {
+        self.max_loop_iterations = Some(iterations);
+        self
+    }
+
+    /// Stops a program that is still running after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stops a program once the interpreter uses more than `bytes` bytes.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Fails when a program moves left of cell 0, instead of growing the
+    /// tape there.
+    pub fn strict_tape(mut self) -> Self {
+        self.strict_tape = true;
+        self
+    }
+
+    /// Keeps the cells on a tape of the kind `backend`, see `tape`.
+    pub fn tape(mut self, backend: Backend) -> Self {
+        self.tape = backend;
+        self
+    }
+
+    /// Gives `,` the bytes of the input as they are, instead of a value for
+    /// each line.
+    pub fn byte_input(mut self) -> Self {
+        self.byte_input = true;
+        self
+    }
+
+    /// Leaves the cell unchanged when `,` finds the input exhausted, instead
+    /// of failing.
+    pub fn eof_unchanged(mut self) -> Self {
+        self.eof_unchanged = true;
+        self
+    }
+
+    pub fn newline(mut self, newline: Newline) -> Self {
+        self.newline = newline;
+        self
+    }
+
+    pub fn output_mode(mut self, mode: OutputMode) -> Self {
+        self.output_mode = mode;
+        self
+    }
+
+    /// Reads stdin and writes stdout, see `State::use_stdio`. An input or
+    /// output set as well takes the place of stdin or stdout.
+    pub fn stdio(mut self) -> Self {
+        self.stdio = true;
+        self
+    }
+
+    pub fn input<R: BufRead + 'static>(mut self, input: R) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn output<W: Write + 'static>(mut self, output: W) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Stops a program once `flag` is set.
+    pub fn interrupt(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    /// Tells `observer` what every instruction does.
+    pub fn observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Reads, parses, lowers and optimizes `source` as configured.
+    pub fn compile(&self, source: &str) -> Result<Program, String> {
+        let tree = parser::parse_tokens_with(&self.dialect.lex(source)?, &self.parse_options)?;
+        Ok(ir::optimize(&ir::lower(&tree), self.opt_level))
+    }
+
+    /// A state that runs `program` as configured. Fails if the tape cannot
+    /// be set up.
+    pub fn state(self, program: Program) -> Result<State, String> {
+        let mut state = State::new(program);
+        state.use_tape(self.tape)?;
+        if self.stdio {
+            state.use_stdio();
+        }
+        if self.input.is_some() {
+            state.input = self.input;
+        }
+        if self.output.is_some() {
+            state.output = self.output;
+        }
+        state.max_steps = self.max_steps;
+        state.max_loop_iterations = self.max_loop_iterations;
+        state.timeout = self.timeout;
+        state.memory_limit = self.memory_limit;
+        state.strict_tape = self.strict_tape;
+        state.byte_input = self.byte_input;
+        state.eof_unchanged = self.eof_unchanged;
+        state.newline = self.newline;
+        state.output_mode = self.output_mode;
+        state.interrupt = self.interrupt;
+        state.observer = self.observer;
+        Ok(state)
+    }
+
+    /// Compiles `source` and returns the state that runs it.
+    pub fn build(self, source: &str) -> Result<State, String> {
+        let program = self.compile(source)?;
+        self.state(program)
+    }
+
+    /// Compiles and runs `source`, see `interp::run`.
+    pub fn run(self, source: &str) -> Result<RunOutcome, String> {
+        run(&mut self.build(source)?)
+    }
+}
@@ -0,0 +1,122 @@
+//! Controlling a running program from another thread, e.g. a GUI's, through
+//! a `Handle` taken from its state with `State::handle`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use interp::{State, Stats};
+use tape::Tape;
+
+/// The machine state between two instructions, see `Handle::snapshot`.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub tape: Vec<u8>,
+    pub pointer: usize,
+    pub pc: usize,
+    pub stats: Stats,
+}
+
+/// Pauses, resumes and stops the program of the state it was taken from.
+/// Requests take effect before the next instruction; they are checked by
+/// `run` and `step`, but not by machine code from the `jit` module, which
+/// is therefore not used for states with a handle.
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+pub(crate) struct Shared {
+    /// Set while a request is waiting, so that the interpreter only has to
+    /// look at this between instructions.
+    pending: AtomicBool,
+    requests: Mutex<Requests>,
+    /// Wakes a paused program to look at the requests again.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Requests {
+    paused: bool,
+    stopped: bool,
+    snapshots: Vec<Sender<Snapshot>>,
+}
+
+impl Handle {
+    pub(crate) fn new(shared: Arc<Shared>) -> Handle {
+        Handle { shared }
+    }
+
+    /// Makes the program wait before its next instruction until `resume` or
+    /// `stop`.
+    pub fn pause(&self) {
+        self.shared.update(|requests| requests.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.shared.update(|requests| requests.paused = false);
+    }
+
+    /// Stops the program before its next instruction, paused or not; the
+    /// run then ends with `RunOutcome::Interrupted`.
+    pub fn stop(&self) {
+        self.shared.update(|requests| requests.stopped = true);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.lock().paused
+    }
+
+    /// Asks for a snapshot of the machine, taken before the next
+    /// instruction, or at once while the program is paused. Nothing is sent
+    /// if the program ends first.
+    pub fn snapshot(&self) -> Receiver<Snapshot> {
+        let (sender, receiver) = mpsc::channel();
+        self.shared.update(|requests| requests.snapshots.push(sender));
+        receiver
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Requests> {
+        // The requests are consistent after every change, even one that
+        // panicked.
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update<F: FnOnce(&mut Requests)>(&self, f: F) {
+        let mut requests = self.lock();
+        f(&mut requests);
+        self.pending.store(true, Ordering::Release);
+        self.changed.notify_all();
+    }
+
+    /// Handles the requests before the next instruction of `state`, waiting
+    /// while the program is paused. Returns `false` once it is stopped.
+    pub(crate) fn check(&self, state: &State) -> bool {
+        if !self.pending.load(Ordering::Acquire) {
+            return true;
+        }
+        let mut requests = self.lock();
+        loop {
+            for sender in requests.snapshots.drain(..) {
+                // Whoever asked may have stopped waiting for the answer.
+                let _ = sender.send(Snapshot {
+                    tape: state.tape().to_vec(),
+                    pointer: state.pointer(),
+                    pc: state.pc(),
+                    stats: state.stats.clone(),
+                });
+            }
+            if requests.stopped {
+                return false;
+            }
+            if !requests.paused {
+                self.pending.store(false, Ordering::Release);
+                return true;
+            }
+            requests = self.changed.wait(requests).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
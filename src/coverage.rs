@@ -0,0 +1,87 @@
+//! Reports of which commands of a program ran, from the counts `--coverage`
+//! records in `State::coverage`. Counts belong to instructions; every
+//! command of the source is counted with the instruction generated from it,
+//! so reports are exact for unoptimized programs, while optimized ones only
+//! count the first command of a combined run. Both ends of a loop count at
+//! its `[`.
+
+use std::collections::BTreeMap;
+
+use ir::Program;
+
+/// How often the command at each source offset ran, for the offsets that
+/// instructions were generated from.
+pub fn hits(program: &Program, counts: &[u64]) -> BTreeMap<usize, u64> {
+    let mut hits = BTreeMap::new();
+    for (&offset, &count) in program.offsets.iter().zip(counts) {
+        let hit = hits.entry(offset).or_insert(0);
+        *hit = count.max(*hit);
+    }
+    hits
+}
+
+/// Runs of each line of `source` with the commands on it, by line number
+/// from 1: the fewest and the most runs of any command on the line, and the
+/// columns, from 0, of the commands that never ran.
+fn lines(source: &str, hits: &BTreeMap<usize, u64>) -> Vec<Option<(u64, u64, Vec<usize>)>> {
+    let mut start = 0;
+    source.split('\n')
+        .map(|line| {
+            let end = start + line.len();
+            let commands: Vec<(usize, u64)> = hits.range(start..end).map(|(&offset, &count)| (offset - start, count)).collect();
+            start = end + 1;
+            let fewest = commands.iter().map(|&(_, count)| count).min()?;
+            let most = commands.iter().map(|&(_, count)| count).max()?;
+            let unrun = commands.iter()
+                .filter(|&&(_, count)| count == 0)
+                .map(|&(column, _)| line[..column].chars().count())
+                .collect();
+            Some((fewest, most, unrun))
+        })
+        .collect()
+}
+
+/// `source` annotated like `gcov` does: every line is prefixed with how
+/// often its commands ran at most, `#####` if none did and `-` if it has
+/// none, and lines that ran in part are followed by a line marking the
+/// commands that did not with `^`.
+pub fn annotate(source: &str, hits: &BTreeMap<usize, u64>) -> String {
+    let source = source.strip_suffix('\n').unwrap_or(source);
+    let mut out = String::new();
+    for (text, line) in source.split('\n').zip(lines(source, hits)) {
+        let count = match line {
+            None => "-".to_owned(),
+            Some((_, 0, _)) => "#####".to_owned(),
+            Some((_, most, _)) => most.to_string(),
+        };
+        out.push_str(&format!("{:>9}: {}\n", count, text));
+        if let Some((0, most, ref unrun)) = line {
+            if most > 0 {
+                let mut marks = vec![' '; unrun.last().map_or(0, |&column| column + 1)];
+                for &column in unrun {
+                    marks[column] = '^';
+                }
+                out.push_str(&format!("{:>9}  {}\n", "", marks.into_iter().collect::<String>()));
+            }
+        }
+    }
+    out
+}
+
+/// The coverage in the tracefile format of `lcov`, for the source at
+/// `path`: a line counts as run as often as its commands ran at most.
+pub fn lcov(path: &str, source: &str, hits: &BTreeMap<usize, u64>) -> String {
+    let mut out = format!("TN:\nSF:{}\n", path);
+    let (mut found, mut hit) = (0, 0);
+    for (i, line) in lines(source, hits).into_iter().enumerate() {
+        if let Some((_, most, _)) = line {
+            out.push_str(&format!("DA:{},{}\n", i + 1, most));
+            found += 1;
+            if most > 0 {
+                hit += 1;
+            }
+        }
+    }
+    out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", found, hit));
+    out
+}
@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::fs;
+
+use interp::{self, State, Stats};
+use ir::Instruction;
+use tape::Tape;
+
+/// Version of the format written by `Debugger::save_session`.
+const SESSION_VERSION: u32 = 1;
+
+/// What a single instruction changed, so that it can be undone.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Change {
+    pc: usize,
+    pointer: usize,
+    /// Where the cell the instruction may write is, relative to `pointer`;
+    /// only `AddAt` and `SetAt` write one other than the current cell.
+    at: isize,
+    /// The value of that cell before the instruction ran, if it was on the
+    /// tape.
+    cell: u8,
+    tape_len: usize,
+    /// Cells the instruction inserted at the left end of the tape.
+    grown_front: usize,
+    stats: Stats,
+}
+
+/// Steps through a program while keeping a bounded history of changes, so
+/// that execution can also be stepped backwards.
+///
+/// Input and output are not undone: stepping back over `,` restores the
+/// cell, but executing it again reads new input.
+pub struct Debugger {
+    history: VecDeque<Change>,
+    capacity: usize,
+}
+
+#[derive(Serialize)]
+struct SessionRef<'a> {
+    version: u32,
+    capacity: usize,
+    history: &'a VecDeque<Change>,
+    state: &'a State,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    version: u32,
+    capacity: usize,
+    history: VecDeque<Change>,
+    state: State,
+}
+
+impl Debugger {
+    /// Creates a debugger remembering the last `capacity` instructions.
+    pub fn new(capacity: usize) -> Self {
+        Debugger {
+            history: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Number of instructions that can currently be stepped back.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Executes one instruction, see `interp::step`.
+    pub fn step(&mut self, state: &mut State) -> Result<bool, String> {
+        let (grown_front, at) = match state.program().instructions.get(state.pc) {
+            Some(&Instruction::Move(n)) if n < 0 => (n.unsigned_abs().saturating_sub(state.pointer), 0),
+            Some(&Instruction::AddAt(at, _)) | Some(&Instruction::SetAt(at, _)) if at < 0 => {
+                (at.unsigned_abs().saturating_sub(state.pointer), at)
+            }
+            Some(&Instruction::AddAt(at, _)) | Some(&Instruction::SetAt(at, _)) => (0, at),
+            _ => (0, 0),
+        };
+        let cell = cell_index(state.pointer, at, state.data.len()).map_or(0, |index| state.data.get(index));
+        let change = Change {
+            pc: state.pc,
+            pointer: state.pointer,
+            at,
+            cell,
+            tape_len: state.data.len(),
+            grown_front,
+            stats: state.stats.clone(),
+        };
+
+        if !interp::step(state)? {
+            return Ok(false);
+        }
+
+        if self.capacity > 0 {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(change);
+        }
+        Ok(true)
+    }
+
+    /// Undoes the most recent instruction. Returns `false` if the history is
+    /// exhausted.
+    pub fn back(&mut self, state: &mut State) -> bool {
+        let change = match self.history.pop_back() {
+            Some(change) => change,
+            None => return false,
+        };
+
+        state.data.shrink_left(change.grown_front);
+        // Shrinking a tape cannot fail.
+        let _ = state.data.resize(change.tape_len);
+        state.pc = change.pc;
+        state.pointer = change.pointer;
+        if let Some(index) = cell_index(state.pointer, change.at, state.data.len()) {
+            state.data.set(index, change.cell);
+        }
+        state.stats = change.stats;
+        true
+    }
+
+    /// Writes the session to `path`: the machine of `state`, the history
+    /// and how long it may grow, so that `restore_session` can pick it up
+    /// again later.
+    pub fn save_session(&self, state: &State, path: &str) -> Result<(), String> {
+        let session = SessionRef {
+            version: SESSION_VERSION,
+            capacity: self.capacity,
+            history: &self.history,
+            state,
+        };
+        let json = ::serde_json::to_vec(&session).map_err(|e| format!("Could not save session: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Could not write session '{}': {}", path, e))
+    }
+
+    /// Continues a session written by `save_session`, replacing the history
+    /// and the machine of `state`. Everything else about `state`, like where
+    /// it reads input from, stays as it is. The session has to be of the
+    /// same program.
+    pub fn restore_session(&mut self, state: &mut State, path: &str) -> Result<(), String> {
+        let file = fs::File::open(path).map_err(|e| format!("Could not read session '{}': {}", path, e))?;
+        let session: Session = ::serde_json::from_reader(::std::io::BufReader::new(file))
+            .map_err(|e| format!("Could not load session: {}", e))?;
+        if session.version != SESSION_VERSION {
+            return Err(format!("Unsupported session version {}.", session.version));
+        }
+        session.state.check_consistent()?;
+        if session.state.program() != state.program() {
+            return Err("The session is of a different program.".to_owned());
+        }
+        let len = state.program().instructions.len();
+        if session.history.iter().any(|change| change.pc > len || change.pointer >= change.tape_len) {
+            return Err("Could not load session: history is inconsistent.".to_owned());
+        }
+
+        state.replace_machine(session.state);
+        self.history = session.history;
+        self.capacity = session.capacity;
+        Ok(())
+    }
+}
+
+/// Index of the cell `at` cells from `pointer`, if it is on a tape of `len`
+/// cells.
+fn cell_index(pointer: usize, at: isize, len: usize) -> Option<usize> {
+    let index = pointer as isize + at;
+    if index >= 0 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
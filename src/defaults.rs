@@ -0,0 +1,153 @@
+//! Defaults for the options of a run, so that settings like the optimization
+//! level or the tape need not be typed every time. They are read from
+//! `bf/config.toml` in the user's config directory, with a line like
+//! `opt_level = 3` for each option, and from environment variables like
+//! `BF_OPT_LEVEL=3`, which take precedence over the file. The command line
+//! passes them in front of its own arguments, which take precedence over
+//! both; a switch turned on here is turned off there by `--name=false`, and
+//! a value set here is undone by `--name=` or `--name=none`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Environment variable that overrides where the file is.
+pub const FILE_VARIABLE: &str = "BF_CONFIG";
+
+/// Prefix of the environment variables setting an option.
+const PREFIX: &str = "BF_";
+
+/// Whether an option is switched on by its name alone or takes a value.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Flag,
+    Value,
+}
+
+/// The options that can have a default, by their long names with `_` for
+/// `-`. Options naming files or the program's input are left out, as those
+/// belong to a single run.
+const OPTIONS: &[(&str, Kind)] = &[
+    ("dialect", Kind::Value),
+    ("opt_level", Kind::Value),
+    ("jit", Kind::Flag),
+    ("lenient", Kind::Flag),
+    ("max_memory", Kind::Value),
+    ("max_steps", Kind::Value),
+    ("max_loop_iterations", Kind::Value),
+    ("timeout", Kind::Value),
+    ("tape", Kind::Value),
+    ("strict_tape", Kind::Flag),
+    ("eof", Kind::Value),
+    ("exit_from_cell", Kind::Flag),
+    ("preset", Kind::Value),
+    ("newline", Kind::Value),
+    ("prompt", Kind::Value),
+    ("no_prompt", Kind::Flag),
+    ("output_mode", Kind::Value),
+    ("output_separator", Kind::Value),
+    ("flush", Kind::Value),
+    ("num_format", Kind::Value),
+];
+
+/// The default of an option.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Setting {
+    /// A flag switched off, or no default for an option taking a value.
+    Off,
+    /// A flag switched on.
+    On,
+    Value(String),
+}
+
+/// The file read for defaults: `$BF_CONFIG`, or else `bf/config.toml` in
+/// the user's config directory. `None` if there is no config directory.
+pub fn file() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(FILE_VARIABLE) {
+        return Some(PathBuf::from(path));
+    }
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("bf").join("config.toml"))
+}
+
+/// The defaults from the file and the environment as command line options,
+/// like `--opt-level=3`. A missing file sets nothing. A flag switched off
+/// or an empty variable in the environment undoes what the file sets.
+pub fn args() -> Result<Vec<String>, String> {
+    let mut settings = BTreeMap::new();
+    if let Some(path) = file() {
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let name = path.display().to_string();
+                settings.extend(parse(&contents).map_err(|e| format!("{}: {}", name, e))?);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("Could not read '{}': {}", path.display(), e)),
+        }
+    }
+    settings.extend(from_env()?);
+
+    let mut args = Vec::new();
+    for &(key, _) in OPTIONS {
+        let name = key.replace('_', "-");
+        match settings.get(key) {
+            Some(Setting::On) => args.push(format!("--{}", name)),
+            Some(Setting::Value(value)) => args.push(format!("--{}={}", name, value)),
+            Some(Setting::Off) | None => (),
+        }
+    }
+    Ok(args)
+}
+
+/// Reads the settings in the TOML `contents` of a config file.
+fn parse(contents: &str) -> Result<BTreeMap<&'static str, Setting>, String> {
+    let table: ::toml::Table = contents.parse().map_err(|e| format!("Invalid config: {}", e))?;
+    let mut settings = BTreeMap::new();
+    for (key, value) in table {
+        let (key, kind) = lookup(&key).ok_or_else(|| format!("Unknown option '{}'.", key))?;
+        let setting = match (kind, value) {
+            (Kind::Flag, ::toml::Value::Boolean(true)) => Setting::On,
+            (Kind::Flag, ::toml::Value::Boolean(false)) => Setting::Off,
+            (Kind::Value, ::toml::Value::String(value)) => Setting::Value(value),
+            (Kind::Value, ::toml::Value::Integer(n)) => Setting::Value(n.to_string()),
+            (Kind::Flag, _) => return Err(format!("Option '{}' must be true or false.", key)),
+            (Kind::Value, _) => return Err(format!("Option '{}' must be a string or a number.", key)),
+        };
+        settings.insert(key, setting);
+    }
+    Ok(settings)
+}
+
+/// Reads the settings in `BF_*` environment variables, of the options
+/// named after the prefix. Other variables with the prefix are ignored.
+fn from_env() -> Result<BTreeMap<&'static str, Setting>, String> {
+    let mut settings = BTreeMap::new();
+    for &(key, kind) in OPTIONS {
+        let variable = format!("{}{}", PREFIX, key.to_uppercase());
+        let value = match env::var(&variable) {
+            Ok(value) => value,
+            Err(env::VarError::NotPresent) => continue,
+            Err(env::VarError::NotUnicode(_)) => return Err(format!("{} is not valid UTF-8.", variable)),
+        };
+        let setting = match kind {
+            _ if value.is_empty() => Setting::Off,
+            Kind::Flag => match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Setting::On,
+                "0" | "false" | "no" | "off" => Setting::Off,
+                _ => return Err(format!("{} must be 1 or 0, not '{}'.", variable, value)),
+            },
+            Kind::Value => Setting::Value(value),
+        };
+        settings.insert(key, setting);
+    }
+    Ok(settings)
+}
+
+fn lookup(key: &str) -> Option<(&'static str, Kind)> {
+    OPTIONS.iter().find(|&&(name, _)| name == key).cloned()
+}
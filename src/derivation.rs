@@ -0,0 +1,278 @@
+//! Small-step operational semantics of a run, written by
+//! `--export-derivation` as Markdown or LaTeX for teaching: every step the
+//! program takes is listed with the rule that allows it and the
+//! configuration it leads to.
+//!
+//! A configuration is `<pc, p, σ, o>`: the program counter, the pointer,
+//! the tape and the output so far. Loops are jumps between the matching
+//! brackets, as in the interpreter.
+
+use harness::SharedBuffer;
+use interp::{step, State};
+use ir::Instruction;
+use tape::Tape;
+
+/// Steps derived unless told otherwise; derivations are meant to be read.
+pub const DEFAULT_MAX_STEPS: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Latex,
+}
+
+impl Format {
+    /// LaTeX for `.tex` files, Markdown for anything else.
+    pub fn from_path(path: &str) -> Format {
+        if path.ends_with(".tex") {
+            Format::Latex
+        } else {
+            Format::Markdown
+        }
+    }
+}
+
+/// A rule of the semantics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    Inc,
+    Dec,
+    Left,
+    Right,
+    In,
+    Out,
+    LoopSkip,
+    LoopEnter,
+    LoopBack,
+    LoopExit,
+}
+
+/// Each rule with its name, premise and step, in Markdown and in LaTeX.
+const RULES: [(Rule, &str, &str, &str, &str, &str); 10] = [
+    (Rule::Inc, "INC", "P[pc] = `+`", "⟨pc+1, p, σ[p ↦ σ(p)+1], o⟩",
+     "P[pc] = \\texttt{+}", "\\langle pc+1, p, \\sigma[p \\mapsto \\sigma(p)+1], o \\rangle"),
+    (Rule::Dec, "DEC", "P[pc] = `-`", "⟨pc+1, p, σ[p ↦ σ(p)-1], o⟩",
+     "P[pc] = \\texttt{-}", "\\langle pc+1, p, \\sigma[p \\mapsto \\sigma(p)-1], o \\rangle"),
+    (Rule::Left, "LEFT", "P[pc] = `<`", "⟨pc+1, p-1, σ, o⟩",
+     "P[pc] = \\texttt{<}", "\\langle pc+1, p-1, \\sigma, o \\rangle"),
+    (Rule::Right, "RIGHT", "P[pc] = `>`", "⟨pc+1, p+1, σ, o⟩",
+     "P[pc] = \\texttt{>}", "\\langle pc+1, p+1, \\sigma, o \\rangle"),
+    (Rule::In, "IN", "P[pc] = `,` and i is the next input", "⟨pc+1, p, σ[p ↦ i], o⟩",
+     "P[pc] = \\texttt{,} \\quad i \\text{ is the next input}", "\\langle pc+1, p, \\sigma[p \\mapsto i], o \\rangle"),
+    (Rule::Out, "OUT", "P[pc] = `.`", "⟨pc+1, p, σ, o·σ(p)⟩",
+     "P[pc] = \\texttt{.}", "\\langle pc+1, p, \\sigma, o \\cdot \\sigma(p) \\rangle"),
+    (Rule::LoopSkip, "LOOP-SKIP", "P[pc] = `[` matching `]` at q, and σ(p) = 0", "⟨q+1, p, σ, o⟩",
+     "P[pc] = \\texttt{[} \\quad \\sigma(p) = 0 \\quad P[q] \\text{ matches}", "\\langle q+1, p, \\sigma, o \\rangle"),
+    (Rule::LoopEnter, "LOOP-ENTER", "P[pc] = `[` and σ(p) ≠ 0", "⟨pc+1, p, σ, o⟩",
+     "P[pc] = \\texttt{[} \\quad \\sigma(p) \\neq 0", "\\langle pc+1, p, \\sigma, o \\rangle"),
+    (Rule::LoopBack, "LOOP-BACK", "P[pc] = `]` matching `[` at q, and σ(p) ≠ 0", "⟨q+1, p, σ, o⟩",
+     "P[pc] = \\texttt{]} \\quad \\sigma(p) \\neq 0 \\quad P[q] \\text{ matches}", "\\langle q+1, p, \\sigma, o \\rangle"),
+    (Rule::LoopExit, "LOOP-EXIT", "P[pc] = `]` and σ(p) = 0", "⟨pc+1, p, σ, o⟩",
+     "P[pc] = \\texttt{]} \\quad \\sigma(p) = 0", "\\langle pc+1, p, \\sigma, o \\rangle"),
+];
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        RULES.iter().find(|r| r.0 == self).map_or("", |r| r.1)
+    }
+
+    fn of(instruction: Instruction, cell: u8) -> Result<Rule, String> {
+        Ok(match instruction {
+            Instruction::Add(1) => Rule::Inc,
+            Instruction::Add(255) => Rule::Dec,
+            Instruction::Move(-1) => Rule::Left,
+            Instruction::Move(1) => Rule::Right,
+            Instruction::Input => Rule::In,
+            Instruction::Output => Rule::Out,
+            Instruction::JumpIfZero(_) if cell == 0 => Rule::LoopSkip,
+            Instruction::JumpIfZero(_) => Rule::LoopEnter,
+            Instruction::JumpIfNotZero(_) if cell != 0 => Rule::LoopBack,
+            Instruction::JumpIfNotZero(_) => Rule::LoopExit,
+            Instruction::Fork => return Err("Derivations cannot follow the threads of Brainfork.".to_owned()),
+            _ => return Err("Derivations need the program without optimizations.".to_owned()),
+        })
+    }
+}
+
+/// A configuration `<pc, p, σ, o>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub pc: usize,
+    pub pointer: usize,
+    /// The tape up to the pointer or the last cell that is not zero.
+    pub tape: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Config {
+    fn of(state: &State, output: &SharedBuffer) -> Config {
+        let tape = state.tape().to_vec();
+        let end = tape.iter().rposition(|&cell| cell != 0).map_or(0, |i| i + 1).max(state.pointer() + 1);
+        Config {
+            pc: state.pc(),
+            pointer: state.pointer(),
+            tape: tape[..end.min(tape.len())].to_vec(),
+            output: output.contents(),
+        }
+    }
+}
+
+/// How a derivation ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum End {
+    /// `pc` is past the end of the program, so no rule applies.
+    Halted,
+    /// The step limit was reached.
+    Limit,
+    /// The run failed, e.g. moving left of the first cell.
+    Error(String),
+}
+
+/// The steps of a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Derivation {
+    /// The program `P`, one command per instruction.
+    pub program: String,
+    pub start: Config,
+    pub steps: Vec<(Rule, Config)>,
+    pub end: End,
+}
+
+/// Runs `state`, whose program has to be unoptimized, for at most
+/// `max_steps` steps, recording each of them. Output is captured instead of
+/// written.
+pub fn derive(state: &mut State, max_steps: usize) -> Result<Derivation, String> {
+    let output = SharedBuffer::default();
+    state.output = Some(Box::new(output.clone()));
+    let start = Config::of(state, &output);
+    let mut steps = Vec::new();
+
+    let end = loop {
+        let instruction = match state.program().instructions.get(state.pc()) {
+            Some(&instruction) => instruction,
+            None => break End::Halted,
+        };
+        if steps.len() >= max_steps {
+            break End::Limit;
+        }
+        let rule = Rule::of(instruction, state.tape().get(state.pointer()))?;
+        if let Err(e) = step(state) {
+            break End::Error(e);
+        }
+        steps.push((rule, Config::of(state, &output)));
+    };
+
+    Ok(Derivation { program: state.program().to_source(), start, steps, end })
+}
+
+impl Derivation {
+    pub fn write(&self, format: Format) -> String {
+        match format {
+            Format::Markdown => self.to_markdown(),
+            Format::Latex => self.to_latex(),
+        }
+    }
+
+    fn end_note(&self) -> String {
+        match self.end {
+            End::Halted => "No rule applies to the last configuration: the program halted.".to_owned(),
+            End::Limit => format!("Stopped after {} steps.", self.steps.len()),
+            End::Error(ref e) => format!("No rule applies to the last configuration: {}", e),
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# Derivation\n\n\
+                               `P = {}`\n\n\
+                               A configuration `⟨pc, p, σ, o⟩` holds the program counter, the \
+                               pointer, the tape and the output so far. `P[pc]` is the command \
+                               at `pc`, counting from 0, and the underlined cell is `σ(p)`.\n\n\
+                               ## Rules\n\n\
+                               | Rule | If | then `⟨pc, p, σ, o⟩ →` |\n\
+                               |------|----|------------------------|\n",
+                              self.program);
+        for rule in &RULES {
+            out.push_str(&format!("| {} | {} | {} |\n", rule.1, rule.2, rule.3));
+        }
+        out.push_str("\n## Steps\n\n| Step | Rule | Configuration |\n|-----:|------|---------------|\n");
+        out.push_str(&format!("| 0 | | {} |\n", markdown_config(&self.start)));
+        for (i, &(rule, ref config)) in self.steps.iter().enumerate() {
+            out.push_str(&format!("| {} | {} | {} |\n", i + 1, rule.name(), markdown_config(config)));
+        }
+        out.push_str(&format!("\n{}\n", self.end_note()));
+        out
+    }
+
+    pub fn to_latex(&self) -> String {
+        let mut out = format!("\\documentclass{{article}}\n\
+                               \\usepackage{{amsmath}}\n\
+                               \\allowdisplaybreaks\n\
+                               \\begin{{document}}\n\n\
+                               \\section*{{Derivation}}\n\n\
+                               $P = \\texttt{{{}}}$\n\n\
+                               A configuration $\\langle pc, p, \\sigma, o \\rangle$ holds the program \
+                               counter, the pointer, the tape and the output so far. $P[pc]$ is the \
+                               command at $pc$, counting from 0, and the underlined cell is $\\sigma(p)$.\n\n\
+                               \\subsection*{{Rules}}\n\n\
+                               \\begin{{gather*}}\n",
+                              latex_escape(&self.program));
+        let rules: Vec<String> = RULES.iter()
+            .map(|rule| format!("\\textsc{{{}}}\\;\\frac{{{}}}{{\\langle pc, p, \\sigma, o \\rangle \\to {}}}",
+                                rule.1.to_lowercase(),
+                                rule.4,
+                                rule.5))
+            .collect();
+        out.push_str(&rules.join(" \\\\[1ex]\n"));
+        out.push_str("\n\\end{gather*}\n\n\\subsection*{Steps}\n\n\\begin{align*}\n");
+        let mut lines = vec![format!("& {}", latex_config(&self.start))];
+        for &(rule, ref config) in &self.steps {
+            lines.push(format!("\\to_{{\\textsc{{{}}}}} & {}", rule.name().to_lowercase(), latex_config(config)));
+        }
+        out.push_str(&lines.join(" \\\\\n"));
+        out.push_str(&format!("\n\\end{{align*}}\n\n{}\n\n\\end{{document}}\n", latex_escape(&self.end_note())));
+        out
+    }
+}
+
+fn markdown_config(config: &Config) -> String {
+    let tape: Vec<String> = config.tape
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| if i == config.pointer { format!("<u>{}</u>", cell) } else { cell.to_string() })
+        .collect();
+    let output: String = config.output.iter().flat_map(|&b| ::std::ascii::escape_default(b)).map(char::from).collect();
+    format!("⟨{}, {}, [{}], `\"{}\"`⟩", config.pc, config.pointer, tape.join(", "), output.replace('|', "\\|"))
+}
+
+fn latex_config(config: &Config) -> String {
+    let tape: Vec<String> = config.tape
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| if i == config.pointer { format!("\\underline{{{}}}", cell) } else { cell.to_string() })
+        .collect();
+    let output: String = config.output.iter().flat_map(|&b| ::std::ascii::escape_default(b)).map(char::from).collect();
+    format!("\\langle {}, {}, [{}], \\texttt{{\"{}\"}} \\rangle",
+            config.pc,
+            config.pointer,
+            tape.join(", "),
+            latex_escape(&output))
+}
+
+fn latex_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '^' => out.push_str("\\textasciicircum{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '<' => out.push_str("\\textless{}"),
+            '>' => out.push_str("\\textgreater{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,98 @@
+//! Errors shown the way compilers show them: a label saying how serious
+//! they are, the message, and the line of source they are about with carets
+//! under the part in question, colored for terminals.
+
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Help,
+}
+
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Help => "help",
+        }
+    }
+
+    /// The ANSI escape that colors the label and the carets.
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Help => "\x1b[1;36m",
+        }
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const GUTTER: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+/// Paints text with ANSI escapes, or leaves it alone.
+#[derive(Clone, Copy)]
+struct Paint(bool);
+
+impl Paint {
+    fn paint(self, style: &str, text: &str) -> String {
+        if self.0 {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+/// The label and `message`, followed by the line of `source` that `span`
+/// starts on with carets under the span, if there is a span and it is
+/// within the source. A span running over several lines is marked to the
+/// end of its first, and one at the end of the source just past it. With
+/// `color`, the text is colored with ANSI escapes.
+pub fn render(severity: Severity, message: &str, source: &str, span: Option<Range<usize>>, color: bool) -> String {
+    let paint = Paint(color);
+    let mut text = format!("{}{}",
+                           paint.paint(severity.color(), &format!("{}:", severity.label())),
+                           paint.paint(BOLD, &format!(" {}", message)));
+    let span = match span {
+        Some(span) if source.is_char_boundary(span.start) => span,
+        _ => return text,
+    };
+
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[span.start..].find('\n').map_or(source.len(), |i| span.start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let number = (source[..line_start].matches('\n').count() + 1).to_string();
+    let column = source[line_start..span.start].chars().count() + 1;
+    let blank = " ".repeat(number.len());
+
+    // Tabs are copied so that the carets line up however wide they are shown.
+    let indent: String = source[line_start..span.start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let end = span.end.min(line_start + line.len()).max(span.start + 1);
+    let width = source.get(span.start..end).map_or(1, |marked| marked.chars().count()).max(1);
+
+    text.push_str(&format!("\n{}{} {}:{}", blank, paint.paint(GUTTER, "-->"), number, column));
+    text.push_str(&format!("\n{} {}", blank, paint.paint(GUTTER, "|")));
+    text.push_str(&format!("\n{} {}", paint.paint(GUTTER, &number), paint.paint(GUTTER, "|")));
+    if !line.is_empty() {
+        text.push_str(&format!(" {}", line));
+    }
+    text.push_str(&format!("\n{} {} {}{}",
+                           blank,
+                           paint.paint(GUTTER, "|"),
+                           indent,
+                           paint.paint(severity.color(), &"^".repeat(width))));
+    text
+}
+
+/// The first offset a message mentions, as in `at offset 12`, which is how
+/// the lexer, parser and interpreter say where an error is.
+pub fn offset_in(message: &str) -> Option<usize> {
+    let i = message.find("offset ")? + 7;
+    let digits = message[i..].find(|c: char| !c.is_ascii_digit()).unwrap_or(message.len() - i);
+    message[i..i + digits].parse().ok()
+}
@@ -0,0 +1,203 @@
+//! Front-ends for languages that are Brainfuck with other spellings, or with
+//! the extra command of Brainfork. Each turns source into the same `Token`s
+//! as `lexer::lex`, so parsing and everything after it works unchanged, and
+//! `Dialect::spell` turns tokens back into source.
+
+use lexer::{self, Token};
+#[cfg(feature = "plugins")]
+use plugin;
+
+/// A way of spelling the eight commands.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Brainfuck,
+    /// Brainfuck with the `Y` of Brainfork, which forks the machine into
+    /// threads that take turns.
+    Brainfork,
+    /// Ook!, where each command is a pair of `Ook.`, `Ook?` and `Ook!`.
+    Ook,
+    /// Each command is a word, as read by `Dialect::from_map`. Anything
+    /// else in the source is ignored, like comments in Brainfuck.
+    Substitution(Vec<(String, Token)>),
+    /// A dialect registered by a plugin, which can be read but not written.
+    #[cfg(feature = "plugins")]
+    Plugin(plugin::Frontend),
+}
+
+/// The words of a substitution dialect, keyed by the command they stand for.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Map {
+    #[serde(rename = "+")]
+    increment: String,
+    #[serde(rename = "-")]
+    decrement: String,
+    #[serde(rename = "<")]
+    shift_left: String,
+    #[serde(rename = ">")]
+    shift_right: String,
+    #[serde(rename = ",")]
+    input: String,
+    #[serde(rename = ".")]
+    output: String,
+    #[serde(rename = "[")]
+    begin_loop: String,
+    #[serde(rename = "]")]
+    end_loop: String,
+}
+
+impl Dialect {
+    /// Finds a built-in dialect, or one a loaded plugin registered.
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name {
+            "bf" | "brainfuck" => Some(Dialect::Brainfuck),
+            "brainfork" => Some(Dialect::Brainfork),
+            "ook" => Some(Dialect::Ook),
+            #[cfg(feature = "plugins")]
+            _ => plugin::dialect(name).map(Dialect::Plugin),
+            #[cfg(not(feature = "plugins"))]
+            _ => None,
+        }
+    }
+
+    /// Reads a substitution dialect from a TOML file that maps each command
+    /// to its word, e.g. `"+" = "moo"`.
+    pub fn from_map(contents: &str) -> Result<Dialect, String> {
+        let map: Map = ::toml::from_str(contents).map_err(|e| format!("Invalid dialect map: {}", e))?;
+        let mut words = vec![(map.increment, Token::Increment),
+                             (map.decrement, Token::Decrement),
+                             (map.shift_left, Token::ShiftLeft),
+                             (map.shift_right, Token::ShiftRight),
+                             (map.input, Token::Input),
+                             (map.output, Token::Output),
+                             (map.begin_loop, Token::BeginLoop),
+                             (map.end_loop, Token::EndLoop)];
+        if words.iter().any(|(word, _)| word.is_empty()) {
+            return Err("Invalid dialect map: words must not be empty.".to_owned());
+        }
+        // Where one word starts another, the longer one has to be tried first.
+        words.sort_by_key(|word| ::std::cmp::Reverse(word.0.len()));
+        if has_duplicates(&words) {
+            return Err("Invalid dialect map: two commands have the same word.".to_owned());
+        }
+        Ok(Dialect::Substitution(words))
+    }
+
+    /// Splits `input` into tokens, each paired with the byte offset of the
+    /// text it was read from.
+    pub fn lex(&self, input: &str) -> Result<Vec<(usize, Token)>, String> {
+        match *self {
+            Dialect::Brainfuck => Ok(lexer::lex(input)),
+            Dialect::Brainfork => Ok(lexer::lex_brainfork(input)),
+            Dialect::Ook => lex_ook(input),
+            Dialect::Substitution(ref words) => Ok(lex_words(input, words)),
+            #[cfg(feature = "plugins")]
+            Dialect::Plugin(ref frontend) => frontend.lex(input),
+        }
+    }
+
+    /// Writes `tokens` in this dialect, the reverse of `lex`, wrapping lines
+    /// at `LINE_WIDTH` characters. Fails if a token has no spelling here.
+    pub fn spell(&self, tokens: &[Token]) -> Result<String, String> {
+        let separator = match *self {
+            Dialect::Brainfuck | Dialect::Brainfork => "",
+            Dialect::Ook | Dialect::Substitution(_) => " ",
+            #[cfg(feature = "plugins")]
+            Dialect::Plugin(ref frontend) => {
+                return Err(format!("Programs cannot be written in the '{}' dialect.", frontend.name))
+            }
+        };
+        let mut out = String::new();
+        let mut line = 0;
+        for &token in tokens {
+            let word = self.word(token)?;
+            if line > 0 && line + separator.len() + word.len() > LINE_WIDTH {
+                out.push('\n');
+                line = 0;
+            } else if line > 0 {
+                out.push_str(separator);
+                line += separator.len();
+            }
+            out.push_str(word);
+            line += word.len();
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    fn word(&self, token: Token) -> Result<&str, String> {
+        let word = match *self {
+            Dialect::Brainfork if token == Token::Fork => Some("Y"),
+            Dialect::Brainfuck | Dialect::Brainfork => SPELLINGS.iter().find(|s| s.0 == token).map(|s| s.1),
+            Dialect::Ook => SPELLINGS.iter().find(|s| s.0 == token).map(|s| s.2),
+            Dialect::Substitution(ref words) => words.iter().find(|word| word.1 == token).map(|word| word.0.as_str()),
+            #[cfg(feature = "plugins")]
+            Dialect::Plugin(_) => None,
+        };
+        word.ok_or_else(|| "Brainfork's 'Y' cannot be written in this dialect.".to_owned())
+    }
+}
+
+/// Each of the eight commands with its Brainfuck and Ook! spelling.
+const SPELLINGS: [(Token, &str, &str); 8] = [(Token::Increment, "+", "Ook. Ook."),
+                                             (Token::Decrement, "-", "Ook! Ook!"),
+                                             (Token::ShiftLeft, "<", "Ook? Ook."),
+                                             (Token::ShiftRight, ">", "Ook. Ook?"),
+                                             (Token::Input, ",", "Ook. Ook!"),
+                                             (Token::Output, ".", "Ook! Ook."),
+                                             (Token::BeginLoop, "[", "Ook! Ook?"),
+                                             (Token::EndLoop, "]", "Ook? Ook!")];
+
+/// Longest line `Dialect::spell` writes, unless a single word is longer.
+pub const LINE_WIDTH: usize = 72;
+
+fn has_duplicates(words: &[(String, Token)]) -> bool {
+    words.iter().enumerate().any(|(i, a)| words[i + 1..].iter().any(|b| a.0 == b.0))
+}
+
+fn lex_words(input: &str, words: &[(String, Token)]) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        match words.iter().find(|(word, _)| input[i..].starts_with(word.as_str())) {
+            Some(&(ref word, token)) => {
+                tokens.push((i, token));
+                i += word.len();
+            }
+            None => i += input[i..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    tokens
+}
+
+fn lex_ook(input: &str) -> Result<Vec<(usize, Token)>, String> {
+    let words: Vec<(usize, u8)> = input.match_indices("Ook")
+        .filter_map(|(i, _)| match input.as_bytes().get(i + 3) {
+            Some(&mark) if mark == b'.' || mark == b'?' || mark == b'!' => Some((i, mark)),
+            _ => None,
+        })
+        .collect();
+    if !words.len().is_multiple_of(2) {
+        return Err("Ook! words must come in pairs, but the last one has no partner.".to_owned());
+    }
+
+    words.chunks(2)
+        .map(|pair| {
+            let token = match (pair[0].1, pair[1].1) {
+                (b'.', b'?') => Token::ShiftRight,
+                (b'?', b'.') => Token::ShiftLeft,
+                (b'.', b'.') => Token::Increment,
+                (b'!', b'!') => Token::Decrement,
+                (b'!', b'.') => Token::Output,
+                (b'.', b'!') => Token::Input,
+                (b'!', b'?') => Token::BeginLoop,
+                (b'?', b'!') => Token::EndLoop,
+                _ => return Err(format!("'Ook? Ook?' at offset {} is not an Ook! command.", pair[0].0)),
+            };
+            Ok((pair[0].0, token))
+        })
+        .collect()
+}
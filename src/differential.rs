@@ -0,0 +1,206 @@
+//! Differential testing for the `verify` command: runs a program on two
+//! backends with the same input and compares what they printed, whether
+//! they failed, and the tapes they left behind. For the `diff` command,
+//! two programs are compared by their output instead, as it is printed.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Cursor;
+
+use bytecode;
+use harness::SharedBuffer;
+use interp::{run, step_threads, State};
+use ir::{self, OptLevel, Program};
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+use jit;
+use tape::Tape;
+
+/// A way of running a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The interpreter on the unoptimized program.
+    Interpreter,
+    /// The interpreter on the optimized program.
+    Optimized,
+    /// The optimized program after a round trip through bytecode.
+    Bytecode,
+    /// The optimized program compiled to machine code.
+    Jit,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "interp" => Some(Backend::Interpreter),
+            "opt" => Some(Backend::Optimized),
+            "bytecode" => Some(Backend::Bytecode),
+            "jit" => Some(Backend::Jit),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Interpreter => "interp",
+            Backend::Optimized => "opt",
+            Backend::Bytecode => "bytecode",
+            Backend::Jit => "jit",
+        }
+    }
+}
+
+/// What a run did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub output: Vec<u8>,
+    pub error: Option<String>,
+    /// The cells that are not zero at the end, by their distance from the
+    /// pointer. Optimized programs can grow the tape less, so cells are
+    /// not compared by their index.
+    pub cells: BTreeMap<isize, u8>,
+}
+
+/// Runs `program` on `backend`, optimizing it at `opt_level` where the
+/// backend does. Fails if the backend is not available.
+pub fn run_on(backend: Backend, program: &Program, opt_level: OptLevel, input: &[u8]) -> Result<Run, String> {
+    let program = match backend {
+        Backend::Interpreter => program.clone(),
+        Backend::Optimized | Backend::Jit => ir::optimize(program, opt_level),
+        Backend::Bytecode => {
+            bytecode::decode(&bytecode::encode(&ir::optimize(program, opt_level), opt_level))?.program
+        }
+    };
+    let output = SharedBuffer::default();
+    let mut state = State::new(program);
+    state.input = Some(Box::new(Cursor::new(input.to_vec())));
+    state.output = Some(Box::new(output.clone()));
+
+    let error = match backend {
+        Backend::Jit => run_jit(&mut state)?,
+        _ => run(&mut state).err(),
+    };
+    let pointer = state.pointer() as isize;
+    let cells = state.tape()
+        .to_vec()
+        .iter()
+        .enumerate()
+        .filter(|&(_, &value)| value != 0)
+        .map(|(cell, &value)| (cell as isize - pointer, value))
+        .collect();
+    Ok(Run { output: output.take(), error, cells })
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+fn run_jit(state: &mut State) -> Result<Option<String>, String> {
+    if !jit::supports(state) {
+        return Err("The program cannot be compiled to machine code.".to_owned());
+    }
+    Ok(jit::run(state).err())
+}
+
+#[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+fn run_jit(_state: &mut State) -> Result<Option<String>, String> {
+    Err("The 'jit' backend needs a build with the 'jit' feature on x86_64.".to_owned())
+}
+
+/// The first way in which `b` differs from `a`, if any: the output, then
+/// whether and how the runs failed, then the tape.
+pub fn divergence(a: &Run, b: &Run) -> Option<String> {
+    if let Some(at) = a.output.iter().zip(&b.output).position(|(x, y)| x != y) {
+        return Some(format!("output byte {} is {} and {}", at, a.output[at], b.output[at]));
+    }
+    if a.output.len() != b.output.len() {
+        return Some(format!("output is {} and {} bytes long", a.output.len(), b.output.len()));
+    }
+    if a.error != b.error {
+        let show = |error: &Option<String>| error.clone().unwrap_or_else(|| "no error".to_owned());
+        return Some(format!("the runs ended with {} and {}", show(&a.error), show(&b.error)));
+    }
+    let at = a.cells.keys().chain(b.cells.keys()).filter(|cell| a.cells.get(cell) != b.cells.get(cell)).min()?;
+    let value = |run: &Run| run.cells.get(at).cloned().unwrap_or(0);
+    Some(format!("the cell at pointer{:+} ends as {} and {}", at, value(a), value(b)))
+}
+
+/// One of the programs `compare_programs` runs, stepped until it prints.
+struct Racer {
+    state: State,
+    output: SharedBuffer,
+    /// Bytes printed but not compared yet.
+    pending: VecDeque<u8>,
+    /// Set once the program has ended, with the error it failed with.
+    ended: Option<Option<String>>,
+}
+
+impl Racer {
+    fn new(program: &Program, input: &[u8], max_steps: Option<u64>) -> Self {
+        let output = SharedBuffer::default();
+        let mut state = State::new(program.clone());
+        state.input = Some(Box::new(Cursor::new(input.to_vec())));
+        state.output = Some(Box::new(output.clone()));
+        state.max_steps = max_steps;
+        Racer { state, output, pending: VecDeque::new(), ended: None }
+    }
+
+    /// Runs the program until it prints its next byte, or `None` once it
+    /// has ended without printing one.
+    fn next_byte(&mut self) -> Option<u8> {
+        while self.pending.is_empty() && self.ended.is_none() {
+            match step_threads(&mut self.state) {
+                Ok(true) => (),
+                Ok(false) => self.ended = Some(self.state.flush().err()),
+                Err(e) => self.ended = Some(Some(e)),
+            }
+            self.pending.extend(self.output.take());
+        }
+        self.pending.pop_front()
+    }
+
+    fn side(&self, byte: Option<u8>) -> Side {
+        Side {
+            byte,
+            steps: self.state.stats.instructions,
+            error: self.ended.clone().unwrap_or(None),
+        }
+    }
+}
+
+/// Where one program was when two were compared, see `compare_programs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Side {
+    /// The byte it printed where the outputs differ, or `None` if it ended
+    /// before printing one there.
+    pub byte: Option<u8>,
+    /// Instructions it had executed by then.
+    pub steps: u64,
+    /// What it failed with, if it ended by failing.
+    pub error: Option<String>,
+}
+
+/// The result of `compare_programs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Both printed the same bytes, this many, and ended.
+    Same { bytes: usize, a: Side, b: Side },
+    /// Their output differs first at byte `at`.
+    Differs { at: usize, a: Side, b: Side },
+}
+
+/// Runs programs `a` and `b`, each on its own copy of `input`, side by
+/// side, a byte of output at a time, and stops at the first byte where
+/// their output differs, so that a program that goes wrong and never ends
+/// still is caught once it prints something else. Each executes at most
+/// `max_steps` instructions, if given, and ends when it fails.
+pub fn compare_programs(a: &Program, b: &Program, input: &[u8], max_steps: Option<u64>) -> Comparison {
+    let mut first = Racer::new(a, input, max_steps);
+    let mut second = Racer::new(b, input, max_steps);
+    let mut at = 0;
+    loop {
+        let (x, y) = (first.next_byte(), second.next_byte());
+        if x != y {
+            return Comparison::Differs { at, a: first.side(x), b: second.side(y) };
+        }
+        if x.is_none() {
+            return Comparison::Same { bytes: at, a: first.side(x), b: second.side(y) };
+        }
+        at += 1;
+    }
+}
@@ -0,0 +1,43 @@
+/// A classic program shipped inside the binary.
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub source: &'static str,
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        name: "hello",
+        description: "Prints \"Hello World!\".",
+        source: include_str!("examples/hello.b"),
+    },
+    Example {
+        name: "cat",
+        description: "Echoes its input until it reads a 0.",
+        source: include_str!("examples/cat.b"),
+    },
+    Example {
+        name: "rot13",
+        description: "Applies ROT13 to its input (by Wikipedia contributors).",
+        source: include_str!("examples/rot13.b"),
+    },
+    Example {
+        name: "fib",
+        description: "Prints the Fibonacci numbers forever (by Daniel B. Cristofani).",
+        source: include_str!("examples/fib.b"),
+    },
+    Example {
+        name: "sierpinski",
+        description: "Draws a Sierpinski triangle (by Daniel B. Cristofani).",
+        source: include_str!("examples/sierpinski.b"),
+    },
+    Example {
+        name: "dbfi",
+        description: "Runs the program before a '!' in its input on the rest (by Daniel B. Cristofani).",
+        source: include_str!("examples/dbfi.b"),
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|e| e.name == name)
+}
@@ -0,0 +1,103 @@
+/// Commands a run has to contain to be considered at all.
+const MIN_COMMANDS: usize = 4;
+
+/// Other characters a run may skip over between two commands.
+const MAX_GAP: usize = 3;
+
+/// A stretch of a file that looks like it could be a Brainfuck program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    /// Byte range of the candidate in the scanned file.
+    pub start: usize,
+    pub end: usize,
+    pub code: String,
+    /// How likely the candidate is an intentional program, from 0 to 1.
+    pub confidence: f64,
+}
+
+fn is_command(c: char) -> bool {
+    "+-<>[].,".contains(c)
+}
+
+/// Finds runs of Brainfuck commands in arbitrary text, best candidates
+/// first. A run may span several lines and skip over a few other characters
+/// between commands, such as the `*` or `//` that start comment lines.
+pub fn candidates(contents: &str) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    let mut run: Option<Candidate> = None;
+    let mut gap = 0;
+    let mut newline = false;
+
+    for (i, c) in contents.char_indices() {
+        if is_command(c) {
+            let current = run.get_or_insert_with(|| Candidate {
+                start: i,
+                end: i,
+                code: String::new(),
+                confidence: 0.0,
+            });
+            if newline {
+                current.code.push('\n');
+            }
+            current.code.push(c);
+            current.end = i + 1;
+            gap = 0;
+            newline = false;
+        } else if c == '\n' {
+            newline = run.is_some();
+        } else if !c.is_whitespace() {
+            gap += 1;
+            if gap > MAX_GAP {
+                candidates.extend(run.take().and_then(finish));
+                newline = false;
+            }
+        }
+    }
+    candidates.extend(run.and_then(finish));
+
+    candidates.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(::std::cmp::Ordering::Equal));
+    candidates
+}
+
+fn finish(mut candidate: Candidate) -> Option<Candidate> {
+    if candidate.code.chars().filter(|&c| is_command(c)).count() < MIN_COMMANDS {
+        return None;
+    }
+    candidate.confidence = confidence(&candidate.code);
+    Some(candidate)
+}
+
+/// Scores a run of commands: real programs have balanced brackets, print
+/// something and use a mix of commands, while separators like `-----` or
+/// arrows like `<-->` do not.
+fn confidence(code: &str) -> f64 {
+    let mut depth = 0i64;
+    let mut balanced = true;
+    let mut kinds = [false; 8];
+    let mut commands = 0;
+    for c in code.chars().filter(|&c| is_command(c)) {
+        commands += 1;
+        kinds["+-<>[].,".find(c).unwrap_or(0)] = true;
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                balanced &= depth >= 0;
+            }
+            _ => (),
+        }
+    }
+    balanced &= depth == 0;
+
+    let variety = match kinds.iter().filter(|&&k| k).count() {
+        0 | 1 => 0.1,
+        2 => 0.4,
+        3 => 0.8,
+        _ => 1.0,
+    };
+    let balance = if balanced { 1.0 } else { 0.3 };
+    let output = if code.contains('.') { 1.0 } else { 0.5 };
+    let length = 1.0 - (-(commands as f64) / 40.0).exp();
+
+    variety * balance * output * length
+}
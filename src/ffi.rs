@@ -0,0 +1,190 @@
+//! A C interface for embedding the interpreter, built with the 'ffi'
+//! feature. `include/bfinterpreter.h` declares it.
+//!
+//! A program is compiled once into a handle and can then be run any number
+//! of times. Without callbacks, input is read from stdin and output written
+//! to stdout; the callbacks see the same bytes those would.
+
+use std::ffi::{CStr, CString};
+use std::io::{self, BufReader, Read, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use interp::{run_fast, State};
+use ir::{self, OptLevel, Program};
+use parser::parse;
+
+/// Returns the next input byte, or a negative value at the end of input.
+pub type InputCallback = extern "C" fn(user: *mut c_void) -> c_int;
+
+/// Receives a byte of output.
+pub type OutputCallback = extern "C" fn(byte: u8, user: *mut c_void);
+
+/// A compiled program and how to run it.
+pub struct Handle {
+    program: Option<Program>,
+    input: Option<(InputCallback, *mut c_void)>,
+    output: Option<(OutputCallback, *mut c_void)>,
+    error: Option<CString>,
+}
+
+impl Handle {
+    fn fail(&mut self, message: String) -> c_int {
+        self.error = Some(CString::new(message.replace('\0', " ")).unwrap_or_default());
+        1
+    }
+}
+
+struct CallbackInput(InputCallback, *mut c_void);
+
+impl Read for CallbackInput {
+    // One byte at a time, so that reading a line never waits for more input
+    // than the line itself.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let value = (self.0)(self.1);
+        if value < 0 {
+            return Ok(0);
+        }
+        buf[0] = value as u8;
+        Ok(1)
+    }
+}
+
+struct CallbackOutput(OutputCallback, *mut c_void);
+
+impl Write for CallbackOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            (self.0)(byte, self.1);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compiles a NUL-terminated program. Always returns a handle, which has to
+/// be released with `bf_free`; if compiling failed, `bf_error` describes why
+/// and `bf_run` fails.
+///
+/// # Safety
+///
+/// `source` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bf_compile(source: *const c_char) -> *mut Handle {
+    let mut handle = Box::new(Handle { program: None, input: None, output: None, error: None });
+    if source.is_null() {
+        handle.fail("No program given.".to_owned());
+        return Box::into_raw(handle);
+    }
+    let compiled = CStr::from_ptr(source)
+        .to_str()
+        .map_err(|_| "Program is not valid UTF-8.".to_owned())
+        .and_then(parse)
+        .map(|tree| ir::optimize(&ir::lower(&tree), OptLevel::MAX));
+    match compiled {
+        Ok(program) => handle.program = Some(program),
+        Err(e) => {
+            handle.fail(e);
+        }
+    }
+    Box::into_raw(handle)
+}
+
+/// Runs the program from the start on a fresh tape. Returns 0 on success
+/// and 1 on error, see `bf_error`.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `bf_compile` and not yet freed, and
+/// any callbacks set must still be valid with their user data.
+#[no_mangle]
+pub unsafe extern "C" fn bf_run(handle: *mut Handle) -> c_int {
+    let handle = match handle.as_mut() {
+        Some(handle) => handle,
+        None => return 1,
+    };
+    let program = match handle.program {
+        Some(ref program) => program.clone(),
+        None => return 1,
+    };
+
+    let mut state = State::new(program);
+    state.use_stdio();
+    if let Some((callback, user)) = handle.input {
+        state.input = Some(Box::new(BufReader::new(CallbackInput(callback, user))));
+    }
+    if let Some((callback, user)) = handle.output {
+        state.output = Some(Box::new(CallbackOutput(callback, user)));
+    }
+
+    // Panics must not unwind into the calling C code.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run_fast(&mut state)))
+        .unwrap_or_else(|_| Err("Program failed.".to_owned()));
+    match result {
+        Ok(_) => {
+            handle.error = None;
+            0
+        }
+        Err(e) => handle.fail(e),
+    }
+}
+
+/// Makes `bf_run` read input from `callback` instead of stdin, passing it
+/// `user` on every call. A NULL callback goes back to stdin.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `bf_compile` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_set_input_callback(handle: *mut Handle, callback: Option<InputCallback>, user: *mut c_void) {
+    if let Some(handle) = handle.as_mut() {
+        handle.input = callback.map(|callback| (callback, user));
+    }
+}
+
+/// Makes `bf_run` write output to `callback` instead of stdout, passing it
+/// `user` on every call. A NULL callback goes back to stdout.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `bf_compile` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_set_output_callback(handle: *mut Handle, callback: Option<OutputCallback>, user: *mut c_void) {
+    if let Some(handle) = handle.as_mut() {
+        handle.output = callback.map(|callback| (callback, user));
+    }
+}
+
+/// The error of the last failed `bf_compile` or `bf_run`, or NULL. The
+/// string belongs to the handle and stays valid until it is next run or
+/// freed.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `bf_compile` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_error(handle: *const Handle) -> *const c_char {
+    match handle.as_ref().and_then(|handle| handle.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Releases a handle. NULL is ignored.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `bf_compile` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_free(handle: *mut Handle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
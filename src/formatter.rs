@@ -0,0 +1,162 @@
+//! Reformatting Brainfuck source: minifying it down to its commands, or
+//! pretty-printing it with loop bodies indented and comments kept.
+
+use lexer::{lex, Token};
+use parser::parse;
+
+/// Width `pretty` wraps lines at unless told otherwise.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Spaces each level of loop nesting is indented by.
+const INDENT: usize = 2;
+
+/// A part of the source: a single command or the text between two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Command(Token),
+    Comment(&'a str),
+}
+
+/// Splits `source` into commands and the comments between them. Unlike
+/// `lex`, nothing is dropped: joining the pieces gives back the source.
+pub fn scan(source: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = 0;
+    for (offset, token) in lex(source) {
+        if offset > rest {
+            pieces.push(Piece::Comment(&source[rest..offset]));
+        }
+        pieces.push(Piece::Command(token));
+        rest = offset + 1;
+    }
+    if rest < source.len() {
+        pieces.push(Piece::Comment(&source[rest..]));
+    }
+    pieces
+}
+
+/// Keeps only the commands of `source`.
+pub fn minify(source: &str) -> String {
+    lex(source).into_iter().map(|(offset, _)| &source[offset..offset + 1]).collect()
+}
+
+/// Lays out `source` with every loop body indented one level deeper than
+/// its brackets, and no line longer than `width` unless a comment is.
+/// Loops without nested loops or comments stay on one line if they fit.
+pub fn pretty(source: &str, width: usize) -> Result<String, String> {
+    parse(source)?;
+
+    let pieces = scan(source);
+    let mut printer = Printer { out: String::new(), line: String::new(), depth: 0, width };
+    let mut i = 0;
+    while i < pieces.len() {
+        match pieces[i] {
+            Piece::Comment(text) => printer.comment(text),
+            Piece::Command(Token::BeginLoop) => match inline_loop(&pieces[i..], printer.room()) {
+                Some(len) => {
+                    printer.group(&pieces[i..i + len]);
+                    i += len - 1;
+                }
+                None => {
+                    printer.finish_line();
+                    printer.line.push('[');
+                    printer.finish_line();
+                    printer.depth += 1;
+                }
+            },
+            Piece::Command(Token::EndLoop) => {
+                printer.finish_line();
+                printer.depth = printer.depth.saturating_sub(1);
+                printer.line.push(']');
+                printer.finish_line();
+            }
+            Piece::Command(token) => printer.group(&[Piece::Command(token)]),
+        }
+        i += 1;
+    }
+    printer.finish_line();
+    Ok(printer.out)
+}
+
+/// The number of pieces of the loop starting `pieces` if it can be printed
+/// on one line of `room` characters.
+fn inline_loop(pieces: &[Piece], room: usize) -> Option<usize> {
+    let mut commands = 1;
+    for (i, piece) in pieces.iter().enumerate().skip(1) {
+        match *piece {
+            Piece::Command(Token::BeginLoop) => return None,
+            Piece::Command(token) => {
+                commands += 1;
+                if commands > room {
+                    return None;
+                }
+                if token == Token::EndLoop {
+                    return Some(i + 1);
+                }
+            }
+            Piece::Comment(text) if !text.trim().is_empty() => return None,
+            Piece::Comment(_) => (),
+        }
+    }
+    None
+}
+
+struct Printer {
+    out: String,
+    /// The line being filled, without its indentation.
+    line: String,
+    depth: usize,
+    width: usize,
+}
+
+impl Printer {
+    /// Characters that fit on a line at the current depth.
+    fn room(&self) -> usize {
+        self.width.saturating_sub(self.depth * INDENT).max(1)
+    }
+
+    /// Adds commands that have to stay on the same line.
+    fn group(&mut self, pieces: &[Piece]) {
+        let commands = pieces.iter().filter(|piece| matches!(piece, Piece::Command(_))).count();
+        if !self.line.is_empty() && self.line.len() + commands > self.room() {
+            self.finish_line();
+        }
+        for piece in pieces {
+            if let Piece::Command(token) = *piece {
+                self.line.push_str(&token.to_string());
+            }
+        }
+    }
+
+    /// Adds a comment after the commands on the current line if it fits and
+    /// started on the same line in the source, or else on lines of its own.
+    fn comment(&mut self, text: &str) {
+        for (i, part) in text.split('\n').enumerate() {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+            let fits = self.line.len() + 1 + part.len() <= self.room();
+            if i > 0 || !fits {
+                self.finish_line();
+            }
+            if !self.line.is_empty() {
+                self.line.push(' ');
+            }
+            self.line.push_str(part);
+            self.finish_line();
+        }
+    }
+
+    fn finish_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        for _ in 0..self.depth * INDENT {
+            self.out.push(' ');
+        }
+        self.out.push_str(&self.line);
+        self.out.push('\n');
+        self.line.clear();
+    }
+}
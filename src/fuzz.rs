@@ -0,0 +1,123 @@
+//! Random input for the `fuzz` command: runs a program on pseudorandom
+//! bytes under limits and sorts the runs into those that finished, failed,
+//! ran out of steps or memory, or wanted more input. Every run is made from
+//! a seed alone, so a run is repeated by giving its seed again, and the
+//! digests of two backends' output can be compared seed by seed.
+
+use std::io::Cursor;
+
+use harness::SharedBuffer;
+use interp::{run, State};
+use ir::{Instruction, Program};
+
+/// Steps a run may take unless told otherwise.
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+/// Bytes of memory a run may use unless told otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 << 20;
+/// The most input bytes a run is given unless told otherwise.
+pub const DEFAULT_MAX_INPUT: usize = 64;
+
+/// What each run may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_steps: u64,
+    pub memory_limit: usize,
+    /// The input is between none and this many bytes long.
+    pub max_input: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: DEFAULT_MAX_STEPS,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            max_input: DEFAULT_MAX_INPUT,
+        }
+    }
+}
+
+/// The SplitMix64 generator, which is small and gives the same bytes for a
+/// seed everywhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Between none and `max` random bytes.
+    pub fn bytes(&mut self, max: usize) -> Vec<u8> {
+        let len = (self.next_u64() % (max as u64 + 1)) as usize;
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+}
+
+/// How a run ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Finished,
+    /// A runtime error other than running out of steps, memory or input.
+    Crashed(String),
+    /// The step limit ran out, which is how a run that never ends shows.
+    Hung,
+    OutOfMemory,
+    /// `,` found the input exhausted, which says more about the input than
+    /// about the program.
+    InputExhausted,
+}
+
+/// A run on the input made from `seed`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    pub seed: u64,
+    pub input: Vec<u8>,
+    pub verdict: Verdict,
+    /// How much the program printed before it ended.
+    pub output_len: usize,
+    /// The FNV-1a hash of that output.
+    pub digest: u64,
+}
+
+/// Runs `program` on the input made from `seed`. Input is read byte by
+/// byte, see `State::byte_input`.
+pub fn run_seed(program: &Program, seed: u64, limits: &Limits) -> Case {
+    let input = Rng::new(seed).bytes(limits.max_input);
+    let output = SharedBuffer::default();
+    let mut state = State::new(program.clone());
+    state.input = Some(Box::new(Cursor::new(input.clone())));
+    state.output = Some(Box::new(output.clone()));
+    state.byte_input = true;
+    state.max_steps = Some(limits.max_steps);
+    state.memory_limit = Some(limits.memory_limit);
+
+    let verdict = match run(&mut state) {
+        Ok(_) => Verdict::Finished,
+        Err(_) if state.stats.instructions >= limits.max_steps => Verdict::Hung,
+        Err(_) if state.stats.peak_memory > limits.memory_limit => Verdict::OutOfMemory,
+        Err(_) if state.stats.input_bytes as usize == input.len() &&
+                  state.program().instructions.get(state.pc()) == Some(&Instruction::Input) => Verdict::InputExhausted,
+        Err(e) => Verdict::Crashed(e),
+    };
+    let output = output.take();
+    Case {
+        seed,
+        input,
+        verdict,
+        output_len: output.len(),
+        digest: digest(&output),
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`.
+pub fn digest(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
@@ -0,0 +1,105 @@
+//! Golden-file tests, run by the `test` command: every `NAME.b` in a
+//! directory is run on `NAME.in`, or on no input without one, and has to
+//! print exactly `NAME.out`.
+
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use harness::SharedBuffer;
+use interp::{run, State};
+use ir::{self, OptLevel};
+use parser::parse;
+
+/// A program with its input and expected output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub program: PathBuf,
+    pub input: Option<PathBuf>,
+    pub expected: Option<PathBuf>,
+}
+
+/// How a case went.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    /// The output differs from the expected one, as described.
+    Fail(String),
+    /// The program could not be read or parsed, or failed while running.
+    Error(String),
+    /// There is no expected output to compare with.
+    Skip,
+}
+
+/// The cases in `dir`, sorted by name.
+pub fn discover(dir: &Path) -> Result<Vec<Case>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Could not read '{}': {}", dir.display(), e))?;
+    let mut cases: Vec<Case> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension == "b"))
+        .map(|program| {
+            let existing = |extension: &str| Some(program.with_extension(extension)).filter(|path| path.is_file());
+            Case {
+                name: program.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+                input: existing("in"),
+                expected: existing("out"),
+                program,
+            }
+        })
+        .collect();
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+/// Runs `case` optimized at `opt_level`, giving up after `max_steps`
+/// instructions.
+pub fn check(case: &Case, opt_level: OptLevel, max_steps: u64) -> Outcome {
+    let expected = match case.expected {
+        Some(ref path) => match fs::read(path) {
+            Ok(expected) => expected,
+            Err(e) => return Outcome::Error(format!("Could not read '{}': {}", path.display(), e)),
+        },
+        None => return Outcome::Skip,
+    };
+    let input = match case.input {
+        Some(ref path) => match fs::read(path) {
+            Ok(input) => input,
+            Err(e) => return Outcome::Error(format!("Could not read '{}': {}", path.display(), e)),
+        },
+        None => Vec::new(),
+    };
+    let source = match fs::read_to_string(&case.program) {
+        Ok(source) => source,
+        Err(e) => return Outcome::Error(format!("Could not read '{}': {}", case.program.display(), e)),
+    };
+    let tree = match parse(&source) {
+        Ok(tree) => tree,
+        Err(e) => return Outcome::Error(e),
+    };
+
+    let output = SharedBuffer::default();
+    let mut state = State::new(ir::optimize(&ir::lower(&tree), opt_level));
+    state.input = Some(Box::new(Cursor::new(input)));
+    state.output = Some(Box::new(output.clone()));
+    state.max_steps = Some(max_steps);
+    if let Err(e) = run(&mut state) {
+        return Outcome::Error(e);
+    }
+    match difference(&expected, &output.take()) {
+        Some(difference) => Outcome::Fail(difference),
+        None => Outcome::Pass,
+    }
+}
+
+/// Where `actual` first differs from `expected`, if it does.
+fn difference(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let show = |byte: u8| format!("'{}'", byte.escape_ascii());
+    let at = expected.iter().zip(actual).position(|(a, b)| a != b);
+    match (at, expected.len(), actual.len()) {
+        (Some(at), _, _) => Some(format!("byte {} is {}, expected {}", at, show(actual[at]), show(expected[at]))),
+        (None, e, a) if a < e => Some(format!("output ends after {} bytes, expected {}", a, e)),
+        (None, e, a) if a > e => Some(format!("output goes on after {} bytes, with {}", e, show(actual[e]))),
+        _ => None,
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+
+use interp::{run, step_threads, State};
+use ir::OptLevel;
+use parser::parse;
+use pool::Pool;
+
+/// An output sink whose contents can still be read after it has been handed
+/// to the interpreter.
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// A buffer that appends to `buffer`, reusing its allocation.
+    pub fn from_vec(buffer: Vec<u8>) -> Self {
+        SharedBuffer(Rc::new(RefCell::new(buffer)))
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn take(&self) -> Vec<u8> {
+        ::std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `source` on `input` and returns everything it printed, giving up
+/// after `max_steps` instructions.
+pub fn run_captured(source: &str, input: &[u8], max_steps: u64) -> Result<Vec<u8>, String> {
+    run_pooled(&mut Pool::default(), source, input, max_steps)
+}
+
+/// Like `run_captured`, but takes the buffers for the run from `pool` and
+/// returns them afterwards. The output can be handed back with
+/// `Pool::put_output` once it is no longer needed.
+pub fn run_pooled(pool: &mut Pool, source: &str, input: &[u8], max_steps: u64) -> Result<Vec<u8>, String> {
+    let tree = parse(source)?;
+
+    let output = SharedBuffer::from_vec(pool.output());
+    let program = pool.compile(&tree, OptLevel::NONE);
+    let mut state = pool.state(program);
+    state.input = Some(Box::new(Cursor::new(input.to_vec())));
+    state.output = Some(Box::new(output.clone()));
+    state.max_steps = Some(max_steps);
+
+    let result = run(&mut state);
+    pool.recycle(state);
+    result.map(|_| output.take())
+}
+
+/// The output of a program, produced lazily by running it only as far as
+/// the next byte needs. See `output_bytes`.
+pub struct OutputBytes {
+    state: State,
+    output: SharedBuffer,
+    /// Bytes written by the last instruction and not yet handed out.
+    ready: VecDeque<u8>,
+    done: bool,
+    /// What the run failed with, handed out after the last byte.
+    error: Option<String>,
+}
+
+/// Turns the program of `state`, reading its input as set up, into an
+/// iterator over the bytes it writes. A failed run ends the iteration with
+/// its error. The output set on `state` is replaced.
+pub fn output_bytes(mut state: State) -> OutputBytes {
+    let output = SharedBuffer::default();
+    state.output = Some(Box::new(output.clone()));
+    OutputBytes {
+        state,
+        output,
+        ready: VecDeque::new(),
+        done: false,
+        error: None,
+    }
+}
+
+impl OutputBytes {
+    /// The state, e.g. to look at the tape after the last byte.
+    pub fn into_state(self) -> State {
+        self.state
+    }
+}
+
+impl Iterator for OutputBytes {
+    type Item = Result<u8, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(byte) = self.ready.pop_front() {
+                return Some(Ok(byte));
+            }
+            if self.done {
+                return self.error.take().map(Err);
+            }
+            match step_threads(&mut self.state) {
+                Ok(true) => {}
+                stepped => {
+                    self.done = true;
+                    let flushed = self.state.flush();
+                    self.error = stepped.and(flushed).err();
+                }
+            }
+            self.ready.extend(self.output.take());
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! How a program uses its memory: the reads and writes of every cell, which
+//! `--heat-map` records and exports as CSV.
+
+use ir::Instruction;
+use names::CellNames;
+use tape::Tape;
+
+/// Accesses of a single cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CellHeat {
+    pub reads: u64,
+    pub writes: u64,
+    /// The largest value the cell held.
+    pub max: u8,
+}
+
+/// Accesses of every cell, by its index on the tape. The threads of a
+/// Brainfork program are counted together.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeatMap {
+    cells: Vec<CellHeat>,
+}
+
+impl HeatMap {
+    pub fn cells(&self) -> &[CellHeat] {
+        &self.cells
+    }
+
+    /// Counts the accesses of `instruction`, which has just run with the
+    /// pointer at `pointer` and left `tape`.
+    pub(crate) fn record<T: Tape>(&mut self, instruction: Instruction, pointer: usize, tape: &T) {
+        let (at, read, write) = match instruction {
+            Instruction::Add(_) => (0, true, true),
+            Instruction::Set(_) | Instruction::Clear | Instruction::Input => (0, false, true),
+            Instruction::AddAt(at, _) => (at, true, true),
+            Instruction::SetAt(at, _) => (at, false, true),
+            Instruction::Output | Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_) => (0, true, false),
+            Instruction::Move(_) | Instruction::Fork => return,
+        };
+        if self.cells.len() < tape.len() {
+            self.cells.resize(tape.len(), CellHeat::default());
+        }
+        let cell = (pointer as isize + at) as usize;
+        let heat = &mut self.cells[cell];
+        if read {
+            heat.reads += 1;
+        }
+        if write {
+            heat.writes += 1;
+            heat.max = heat.max.max(tape.get(cell));
+        }
+    }
+
+    /// Moves the counts along with the cells when the tape grows by `n`
+    /// cells at its left end.
+    pub(crate) fn grew_left(&mut self, n: usize) {
+        self.cells.splice(0..0, std::iter::repeat_n(CellHeat::default(), n));
+    }
+
+    /// A line `cell,name,reads,writes,max` for every cell, after a header.
+    /// The name is the one `names` gives the cell, or empty. Names contain
+    /// no commas, so they need no quotes.
+    pub fn to_csv(&self, names: &CellNames) -> String {
+        let mut csv = "cell,name,reads,writes,max\n".to_owned();
+        for (cell, heat) in self.cells.iter().enumerate() {
+            let name = names.name(cell).unwrap_or("");
+            csv.push_str(&format!("{},{},{},{},{}\n", cell, name, heat.reads, heat.writes, heat.max));
+        }
+        csv
+    }
+}
@@ -0,0 +1,87 @@
+use lexer::Token;
+use parser::SyntaxItem;
+
+/// A likely cause for a program misbehaving.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hint {
+    /// Source offset the hint refers to, if any.
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+/// Looks for common mistakes that make a program print nothing or run forever.
+pub fn analyze(items: &[SyntaxItem]) -> Vec<Hint> {
+    let mut hints = Vec::new();
+
+    if !contains(items, Token::Output) {
+        hints.push(Hint {
+            offset: None,
+            message: "the program has no `.`, so it never prints anything".to_owned(),
+        });
+    }
+
+    // All cells start at zero, so a leading loop is skipped.
+    if let Some(&SyntaxItem::Loop(offset, ref body, _)) = items.first() {
+        if contains(body, Token::Output) {
+            hints.push(Hint {
+                offset: Some(offset),
+                message: "this loop is never entered because the current cell is still zero when the \
+                          program starts, so the output inside it never happens"
+                    .to_owned(),
+            });
+        }
+    }
+
+    analyze_loops(items, &mut hints);
+    hints
+}
+
+fn analyze_loops(items: &[SyntaxItem], hints: &mut Vec<Hint>) {
+    for item in items {
+        if let SyntaxItem::Loop(offset, ref body, _) = *item {
+            if let Some(message) = check_loop(body) {
+                hints.push(Hint {
+                    offset: Some(offset),
+                    message,
+                });
+            }
+            analyze_loops(body, hints);
+        }
+    }
+}
+
+/// Inspects a loop body without nested loops, whose effect is known exactly.
+fn check_loop(body: &[SyntaxItem]) -> Option<String> {
+    let mut pointer = 0isize;
+    let mut delta = 0i32;
+    let mut reads_tested_cell = false;
+
+    for item in body {
+        match *item {
+            SyntaxItem::Single(_, Token::Increment) if pointer == 0 => delta += 1,
+            SyntaxItem::Single(_, Token::Decrement) if pointer == 0 => delta -= 1,
+            SyntaxItem::Single(_, Token::Input) if pointer == 0 => reads_tested_cell = true,
+            SyntaxItem::Single(_, Token::ShiftLeft) => pointer -= 1,
+            SyntaxItem::Single(_, Token::ShiftRight) => pointer += 1,
+            SyntaxItem::Single(..) => (),
+            SyntaxItem::Loop(..) => return None,
+        }
+    }
+
+    if pointer == 0 && delta.rem_euclid(256) == 0 && !reads_tested_cell {
+        Some("this loop never changes the cell it tests, so it runs forever once entered".to_owned())
+    } else if pointer != 0 && delta == 0 && !body.is_empty() {
+        Some(format!("this loop moves the pointer by {} on every iteration and only stops at a zero \
+                      cell; if it never finds one it walks off the tape",
+                     pointer))
+    } else {
+        None
+    }
+}
+
+fn contains(items: &[SyntaxItem], token: Token) -> bool {
+    items.iter().any(|item| match *item {
+        SyntaxItem::Single(_, t) => t == token,
+        SyntaxItem::Loop(_, ref body, _) => contains(body, token),
+    })
+}
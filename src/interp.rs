@@ -0,0 +1,954 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use control::{Handle, Shared};
+use heat::HeatMap;
+use ir::{Instruction, Program};
+use metrics::Metrics;
+use newline::Newline;
+use observer::Observer;
+use output::{FlushPolicy, OutputMode};
+use rate::RateLimiter;
+use tape::{Backend, Cells, Tape};
+use trace::Tracer;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub instructions: u64,
+    pub peak_memory: usize,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// Time spent waiting on the output rate limit.
+    pub throttled: Duration,
+    /// Instructions the optimizer removed as dead code before the run.
+    #[serde(default)]
+    pub dead_code: u64,
+}
+
+/// Execution profile of a single loop, keyed by its source offset.
+#[derive(Clone, Debug, Default)]
+pub struct LoopProfile {
+    /// How often the loop was reached.
+    pub entries: u64,
+    /// How often its body was executed in total.
+    pub iterations: u64,
+    /// Time spent in the loop, including any nested loops.
+    pub time: Duration,
+}
+
+/// Where and how often `run` writes checkpoints of the machine state.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub every: u64,
+    pub path: String,
+}
+
+/// How a run ended, if not with an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The program ran to its end.
+    Finished,
+    /// Whoever read the output closed it, e.g. `head` at the end of a pipe,
+    /// so the program was stopped.
+    OutputClosed,
+    /// The `interrupt` flag was set, e.g. by Ctrl-C, or `Handle::stop` was
+    /// called, so the program was stopped before the instruction at the
+    /// program counter.
+    Interrupted,
+}
+
+/// A thread of a Brainfork program waiting for its turn, see `step_threads`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Thread {
+    data: Cells,
+    pointer: usize,
+    pc: usize,
+}
+
+/// Version of the format written by `State::save`.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// How many instructions are executed between looks at the clock for
+/// `State::timeout`, which would be slow on every one.
+const TIMEOUT_CHECK_INTERVAL: u64 = 4096;
+
+/// The complete machine state. Only the fields describing the machine
+/// itself are part of a snapshot; the remaining configuration has to be
+/// set up again after `State::load`.
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    pub(crate) data: Cells,
+    pub(crate) pointer: usize,
+
+    program: Program,
+    pub(crate) pc: usize,
+
+    /// Threads started by `Instruction::Fork` other than the running one,
+    /// next to run first.
+    #[serde(default)]
+    threads: VecDeque<Thread>,
+
+    pub stats: Stats,
+
+    #[serde(skip)]
+    pub memory_limit: Option<usize>,
+    #[serde(skip)]
+    pub profile: Option<HashMap<usize, LoopProfile>>,
+    /// How often each instruction ran, by its index; set it to a zero for
+    /// every instruction of the program to count them.
+    #[serde(skip)]
+    pub coverage: Option<Vec<u64>>,
+    /// Reads and writes of every cell, if they are recorded.
+    #[serde(skip)]
+    pub heat: Option<HeatMap>,
+    #[serde(skip)]
+    pub trace: Option<Tracer>,
+    #[serde(skip)]
+    pub output_rate: Option<RateLimiter>,
+    #[serde(skip)]
+    pub checkpoint: Option<Checkpoint>,
+    #[serde(skip)]
+    pub metrics: Option<Metrics>,
+    /// Told what every instruction does, if set.
+    #[serde(skip)]
+    pub observer: Option<Box<dyn Observer>>,
+    /// Where `,` reads from. Unset, the program has no input and `,` fails;
+    /// `use_stdio` reads stdin instead.
+    #[serde(skip)]
+    pub input: Option<Box<dyn BufRead>>,
+    /// Receives a copy of everything `,` reads.
+    #[serde(skip)]
+    pub input_record: Option<Box<dyn Write>>,
+    /// Where `.` writes to. Unset, output is counted but discarded;
+    /// `use_stdio` writes to stdout instead.
+    #[serde(skip)]
+    pub output: Option<Box<dyn Write>>,
+    /// How output bytes are written.
+    #[serde(skip)]
+    pub output_mode: OutputMode,
+    /// When the output is flushed.
+    #[serde(skip)]
+    pub flush_policy: FlushPolicy,
+    /// Maximum number of instructions to execute before giving up.
+    #[serde(skip)]
+    pub max_steps: Option<u64>,
+    /// Maximum number of times any one loop may go around before giving up,
+    /// counted again each time the loop is entered.
+    #[serde(skip)]
+    pub max_loop_iterations: Option<u64>,
+    /// How long the program may run, counted from the first instruction it
+    /// executes and checked every few thousand instructions. Time spent
+    /// waiting for input counts, but does not end the wait.
+    #[serde(skip)]
+    pub timeout: Option<Duration>,
+    /// Fail when the program moves left of cell 0, rather than growing the
+    /// tape there, which shifts the index of every cell.
+    #[serde(skip)]
+    pub strict_tape: bool,
+    /// Give `,` the bytes of the input as they are, instead of one value
+    /// for each line, which is a number or a character.
+    #[serde(skip)]
+    pub byte_input: bool,
+    /// Leave the cell unchanged when `,` finds the input exhausted, rather
+    /// than failing.
+    #[serde(skip)]
+    pub eof_unchanged: bool,
+    /// Written to stderr before `,` waits for input, so that a program
+    /// waiting for input can be told from one that is stuck in a loop. The
+    /// command line sets it when the input is typed at a terminal.
+    #[serde(skip)]
+    pub prompt: Option<String>,
+    /// Stops the program once set, checked before every instruction.
+    #[serde(skip)]
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Shared with the handles given out by `handle`.
+    #[serde(skip)]
+    pub(crate) control: Option<Arc<Shared>>,
+    /// Line ending the program uses, translated from and to `\n`.
+    #[serde(skip)]
+    pub newline: Newline,
+    /// Input bytes still to be handed out, from a translated line ending.
+    #[serde(default)]
+    pending_input: Vec<u8>,
+    /// A `\r` written by the program that may start a CRLF.
+    #[serde(default)]
+    held_cr: bool,
+    /// Numbers separated by spaces were written on a line not yet ended.
+    #[serde(skip)]
+    open_line: bool,
+    /// Set once writing the output failed because it was closed.
+    #[serde(skip)]
+    output_closed: bool,
+    /// Set once the program was stopped by `interrupt`.
+    #[serde(skip)]
+    interrupted: bool,
+    /// When `timeout` runs out, from the first step.
+    #[serde(skip)]
+    deadline: Option<Instant>,
+    /// Start times of the loops currently being profiled, innermost last.
+    #[serde(skip)]
+    loop_starts: Vec<Instant>,
+    /// Times around each loop since it was last entered, by the index of
+    /// its `JumpIfZero`, kept while `max_loop_iterations` is set.
+    #[serde(skip)]
+    loop_iterations: Vec<u64>,
+}
+
+/// Stdout behind a buffer of its own, so that it is only written to when
+/// `State::flush_policy` says or the buffer is full, rather than after
+/// every line as the standard library does.
+pub struct Stdout(BufWriter<io::Stdout>);
+
+impl Default for Stdout {
+    fn default() -> Self {
+        Stdout(BufWriter::new(io::stdout()))
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    state: &'a State,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    state: State,
+}
+
+impl State {
+    pub fn new(program: Program) -> Self {
+        State::with_tape(program, Vec::new())
+    }
+
+    /// Like `new`, but reuses the allocation of `tape`, whose contents are
+    /// discarded.
+    pub(crate) fn with_tape(program: Program, mut tape: Vec<u8>) -> Self {
+        tape.clear();
+        let mut state = State {
+            data: Cells::Vec(tape),
+            pointer: 0,
+
+            program,
+            pc: 0,
+
+            threads: VecDeque::new(),
+
+            stats: Stats::default(),
+
+            memory_limit: None,
+            profile: None,
+            coverage: None,
+            heat: None,
+            trace: None,
+            output_rate: None,
+            checkpoint: None,
+            metrics: None,
+            observer: None,
+            input: None,
+            input_record: None,
+            output: None,
+            output_mode: OutputMode::Raw,
+            flush_policy: FlushPolicy::default(),
+            max_steps: None,
+            max_loop_iterations: None,
+            timeout: None,
+            strict_tape: false,
+            byte_input: false,
+            eof_unchanged: false,
+            prompt: None,
+            interrupt: None,
+            control: None,
+            newline: Newline::Lf,
+            pending_input: Vec::new(),
+            held_cr: false,
+            open_line: false,
+            output_closed: false,
+            interrupted: false,
+            deadline: None,
+            loop_starts: Vec::new(),
+            loop_iterations: Vec::new(),
+        };
+        state.data.resize(1).expect("a Vec tape always grows");
+        state.stats.peak_memory = state.memory_usage();
+        state
+    }
+
+    pub fn tape(&self) -> &Cells {
+        &self.data
+    }
+
+    /// Moves the cells to a tape of the kind `backend`.
+    pub fn use_tape(&mut self, backend: Backend) -> Result<(), String> {
+        if self.data.backend() == backend {
+            return Ok(());
+        }
+        let mut cells = Cells::new(backend)?;
+        cells.resize(self.data.len())?;
+        for cell in 0..self.data.len() {
+            cells.set(cell, self.data.get(cell));
+        }
+        self.data = cells;
+        self.check_memory()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// A handle to pause, resume or stop the program from another thread.
+    pub fn handle(&mut self) -> Handle {
+        Handle::new(Arc::clone(self.control.get_or_insert_with(Arc::default)))
+    }
+
+    /// Number of threads, including the running one.
+    pub fn threads(&self) -> usize {
+        self.threads.len() + 1
+    }
+
+    /// How the run ended, once `step` has returned `false`.
+    pub fn outcome(&self) -> RunOutcome {
+        if self.output_closed {
+            RunOutcome::OutputClosed
+        } else if self.interrupted {
+            RunOutcome::Interrupted
+        } else {
+            RunOutcome::Finished
+        }
+    }
+
+    /// Gives up the tape and program so that their allocations can be reused.
+    pub(crate) fn into_buffers(self) -> (Vec<u8>, Program) {
+        match self.data {
+            Cells::Vec(tape) => (tape, self.program),
+            _ => (Vec::new(), self.program),
+        }
+    }
+
+    /// Serializes the machine state so that it can be resumed with `State::load`.
+    pub fn save<W: Write>(&self, out: W) -> Result<(), String> {
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            state: self,
+        };
+        ::serde_json::to_writer(out, &snapshot).map_err(|e| format!("Could not save state: {}", e))
+    }
+
+    pub fn load<R: Read>(input: R) -> Result<State, String> {
+        let snapshot: Snapshot = ::serde_json::from_reader(input)
+            .map_err(|e| format!("Could not load state: {}", e))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}.", snapshot.version));
+        }
+
+        let state = snapshot.state;
+        state.check_consistent()?;
+        Ok(state)
+    }
+
+    /// Fails if a deserialized state points outside its tape or program.
+    pub(crate) fn check_consistent(&self) -> Result<(), String> {
+        let len = self.program.instructions.len();
+        if self.data.is_empty() || self.pointer >= self.data.len() || self.pc > len ||
+           self.threads.iter().any(|thread| thread.pointer >= thread.data.len() || thread.pc > len) {
+            return Err("Could not load state: snapshot is inconsistent.".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Takes over the machine of `loaded`, the part a snapshot holds, while
+    /// keeping the configuration of this state.
+    pub(crate) fn replace_machine(&mut self, loaded: State) {
+        self.data = loaded.data;
+        self.pointer = loaded.pointer;
+        self.program = loaded.program;
+        self.pc = loaded.pc;
+        self.threads = loaded.threads;
+        self.stats = loaded.stats;
+        self.pending_input = loaded.pending_input;
+        self.held_cr = loaded.held_cr;
+    }
+
+    /// Saves the state to `path`, replacing any previous checkpoint only
+    /// once the new one has been written completely.
+    pub fn save_to(&self, path: &str) -> Result<(), String> {
+        let tmp = format!("{}.tmp", path);
+        let file = fs::File::create(&tmp).map_err(|e| format!("Could not write checkpoint '{}': {}", tmp, e))?;
+        let mut out = ::std::io::BufWriter::new(file);
+        self.save(&mut out)?;
+        out.flush().map_err(|e| format!("Could not write checkpoint '{}': {}", tmp, e))?;
+        drop(out);
+        fs::rename(&tmp, path).map_err(|e| format!("Could not write checkpoint '{}': {}", path, e))
+    }
+
+    /// Reads input from stdin and writes output to stdout, flushed as
+    /// `flush_policy` says, as the command line does. Nothing else in the library
+    /// touches stdin or stdout.
+    pub fn use_stdio(&mut self) {
+        // Reading a byte at a time leaves the rest of stdin to other readers,
+        // like the debugger's prompt.
+        self.input = Some(Box::new(BufReader::with_capacity(1, io::stdin())));
+        self.output = Some(Box::new(Stdout::default()));
+    }
+
+    /// Discards the input that was consumed before this state was saved, so
+    /// that a resumed run sees the same input as the original one. Callers
+    /// should not skip input from a terminal, which only ever delivers new
+    /// data.
+    pub fn skip_consumed_input(&mut self) -> Result<(), String> {
+        let consumed = self.stats.input_bytes;
+        match self.input {
+            Some(ref mut input) => io::copy(&mut input.take(consumed), &mut io::sink())
+                .map(|_| ())
+                .map_err(|e| format!("Unable to read input: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes the final metrics sample and flushes the trace, metrics, input
+    /// recording and output. `run` does this when it returns; callers driving
+    /// `step` themselves need to do it once done.
+    pub fn flush(&mut self) -> Result<(), String> {
+        if let Some(ref mut metrics) = self.metrics {
+            metrics.sample(&self.data, self.pointer, &self.stats)?;
+            metrics.flush()?;
+        }
+        if let Some(ref mut trace) = self.trace {
+            trace.flush()?;
+        }
+        if let Some(ref mut record) = self.input_record {
+            record.flush().map_err(|e| format!("Could not record input: {}", e))?;
+        }
+        if self.held_cr {
+            self.held_cr = false;
+            self.write_output(b"\r")?;
+        }
+        if self.open_line {
+            self.open_line = false;
+            self.write_encoded(b"\n")?;
+        }
+        if let Some(ref mut output) = self.output {
+            let flushed = output.flush();
+            self.check_output(flushed)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the next instruction reads a line of input, rather than a
+    /// byte left over from the last one.
+    #[cfg(feature = "async")]
+    pub(crate) fn wants_line(&self) -> bool {
+        self.pending_input.is_empty() && self.program.instructions.get(self.pc) == Some(&Instruction::Input)
+    }
+
+    /// Reads the next input value for `,`, or `None` once the input is
+    /// exhausted if `eof_unchanged` is set.
+    pub(crate) fn read_input(&mut self) -> Result<Option<u8>, String> {
+        if let Some(value) = self.pending_input.pop() {
+            return Ok(Some(value));
+        }
+
+        if self.flush_policy != FlushPolicy::AtExit {
+            if let Some(ref mut output) = self.output {
+                let flushed = output.flush();
+                self.check_output(flushed)?;
+            }
+        }
+        if let Some(ref prompt) = self.prompt {
+            // The program is better off without a prompt than stopped by one.
+            let stderr = io::stderr();
+            let mut stderr = stderr.lock();
+            let _ = stderr.write_all(prompt.as_bytes()).and_then(|_| stderr.flush());
+        }
+
+        // Bytes are taken as they arrive, so that `,` waits for no more than
+        // a line typed at a terminal, or a single key in raw mode.
+        let byte_input = self.byte_input;
+        let mut s = String::new();
+        let mut bytes = Vec::new();
+        let read = match self.input {
+            Some(ref mut input) if byte_input => input.fill_buf()
+                .map(|available| bytes.extend_from_slice(available))
+                .map(|_| {
+                    input.consume(bytes.len());
+                    bytes.len()
+                }),
+            Some(ref mut input) => input.read_line(&mut s),
+            None => return Err("The program reads input, but none was given.".to_owned()),
+        };
+        let read = read.map_err(|e| format!("Unable to read input: {}", e))?;
+        if read == 0 {
+            if self.eof_unchanged {
+                return Ok(None);
+            }
+            return Err("Input exhausted: the program reads more input than was given.".to_owned());
+        }
+        self.stats.input_bytes += read as u64;
+        if let Some(ref mut record) = self.input_record {
+            let line = if byte_input { &bytes[..] } else { s.as_bytes() };
+            record.write_all(line).map_err(|e| format!("Could not record input: {}", e))?;
+        }
+        if byte_input {
+            self.pending_input.extend(bytes.iter().rev());
+            return Ok(self.pending_input.pop());
+        }
+
+        let trim = s.trim();
+        let value = match trim.parse::<u8>() {
+            Ok(i) => i,
+            Err(_) => {
+                match trim.chars().next() {
+                    Some(c) => c as u8,
+                    None => return Err("Could not parse input.".to_owned()),
+                }
+            }
+        };
+        if value == b'\n' {
+            self.pending_input.extend(self.newline.input().iter().rev());
+            return Ok(Some(self.pending_input.pop().unwrap_or(value)));
+        }
+        Ok(Some(value))
+    }
+
+    /// Writes a byte for `.`, subject to the rate limit and line ending
+    /// translation.
+    pub(crate) fn output_byte(&mut self, value: u8) -> Result<(), String> {
+        if let Some(ref mut limiter) = self.output_rate {
+            self.stats.throttled += limiter.acquire()?;
+        }
+        self.stats.output_bytes += 1;
+
+        // Numbers are not text, so line endings are not translated.
+        if self.output_mode.is_numeric() {
+            let mut number = Vec::new();
+            self.output_mode.encode(value, !self.open_line, &mut number);
+            self.open_line = self.output_mode.ends_lines();
+            return self.write_encoded(&number);
+        }
+        let (bytes, n) = self.newline.output(value, &mut self.held_cr);
+        self.write_output(&bytes[..n])
+    }
+
+    /// Writes bytes to the output, encoded as `output_mode` says.
+    fn write_output(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.output_mode == OutputMode::Raw {
+            return self.write_encoded(bytes);
+        }
+        let mut encoded = Vec::with_capacity(bytes.len() * 2);
+        for &byte in bytes {
+            self.output_mode.encode(byte, false, &mut encoded);
+        }
+        self.write_encoded(&encoded)
+    }
+
+    /// Writes bytes to the output as they are.
+    fn write_encoded(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.output_closed {
+            return Ok(());
+        }
+        let policy = self.flush_policy;
+        let written = match self.output {
+            Some(ref mut output) => output.write_all(bytes).and_then(|_| match policy {
+                FlushPolicy::Always => output.flush(),
+                // A carriage return ends a line too, where progress is shown.
+                FlushPolicy::Line if bytes.iter().any(|&byte| byte == b'\n' || byte == b'\r') => output.flush(),
+                _ => Ok(()),
+            }),
+            None => Ok(()),
+        };
+        self.check_output(written)
+    }
+
+    /// Turns a failed write into an error, unless the output was closed,
+    /// which ends the program instead.
+    fn check_output(&mut self, result: io::Result<()>) -> Result<(), String> {
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.output_closed = true;
+                Ok(())
+            }
+            Err(e) => Err(format!("Could not write output: {}", e)),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Bytes currently held by the interpreter: the tape allocations, the
+    /// program and the state itself.
+    pub fn memory_usage(&self) -> usize {
+        let threads: usize = self.threads.iter().map(|thread| std::mem::size_of::<Thread>() + thread.data.memory_usage()).sum();
+        std::mem::size_of::<State>() + self.program.size() + self.data.memory_usage() + threads
+    }
+
+    /// Parks the running thread, unless it has `finished`, and runs the next
+    /// one. Returns `false` if there is none.
+    fn switch_thread(&mut self, finished: bool) -> bool {
+        let next = match self.threads.pop_front() {
+            Some(next) => next,
+            None => return false,
+        };
+        let data = ::std::mem::replace(&mut self.data, next.data);
+        let pointer = ::std::mem::replace(&mut self.pointer, next.pointer);
+        let pc = ::std::mem::replace(&mut self.pc, next.pc);
+        if !finished {
+            self.threads.push_back(Thread { data, pointer, pc });
+        }
+        true
+    }
+
+    fn check_memory(&mut self) -> Result<(), String> {
+        let usage = self.memory_usage();
+        if usage > self.stats.peak_memory {
+            self.stats.peak_memory = usage;
+        }
+
+        match self.memory_limit {
+            Some(limit) if usage > limit => {
+                Err(format!("Memory limit of {} bytes exceeded ({} bytes in use).", limit, usage))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the pointer, growing the tape in either direction as needed,
+    /// unless `strict_tape` forbids growing it to the left.
+    pub(crate) fn shift(&mut self, n: isize) -> Result<(), String> {
+        if self.strict_tape && n < 0 && n.unsigned_abs() > self.pointer {
+            // The instruction moving there has already been counted.
+            let offset = self.program.offsets.get(self.pc.wrapping_sub(1)).cloned().unwrap_or(0);
+            return Err(format!("Moved to cell -{}, left of the start of the tape, at offset {}.", n.unsigned_abs() - self.pointer, offset));
+        }
+        let len = self.data.len();
+        let (pointer, grown) = self.data.move_by(self.pointer, n)?;
+        self.pointer = pointer;
+        if grown > 0 {
+            trace!("grew the tape by {} cells to the left, shifting the index of every cell", grown);
+            if let Some(ref mut heat) = self.heat {
+                heat.grew_left(grown);
+            }
+        }
+        if self.data.len() != len {
+            return self.check_memory();
+        }
+        Ok(())
+    }
+
+    /// Index of the cell `n` cells away from the pointer, growing the tape
+    /// to reach it without moving the pointer.
+    pub(crate) fn cell_at(&mut self, n: isize) -> Result<usize, String> {
+        let index = self.pointer as isize + n;
+        if index < 0 || index as usize >= self.data.len() {
+            self.shift(n)?;
+            self.shift(-n)?;
+        }
+        Ok((self.pointer as isize + n) as usize)
+    }
+
+    /// Whether anything needs to see every instruction the program executes,
+    /// or count them exactly, which rules out `scan_loop`.
+    fn watched(&self) -> bool {
+        self.max_steps.is_some() || self.max_loop_iterations.is_some() || self.timeout.is_some() ||
+        self.profile.is_some() || self.coverage.is_some() || self.heat.is_some() || self.trace.is_some() ||
+        self.checkpoint.is_some() || self.metrics.is_some() || self.observer.is_some() || self.control.is_some()
+    }
+
+    /// Reports the value just written to `cell` to the tracer and observer.
+    /// A sparse tape takes up memory when written to rather than when it
+    /// grows, so that is when its memory is checked.
+    fn cell_written(&mut self, cell: usize) -> Result<(), String> {
+        if let Cells::Sparse(_) = self.data {
+            self.check_memory()?;
+        }
+        let value = self.data.get(cell);
+        if let Some(ref mut trace) = self.trace {
+            trace.cell_write(cell, value)?;
+        }
+        if let Some(ref mut observer) = self.observer {
+            observer.on_cell_write(cell, value);
+        }
+        Ok(())
+    }
+}
+
+pub fn run(state: &mut State) -> Result<RunOutcome, String> {
+    debug!("running {} instructions from pc {}", state.program.instructions.len(), state.pc);
+    let result = run_to_end(state);
+    let flushed = state.flush();
+    let result = result.and(flushed).map(|_| state.outcome());
+    debug!("stopped after {} steps with {:?}", state.stats.instructions, result);
+    result
+}
+
+/// Like `run`, but compiles the program to machine code first when the crate
+/// is built with the `jit` feature and `jit::supports` the state.
+pub fn run_fast(state: &mut State) -> Result<RunOutcome, String> {
+    #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+    {
+        if ::jit::supports(state) {
+            return ::jit::run(state);
+        }
+    }
+    run(state)
+}
+
+fn run_to_end(state: &mut State) -> Result<(), String> {
+    let scan = !state.watched();
+    loop {
+        if scan && scan_loop(state)? {
+            continue;
+        }
+        if !step_threads(state)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Runs a loop that only moves the pointer, like `[>]` or `[<<]`, in one go
+/// if the program counter is at its start, which is much faster than a step
+/// at a time. Returns `false`, without doing anything, if it is not, or if
+/// the loop would move left of the tape, which `step` deals with.
+fn scan_loop(state: &mut State) -> Result<bool, String> {
+    let pc = state.pc;
+    let n = match state.program.instructions.get(pc..pc + 3) {
+        Some(&[Instruction::JumpIfZero(end), Instruction::Move(n), Instruction::JumpIfNotZero(_)]) if end == pc + 3 && n != 0 => n,
+        _ => return Ok(false),
+    };
+    if !state.threads.is_empty() || state.interrupted || state.output_closed {
+        return Ok(false);
+    }
+    let cell = match state.data.scan_zero(state.pointer, n) {
+        Some(cell) => cell,
+        None => return Ok(false),
+    };
+
+    // The loop starts, and every time around moves and jumps back.
+    let distance = cell as isize - state.pointer as isize;
+    state.stats.instructions += 1 + 2 * (distance / n) as u64;
+    state.pc = pc + 3;
+    if let Err(e) = state.shift(distance) {
+        state.pc = pc + 1;
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// Like `step`, but for every thread of a Brainfork program: each executes
+/// one instruction in turn, and the program has finished once all of them
+/// have. This is what `run` uses; with a single thread it is the same as
+/// `step`.
+pub fn step_threads(state: &mut State) -> Result<bool, String> {
+    if step(state)? {
+        if !state.threads.is_empty() {
+            state.switch_thread(false);
+        }
+        return Ok(true);
+    }
+    Ok(!state.output_closed && !state.interrupted && state.switch_thread(true))
+}
+
+/// Executes the instruction at the program counter. Returns `false`, without
+/// doing anything, once the program has finished, its output was closed or
+/// it was interrupted.
+pub fn step(state: &mut State) -> Result<bool, String> {
+    if state.output_closed {
+        return Ok(false);
+    }
+    if state.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(Ordering::Relaxed)) ||
+       state.control.as_ref().is_some_and(|control| !control.check(state)) {
+        state.interrupted = true;
+    }
+    if state.interrupted {
+        return Ok(false);
+    }
+    let instruction = match state.program.instructions.get(state.pc) {
+        Some(&instruction) => instruction,
+        None => return Ok(false),
+    };
+
+    if let Some(ref checkpoint) = state.checkpoint {
+        if state.stats.instructions > 0 && state.stats.instructions.is_multiple_of(checkpoint.every) {
+            state.save_to(&checkpoint.path)?;
+        }
+    }
+
+    if let Some(ref mut metrics) = state.metrics {
+        if state.stats.instructions > 0 && state.stats.instructions.is_multiple_of(metrics.every) {
+            metrics.sample(&state.data, state.pointer, &state.stats)?;
+        }
+    }
+
+    if let Some(ref mut trace) = state.trace {
+        trace.instruction(state.pc, instruction, state.pointer, state.data.get(state.pointer))?;
+    }
+    if let Some(ref mut observer) = state.observer {
+        observer.on_instruction(state.pc, instruction, state.pointer);
+    }
+
+    if let Some(limit) = state.max_steps {
+        if state.stats.instructions >= limit {
+            return Err(format!("Step limit of {} instructions exceeded.", limit));
+        }
+    }
+    if let Some(timeout) = state.timeout {
+        let deadline = *state.deadline.get_or_insert_with(|| Instant::now() + timeout);
+        if state.stats.instructions.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && Instant::now() >= deadline {
+            return Err(format!("Time limit of {:?} exceeded after {} instructions.", timeout, state.stats.instructions));
+        }
+    }
+
+    let pc = state.pc;
+    if let Some(count) = state.coverage.as_mut().and_then(|coverage| coverage.get_mut(pc)) {
+        *count += 1;
+    }
+    state.stats.instructions += 1;
+    state.pc += 1;
+
+    if let Err(e) = execute(state, instruction) {
+        // The program stopped at the instruction that failed.
+        state.pc = pc;
+        return Err(e);
+    }
+
+    if let Some(ref mut heat) = state.heat {
+        heat.record(instruction, state.pointer, &state.data);
+    }
+    Ok(true)
+}
+
+/// Carries out `instruction`, with the program counter already past it.
+fn execute(state: &mut State, instruction: Instruction) -> Result<(), String> {
+    match instruction {
+        Instruction::Add(n) => {
+            state.data.set(state.pointer, state.data.get(state.pointer).wrapping_add(n));
+            state.cell_written(state.pointer)?;
+        }
+        Instruction::Set(n) => {
+            state.data.set(state.pointer, n);
+            state.cell_written(state.pointer)?;
+        }
+        Instruction::Clear => {
+            state.data.set(state.pointer, 0);
+            state.cell_written(state.pointer)?;
+        }
+        Instruction::AddAt(at, n) => {
+            let cell = state.cell_at(at)?;
+            state.data.set(cell, state.data.get(cell).wrapping_add(n));
+            state.cell_written(cell)?;
+        }
+        Instruction::SetAt(at, n) => {
+            let cell = state.cell_at(at)?;
+            state.data.set(cell, n);
+            state.cell_written(cell)?;
+        }
+        Instruction::Move(n) => state.shift(n)?,
+        Instruction::Input => {
+            if let Some(ref mut trace) = state.trace {
+                trace.input_requested(state.pointer)?;
+            }
+
+            if let Some(value) = state.read_input()? {
+                state.data.set(state.pointer, value);
+                if let Some(ref mut observer) = state.observer {
+                    observer.on_input(state.pointer, value);
+                }
+                state.cell_written(state.pointer)?;
+            }
+        }
+        Instruction::Output => {
+            let value = state.data.get(state.pointer);
+            if let Some(ref mut trace) = state.trace {
+                trace.output(value)?;
+            }
+            if let Some(ref mut observer) = state.observer {
+                observer.on_output(value);
+            }
+            state.output_byte(value)?;
+        }
+        Instruction::JumpIfZero(target) => {
+            let zero = state.data.get(state.pointer) == 0;
+            if let Some(ref mut profile) = state.profile {
+                let entry = profile.entry(state.program.offsets[state.pc - 1]).or_default();
+                entry.entries += 1;
+                if !zero {
+                    entry.iterations += 1;
+                    state.loop_starts.push(Instant::now());
+                }
+            }
+
+            if !zero && state.max_loop_iterations.is_some() {
+                let start = state.pc - 1;
+                if state.loop_iterations.len() <= start {
+                    state.loop_iterations.resize(state.program.instructions.len(), 0);
+                }
+                state.loop_iterations[start] = 1;
+            }
+
+            if zero {
+                state.pc = target;
+            } else if let Some(ref mut observer) = state.observer {
+                observer.on_loop_enter(state.pc - 1);
+            }
+        }
+        Instruction::JumpIfNotZero(target) => {
+            let zero = state.data.get(state.pointer) == 0;
+            if let Some(ref mut profile) = state.profile {
+                // The target is the instruction right after the loop's JumpIfZero.
+                let entry = profile.entry(state.program.offsets[target - 1]).or_default();
+                if zero {
+                    entry.time += state.loop_starts.pop().map_or(Duration::default(), |t| t.elapsed());
+                } else {
+                    entry.iterations += 1;
+                }
+            }
+
+            if let Some(limit) = state.max_loop_iterations.filter(|_| !zero) {
+                let start = target - 1;
+                if state.loop_iterations.len() <= start {
+                    state.loop_iterations.resize(state.program.instructions.len(), 0);
+                }
+                state.loop_iterations[start] += 1;
+                if state.loop_iterations[start] > limit {
+                    // Counted afresh, so that a debugger can carry on from here.
+                    state.loop_iterations[start] = 0;
+                    return Err(format!("Loop at offset {} ran more than {} iterations.", state.program.offsets[start], limit));
+                }
+            }
+
+            if !zero {
+                state.pc = target;
+            } else if let Some(ref mut observer) = state.observer {
+                observer.on_loop_exit(target - 1);
+            }
+        }
+        Instruction::Fork => {
+            let mut data = state.data.clone();
+            data.set(state.pointer, 0);
+            state.threads.push_back(Thread { data, pointer: state.pointer, pc: state.pc });
+            trace!("forked thread {} at pc {}", state.threads.len(), state.pc - 1);
+            state.check_memory()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,414 @@
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+
+use lexer::Token;
+use parser::{Ast, SyntaxItem};
+use partial;
+
+/// A single instruction of the flat program executed by the interpreter.
+///
+/// Jump targets are instruction indices: `JumpIfZero` points just past its
+/// matching `JumpIfNotZero` and vice versa.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Instruction {
+    /// Adds `n as i8` to the current cell, which for 8-bit cells is the same
+    /// as adding `n` modulo 256. Reading it as signed keeps it exact for
+    /// wider cells in generated code.
+    Add(u8),
+    /// Sets the current cell to zero, replacing a `[-]` or `[+]` loop.
+    Clear,
+    /// Sets the current cell to a value computed ahead of time, see
+    /// `partial::evaluate_prefix`.
+    Set(u8),
+    /// Like `Add`, but on the cell `isize` cells away from the current one,
+    /// see `OptLevel` 4.
+    AddAt(isize, u8),
+    /// Like `Set`, but on the cell `isize` cells away from the current one.
+    SetAt(isize, u8),
+    Move(isize),
+    Input,
+    Output,
+    JumpIfZero(usize),
+    JumpIfNotZero(usize),
+    /// Starts a new thread with a copy of the tape whose current cell is
+    /// zero, see `interp::step_threads`.
+    Fork,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Add(n) => write!(f, "add {}", n),
+            Instruction::Clear => write!(f, "clear"),
+            Instruction::Set(n) => write!(f, "set {}", n),
+            Instruction::AddAt(at, n) => write!(f, "add [{:+}] {}", at, n),
+            Instruction::SetAt(at, n) => write!(f, "set [{:+}] {}", at, n),
+            Instruction::Move(n) => write!(f, "move {}", n),
+            Instruction::Input => write!(f, "in"),
+            Instruction::Output => write!(f, "out"),
+            Instruction::JumpIfZero(target) => write!(f, "jz {}", target),
+            Instruction::JumpIfNotZero(target) => write!(f, "jnz {}", target),
+            Instruction::Fork => write!(f, "fork"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// Source offset each instruction was generated from.
+    pub offsets: Vec<usize>,
+    /// One past the source offset of the last command each instruction was
+    /// generated from, see `span_of`. Programs saved without it have spans
+    /// of a single byte.
+    #[serde(default)]
+    pub ends: Vec<usize>,
+}
+
+impl Program {
+    /// The source an instruction stands for: from the first command it was
+    /// generated from to the start of the last, plus one, which in Brainfuck
+    /// is exactly those commands. Both jumps of a loop stand for all of it,
+    /// and so does an instruction replacing a loop, like `Clear`.
+    pub fn span_of(&self, pc: usize) -> Option<Range<usize>> {
+        self.offsets.get(pc).map(|&start| start..self.end(pc))
+    }
+
+    /// The end of the span of the instruction at `pc`, see `span_of`.
+    pub(crate) fn end(&self, pc: usize) -> usize {
+        self.ends.get(pc).cloned().unwrap_or(self.offsets[pc] + 1)
+    }
+
+    /// Writes the program as Brainfuck source that behaves the same, the
+    /// counterpart of `parser::to_source` for optimized programs.
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for instruction in &self.instructions {
+            let (command, times) = match *instruction {
+                Instruction::Add(n) => add(n),
+                Instruction::Clear => {
+                    source.push_str("[-]");
+                    continue;
+                }
+                Instruction::Set(n) => {
+                    source.push_str("[-]");
+                    add(n)
+                }
+                Instruction::AddAt(at, n) | Instruction::SetAt(at, n) => {
+                    let (there, back) = if at < 0 { ('<', '>') } else { ('>', '<') };
+                    source.extend(::std::iter::repeat_n(there, at.unsigned_abs()));
+                    if let Instruction::SetAt(..) = *instruction {
+                        source.push_str("[-]");
+                    }
+                    let (command, times) = add(n);
+                    source.extend(::std::iter::repeat_n(command, times));
+                    (back, at.unsigned_abs())
+                }
+                Instruction::Move(n) if n < 0 => ('<', n.unsigned_abs()),
+                Instruction::Move(n) => ('>', n as usize),
+                Instruction::Input => (',', 1),
+                Instruction::Output => ('.', 1),
+                Instruction::JumpIfZero(_) => ('[', 1),
+                Instruction::JumpIfNotZero(_) => (']', 1),
+                Instruction::Fork => ('Y', 1),
+            };
+            source.extend(::std::iter::repeat_n(command, times));
+        }
+        source
+    }
+
+    /// Bytes held by the instruction stream and its offset table.
+    pub fn size(&self) -> usize {
+        self.instructions.capacity() * ::std::mem::size_of::<Instruction>() +
+        (self.offsets.capacity() + self.ends.capacity()) * ::std::mem::size_of::<usize>()
+    }
+}
+
+/// The run of `+` or `-` that adds `n as i8` to a cell.
+fn add(n: u8) -> (char, usize) {
+    match n as i8 {
+        n if n < 0 => ('-', n.unsigned_abs() as usize),
+        n => ('+', n as usize),
+    }
+}
+
+/// Lowers a syntax tree into a flat program, the second stage after
+/// `parser::parse`.
+pub fn lower(ast: &Ast) -> Program {
+    let mut program = Program::default();
+    lower_into(&mut program, ast);
+    program
+}
+
+/// Like `lower`, but replaces the contents of `program`, keeping its buffers.
+pub fn lower_into(program: &mut Program, ast: &Ast) {
+    program.instructions.clear();
+    program.offsets.clear();
+    program.ends.clear();
+    lower_items(program, ast);
+}
+
+fn lower_items(program: &mut Program, items: &[SyntaxItem]) {
+    // The loops being lowered, innermost last, each with the items after it
+    // and the index of its `JumpIfZero`. Deep nesting needs no recursion.
+    let mut open = Vec::new();
+    let mut items = items.iter();
+    loop {
+        let item = match items.next() {
+            Some(item) => item,
+            None => match open.pop() {
+                Some((rest, start)) => {
+                    let close = program.instructions.len();
+                    let (offset, end) = (program.offsets[start], program.ends[start]);
+                    program.instructions.push(Instruction::JumpIfNotZero(start + 1));
+                    program.offsets.push(offset);
+                    program.ends.push(end);
+                    program.instructions[start] = Instruction::JumpIfZero(close + 1);
+                    items = rest;
+                    continue;
+                }
+                None => break,
+            },
+        };
+        match *item {
+            SyntaxItem::Single(offset, token) => {
+                let instruction = match token {
+                    Token::Increment => Instruction::Add(1),
+                    Token::Decrement => Instruction::Add(255),
+                    Token::ShiftLeft => Instruction::Move(-1),
+                    Token::ShiftRight => Instruction::Move(1),
+                    Token::Input => Instruction::Input,
+                    Token::Output => Instruction::Output,
+                    Token::Fork => Instruction::Fork,
+                    Token::BeginLoop | Token::EndLoop => continue,
+                };
+                program.instructions.push(instruction);
+                program.offsets.push(offset);
+                program.ends.push(offset + 1);
+            }
+            SyntaxItem::Loop(offset, ref body, close) => {
+                open.push((mem::replace(&mut items, body.iter()), program.instructions.len()));
+                program.instructions.push(Instruction::JumpIfZero(0));
+                program.offsets.push(offset);
+                program.ends.push(close + 1);
+            }
+        }
+    }
+}
+
+/// How much `optimize` rewrites a program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OptLevel(u32);
+
+impl OptLevel {
+    /// Leaves the program unchanged.
+    pub const NONE: OptLevel = OptLevel(0);
+    /// The highest level there is.
+    pub const MAX: OptLevel = OptLevel(4);
+
+    /// The level with the given number, if there is one.
+    pub fn new(level: u32) -> Option<OptLevel> {
+        if level <= OptLevel::MAX.0 {
+            Some(OptLevel(level))
+        } else {
+            None
+        }
+    }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Rewrites a program into an equivalent one that executes fewer
+/// instructions. Level 0 leaves it unchanged; level 1 folds runs of `+`/`-`
+/// and `<`/`>` into single instructions and turns clear loops into `Clear`;
+/// level 2 also removes loops that are never entered, because the current
+/// cell is still zero at the start of the program or just after another
+/// loop, as in the comment loops some programs begin with. Level 3 also
+/// runs the start of the program that needs no input ahead of time, see
+/// `partial::evaluate_prefix`. Level 4 also addresses cells relative to the
+/// pointer instead of moving it, see `address_by_offset`.
+///
+/// Each instruction keeps the source offset of the first one it replaces.
+pub fn optimize(program: &Program, level: OptLevel) -> Program {
+    let mut optimized = Program::default();
+    optimize_into(&mut optimized, program, level);
+    optimized
+}
+
+/// Like `optimize`, but writes the result into `optimized`, replacing its
+/// contents and keeping its buffers. Returns how many instructions of
+/// `program` were removed as dead code.
+pub fn optimize_into(optimized: &mut Program, program: &Program, level: OptLevel) -> usize {
+    optimized.instructions.clear();
+    optimized.offsets.clear();
+    optimized.ends.clear();
+    if level == OptLevel::NONE {
+        optimized.instructions.extend_from_slice(&program.instructions);
+        optimized.offsets.extend_from_slice(&program.offsets);
+        optimized.ends.extend((0..program.instructions.len()).map(|i| program.end(i)));
+        return 0;
+    }
+
+    let mut opens = Vec::new();
+    let mut dead = 0;
+    let mut skip_to = 0;
+
+    for (i, (&instruction, &offset)) in program.instructions.iter().zip(&program.offsets).enumerate() {
+        if i < skip_to {
+            continue;
+        }
+        let span_end = program.end(i);
+        let last = optimized.instructions.last().cloned();
+        match (last, instruction) {
+            // The cell is zero, so the loop is skipped up to its end.
+            (None, Instruction::JumpIfZero(end)) |
+            (Some(Instruction::JumpIfNotZero(_)), Instruction::JumpIfZero(end)) |
+            (Some(Instruction::Clear), Instruction::JumpIfZero(end)) if level >= OptLevel(2) => {
+                trace!("removed the loop at offset {}, which is never entered", offset);
+                dead += end - i;
+                skip_to = end;
+            }
+            // Runs are only folded as far as the sum fits in an `i8`, see `Add`.
+            (Some(Instruction::Add(a)), Instruction::Add(b)) if (a as i8).checked_add(b as i8).is_some() => {
+                optimized.instructions.pop();
+                optimized.ends.pop();
+                let folded_offset = optimized.offsets.pop().unwrap_or(offset);
+                if a.wrapping_add(b) != 0 {
+                    optimized.instructions.push(Instruction::Add(a.wrapping_add(b)));
+                    optimized.offsets.push(folded_offset);
+                    optimized.ends.push(span_end);
+                }
+            }
+            (Some(Instruction::Move(a)), Instruction::Move(b)) => {
+                optimized.instructions.pop();
+                optimized.ends.pop();
+                let folded_offset = optimized.offsets.pop().unwrap_or(offset);
+                if a + b != 0 {
+                    optimized.instructions.push(Instruction::Move(a + b));
+                    optimized.offsets.push(folded_offset);
+                    optimized.ends.push(span_end);
+                }
+            }
+            (_, Instruction::JumpIfZero(_)) => {
+                opens.push(optimized.instructions.len());
+                optimized.instructions.push(Instruction::JumpIfZero(0));
+                optimized.offsets.push(offset);
+                optimized.ends.push(span_end);
+            }
+            (_, Instruction::JumpIfNotZero(_)) => {
+                let open = opens.pop().expect("Unbalanced jumps in program.");
+                let body = &optimized.instructions[open + 1..];
+                // An odd step reaches zero from any value, so the loop always ends.
+                if let [Instruction::Add(n)] = *body {
+                    if n % 2 == 1 {
+                        trace!("replaced the loop at offset {} with clear", optimized.offsets[open]);
+                        optimized.instructions.truncate(open);
+                        optimized.offsets.truncate(open);
+                        optimized.ends.truncate(open);
+                        optimized.instructions.push(Instruction::Clear);
+                        optimized.offsets.push(offset);
+                        optimized.ends.push(span_end);
+                        continue;
+                    }
+                }
+                let close = optimized.instructions.len();
+                optimized.instructions.push(Instruction::JumpIfNotZero(open + 1));
+                optimized.offsets.push(offset);
+                optimized.ends.push(span_end);
+                optimized.instructions[open] = Instruction::JumpIfZero(close + 1);
+            }
+            (_, instruction) => {
+                optimized.instructions.push(instruction);
+                optimized.offsets.push(offset);
+                optimized.ends.push(span_end);
+            }
+        }
+    }
+    debug!("folded {} instructions into {}, {} of them never run",
+           program.instructions.len(),
+           optimized.instructions.len(),
+           dead);
+    if level >= OptLevel(3) {
+        partial::evaluate_prefix(optimized);
+    }
+    if level >= OptLevel(4) {
+        address_by_offset(optimized);
+    }
+    dead
+}
+
+/// Replaces the moves between changes to cells with `AddAt` and `SetAt`, so
+/// that a run like `>+>>-<<<` becomes `add [+1] 1`, `add [+3] 255` and a
+/// single `move -1` where it ends, before the next loop or I/O.
+fn address_by_offset(program: &mut Program) {
+    let mut instructions = Vec::with_capacity(program.instructions.len());
+    let mut offsets = Vec::with_capacity(program.offsets.len());
+    let mut ends = Vec::with_capacity(program.offsets.len());
+    let mut opens = Vec::new();
+    // How far the pointer should have moved since the last `Move` written,
+    // and the span of the moves folded into it.
+    let mut at = 0;
+    let mut moved = None;
+
+    for (i, (&instruction, &offset)) in program.instructions.iter().zip(&program.offsets).enumerate() {
+        let end = program.end(i);
+        let instruction = match instruction {
+            Instruction::Move(n) => {
+                at += n;
+                moved = Some((moved.map_or(offset, |(from, _)| from), end));
+                continue;
+            }
+            Instruction::Add(n) if at != 0 => Instruction::AddAt(at, n),
+            Instruction::Clear if at != 0 => Instruction::SetAt(at, 0),
+            Instruction::Set(n) if at != 0 => Instruction::SetAt(at, n),
+            Instruction::Add(_) | Instruction::Clear | Instruction::Set(_) |
+            Instruction::AddAt(..) | Instruction::SetAt(..) => instruction,
+            _ => {
+                if let Some((moved_from, moved_to)) = moved.take() {
+                    if at != 0 {
+                        instructions.push(Instruction::Move(at));
+                        offsets.push(moved_from);
+                        ends.push(moved_to);
+                    }
+                    at = 0;
+                }
+                match instruction {
+                    Instruction::JumpIfZero(_) => {
+                        opens.push(instructions.len());
+                        Instruction::JumpIfZero(0)
+                    }
+                    Instruction::JumpIfNotZero(_) => {
+                        let open = opens.pop().expect("Unbalanced jumps in program.");
+                        instructions[open] = Instruction::JumpIfZero(instructions.len() + 1);
+                        Instruction::JumpIfNotZero(open + 1)
+                    }
+                    instruction => instruction,
+                }
+            }
+        };
+        instructions.push(instruction);
+        offsets.push(offset);
+        ends.push(end);
+    }
+    if let Some((moved_from, moved_to)) = moved {
+        if at != 0 {
+            instructions.push(Instruction::Move(at));
+            offsets.push(moved_from);
+            ends.push(moved_to);
+        }
+    }
+
+    debug!("addressed cells by offset, {} instructions became {}", program.instructions.len(), instructions.len());
+    program.instructions = instructions;
+    program.offsets = offsets;
+    program.ends = ends;
+}
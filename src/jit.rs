@@ -0,0 +1,348 @@
+//! Compiles programs to x86-64 machine code at runtime.
+//!
+//! The generated code keeps the tape and pointer in registers and calls back
+//! into the interpreter for input, output and growing the tape, so those
+//! behave exactly as under `interp::run`.
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+use dynasm::dynasm;
+use dynasmrt::{x64, DynamicLabel, DynasmApi, DynasmLabelApi, ExecutableBuffer};
+
+use interp::{RunOutcome, State};
+use ir::{Instruction, Program};
+use tape::{Backend, Tape};
+
+/// What compiled code reads and updates, shared with the helpers it calls.
+/// The generated code depends on the order of the first four fields.
+#[repr(C)]
+struct Context {
+    tape: *mut u8,
+    len: usize,
+    pointer: usize,
+    steps: u64,
+    state: *mut State,
+    error: Option<String>,
+}
+
+const TAPE: i32 = 0;
+const LEN: i32 = 8;
+const POINTER: i32 = 16;
+const STEPS: i32 = 24;
+
+impl Context {
+    /// Picks up the tape after a helper may have moved or resized it.
+    fn sync(&mut self, state: &mut State, result: Result<(), String>) -> u8 {
+        let (tape, len) = tape_of(state);
+        self.tape = tape;
+        self.len = len;
+        self.pointer = state.pointer;
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                self.error = Some(e);
+                1
+            }
+        }
+    }
+}
+
+/// Where the cells of `state` are and how many there are, which `supports`
+/// makes sure are kept in a single slice.
+fn tape_of(state: &mut State) -> (*mut u8, usize) {
+    let cells = state.data.as_mut_slice().expect("the tape is kept in a single slice");
+    (cells.as_mut_ptr(), cells.len())
+}
+
+/// Runs a helper on the interpreter state. Panics must not unwind into the
+/// generated code, so they are turned into errors.
+fn helper<F>(context: &mut Context, f: F) -> u8
+    where F: FnOnce(&mut State) -> Result<(), String>
+{
+    let state = unsafe { &mut *context.state };
+    state.pointer = context.pointer;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut *state)))
+        .unwrap_or_else(|_| Err("Compiled program failed.".to_owned()));
+    context.sync(state, result)
+}
+
+extern "sysv64" fn shift(context: &mut Context, n: isize) -> u8 {
+    helper(context, |state| state.shift(n))
+}
+
+extern "sysv64" fn input(context: &mut Context) -> u8 {
+    helper(context, |state| {
+        if let Some(value) = state.read_input()? {
+            state.data.set(state.pointer, value);
+        }
+        Ok(())
+    })
+}
+
+/// Also stops the program once its output was closed.
+extern "sysv64" fn output(context: &mut Context, value: u8) -> u8 {
+    let status = helper(context, |state| state.output_byte(value));
+    let closed = unsafe { (*context.state).outcome() == RunOutcome::OutputClosed };
+    status | closed as u8
+}
+
+/// A program compiled to machine code.
+pub struct Compiled {
+    code: ExecutableBuffer,
+    entry: dynasmrt::AssemblyOffset,
+}
+
+/// Whether `state` can run compiled: tracing, profiling, coverage, heat
+/// maps, step limits, checkpoints, metrics, the interrupt flag and handles
+/// observe single instructions and need the interpreter, as do the threads
+/// of Brainfork, and compiled code always starts at the beginning of the
+/// program.
+pub fn supports(state: &State) -> bool {
+    state.trace.is_none() &&
+    state.profile.is_none() &&
+    state.coverage.is_none() &&
+    state.heat.is_none() &&
+    state.max_steps.is_none() &&
+    state.max_loop_iterations.is_none() &&
+    state.timeout.is_none() &&
+    !state.strict_tape &&
+    state.checkpoint.is_none() &&
+    state.tape().backend() != Backend::Sparse &&
+    state.metrics.is_none() &&
+    state.observer.is_none() &&
+    state.interrupt.is_none() &&
+    state.control.is_none() &&
+    !state.program().instructions.contains(&Instruction::Fork) &&
+    state.pc() == 0
+}
+
+/// Runs the program of `state` as machine code, see `supports`.
+pub fn run(state: &mut State) -> Result<RunOutcome, String> {
+    let compiled = compile(state.program())?;
+    run_compiled(&compiled, state)
+}
+
+/// Runs code compiled from the program of `state`, see `supports`.
+pub fn run_compiled(compiled: &Compiled, state: &mut State) -> Result<RunOutcome, String> {
+    let result = execute(compiled, state);
+    let flushed = state.flush();
+    result.and(flushed).map(|_| state.outcome())
+}
+
+fn execute(compiled: &Compiled, state: &mut State) -> Result<(), String> {
+    let (tape, len) = tape_of(state);
+    let mut context = Context {
+        tape,
+        len,
+        pointer: state.pointer,
+        steps: 0,
+        state: state as *mut State,
+        error: None,
+    };
+
+    let status = unsafe {
+        let f: extern "sysv64" fn(*mut Context) -> u8 = mem::transmute(compiled.code.ptr(compiled.entry));
+        f(&mut context)
+    };
+
+    state.pointer = context.pointer;
+    state.stats.instructions += context.steps;
+    match context.error {
+        Some(e) => Err(e),
+        None if status == 0 => {
+            state.pc = state.program().instructions.len();
+            Ok(())
+        }
+        None if state.outcome() == RunOutcome::OutputClosed => Ok(()),
+        None => Err("Compiled program failed.".to_owned()),
+    }
+}
+
+/// Generates machine code for `program`.
+///
+/// Registers: rbx holds the context, r12 the tape, r13 the pointer, r14 the
+/// tape length and r15 the number of instructions executed.
+pub fn compile(program: &Program) -> Result<Compiled, String> {
+    let mut ops = x64::Assembler::new().map_err(|e| format!("Could not allocate code: {}", e))?;
+    let entry = ops.offset();
+    let fail = ops.new_dynamic_label();
+
+    dynasm!(ops
+        ; .arch x64
+        ; push rbx
+        ; push r12
+        ; push r13
+        ; push r14
+        ; push r15
+        ; mov rbx, rdi
+        ; mov r12, [rbx + TAPE]
+        ; mov r14, [rbx + LEN]
+        ; mov r13, [rbx + POINTER]
+        ; xor r15, r15
+    );
+
+    // Instructions executed since r15 was last updated. They are added up
+    // before every branch and call, so the count is exact when code stops.
+    let mut pending = 0;
+    let mut loops: Vec<(DynamicLabel, DynamicLabel)> = Vec::new();
+
+    for instruction in &program.instructions {
+        pending += 1;
+        match *instruction {
+            Instruction::Add(n) => {
+                dynasm!(ops ; .arch x64 ; add BYTE [r12 + r13], n as i8);
+            }
+            Instruction::Clear => {
+                dynasm!(ops ; .arch x64 ; mov BYTE [r12 + r13], 0);
+            }
+            Instruction::Set(n) => {
+                dynasm!(ops ; .arch x64 ; mov BYTE [r12 + r13], n as i8);
+            }
+            Instruction::AddAt(at, n) | Instruction::SetAt(at, n) => {
+                flush_steps(&mut ops, &mut pending);
+                let retry = ops.new_dynamic_label();
+                let slow = ops.new_dynamic_label();
+                let done = ops.new_dynamic_label();
+                dynasm!(ops
+                    ; .arch x64
+                    ; =>retry
+                    ; mov rax, QWORD at as i64
+                    ; add rax, r13
+                    ; cmp rax, r14
+                    ; jae =>slow
+                );
+                if let Instruction::AddAt(..) = *instruction {
+                    dynasm!(ops ; .arch x64 ; add BYTE [r12 + rax], n as i8);
+                } else {
+                    dynasm!(ops ; .arch x64 ; mov BYTE [r12 + rax], n as i8);
+                }
+                // Off the tape: grow it the way moving there and back would.
+                dynasm!(ops ; .arch x64 ; jmp =>done ; =>slow);
+                shift_pointer(&mut ops, at, fail);
+                shift_pointer(&mut ops, -at, fail);
+                dynasm!(ops ; .arch x64 ; jmp =>retry ; =>done);
+            }
+            Instruction::Move(n) => {
+                flush_steps(&mut ops, &mut pending);
+                shift_pointer(&mut ops, n, fail);
+            }
+            Instruction::Input => {
+                flush_steps(&mut ops, &mut pending);
+                dynasm!(ops
+                    ; .arch x64
+                    ; mov [rbx + POINTER], r13
+                    ; mov rdi, rbx
+                    ; mov rax, QWORD input as *const () as i64
+                    ; call rax
+                    ; test al, al
+                    ; jnz =>fail
+                );
+            }
+            Instruction::Output => {
+                flush_steps(&mut ops, &mut pending);
+                dynasm!(ops
+                    ; .arch x64
+                    ; mov rdi, rbx
+                    ; movzx esi, BYTE [r12 + r13]
+                    ; mov rax, QWORD output as *const () as i64
+                    ; call rax
+                    ; test al, al
+                    ; jnz =>fail
+                );
+            }
+            Instruction::JumpIfZero(_) => {
+                flush_steps(&mut ops, &mut pending);
+                let body = ops.new_dynamic_label();
+                let end = ops.new_dynamic_label();
+                dynasm!(ops
+                    ; .arch x64
+                    ; cmp BYTE [r12 + r13], 0
+                    ; je =>end
+                    ; =>body
+                );
+                loops.push((body, end));
+            }
+            Instruction::JumpIfNotZero(_) => {
+                flush_steps(&mut ops, &mut pending);
+                let (body, end) = loops.pop().ok_or_else(|| "Unbalanced jumps in program.".to_owned())?;
+                dynasm!(ops
+                    ; .arch x64
+                    ; cmp BYTE [r12 + r13], 0
+                    ; jne =>body
+                    ; =>end
+                );
+            }
+            Instruction::Fork => return Err("Brainfork cannot be compiled.".to_owned()),
+        }
+    }
+    flush_steps(&mut ops, &mut pending);
+
+    dynasm!(ops
+        ; .arch x64
+        ; xor eax, eax
+        ; jmp >exit
+        ; =>fail
+        ; mov eax, 1
+        ; exit:
+        ; mov [rbx + POINTER], r13
+        ; mov [rbx + STEPS], r15
+        ; pop r15
+        ; pop r14
+        ; pop r13
+        ; pop r12
+        ; pop rbx
+        ; ret
+    );
+
+    let code = ops.finalize().map_err(|_| "Could not finalize compiled code.".to_owned())?;
+    Ok(Compiled { code, entry })
+}
+
+/// Moves the pointer by `n`, growing the tape through `shift` when that
+/// leaves it.
+fn shift_pointer(ops: &mut x64::Assembler, n: isize, fail: DynamicLabel) {
+    let magnitude = n.unsigned_abs() as i64;
+    if n > 0 {
+        dynasm!(ops
+            ; .arch x64
+            ; mov rax, QWORD magnitude
+            ; add rax, r13
+            ; cmp rax, r14
+            ; jae >slow
+            ; mov r13, rax
+            ; jmp >done
+        );
+    } else {
+        dynasm!(ops
+            ; .arch x64
+            ; mov rax, QWORD magnitude
+            ; cmp r13, rax
+            ; jb >slow
+            ; sub r13, rax
+            ; jmp >done
+        );
+    }
+    dynasm!(ops
+        ; .arch x64
+        ; slow:
+        ; mov [rbx + POINTER], r13
+        ; mov rdi, rbx
+        ; mov rsi, QWORD n as i64
+        ; mov rax, QWORD shift as *const () as i64
+        ; call rax
+        ; test al, al
+        ; jnz =>fail
+        ; mov r12, [rbx + TAPE]
+        ; mov r14, [rbx + LEN]
+        ; mov r13, [rbx + POINTER]
+        ; done:
+    );
+}
+
+fn flush_steps(ops: &mut x64::Assembler, pending: &mut i32) {
+    if *pending > 0 {
+        dynasm!(ops ; .arch x64 ; add r15, *pending);
+        *pending = 0;
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Increment,
+    Decrement,
+    ShiftLeft,
+    ShiftRight,
+    Input,
+    Output,
+    BeginLoop,
+    EndLoop,
+    /// Brainfork's `Y`, which starts a new thread; only read by `lex_brainfork`.
+    Fork,
+}
+
+impl fmt::Display for Token {
+    /// Writes the command as it is spelled in Brainfuck, or Brainfork for
+    /// `Fork`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = match *self {
+            Token::Increment => '+',
+            Token::Decrement => '-',
+            Token::ShiftLeft => '<',
+            Token::ShiftRight => '>',
+            Token::Input => ',',
+            Token::Output => '.',
+            Token::BeginLoop => '[',
+            Token::EndLoop => ']',
+            Token::Fork => 'Y',
+        };
+        write!(f, "{}", c)
+    }
+}
+
+/// Splits the input into tokens, each paired with its byte offset in the source.
+pub fn lex(input: &str) -> Vec<(usize, Token)> {
+    lex_with(input, false)
+}
+
+/// Like `lex`, but also reads the `Y` of Brainfork as `Token::Fork`.
+pub fn lex_brainfork(input: &str) -> Vec<(usize, Token)> {
+    lex_with(input, true)
+}
+
+fn lex_with(input: &str, fork: bool) -> Vec<(usize, Token)> {
+    let tokens: Vec<(usize, Token)> = input.char_indices()
+        .filter_map(|(i, c)| token(c).filter(|&t| fork || t != Token::Fork).map(|t| (i, t)))
+        .collect();
+    trace!("lexed {} commands from {} bytes", tokens.len(), input.len());
+    tokens
+}
+
+/// The command spelled `c` in Brainfuck, or Brainfork for `Y`.
+pub fn token(c: char) -> Option<Token> {
+    match c {
+        '+' => Some(Token::Increment),
+        '-' => Some(Token::Decrement),
+        '<' => Some(Token::ShiftLeft),
+        '>' => Some(Token::ShiftRight),
+        ',' => Some(Token::Input),
+        '.' => Some(Token::Output),
+        '[' => Some(Token::BeginLoop),
+        ']' => Some(Token::EndLoop),
+        'Y' => Some(Token::Fork),
+        _ => None,
+    }
+}
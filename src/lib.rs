@@ -0,0 +1,99 @@
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate crossterm;
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+extern crate libc;
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+extern crate signal_hook;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+extern crate dynasm;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+extern crate dynasmrt;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+extern crate wasm_bindgen;
+#[cfg(feature = "async")]
+extern crate tokio;
+// The code PyO3 generates names `::core`, which a 2015 crate has to bring in.
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "python")]
+extern crate pyo3;
+
+pub mod analysis;
+pub mod animation;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod audit;
+pub mod bench;
+pub mod bytecode;
+pub mod codegen;
+pub mod config;
+pub mod control;
+pub mod coverage;
+pub mod debugger;
+pub mod defaults;
+pub mod derivation;
+pub mod diagnostic;
+pub mod dialect;
+pub mod differential;
+pub mod examples;
+pub mod extract;
+pub mod formatter;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fuzz;
+pub mod golden;
+pub mod harness;
+pub mod heat;
+pub mod hints;
+pub mod interp;
+pub mod ir;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+pub mod jit;
+pub mod lexer;
+pub mod library;
+pub mod lsp;
+pub mod metrics;
+pub mod minimize;
+pub mod names;
+pub mod nested;
+pub mod newline;
+pub mod numfmt;
+pub mod observer;
+pub mod outline;
+pub mod output;
+pub mod parser;
+pub mod partial;
+pub mod pipeline;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod pool;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod preprocess;
+pub mod rate;
+pub mod repair;
+pub mod report;
+pub mod server;
+pub mod split;
+pub mod storage;
+pub mod tape;
+pub mod textgen;
+pub mod trace;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tui;
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+pub mod tty;
+pub mod tutorial;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+
+pub use interp::{run, run_fast, RunOutcome, State, Stats};
+pub use lexer::Token;
+pub use ir::{lower, optimize, OptLevel, Program};
+pub use parser::{parse, to_source, Ast, LoopRef, ParseOptions, SyntaxItem};
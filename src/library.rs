@@ -0,0 +1,101 @@
+//! A per-user library of named programs, added with `lib add` and run as
+//! `@NAME`. Each entry is the program's Brainfuck source with its bytecode
+//! next to it, compiled at the highest optimization level, which the command
+//! line picks up as the source's cache.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use bytecode;
+use ir::{self, Instruction, OptLevel};
+use parser::{self, Ast};
+
+/// Environment variable that overrides the directory of the library.
+pub const DIR_VARIABLE: &str = "BFINTERPRETER_LIBRARY";
+
+/// The directory holding the library: `$BFINTERPRETER_LIBRARY`, or else
+/// `bfinterpreter/library` in the user's cache directory.
+pub fn dir() -> Result<PathBuf, String> {
+    if let Some(dir) = env::var_os(DIR_VARIABLE) {
+        return Ok(PathBuf::from(dir));
+    }
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .ok_or_else(|| format!("Could not find a cache directory for the library; set {}.", DIR_VARIABLE))?;
+    Ok(cache.join("bfinterpreter").join("library"))
+}
+
+/// Fails unless `name` is usable as an entry's name: letters, digits, `-`
+/// and `_`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid program name '{}': use letters, digits, '-' and '_'.", name));
+    }
+    Ok(())
+}
+
+/// Where the source of the entry `name` is, whether or not it exists.
+fn source_path(name: &str) -> Result<PathBuf, String> {
+    check_name(name)?;
+    Ok(dir()?.join(format!("{}.b", name)))
+}
+
+/// The source of the entry `name`, to run in place of a file.
+pub fn path(name: &str) -> Result<PathBuf, String> {
+    let path = source_path(name)?;
+    if !path.is_file() {
+        return Err(format!("There is no program '{}' in the library, see 'lib add'.", name));
+    }
+    Ok(path)
+}
+
+/// Adds `tree`, read in any dialect, to the library as `name`, replacing any
+/// entry of that name. Returns the path of its source.
+pub fn add(name: &str, tree: &Ast) -> Result<PathBuf, String> {
+    let path = source_path(name)?;
+    let program = ir::lower(tree);
+    if program.instructions.contains(&Instruction::Fork) {
+        return Err("The library only holds Brainfuck programs, which cannot hold Brainfork's 'Y'.".to_owned());
+    }
+    let program = ir::optimize(&program, OptLevel::MAX);
+
+    let dir = dir()?;
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+    // The source is written first, so that the bytecode is never older.
+    fs::write(&path, parser::to_source(tree)).map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+    let compiled = path.with_extension(bytecode::EXTENSION);
+    fs::write(&compiled, bytecode::encode(&program, OptLevel::MAX))
+        .map_err(|e| format!("Could not write '{}': {}", compiled.display(), e))?;
+    Ok(path)
+}
+
+/// Removes the entry `name`.
+pub fn remove(name: &str) -> Result<(), String> {
+    let path = path(name)?;
+    fs::remove_file(&path).map_err(|e| format!("Could not remove '{}': {}", path.display(), e))?;
+    // Without the source the bytecode is never used, so it does not matter
+    // if it is already gone.
+    let _ = fs::remove_file(path.with_extension(bytecode::EXTENSION));
+    Ok(())
+}
+
+/// Names of the entries, sorted.
+pub fn list() -> Result<Vec<String>, String> {
+    let dir = dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) if !dir.exists() => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Could not read '{}': {}", dir.display(), e)),
+    };
+    let mut names: Vec<String> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "b"))
+        .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(str::to_owned))
+        .filter(|name| check_name(name).is_ok())
+        .collect();
+    names.sort();
+    Ok(names)
+}
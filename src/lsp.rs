@@ -0,0 +1,269 @@
+//! A language server for the `lsp` command, speaking the Language Server
+//! Protocol over stdin and stdout. It reports unmatched brackets as
+//! diagnostics, jumps from a bracket to its partner, and describes a loop
+//! when hovering over one of its brackets.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+use serde_json::Value;
+
+use lexer::{lex, Token};
+use outline::{self, Loop};
+use repair::{self, Edit};
+
+/// The JSON-RPC error for requests the server does not handle.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Answers the messages read from `input` on `output` until the client
+/// says to exit or closes `input`.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<(), String> {
+    let mut documents: HashMap<String, String> = HashMap::new();
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_owned();
+        let result = match method {
+            "initialize" => ::serde_json::json!({
+                "capabilities": {
+                    // The whole document is sent on every change.
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "bfinterpreter" },
+            }),
+            "shutdown" => Value::Null,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str()),
+                };
+                if let Some(text) = text {
+                    let diagnostics = diagnostics(text);
+                    documents.insert(uri.clone(), text.to_owned());
+                    publish(&mut output, &uri, diagnostics)?;
+                }
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish(&mut output, &uri, Vec::new())?;
+                continue;
+            }
+            "textDocument/definition" | "textDocument/hover" => {
+                let source = documents.get(&uri).map_or("", String::as_str);
+                let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+                let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+                match offset(source, line, character) {
+                    Some(at) if method == "textDocument/definition" => definition(source, &uri, at),
+                    Some(at) => hover(source, at),
+                    None => Value::Null,
+                }
+            }
+            _ => {
+                // Notifications the server does not handle are ignored.
+                if let Some(id) = message.get("id") {
+                    let error = ::serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": METHOD_NOT_FOUND, "message": format!("Unknown method '{}'.", method) },
+                    });
+                    write_message(&mut output, &error)?;
+                }
+                continue;
+            }
+        };
+        if let Some(id) = message.get("id") {
+            write_message(&mut output, &::serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the next message, or `None` once `input` is closed.
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| format!("Could not read message: {}", e))? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| format!("Invalid header '{}'.", header))?);
+        }
+    }
+    let length = length.ok_or_else(|| "Message without a Content-Length header.".to_owned())?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| format!("Could not read message: {}", e))?;
+    ::serde_json::from_slice(&body).map(Some).map_err(|e| format!("Invalid message: {}", e))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Could not write message: {}", e))
+}
+
+fn publish<W: Write>(output: &mut W, uri: &str, diagnostics: Vec<Value>) -> Result<(), String> {
+    write_message(output,
+                  &::serde_json::json!({
+                      "jsonrpc": "2.0",
+                      "method": "textDocument/publishDiagnostics",
+                      "params": { "uri": uri, "diagnostics": diagnostics },
+                  }))
+}
+
+/// A diagnostic for every bracket without a partner, see `repair::suggest`.
+fn diagnostics(source: &str) -> Vec<Value> {
+    repair::suggest(source)
+        .into_iter()
+        .map(|edit| {
+            let (at, message) = match edit {
+                Edit::Delete(offset) => (offset, "this `]` closes no loop"),
+                Edit::Insert { opened, .. } => (opened, "this `[` is never closed"),
+            };
+            ::serde_json::json!({
+                "range": range(source, at, at + 1),
+                "severity": 1,
+                "source": "bfinterpreter",
+                "message": message,
+            })
+        })
+        .collect()
+}
+
+/// The partner of the bracket at `at`, as a location.
+fn definition(source: &str, uri: &str, at: usize) -> Value {
+    match partners(source).get(&at) {
+        Some(&partner) => ::serde_json::json!({ "uri": uri, "range": range(source, partner, partner + 1) }),
+        None => Value::Null,
+    }
+}
+
+/// What the loop with a bracket at `at` does on every iteration.
+fn hover(source: &str, at: usize) -> Value {
+    let tokens = lex(source);
+    let loops = outline::outline(&tokens);
+    let found = match find_loop(&loops, at) {
+        Some(found) => found,
+        None => return Value::Null,
+    };
+
+    let (line, column) = line_col(source, found.start);
+    let (end_line, end_column) = line_col(source, found.end);
+    let mut text = format!("loop {}:{}-{}:{}", line + 1, column + 1, end_line + 1, end_column + 1);
+    if let Some(idiom) = found.idiom {
+        text.push_str(&format!(" ({})", idiom));
+    }
+    match found.net_move {
+        Some(0) => text.push_str("\nthe pointer ends where it started"),
+        Some(n) => text.push_str(&format!("\nmoves the pointer by {:+} per iteration", n)),
+        None => text.push_str("\nhow far it moves the pointer depends on nested loops"),
+    }
+    // Cells are only known to change by fixed amounts without nested loops
+    // and input.
+    let reads = tokens.iter().any(|&(offset, token)| token == Token::Input && found.start < offset && offset < found.end);
+    if found.body.is_empty() && !reads {
+        let changes = changes(&tokens, found.start, found.end);
+        if changes.is_empty() {
+            text.push_str("\nchanges no cell");
+        } else {
+            let changes: Vec<String> = changes.iter().map(|(cell, n)| format!("[{:+}] {:+}", cell, n)).collect();
+            text.push_str(&format!("\nchanges per iteration: {}", changes.join(", ")));
+        }
+    }
+    ::serde_json::json!({
+        "contents": { "kind": "plaintext", "value": text },
+        "range": range(source, at, at + 1),
+    })
+}
+
+/// The loop among `loops` and their bodies with a bracket at `at`.
+fn find_loop(loops: &[Loop], at: usize) -> Option<&Loop> {
+    let mut loops = loops;
+    loop {
+        let l = loops.iter().find(|l| l.start <= at && at <= l.end)?;
+        if l.start == at || l.end == at {
+            return Some(l);
+        }
+        loops = &l.body;
+    }
+}
+
+/// How much the loop between the brackets at `start` and `end`, which has
+/// no nested loops or input, changes each cell relative to the tested one.
+fn changes(tokens: &[(usize, Token)], start: usize, end: usize) -> BTreeMap<isize, i32> {
+    let mut pointer = 0;
+    let mut changes = BTreeMap::new();
+    for &(_, token) in tokens.iter().filter(|&&(offset, _)| start < offset && offset < end) {
+        match token {
+            Token::ShiftLeft => pointer -= 1,
+            Token::ShiftRight => pointer += 1,
+            Token::Increment => *changes.entry(pointer).or_insert(0) += 1,
+            Token::Decrement => *changes.entry(pointer).or_insert(0) -= 1,
+            _ => (),
+        }
+    }
+    changes.retain(|_, n: &mut i32| n.rem_euclid(256) != 0);
+    changes
+}
+
+/// The offsets of matching brackets, both ways.
+fn partners(source: &str) -> HashMap<usize, usize> {
+    let mut partners = HashMap::new();
+    let mut open = Vec::new();
+    for (offset, token) in lex(source) {
+        match token {
+            Token::BeginLoop => open.push(offset),
+            Token::EndLoop => {
+                if let Some(start) = open.pop() {
+                    partners.insert(start, offset);
+                    partners.insert(offset, start);
+                }
+            }
+            _ => (),
+        }
+    }
+    partners
+}
+
+/// The line and character of `offset`, both from 0, with characters counted
+/// in UTF-16 code units as the protocol does.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count(), before[line_start..].encode_utf16().count())
+}
+
+fn range(source: &str, start: usize, end: usize) -> Value {
+    let (line, character) = line_col(source, start);
+    let (end_line, end_character) = line_col(source, end);
+    ::serde_json::json!({
+        "start": { "line": line, "character": character },
+        "end": { "line": end_line, "character": end_character },
+    })
+}
+
+/// The byte offset of a position, if it is within the source.
+fn offset(source: &str, line: usize, character: usize) -> Option<usize> {
+    let line_start = if line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return if units == character { Some(line_start + i) } else { None };
+        }
+        units += c.len_utf16();
+    }
+    None
+}
@@ -1,205 +1,3250 @@
+extern crate bfinterpreter;
+extern crate log;
+extern crate signal_hook;
+
+use std::collections::HashMap;
 use std::io::Write;
-use std::io::Read;
+use std::io::{BufRead, IsTerminal, Read};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bfinterpreter::{analysis, animation, audit, bench, bytecode, codegen, coverage, defaults, derivation, differential, examples, extract, formatter, fuzz, golden, heat, hints, ir, library, lsp, minimize, nested, newline, numfmt, outline, output, pipeline, preprocess, rate, repair, report, server, split, storage, textgen, trace, tui, tutorial};
+use bfinterpreter::codegen::bundle;
+use bfinterpreter::codegen::verify::Verdict;
+use bfinterpreter::debugger::Debugger;
+use bfinterpreter::diagnostic::{self, Severity};
+use bfinterpreter::dialect::Dialect;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+use bfinterpreter::jit;
+#[cfg(feature = "plugins")]
+use bfinterpreter::plugin;
+use bfinterpreter::interp::{Checkpoint, LoopProfile, Stdout};
+use bfinterpreter::ir::{OptLevel, Program};
+use bfinterpreter::metrics::{Metric, Metrics};
+use bfinterpreter::names::CellNames;
+use bfinterpreter::numfmt::NumFormat;
+use bfinterpreter::rate::RateLimiter;
+use bfinterpreter::tape::{self, Tape};
+use bfinterpreter::trace::Tracer;
+use bfinterpreter::{parse, parser, run, Ast, RunOutcome, State, SyntaxItem};
+
+fn dump_ir(program: &Program) {
+    for (i, instruction) in program.instructions.iter().enumerate() {
+        println!("{:>6}  {}", i, instruction);
+    }
+}
+
+/// Like `dump_ir`, but prints each instruction under the part of `source` it
+/// was generated from, as `objdump -S` does.
+fn dump_ir_interleaved(program: &Program, source: &str, dialect: &Dialect) {
+    let tokens = dialect.lex(source).expect("Could not lex a program that parsed.");
+    let mut opens = Vec::new();
+    let mut closes = HashMap::new();
+    for &(offset, token) in &tokens {
+        match token {
+            bfinterpreter::Token::BeginLoop => opens.push(offset),
+            bfinterpreter::Token::EndLoop => {
+                if let Some(open) = opens.pop() {
+                    closes.insert(open, offset);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // A loop's closing jump has the offset of its opening bracket, but came
+    // from the closing one.
+    let starts: Vec<usize> = program.instructions.iter().zip(&program.offsets)
+        .map(|(instruction, &offset)| match *instruction {
+            ir::Instruction::JumpIfNotZero(_) => closes.get(&offset).cloned().unwrap_or(offset),
+            _ => offset,
+        })
+        .collect();
+    let mut bounds = starts.clone();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut last = None;
+    for (i, (instruction, &start)) in program.instructions.iter().zip(&starts).enumerate() {
+        if last != Some(start) {
+            let end = bounds.iter().cloned().find(|&bound| bound > start).unwrap_or(source.len());
+            let excerpt = source[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+            let excerpt = if excerpt.chars().count() > 60 {
+                format!("{}...", excerpt.chars().take(57).collect::<String>())
+            } else {
+                excerpt
+            };
+            let (line, column) = line_col(source, start);
+            println!("{}:{}: {}", line, column, excerpt);
+            last = Some(start);
+        }
+        println!("{:>6}  {}", i, instruction);
+    }
+}
+
+fn dump_ast(items: &[SyntaxItem]) {
+    // The loops being printed, each with the items after it.
+    let mut open = Vec::new();
+    let mut items = items.iter();
+    loop {
+        match items.next() {
+            Some(SyntaxItem::Single(_, t)) => println!("{}{:?}", "  ".repeat(open.len()), t),
+            Some(SyntaxItem::Loop(_, v, _)) => {
+                println!("{}Loop", "  ".repeat(open.len()));
+                open.push(std::mem::replace(&mut items, v.iter()));
+            }
+            None => match open.pop() {
+                Some(rest) => items = rest,
+                None => break,
+            },
+        }
+    }
+}
+
+fn print_stats(state: &State, num: &NumFormat) {
+    eprintln!("instructions: {}", num.format(state.stats.instructions));
+    eprintln!("tape cells:   {}", num.format(state.tape().len() as u64));
+    eprintln!("memory:       {} bytes (peak {} bytes)",
+              num.format(state.memory_usage() as u64),
+              num.format(state.stats.peak_memory as u64));
+    eprintln!("output:       {} bytes", num.format(state.stats.output_bytes));
+    if state.stats.dead_code > 0 {
+        eprintln!("dead code:    {} instructions removed", num.format(state.stats.dead_code));
+    }
+    if state.output_rate.is_some() {
+        eprintln!("throttled:    {:.3} ms", state.stats.throttled.as_secs_f64() * 1000.0);
+    }
+    if let Some(limit) = state.memory_limit {
+        eprintln!("memory limit: {} bytes", num.format(limit as u64));
+    }
+}
+
+/// Prints the first `cells` cells of the tape, or all of it, sixteen to a
+/// line like a hex dump, with the cell under the pointer in brackets.
+fn print_tape(state: &State, cells: Option<usize>, names: &CellNames) {
+    let tape = state.tape();
+    let len = cells.unwrap_or(tape.len());
+    let value = |cell: usize| tape.get(cell);
+    for start in (0..len).step_by(16) {
+        let end = (start + 16).min(len);
+        let mut line = format!("{:08x} ", start);
+        for cell in start..end {
+            line.push(match cell {
+                _ if cell == state.pointer() => '[',
+                _ if cell > start && cell - 1 == state.pointer() => ']',
+                _ => ' ',
+            });
+            line.push_str(&format!("{:02x}", value(cell)));
+        }
+        line.push(if end - 1 == state.pointer() { ']' } else { ' ' });
+        line.push_str(&"   ".repeat(start + 16 - end));
+        let text: String = (start..end)
+            .map(|cell| if value(cell).is_ascii_graphic() || value(cell) == b' ' { value(cell) as char } else { '.' })
+            .collect();
+        eprintln!("{} |{}|", line, text);
+    }
+    match names.name(state.pointer()) {
+        Some(name) => eprintln!("ptr {} ({})", state.pointer(), name),
+        None => eprintln!("ptr {}", state.pointer()),
+    }
+    if !names.is_empty() {
+        let named: Vec<String> = names.iter()
+            .map(|(cell, name)| format!("{}={}", name, value(cell)))
+            .collect();
+        eprintln!("named: {}", named.join(" "));
+    }
+}
+
+/// Time spent in each stage of running or compiling a program.
+#[derive(Default)]
+struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    fn measure<T, F: FnOnce() -> T>(&mut self, stage: &'static str, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.stages.push((stage, start.elapsed()));
+        result
+    }
+
+    fn print(&self) {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        for &(stage, time) in &self.stages {
+            eprintln!("{:<13} {:.3} ms", format!("{}:", stage), ms(time));
+        }
+        eprintln!("{:<13} {:.3} ms", "total:", ms(self.stages.iter().map(|&(_, time)| time).sum()));
+    }
+}
+
+fn print_metrics(metrics: &Metrics, num: &NumFormat) {
+    for metric in &metrics.metrics {
+        eprintln!("metric {}: {} (min {}, max {}, {} samples)",
+                  metric.name,
+                  num.format(metric.last),
+                  num.format(metric.min),
+                  num.format(metric.max),
+                  metrics.samples);
+    }
+}
+
+/// Returns the 1-based line and column of a byte offset into `source`.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// Prints `message` with the line of `source` that `span` is on, colored
+/// unless stderr is not a terminal or `NO_COLOR` is set.
+fn print_diagnostic(severity: Severity, message: &str, source: &str, span: Option<std::ops::Range<usize>>) {
+    let color = std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal();
+    eprintln!("{}", diagnostic::render(severity, message, source, span, color));
+}
+
+fn print_repairs(edits: &[repair::Edit], source: &str) {
+    for edit in edits {
+        match *edit {
+            repair::Edit::Delete(offset) => {
+                let (line, column) = line_col(source, offset);
+                print_diagnostic(Severity::Help,
+                                 &format!("delete the `]` at {}:{}, it closes no loop", line, column),
+                                 source,
+                                 Some(offset..offset + 1));
+            }
+            repair::Edit::Insert { at, opened } => {
+                let (line, column) = line_col(source, at);
+                let (open_line, open_column) = line_col(source, opened);
+                print_diagnostic(Severity::Help,
+                                 &format!("insert `]` at {}:{} to close the loop opened at {}:{}",
+                                          line, column, open_line, open_column),
+                                 source,
+                                 Some(at..at + 1));
+            }
+        }
+    }
+    if !edits.is_empty() {
+        print_diagnostic(Severity::Help, "run with --fix to apply these changes", source, None);
+    }
+}
+
+fn print_hints(hints: &[hints::Hint], source: &str) {
+    if hints.is_empty() {
+        return;
+    }
+
+    eprintln!("hints:");
+    for hint in hints {
+        match hint.offset {
+            Some(offset) => {
+                let (line, column) = line_col(source, offset);
+                eprintln!("  {}:{}: {}", line, column, hint.message);
+            }
+            None => eprintln!("  {}", hint.message),
+        }
+    }
+}
+
+fn print_profile(profile: &HashMap<usize, LoopProfile>, source: &str, top: usize, num: &NumFormat) {
+    let mut loops: Vec<_> = profile.iter().collect();
+    loops.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+
+    eprintln!("{:>10} {:>12} {:>14} {:>12}", "location", "entries", "iterations", "time (ms)");
+    for (&offset, entry) in loops.into_iter().take(top) {
+        let (line, column) = line_col(source, offset);
+        eprintln!("{:>10} {:>12} {:>14} {:>12.3}",
+                  format!("{}:{}", line, column),
+                  num.format(entry.entries),
+                  num.format(entry.iterations),
+                  entry.time.as_secs_f64() * 1000.0);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Options {
+    filename: String,
+    dump_ast: bool,
+    dialect: Dialect,
+    dump_ir: bool,
+    interleave_source: bool,
+    opt_level: OptLevel,
+    jit: bool,
+    trace: bool,
+    trace_file: Option<String>,
+    trace_format: Option<trace::Format>,
+    stats: bool,
+    stats_json: bool,
+    /// Print the tape once the program halts.
+    dump_tape: bool,
+    /// Cells of the tape to print, or all of it.
+    dump_tape_cells: Option<usize>,
+    timings: bool,
+    num_format: NumFormat,
+    max_memory: Option<usize>,
+    max_steps: Option<u64>,
+    max_loop_iterations: Option<u64>,
+    timeout: Option<Duration>,
+    strict_tape: bool,
+    tape: tape::Backend,
+    byte_input: bool,
+    eof_unchanged: bool,
+    /// Take the input from after a `!` in the file, if there is one.
+    split_input: bool,
+    /// Read keys from a terminal as they are pressed, without echo.
+    raw_tty: bool,
+    /// Exit with the value of the cell under the pointer once the program
+    /// finishes.
+    exit_from_cell: bool,
+    /// Shown when `,` waits for input typed at a terminal, instead of the
+    /// default.
+    prompt: Option<String>,
+    no_prompt: bool,
+    parse_options: parser::ParseOptions,
+    profile: bool,
+    coverage: bool,
+    coverage_file: Option<String>,
+    coverage_lcov: bool,
+    heat_map: Option<String>,
+    profile_top: usize,
+    hints: bool,
+    debug: bool,
+    tui: bool,
+    nested: bool,
+    newline: newline::Newline,
+    fix: bool,
+    history: usize,
+    audit_log: Option<String>,
+    output_rate: Option<u64>,
+    output_rate_policy: rate::Policy,
+    checkpoint_every: Option<u64>,
+    /// Parsed once the source, which may name cells, has been read.
+    metrics: Vec<String>,
+    metrics_every: Option<u64>,
+    metrics_file: Option<String>,
+    checkpoint_file: Option<String>,
+    resume: Option<String>,
+    record_input: Option<String>,
+    /// From `--input-file` or `--replay-input`, which are the same.
+    input_file: Option<String>,
+    input_text: Option<String>,
+    /// Optimizer passes of plugins, run in order after the built-in ones.
+    passes: Vec<String>,
+    output_sink: Option<String>,
+    output_file: Option<String>,
+    tee: bool,
+    output_mode: output::OutputMode,
+    /// When output is flushed, if not the default for where it goes.
+    flush_policy: Option<output::FlushPolicy>,
+    output_separator: Option<output::Separator>,
+    export_derivation: Option<String>,
+    derivation_steps: usize,
+    /// Run the source through the preprocessor, see `--ext=include`.
+    include: bool,
+    /// Set once the preprocessor has run.
+    expansion: Option<preprocess::Expansion>,
+    /// Every file to run, when more than one is given or `--glob` is.
+    batch: Vec<String>,
+    /// The arguments without the files, which every run of a batch gets.
+    batch_args: Vec<String>,
+}
+
+/// Parses a byte count with an optional binary suffix, e.g. `4096`, `512K` or `64M`.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits.parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size '{}'.", s))
+}
+
+/// Parses a duration with a unit, e.g. `500ms`, `5s` or `2m`, where a
+/// number alone is seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let seconds = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("Invalid duration '{}'.", s)),
+    };
+    number.parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n * seconds).ok())
+        .ok_or_else(|| format!("Invalid duration '{}'.", s))
+}
+
+fn parse_rate_policy(s: &str) -> Result<rate::Policy, String> {
+    match s {
+        "throttle" => Ok(rate::Policy::Throttle),
+        "abort" => Ok(rate::Policy::Abort),
+        other => Err(format!("Unknown output rate policy '{}'.", other)),
+    }
+}
+
+fn parse_opt_level(s: &str) -> Result<OptLevel, String> {
+    s.parse()
+        .ok()
+        .and_then(OptLevel::new)
+        .ok_or_else(|| format!("Invalid optimization level '{}', expected 0 to {}.", s, OptLevel::MAX))
+}
+
+fn parse_dialect(name: &str) -> Result<Dialect, String> {
+    Dialect::from_name(name).ok_or_else(|| format!("Unknown dialect '{}'.", name))
+}
+
+fn load_dialect_map(path: &str) -> Result<Dialect, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    Dialect::from_map(&contents)
+}
+
+/// Reads the file at `path`, or exits with an error.
+fn read_or_exit<P: AsRef<std::path::Path>>(path: P) -> String {
+    let path = path.as_ref();
+    std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Could not read '{}': {}", path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// Reads the bytes of the file at `path`, or exits with an error.
+fn read_bytes_or_exit<P: AsRef<std::path::Path>>(path: P) -> Vec<u8> {
+    let path = path.as_ref();
+    std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Could not read '{}': {}", path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// Creates the file at `path` to write to, or exits with an error.
+fn create_or_exit(path: &str) -> std::fs::File {
+    std::fs::File::create(path).unwrap_or_else(|e| {
+        eprintln!("Could not create '{}': {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+/// Writes `contents` to the file at `path`, or exits with an error.
+fn write_or_exit<P: AsRef<std::path::Path>, C: AsRef<[u8]>>(path: P, contents: C) {
+    let path = path.as_ref();
+    if let Err(e) = std::fs::write(path, contents) {
+        eprintln!("Could not write '{}': {}", path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Splits `contents` into tokens and parses them, or exits with an error.
+/// Brackets can only be repaired in Brainfuck itself.
+fn parse_or_exit(contents: &str, dialect: &Dialect, limits: &parser::ParseOptions, timings: &mut Timings) -> Ast {
+    let tokens = match timings.measure("lex", || dialect.lex(contents)) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            print_diagnostic(Severity::Error, &e, contents, diagnostic::offset_in(&e).map(|offset| offset..offset + 1));
+            std::process::exit(1);
+        }
+    };
+    match timings.measure("parse", || parser::parse_tokens_with(&tokens, limits)) {
+        Ok(tree) => tree,
+        Err(e) => {
+            print_diagnostic(Severity::Error, &e, contents, diagnostic::offset_in(&e).map(|offset| offset..offset + 1));
+            if *dialect == Dialect::Brainfuck {
+                print_repairs(&repair::suggest(contents), contents);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Like `parse_or_exit` for a program put together by the preprocessor,
+/// whose errors point into the files as written. Brackets are not repaired,
+/// since the fixes would be for the expansion.
+fn parse_expanded_or_exit(expansion: &preprocess::Expansion, options: &Options, timings: &mut Timings) -> Ast {
+    let tokens = timings.measure("lex", || options.dialect.lex(&expansion.source));
+    match tokens.and_then(|tokens| timings.measure("parse", || parser::parse_tokens_with(&tokens, &options.parse_options))) {
+        Ok(tree) => tree,
+        Err(e) => {
+            eprintln!("{}", expansion.explain(&e));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The files matching `pattern`, in order, where `*` and `?` may only
+/// appear in the file name, as in `progs/*.b`.
+fn glob(pattern: &str) -> Result<Vec<String>, String> {
+    let path = std::path::Path::new(pattern);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    if dir.to_string_lossy().contains(['*', '?']) {
+        return Err(format!("Invalid pattern '{}': only file names can have wildcards.", pattern));
+    }
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Could not read '{}': {}", dir.display(), e))?;
+    let mut matched: Vec<String> = entries.filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|file| wildcard_match(name, file)))
+        .map(|entry| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => entry.path().to_string_lossy().into_owned(),
+            _ => entry.file_name().to_string_lossy().into_owned(),
+        })
+        .collect();
+    matched.sort();
+    Ok(matched)
+}
+
+/// Whether `name` matches `pattern`, in which `*` stands for any run of
+/// characters and `?` for any one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // The last `*` seen and where in `name` it was tried to end.
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Runs every file of a batch in a process of its own, with the options
+/// given for all of them, and sums up how each run went and what it used.
+fn run_batch(options: &Options) {
+    if options.debug || options.tui {
+        eprintln!("--debug and --tui only run a single file.");
+        std::process::exit(2);
+    }
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("Could not find the interpreter: {}", e);
+        std::process::exit(1);
+    });
+    // Plugins and `--verbose` are taken off the arguments before they are
+    // parsed, so every run has to be given them again.
+    let mut plugins = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--plugin" {
+            plugins.push(arg);
+            plugins.extend(args.next());
+        } else if arg.starts_with("--plugin=") || ["-v", "-vv", "--verbose"].contains(&arg.as_str()) {
+            plugins.push(arg);
+        }
+    }
+
+    let num = &options.num_format;
+    let (mut failed, mut instructions, mut output_bytes) = (0, 0, 0);
+    let mut summary = Vec::new();
+    for file in &options.batch {
+        let start = Instant::now();
+        let run = std::process::Command::new(&exe)
+            .args(&plugins)
+            .args(&options.batch_args)
+            .arg("--stats-format=json")
+            .arg(file)
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|child| child.wait_with_output());
+        let run = run.unwrap_or_else(|e| {
+            eprintln!("Could not run '{}': {}", file, e);
+            std::process::exit(1);
+        });
+
+        // Everything but the statistics goes on to stderr, marked with the
+        // file it is about.
+        let mut report = None;
+        for line in String::from_utf8_lossy(&run.stderr).lines() {
+            match report::StatsReport::from_json(line) {
+                Ok(stats) => report = Some(stats),
+                Err(_) => eprintln!("{}: {}", file, line),
+            }
+        }
+        let status = match run.status.code() {
+            Some(0) => "ok",
+            Some(EXIT_OUTPUT_CLOSED) => "closed",
+            Some(EXIT_INTERRUPTED) | None => "stopped",
+            Some(_) => "failed",
+        };
+        if status != "ok" {
+            failed += 1;
+        }
+        let used = match report {
+            Some(ref stats) => {
+                instructions += stats.instructions;
+                output_bytes += stats.output_bytes;
+                format!("{} instructions, {} bytes of output",
+                        num.format(stats.instructions),
+                        num.format(stats.output_bytes))
+            }
+            None => "did not run".to_owned(),
+        };
+        summary.push(format!("{:<8} {}: {}, {:.3} s", status, file, used, start.elapsed().as_secs_f64()));
+        if status == "stopped" {
+            break;
+        }
+    }
+
+    for line in &summary {
+        eprintln!("{}", line);
+    }
+    eprintln!("{} of {} files ran without error; {} instructions, {} bytes of output in total.",
+              summary.len() - failed,
+              options.batch.len(),
+              num.format(instructions),
+              num.format(output_bytes));
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        profile_top: 10,
+        history: 10_000,
+        derivation_steps: derivation::DEFAULT_MAX_STEPS,
+        ..Options::default()
+    };
+    let mut files = Vec::new();
+    let mut globbed = false;
+    // The indices of the files and `--glob`, which a batch leaves out.
+    let mut not_shared = Vec::new();
+
+    let mut it = args.iter().enumerate();
+    while let Some((i, arg)) = it.next() {
+        // Options taking a value accept both `--name=value` and `--name value`.
+        let (name, mut value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let inline = value.is_some();
+        // Switches that can be set by `defaults` also accept `--name=false`,
+        // which turns them off again.
+        let switch = value.clone();
+        let on = |name: &str| match switch.as_deref() {
+            None | Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(other) => Err(format!("Option '{}' takes true or false, not '{}'.", name, other)),
+        };
+        // The other switches take no value at all.
+        let flag = |name: &str| if inline {
+            Err(format!("Option '{}' takes no value.", name))
+        } else {
+            Ok(true)
+        };
+        // Options taking a value that can be set by `defaults` are reset by
+        // an empty value or `none`, which undoes the default.
+        let cleared = matches!(switch.as_deref(), Some("") | Some("none"));
+        let mut value = |name: &str| {
+            value.take()
+                .or_else(|| it.next().map(|(_, value)| value.clone()))
+                .ok_or_else(|| format!("Option '{}' requires a value.", name))
+        };
+
+        match name {
+            "--dialect" if cleared => options.dialect = Dialect::default(),
+            "--opt-level" if cleared => options.opt_level = OptLevel::default(),
+            "--max-memory" if cleared => options.max_memory = None,
+            "--max-steps" if cleared => options.max_steps = None,
+            "--max-loop-iterations" if cleared => options.max_loop_iterations = None,
+            "--timeout" if cleared => options.timeout = None,
+            "--tape" if cleared => options.tape = tape::Backend::default(),
+            "--eof" if cleared => options.eof_unchanged = false,
+            "--preset" if cleared => {
+                options.byte_input = options.raw_tty;
+                options.eof_unchanged = false;
+                options.split_input = false;
+            }
+            "--newline" if cleared => options.newline = newline::Newline::default(),
+            "--prompt" if cleared => options.prompt = None,
+            "--output-mode" if cleared => options.output_mode = output::OutputMode::default(),
+            "--output-separator" if cleared => options.output_separator = None,
+            "--flush" if cleared => options.flush_policy = None,
+            "--num-format" if cleared => options.num_format.radix = numfmt::Radix::default(),
+            "--dump-ast" => options.dump_ast = flag(name)?,
+            "--dump-ir" => options.dump_ir = flag(name)?,
+            "--interleave-source" => {
+                options.dump_ir = flag(name)?;
+                options.interleave_source = true;
+            }
+            "--dialect" => options.dialect = parse_dialect(&value(name)?)?,
+            "--dialect-map" => options.dialect = load_dialect_map(&value(name)?)?,
+            "--trace" => options.trace = flag(name)?,
+            "--trace-file" => {
+                options.trace = true;
+                options.trace_file = Some(value(name)?);
+            }
+            "--trace-format" => {
+                options.trace = true;
+                options.trace_format = Some(match value(name)?.as_str() {
+                    "text" => trace::Format::Text,
+                    "json" => trace::Format::Json,
+                    other => return Err(format!("Unknown trace format '{}'.", other)),
+                });
+            }
+            "--opt-level" => options.opt_level = parse_opt_level(&value(name)?)?,
+            "--jit" => options.jit = on(name)?,
+            "--stats" => options.stats = flag(name)?,
+            "--stats-format" => {
+                options.stats = true;
+                options.stats_json = match value(name)?.as_str() {
+                    "text" => false,
+                    "json" => true,
+                    other => return Err(format!("Unknown stats format '{}'.", other)),
+                };
+            }
+            "--dump-tape" => {
+                options.dump_tape = true;
+                // The count is optional, so it can only follow '='.
+                if let Some(cells) = arg.get(name.len() + 1..) {
+                    options.dump_tape_cells = Some(cells.parse().map_err(|_| format!("Invalid count '{}'.", cells))?);
+                }
+            }
+            "--timings" => options.timings = flag(name)?,
+            "--num-format" => {
+                let name = value(name)?;
+                options.num_format.radix = numfmt::Radix::from_name(&name)
+                    .ok_or_else(|| format!("Unknown number format '{}'.", name))?;
+            }
+            "--num-width" => {
+                let width = value(name)?;
+                options.num_format.width = width.parse().map_err(|_| format!("Invalid count '{}'.", width))?;
+            }
+            "--num-separator" => {
+                let separator = value(name)?;
+                let mut chars = separator.chars();
+                options.num_format.separator = match (chars.next(), chars.next()) {
+                    (None, _) => None,
+                    (Some(c), None) => Some(c),
+                    _ => return Err(format!("Separator '{}' must be a single character.", separator)),
+                };
+            }
+            "--max-memory" => options.max_memory = Some(parse_size(&value(name)?)?),
+            "--max-steps" => {
+                let steps = value(name)?;
+                options.max_steps = Some(steps.parse().map_err(|_| format!("Invalid count '{}'.", steps))?);
+            }
+            "--max-loop-iterations" => {
+                let iterations = value(name)?;
+                options.max_loop_iterations =
+                    Some(iterations.parse().map_err(|_| format!("Invalid count '{}'.", iterations))?);
+            }
+            "--timeout" => options.timeout = Some(parse_duration(&value(name)?)?),
+            "--strict-tape" => options.strict_tape = on(name)?,
+            "--exit-from-cell" => options.exit_from_cell = on(name)?,
+            "--prompt" => options.prompt = Some(value(name)?),
+            "--no-prompt" => options.no_prompt = on(name)?,
+            "--tape" => options.tape = value(name)?.parse()?,
+            "--eof" => options.eof_unchanged = match value(name)?.as_str() {
+                "fail" => false,
+                "unchanged" => true,
+                other => return Err(format!("Unknown EOF behavior '{}', expected fail or unchanged.", other)),
+            },
+            "--raw-tty" => {
+                options.raw_tty = flag(name)?;
+                options.byte_input = true;
+            }
+            "--preset" => match value(name)?.as_str() {
+                // What classic self-interpreters like dbfi expect; cells wrap
+                // at 8 bits and the tape grows to the right anyway.
+                "dbfi" => {
+                    options.byte_input = true;
+                    options.eof_unchanged = true;
+                    options.split_input = true;
+                }
+                other => return Err(format!("Unknown preset '{}'.", other)),
+            },
+            "--lenient" => options.parse_options.lenient = on(name)?,
+            "--ext" => {
+                for extension in value(name)?.split(',') {
+                    match extension {
+                        "include" => options.include = true,
+                        other => return Err(format!("Unknown extension '{}'.", other)),
+                    }
+                }
+            }
+            "--max-nesting" => {
+                let depth = value(name)?;
+                options.parse_options.max_nesting = Some(depth.parse().map_err(|_| format!("Invalid depth '{}'.", depth))?);
+            }
+            "--max-program-size" => {
+                let size = value(name)?;
+                options.parse_options.max_size = Some(size.parse().map_err(|_| format!("Invalid count '{}'.", size))?);
+            }
+            "--profile" => options.profile = flag(name)?,
+            "--coverage" => options.coverage = flag(name)?,
+            "--coverage-file" => {
+                options.coverage = true;
+                options.coverage_file = Some(value(name)?);
+            }
+            "--heat-map" => options.heat_map = Some(value(name)?),
+            "--coverage-format" => {
+                options.coverage = true;
+                options.coverage_lcov = match value(name)?.as_str() {
+                    "annotated" => false,
+                    "lcov" => true,
+                    other => return Err(format!("Unknown coverage format '{}'.", other)),
+                };
+            }
+            "--hints" => options.hints = flag(name)?,
+            "--debug" => options.debug = flag(name)?,
+            "--tui" => options.tui = flag(name)?,
+            "--nested" => options.nested = flag(name)?,
+            "--newline" => {
+                let name = value(name)?;
+                options.newline = newline::Newline::from_name(&name)
+                    .ok_or_else(|| format!("Unknown line ending '{}'.", name))?;
+            }
+            "--fix" => options.fix = flag(name)?,
+            "--history" => {
+                let history = value(name)?;
+                options.history = history.parse().map_err(|_| format!("Invalid count '{}'.", history))?;
+            }
+            "--audit-log" => options.audit_log = Some(value(name)?),
+            "--output-rate" => options.output_rate = Some(parse_size(&value(name)?)? as u64),
+            "--checkpoint-every" => {
+                let every = value(name)?;
+                options.checkpoint_every = match every.parse() {
+                    Ok(0) | Err(_) => return Err(format!("Invalid count '{}'.", every)),
+                    Ok(n) => Some(n),
+                };
+            }
+            "--checkpoint-file" => options.checkpoint_file = Some(value(name)?),
+            "--metric" => options.metrics.push(value(name)?),
+            "--metrics-every" => {
+                let every = value(name)?;
+                options.metrics_every = match every.parse() {
+                    Ok(0) | Err(_) => return Err(format!("Invalid count '{}'.", every)),
+                    Ok(n) => Some(n),
+                };
+            }
+            "--metrics-file" => options.metrics_file = Some(value(name)?),
+            "--resume" => options.resume = Some(value(name)?),
+            "--record-input" => options.record_input = Some(value(name)?),
+            "--input-file" | "--replay-input" => options.input_file = Some(value(name)?),
+            "--input" => options.input_text = Some(value(name)?),
+            "--pass" => options.passes.push(value(name)?),
+            "--output-sink" => options.output_sink = Some(value(name)?),
+            "--output-file" => options.output_file = Some(value(name)?),
+            "--tee" => options.tee = flag(name)?,
+            "--output-mode" => {
+                let mode = value(name)?;
+                options.output_mode = output::OutputMode::from_name(&mode)
+                    .ok_or_else(|| format!("Unknown output mode '{}'.", mode))?;
+            }
+            "--flush" => {
+                let policy = value(name)?;
+                options.flush_policy = Some(output::FlushPolicy::from_name(&policy)
+                    .ok_or_else(|| format!("Unknown flush policy '{}'.", policy))?);
+            }
+            "--output-separator" => {
+                let separator = value(name)?;
+                options.output_separator = Some(output::Separator::from_name(&separator)
+                    .ok_or_else(|| format!("Unknown output separator '{}'.", separator))?);
+            }
+            "--export-derivation" => options.export_derivation = Some(value(name)?),
+            "--derivation-steps" => {
+                let steps = value(name)?;
+                options.derivation_steps = steps.parse().map_err(|_| format!("Invalid count '{}'.", steps))?;
+            }
+            "--output-rate-policy" => options.output_rate_policy = parse_rate_policy(&value(name)?)?,
+            "--profile-top" => {
+                let top = value(name)?;
+                options.profile_top = top.parse().map_err(|_| format!("Invalid count '{}'.", top))?;
+            }
+            "--glob" => {
+                let pattern = value(name)?;
+                let matched = glob(&pattern)?;
+                if matched.is_empty() {
+                    return Err(format!("No files match '{}'.", pattern));
+                }
+                files.extend(matched);
+                globbed = true;
+                not_shared.push(i);
+                if !inline {
+                    not_shared.push(i + 1);
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'.", arg)),
+            _ => {
+                files.push(arg.clone());
+                not_shared.push(i);
+            }
+        }
+    }
+
+    if options.output_file.is_some() && options.output_sink.is_some() {
+        return Err("Only one of --output-file and --output-sink can be given.".to_owned());
+    }
+    if options.tee && options.output_file.is_none() {
+        return Err("--tee needs --output-file.".to_owned());
+    }
+    if options.input_file.is_some() && options.input_text.is_some() {
+        return Err("Only one of --input and --input-file can be given.".to_owned());
+    }
+    if options.raw_tty && (options.debug || options.tui) {
+        return Err("--raw-tty cannot be used with --debug or --tui, which read the terminal themselves.".to_owned());
+    }
+    if let Some(separator) = options.output_separator {
+        if !options.output_mode.is_numeric() {
+            return Err("--output-separator needs --output-mode=decimal or --output-mode=hex.".to_owned());
+        }
+        options.output_mode = options.output_mode.separated_by(separator);
+    }
+    options.filename = files.first().cloned().ok_or_else(|| "No input file given.".to_owned())?;
+    if globbed || files.len() > 1 {
+        options.batch = files;
+        options.batch_args = args.iter()
+            .enumerate()
+            .filter(|&(i, _)| !not_shared.contains(&i))
+            .map(|(_, arg)| arg.clone())
+            .collect();
+    }
+    Ok(options)
+}
+
+/// Exit status when the program was stopped because its output was closed,
+/// the same a shell reports for a process killed by SIGPIPE.
+const EXIT_OUTPUT_CLOSED: i32 = 141;
+
+/// Exit status when the program was interrupted with Ctrl-C, the same a
+/// shell reports for a process killed by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Shown on stderr when `,` waits for input typed at a terminal, unless
+/// `--prompt` gives another; dimmed unless `NO_COLOR` is set.
+const DEFAULT_PROMPT: &str = "> ";
+const DEFAULT_PROMPT_COLORED: &str = "\x1b[2m> \x1b[0m";
+
+/// Makes Ctrl-C set the returned flag instead of killing the process, so
+/// that the interpreter can stop and report where it was. A second Ctrl-C
+/// quits at once.
+fn catch_interrupts() -> Result<Arc<AtomicBool>, String> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    // Registered first, this only sees the flag set by an earlier Ctrl-C.
+    signal_hook::flag::register_conditional_shutdown(signal_hook::consts::SIGINT,
+                                                     EXIT_INTERRUPTED,
+                                                     Arc::clone(&interrupted))
+        .and_then(|_| signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&interrupted)))
+        .map_err(|e| format!("Could not handle Ctrl-C: {}", e))?;
+    Ok(interrupted)
+}
+
+const USAGE: &str = "\
+Usage: bfinterpreter [options] <file | @name>...
+       bfinterpreter [options] --glob=PATTERN
+       bfinterpreter examples list
+       bfinterpreter examples run <name> [options]
+       bfinterpreter tutorial
+       bfinterpreter split <file> --max-len=N [--out-dir=DIR]
+       bfinterpreter extract <file> [--all] [--run [options]]
+       bfinterpreter compile [--target=c|rust|wasm|bytecode] [--opt-level=N]
+                             [--tape-len=N] [--dialect=NAME] [--dialect-map=FILE]
+                             [--codegen NAME=VALUE]... [--timings] [-o FILE]
+                             [--verify [--verify-input=FILE]] <file>
+       bfinterpreter compile --bundle=DIR [options] <file>
+       bfinterpreter disasm <file.bfc>
+       bfinterpreter outline [--depth=N] [--dialect=NAME] [--dialect-map=FILE] <file>
+       bfinterpreter analyze [--dialect=NAME] [--dialect-map=FILE] <file>
+       bfinterpreter translate [--from=NAME] [--from-map=FILE] [--to=NAME] [--to-map=FILE]
+                               [-o FILE] <file>
+       bfinterpreter generate-text [--dialect=NAME] [--dialect-map=FILE] [-o FILE] <text>
+       bfinterpreter minimize-input [--keep=output|error|limit|coverage] [--prefix=N]
+                                    [--max-steps=N] [--max-memory=SIZE] [--dialect=NAME]
+                                    [-o FILE] <file> <input>
+       bfinterpreter fmt [--minify] [--width=N] [--in-place | -o FILE] <file>
+       bfinterpreter bench [--iterations=N] [--opt-level=N] [--compare=N] [--input-file=FILE]
+                           [--jit] [--tape=vec|mmap|sparse] [--dialect=NAME] [--dialect-map=FILE] <file>
+       bfinterpreter lib add [--dialect=NAME] [--dialect-map=FILE] <name> <file>
+       bfinterpreter lib remove <name> | lib list | lib path [name]
+       bfinterpreter test [--opt-level=N] [--max-steps=N] <dir>
+       bfinterpreter verify [--backends=A,B] [--opt-level=N] [--input-file=FILE]
+                            [--dialect=NAME] [--dialect-map=FILE] <file>
+       bfinterpreter diff [--input=TEXT | --input-file=FILE] [--opt-level=N] [--max-steps=N]
+                          [--dialect=NAME] [--dialect-map=FILE] <a> <b>
+       bfinterpreter render --out FILE [--every=N] [--max-frames=N] [--cells=N]
+                            [--input=TEXT | --input-file=FILE] [--opt-level=N]
+                            [--dialect=NAME] [--dialect-map=FILE] <file>
+       bfinterpreter fuzz [--seed=S] [--runs=N] [--opt-level=N] [--max-steps=N] [--max-memory=SIZE]
+                          [--max-input=N] [--dialect=NAME] [--dialect-map=FILE] <file>
+       bfinterpreter pipe [--opt-level=N] [--max-steps=N] [--dialect=NAME] [--dialect-map=FILE]
+                          <file | @name>...
+       bfinterpreter serve [--host=HOST] [--port=N] [--opt-level=N] [--max-program=SIZE]
+                           [--max-steps=N] [--max-memory=SIZE] [--idle-timeout=SECONDS]
+                           [--connections=N] [--audit-log=FILE] [--output-rate=BYTES]
+                           [--output-rate-policy=throttle|abort] [--store=memory[:N]|dir:PATH]
+       bfinterpreter lsp
+
+Options:
+  --glob=PATTERN              Run the files matching PATTERN, e.g. 'progs/*.b'; given more
+                              than one file, each runs in turn and a summary with their
+                              statistics follows
+  --dump-ast                  Print the syntax tree and exit
+  --dump-ir                   Print the instruction stream and exit
+  --interleave-source         With --dump-ir, print each instruction under the source it came from
+  --dialect=bf|brainfork|ook  Language the program is written in (default: bf); brainfork
+                              adds 'Y', which forks the program into threads that take turns
+  --dialect-map=FILE          Read the program with the command words mapped in a TOML file,
+                              e.g. '\"+\" = \"moo\"' for each of the eight commands
+  --opt-level=N               Optimize the instruction stream before running it (0-4, default: 0);
+                              2 also removes loops that can never be entered, 3 also runs the
+                              start of the program that reads no input ahead of time, 4 also
+                              addresses cells by offset instead of moving the pointer
+  --jit                       Compile the program to machine code before running it, when
+                              built with the 'jit' feature and no option needs single steps
+  --fix                       Balance unmatched brackets in the file before running it
+  --trace                     Log every executed instruction to stderr
+  --trace-file=FILE           Write the trace to FILE instead
+  --trace-format=text|json    Trace format (default: text)
+  --stats                     Print execution statistics to stderr
+  --stats-format=text|json    Statistics format (default: text); json is one line following
+                              a versioned schema, see the 'report' module
+  --dump-tape[=N]             Print the first N cells of the tape, or all it grew to, to stderr
+                              once the program halts, with the pointer in brackets
+  --timings                   Print the time spent lexing, parsing, lowering, optimizing,
+                              compiling and running to stderr
+  --num-format=dec|hex|bin    Base of numbers in statistics and profiles (default: dec)
+  --num-width=N               Pad those numbers with zeros to at least N digits
+  --num-separator=C           Group their digits with C, e.g. '_' (default: none)
+  --max-memory=SIZE           Abort when the interpreter uses more than SIZE bytes
+  --max-steps=N               Abort after executing N instructions
+  --max-loop-iterations=N     Abort when any one loop goes around more than N times without
+                              being left, pointing at that loop; --debug stops there instead
+  --timeout=DURATION          Abort once the program has run for DURATION, e.g. 500ms, 5s or 2m
+  --strict-tape               Fail when the program moves left of cell 0, instead of adding
+                              cells there
+  --tape=vec|mmap|sparse      Where the cells are kept (default: vec); mmap reserves address
+                              space that memory is only taken from as the tape reaches it, for
+                              huge tapes, and needs the 'mmap' feature; sparse only keeps the
+                              regions written to, for programs that jump far
+  --exit-from-cell            Exit with the value of the cell under the pointer once the program
+                              finishes, 0 to 255, so that it can be tested with && and ||; a
+                              failed run still exits with 1, indistinguishable from a cell of 1
+  --eof=fail|unchanged        What ',' does once the input is exhausted: fail (default) or leave
+                              the cell unchanged
+  --raw-tty                   Give ',' every key as it is pressed, without waiting for Enter or
+                              echoing it, when stdin is a terminal; input is read byte by byte
+  --prompt=TEXT               Show TEXT on stderr when ',' waits for input typed at a terminal
+                              (default: a dimmed '> '); input from a pipe or file is read silently
+  --no-prompt                 Show no prompt when ',' waits for input
+  --preset=dbfi               Run the program the way classic self-interpreters expect: input
+                              follows a '!' in the file, or comes from stdin, and is read byte
+                              by byte, and ',' leaves the cell unchanged once it is exhausted
+  --lenient                   Ignore a ']' that closes no loop instead of failing
+  --ext=include               Preprocess the source: a line '@include \"FILE\"' is replaced by
+                              FILE, and '@define NAME COMMANDS' defines a macro used as '@NAME'
+  --max-nesting=N             Refuse programs with loops nested more than N deep
+  --max-program-size=N        Refuse programs with more than N commands
+  --profile                   Report the hottest loops to stderr
+  --coverage                  Print the source to stderr with how often each line ran, marking
+                              commands that never did; exact without --opt-level
+  --coverage-file=FILE        Write the coverage report to FILE instead
+  --coverage-format=annotated|lcov
+                              Coverage format (default: annotated)
+  --heat-map=FILE             Write the reads, writes and largest value of every cell to FILE
+                              as CSV, with the names given to cells by ';@name' directives
+  --profile-top=N             Number of loops to report (default: 10)
+  --hints                     Suggest likely causes when a run prints nothing or hits a limit
+  --debug                     Step through the program interactively, forwards and backwards
+  --history=N                 Instructions the debugger can step back (default: 10000)
+  --tui                       Watch the tape, source and output while the program runs
+  --nested                    Run the program inside a Brainfuck self-interpreter (dbfi); input
+                              follows a '!' in the file, or is read from stdin up to EOF
+  --newline=lf|crlf|cr        Line ending the program uses, translated to and from \\n (default: lf)
+  --audit-log=FILE            Append a JSON record of the run, or with serve of every run, to FILE
+  --output-rate=BYTES         Limit output to BYTES per second
+  --output-rate-policy=throttle|abort
+                              What to do when the rate is exceeded (default: throttle)
+  --checkpoint-every=N        Save the machine state every N instructions
+  --checkpoint-file=FILE      Where to save it (default: <file>.checkpoint)
+  --metric=NAME=EXPR          Track cell(N), ptr or output as NAME and report its range; N may
+                              be a cell named in the source with ';@name N NAME'
+  --metrics-every=N           Sample metrics every N instructions (default: 100000)
+  --metrics-file=FILE         Append every sample to FILE as a line of JSON
+  --resume=FILE               Continue from a saved state instead of the start
+  --record-input=FILE         Save everything the program reads to FILE
+  --replay-input=FILE         Read input from a recording instead of stdin
+  --input-file=FILE           Read input from FILE instead of stdin, the same as --replay-input
+  --input=TEXT                Read input from TEXT instead of stdin, e.g. $'a\\n3\\n'
+  -v, --verbose               Log how the program is lexed, parsed, optimized and run to stderr;
+                              -vv also logs every loop the optimizer rewrites; works with
+                              every command
+  --plugin=FILE               Load a plugin, which can add dialects, passes and sinks; works
+                              with every command and needs the 'plugins' feature
+  --pass=NAME                 Run a plugin's optimizer pass on the program, after --opt-level
+  --output-sink=NAME[:ARG]    Write output to a plugin's sink instead of stdout
+  --output-file=FILE          Write output to FILE instead of stdout
+  --tee                       With --output-file, also write the output to stdout
+  --output-mode=raw|utf8-lossy|decimal|hex
+                              Write each output byte as it is (default), as the character with
+                              that code in UTF-8, or as a decimal or two-digit hex number
+  --output-separator=newline|space
+                              Put those numbers on lines of their own (default) or on one line
+  --flush=always|line|on-input|at-exit
+                              When output is written out: after every byte, after every line, only
+                              before ',' waits for input, or only at the end (default: line for a
+                              terminal, on-input otherwise)
+  --export-derivation=FILE    Write the steps of the run as a small-step operational semantics
+                              derivation, in LaTeX for a .tex file and Markdown otherwise
+  --derivation-steps=N        Steps to derive at most (default: 200)
+
+Defaults for the options of a run can be set in ~/.config/bf/config.toml, or the file named
+by $BF_CONFIG, with a line like 'opt_level = 3' or 'strict_tape = true' for each, and in
+environment variables like BF_OPT_LEVEL=3, which take precedence over the file. The options
+given override both. Those that can be set are --dialect, --opt-level, --jit, --lenient,
+--max-memory, --max-steps, --max-loop-iterations, --timeout, --tape, --strict-tape, --eof,
+--exit-from-cell, --preset, --newline, --prompt, --no-prompt, --output-mode,
+--output-separator, --flush and --num-format. A switch among them that a default turns on is
+turned off again by giving it as false, e.g. --strict-tape=false, and any other of them goes
+back to its built-in default when given an empty value or none, e.g. --max-steps=none.";
+
+/// Parses `args` after the defaults from the config file and environment,
+/// which they override.
+fn parse_args_or_exit(args: &[String]) -> Options {
+    let mut all = match defaults::args() {
+        Ok(defaults) => defaults,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    all.extend_from_slice(args);
+    match parse_args(&all) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// The arguments of a subcommand, split by `parse_command`.
+struct CommandArgs {
+    /// The arguments that are not options, in order.
+    operands: Vec<String>,
+    /// `--opt-level`, if given; commands differ in the level they default to.
+    opt_level: Option<OptLevel>,
+    /// `--dialect` or `--dialect-map`, Brainfuck if neither is given.
+    dialect: Dialect,
+    max_steps: Option<u64>,
+}
+
+impl CommandArgs {
+    /// The only operand, or else the usage and an exit.
+    fn operand(&self) -> String {
+        match self.operands.as_slice() {
+            [operand] => operand.clone(),
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+}
+
+/// Splits the arguments of a subcommand into options and operands, exiting
+/// with an error on a bad one. An option takes a value, after `=` or as the
+/// next argument, unless it is one of `switches`, which take none. Those of
+/// `--opt-level`, `--dialect` (with `--dialect-map`) and `--max-steps` that
+/// are in `shared` are parsed here, and every other option is handed to
+/// `option` with its value, `None` for a switch.
+fn parse_command<F>(args: &[String], shared: &[&str], switches: &[&str], mut option: F) -> CommandArgs
+    where F: FnMut(&str, Option<String>) -> Result<(), String>
+{
+    let mut parsed = CommandArgs {
+        operands: Vec::new(),
+        opt_level: None,
+        dialect: Dialect::Brainfuck,
+        max_steps: None,
+    };
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let (name, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let shares = |name: &str| shared.contains(&name);
+        let result = if !name.starts_with('-') {
+            parsed.operands.push(arg.clone());
+            Ok(())
+        } else if switches.contains(&name) {
+            match inline {
+                Some(_) => Err(format!("Option '{}' takes no value.", name)),
+                None => option(name, None),
+            }
+        } else {
+            match inline.or_else(|| it.next().cloned()) {
+                None => Err(format!("Option '{}' requires a value.", name)),
+                Some(value) => match name {
+                    "--opt-level" if shares(name) => parse_opt_level(&value).map(|level| parsed.opt_level = Some(level)),
+                    "--dialect" if shares(name) => parse_dialect(&value).map(|d| parsed.dialect = d),
+                    "--dialect-map" if shares("--dialect") => load_dialect_map(&value).map(|d| parsed.dialect = d),
+                    "--max-steps" if shares(name) => value.parse()
+                        .map(|n| parsed.max_steps = Some(n))
+                        .map_err(|_| format!("Invalid count '{}'.", value)),
+                    _ => option(name, Some(value)),
+                },
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+    parsed
+}
+
+fn examples_command(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("list") => {
+            for example in examples::EXAMPLES {
+                println!("{:<12} {}", example.name, example.description);
+            }
+        }
+        Some("run") => {
+            // The example name takes the place of the file name.
+            let options = parse_args_or_exit(&args[1..]);
+            match examples::find(&options.filename) {
+                Some(example) => run_source(example.source, &options, None),
+                None => {
+                    eprintln!("Unknown example '{}'.", options.filename);
+                    std::process::exit(2);
+                }
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn tutorial_command() {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines().map_while(Result::ok);
+
+    let count = tutorial::EXERCISES.len();
+    for (i, exercise) in tutorial::EXERCISES.iter().enumerate() {
+        println!();
+        println!("Exercise {}/{}: {}", i + 1, count, exercise.title);
+        println!("{}", exercise.task);
+
+        loop {
+            println!();
+            println!("Enter your program followed by an empty line, or one of: hint, skip, quit.");
+
+            let mut source = String::new();
+            let mut eof = true;
+            for line in lines.by_ref() {
+                eof = false;
+                if line.trim().is_empty() {
+                    break;
+                }
+                source.push_str(&line);
+                source.push('\n');
+            }
+
+            match source.trim() {
+                _ if eof => return,
+                "" => continue,
+                "quit" => return,
+                "skip" => break,
+                "hint" => {
+                    println!("Hint: {}", exercise.hint);
+                    continue;
+                }
+                _ => (),
+            }
+
+            match tutorial::check(exercise, &source) {
+                Ok(()) => {
+                    println!("Correct!");
+                    break;
+                }
+                Err(tutorial::Failure::Error(e)) => println!("Your program failed: {}", e),
+                Err(tutorial::Failure::WrongOutput { input, expected, actual }) => {
+                    println!("Not quite. With input {:?} the output should be {:?}, but was {:?}.",
+                             input, expected, actual);
+                }
+            }
+        }
+    }
+
+    println!();
+    println!("That was the last exercise. Well done!");
+}
+
+const DEBUG_HELP: &str = "\
+step [N]      (s)   execute N instructions (default 1)
+back [N]      (b)   undo N instructions (default 1)
+continue      (c)   run until the program ends
+watch CELL    (w)   run until CELL, an index or a name, changes
+rwatch CELL   (rw)  step back to just before CELL last changed
+print         (p)   show the current instruction and the tape around the pointer
+save FILE           write the tape, pointer and history to FILE to continue later
+restore FILE        continue a session written with 'save'
+quit          (q)   stop debugging
+An empty line repeats the previous command.";
+
+/// The instruction at the program counter and the commands of `source` it
+/// was generated from, which optimized instructions can have several of.
+fn position(state: &State, source: &str) -> String {
+    let instruction = match state.program().instructions.get(state.pc()) {
+        Some(instruction) => instruction,
+        None => return format!("pc {}: end of program", state.pc()),
+    };
+    match state.program().span_of(state.pc()) {
+        // Programs loaded from bytecode come without their source.
+        Some(ref span) if span.end <= source.len() => {
+            let (line, column) = line_col(source, span.start);
+            let (end_line, end_column) = line_col(source, span.end - 1);
+            if (line, column) == (end_line, end_column) {
+                format!("pc {} ({}:{}): {}", state.pc(), line, column, instruction)
+            } else {
+                format!("pc {} ({}:{}-{}:{}): {}", state.pc(), line, column, end_line, end_column, instruction)
+            }
+        }
+        _ => format!("pc {}: {}", state.pc(), instruction),
+    }
+}
+
+fn print_position(state: &State, source: &str, names: &CellNames) {
+    eprintln!("{}", position(state, source));
+
+    let tape = state.tape();
+    let start = state.pointer().saturating_sub(8);
+    let end = (state.pointer() + 9).min(tape.len());
+    let cells: Vec<String> = (start..end)
+        .map(|i| if i == state.pointer() {
+            format!("[{}]", tape.get(i))
+        } else {
+            tape.get(i).to_string()
+        })
+        .collect();
+    let pointer = match names.name(state.pointer()) {
+        Some(name) => format!("{} ({})", state.pointer(), name),
+        None => state.pointer().to_string(),
+    };
+    eprintln!("ptr {}: {}{}{}",
+              pointer,
+              if start > 0 { "... " } else { "" },
+              cells.join(" "),
+              if end < tape.len() { " ..." } else { "" });
+    if !names.is_empty() {
+        let named: Vec<String> = names.iter()
+            .map(|(cell, name)| format!("{}={}", name, tape.get(cell)))
+            .collect();
+        eprintln!("named: {}", named.join(" "));
+    }
+}
+
+/// Steps forward while `keep_going` holds. Returns `false` once the program
+/// has finished.
+fn debug_forward<F>(debugger: &mut Debugger, state: &mut State, mut keep_going: F) -> Result<bool, String>
+    where F: FnMut(&State) -> bool
+{
+    while keep_going(state) {
+        if !debugger.step(state)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn debug_session(state: &mut State, source: &str, names: &CellNames, history: usize) -> Result<(), String> {
+    let mut debugger = Debugger::new(history);
+    let mut result = Ok(());
+    let mut previous = String::new();
+
+    eprintln!("Type 'help' for a list of commands.");
+    print_position(state, source, names);
+
+    loop {
+        eprint!("(bf) ");
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        if line.trim().is_empty() {
+            line = previous.clone();
+        }
+        previous = line.clone();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = words.first().cloned().unwrap_or("");
+        let takes_cell = matches!(command, "watch" | "w" | "rwatch" | "rw");
+        let takes_path = matches!(command, "save" | "restore");
+        let argument = match words.get(1) {
+            _ if takes_path => None,
+            Some(word) if takes_cell => match names.resolve(word) {
+                Ok(cell) => Some(cell),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            },
+            Some(word) => match word.parse::<usize>() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    eprintln!("'{}' is not a number.", word);
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let outcome = match command {
+            "step" | "s" => {
+                let mut remaining = argument.unwrap_or(1);
+                debug_forward(&mut debugger, state, |_| {
+                    let more = remaining > 0;
+                    remaining = remaining.saturating_sub(1);
+                    more
+                })
+            }
+            "continue" | "c" => debug_forward(&mut debugger, state, |_| true),
+            "watch" | "w" | "rwatch" | "rw" if argument.is_none() => {
+                eprintln!("'{}' needs a cell index.", command);
+                continue;
+            }
+            "watch" | "w" => {
+                let cell = argument.unwrap_or(0);
+                let value = state.tape().get(cell);
+                debug_forward(&mut debugger, state, |s| s.tape().get(cell) == value)
+            }
+            "back" | "b" | "rwatch" | "rw" => {
+                let cell = argument.unwrap_or(0);
+                let value = state.tape().get(cell);
+                let mut remaining = argument.unwrap_or(1);
+                let watching = command.starts_with('r');
+                loop {
+                    if !debugger.back(state) {
+                        eprintln!("No more history.");
+                        break;
+                    }
+                    remaining = remaining.saturating_sub(1);
+                    if watching && state.tape().get(cell) != value || !watching && remaining == 0 {
+                        break;
+                    }
+                }
+                Ok(true)
+            }
+            "print" | "p" => {
+                print_position(state, source, names);
+                continue;
+            }
+            "save" | "restore" if words.len() != 2 => {
+                eprintln!("'{}' needs a file name.", command);
+                continue;
+            }
+            "save" => {
+                match debugger.save_session(state, words[1]) {
+                    Ok(()) => eprintln!("Saved the session to '{}'.", words[1]),
+                    Err(e) => eprintln!("{}", e),
+                }
+                continue;
+            }
+            "restore" => match debugger.restore_session(state, words[1]) {
+                Ok(()) => Ok(state.pc() < state.program().instructions.len()),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            },
+            "help" | "h" => {
+                eprintln!("{}", DEBUG_HELP);
+                continue;
+            }
+            "quit" | "q" => break,
+            _ => {
+                eprintln!("Unknown command '{}'. Type 'help' for a list of commands.", command);
+                continue;
+            }
+        };
+
+        match outcome {
+            Ok(running) => {
+                if !running {
+                    eprintln!("Program finished.");
+                }
+                result = Ok(());
+            }
+            Err(e) => {
+                eprintln!("Program failed: {}", e);
+                result = Err(e);
+            }
+        }
+        print_position(state, source, names);
+    }
+
+    let flushed = state.flush();
+    result.and(flushed)
+}
+
+fn split_command(args: &[String]) {
+    let mut max_len = None;
+    let mut out_dir = None;
+
+    let parsed = parse_command(args, &[], &[], |name, value| match (name, value) {
+        ("--max-len", Some(value)) => match value.parse::<usize>() {
+            Ok(n) if n > 0 => {
+                max_len = Some(n);
+                Ok(())
+            }
+            _ => Err(format!("Invalid length '{}'.", value)),
+        },
+        ("--out-dir", value) => {
+            out_dir = value;
+            Ok(())
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let max_len = max_len.unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    });
+
+    let contents = read_or_exit(&filename);
+    if let Err(e) = parse(&contents) {
+        eprintln!("{}", e);
+        print_repairs(&repair::suggest(&contents), &contents);
+        std::process::exit(1);
+    }
+
+    let path = std::path::Path::new(&filename);
+    let stem = path.file_stem().map_or("program".into(), |s| s.to_string_lossy());
+    let dir = out_dir.map_or_else(|| path.parent().unwrap_or_else(|| std::path::Path::new("")).to_owned(),
+                                  std::path::PathBuf::from);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("Could not create '{}': {}", dir.display(), e);
+        std::process::exit(1);
+    }
+
+    let chunks = split::split(&contents, max_len);
+    let mut manifest = format!("{}\n", split::MANIFEST_HEADER);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let name = format!("{}.{:03}.b", stem, i + 1);
+        if chunk.len() > max_len {
+            eprintln!("warning: {} is {} bytes long, since it holds a single loop", name, chunk.len());
+        }
+        write_or_exit(dir.join(&name), chunk);
+        manifest.push_str(&name);
+        manifest.push('\n');
+    }
+
+    let manifest_path = dir.join(format!("{}.manifest", stem));
+    write_or_exit(&manifest_path, &manifest);
+    eprintln!("Wrote {} chunks and {}.", chunks.len(), manifest_path.display());
+}
+
+fn compile_command(args: &[String]) {
+    let mut target = None;
+    let mut bundle = None;
+    let mut out = None;
+    let mut tape_len = codegen::DEFAULT_TAPE_LEN;
+    let mut print_timings = false;
+    let mut codegen_options = Vec::new();
+    let mut verify = false;
+    let mut verify_input = None;
+
+    let shared = ["--opt-level", "--dialect"];
+    let parsed = parse_command(args, &shared, &["--timings", "--verify"], |name, value| match (name, value) {
+        ("--timings", None) => {
+            print_timings = true;
+            Ok(())
+        }
+        ("--verify", None) => {
+            verify = true;
+            Ok(())
+        }
+        ("--target", Some(value)) => codegen::Target::from_name(&value)
+            .map(|t| target = Some(t))
+            .ok_or_else(|| format!("Unknown target '{}'.", value)),
+        ("--bundle", value) => {
+            bundle = value;
+            Ok(())
+        }
+        ("--tape-len", Some(value)) => match value.parse::<usize>() {
+            Ok(n) if n > 0 && n <= i32::MAX as usize => {
+                tape_len = n;
+                Ok(())
+            }
+            _ => Err(format!("Invalid tape length '{}'.", value)),
+        },
+        // Applied once the target is known, which may come later.
+        ("--codegen", Some(value)) => {
+            codegen_options.push(value);
+            Ok(())
+        }
+        ("--verify-input", value) => {
+            verify_input = value;
+            Ok(())
+        }
+        ("-o", value) | ("--output", value) => {
+            out = value;
+            Ok(())
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::MAX);
+    let dialect = parsed.dialect;
+
+    if bundle.is_some() && (target.is_some() || out.is_some()) {
+        eprintln!("--bundle writes both C and Rust into its directory, so it takes no --target or -o.");
+        std::process::exit(2);
+    }
+    let target = target.unwrap_or(codegen::Target::C);
+    let targets = match bundle {
+        Some(_) => vec![codegen::Target::C, codegen::Target::Rust],
+        None => vec![target],
+    };
+
+    let mut options = codegen::CodegenOptions { opt_level, tape_len, ..Default::default() };
+    for option in &codegen_options {
+        if let Err(e) = targets.iter().try_for_each(|&target| options.set(target, option)) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    let contents = read_or_exit(&filename);
+    let mut timings = Timings::default();
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut timings);
+
+    let program = timings.measure("lower", || ir::lower(&tree));
+    let program = timings.measure("optimize", || ir::optimize(&program, opt_level));
+    if let Some(dir) = bundle {
+        let stem = std::path::Path::new(&filename).file_stem().map(|s| s.to_string_lossy().into_owned());
+        let name = bundle::package_name(&stem.unwrap_or_default());
+        let files = match timings.measure("codegen", || bundle::files(&program, &name, &options)) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = write_bundle(&dir, &files) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        if print_timings {
+            timings.print();
+        }
+        if verify {
+            let input = match verify_input {
+                Some(path) => read_bytes_or_exit(&path),
+                None => Vec::new(),
+            };
+            for target in targets {
+                verify_or_exit(&program, target, &options, &input);
+            }
+        }
+        return;
+    }
+    let code = match timings.measure("codegen", || codegen::generate(&program, target, &options)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match out {
+        Some(path) => write_or_exit(&path, code),
+        None => {
+            if let Err(e) = std::io::stdout().write_all(&code) {
+                eprintln!("Could not write output: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if print_timings {
+        timings.print();
+    }
+
+    if verify {
+        let input = match verify_input {
+            Some(path) => read_bytes_or_exit(&path),
+            None => Vec::new(),
+        };
+        verify_or_exit(&program, target, &options, &input);
+    }
+}
+
+/// Writes the files of a bundle into `dir`, creating directories as needed.
+fn write_bundle(dir: &str, files: &[(String, String)]) -> Result<(), String> {
+    for (path, contents) in files {
+        let path = std::path::Path::new(dir).join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Could not create '{}': {}", parent.display(), e))?;
+        }
+        std::fs::write(&path, contents).map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Compares the generated code with the interpreter, exiting with 1 if
+/// they differ.
+fn verify_or_exit(program: &Program, target: codegen::Target, options: &codegen::CodegenOptions, input: &[u8]) {
+    match codegen::verify::verify(program, target, options, input) {
+        Ok(Verdict::Match) => eprintln!("Verified: the {} target prints the same as the interpreter.", target.name()),
+        Ok(Verdict::Unavailable(why)) => eprintln!("Skipped verification: {}.", why),
+        Ok(Verdict::Mismatch { expected, actual }) => {
+            eprintln!("Verification failed: the {} target differs from the interpreter.", target.name());
+            if expected.success != actual.success {
+                eprintln!("The interpreter {} but the generated code {}.",
+                          if expected.success { "succeeded" } else { "failed" },
+                          if actual.success { "succeeded" } else { "failed" });
+            }
+            let common = expected.output.iter().zip(&actual.output).take_while(|&(a, b)| a == b).count();
+            if common < expected.output.len().max(actual.output.len()) {
+                eprintln!("Output differs at byte {}: the interpreter printed {} bytes, the generated code {}.",
+                          common,
+                          expected.output.len(),
+                          actual.output.len());
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn disasm_command(args: &[String]) {
+    let filename = match args {
+        [filename] => filename,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let bytes = read_bytes_or_exit(filename);
+    if !bytecode::is_bytecode(&bytes) {
+        eprintln!("'{}' is not a bytecode file, see 'compile --target=bytecode'.", filename);
+        std::process::exit(1);
+    }
+    match bytecode::decode(&bytes) {
+        Ok(loaded) => print!("{}", bytecode::disassemble(&loaded)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints `loops` one per line, indented by nesting, with loops deeper than
+/// `max_depth` collapsed into a count.
+fn print_outline(loops: &[outline::Loop], source: &str, max_depth: Option<usize>) {
+    // The loops still to be printed at every depth, innermost last.
+    let mut open = vec![loops.iter()];
+    loop {
+        let depth = match open.len() {
+            0 => return,
+            n => n - 1,
+        };
+        let l = match open[depth].next() {
+            Some(l) => l,
+            None => {
+                open.pop();
+                continue;
+            }
+        };
+        let (line, column) = line_col(source, l.start);
+        let (end_line, end_column) = line_col(source, l.end);
+        let net = match l.net_move {
+            Some(n) if n > 0 => format!("+{}", n),
+            Some(n) => n.to_string(),
+            None => "?".to_owned(),
+        };
+        let entry = format!("{:indent$}[ {:<16} net {:<4} {}",
+                            "",
+                            format!("{}:{}-{}:{}", line, column, end_line, end_column),
+                            net,
+                            l.idiom.unwrap_or(""),
+                            indent = depth * 2);
+        println!("{}", entry.trim_end());
+
+        if l.body.is_empty() {
+            continue;
+        }
+        if max_depth.is_some_and(|max| depth + 1 > max) {
+            let count: usize = l.body.iter().map(outline::Loop::count).sum();
+            let plural = if count == 1 { "" } else { "s" };
+            println!("{:indent$}... {} nested loop{}", "", count, plural, indent = (depth + 1) * 2);
+        } else {
+            open.push(l.body.iter());
+        }
+    }
+}
+
+fn outline_command(args: &[String]) {
+    let mut max_depth = None;
+
+    let parsed = parse_command(args, &["--dialect"], &[], |name, value| match (name, value) {
+        ("--depth", Some(value)) => value.parse::<usize>()
+            .map(|n| max_depth = Some(n))
+            .map_err(|_| format!("Invalid count '{}'.", value)),
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let dialect = parsed.dialect;
+
+    let contents = read_or_exit(&filename);
+    // Parsed only to report unbalanced brackets, which the outline assumes.
+    parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    let tokens = dialect.lex(&contents).expect("Could not lex a program that parsed.");
+    print_outline(&outline::outline(&tokens), &contents, max_depth);
+}
+
+fn analyze_command(args: &[String]) {
+    let parsed = parse_command(args, &["--dialect"], &[], |name, _| Err(format!("Unknown option '{}'.", name)));
+    let filename = parsed.operand();
+    let dialect = parsed.dialect;
+
+    let contents = read_or_exit(&filename);
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    // Level 2 leaves out the loops that are never entered, which would only
+    // add noise, without running any of the program.
+    let program = ir::optimize(&ir::lower(&tree), OptLevel::new(2).expect("Level 2 exists."));
+    let found = analysis::analyze(&program);
+    let at = |pc: usize| {
+        let (line, column) = line_col(&contents, program.offsets[pc]);
+        format!("{}:{}", line, column)
+    };
+
+    println!("reads input: {}", if found.reads_input { "yes" } else { "no" });
+    match found.pointer_range {
+        Some(ref range) => println!("pointer range: cells {} to {}", range.start(), range.end()),
+        None => println!("pointer range: unknown"),
+    }
+    match found.unread_cells {
+        Some(ref cells) if cells.is_empty() => println!("cells written but never read: none"),
+        Some(ref cells) => {
+            let cells: Vec<String> = cells.iter().map(isize::to_string).collect();
+            println!("cells written but never read: {}", cells.join(", "));
+        }
+        None => println!("cells written but never read: unknown"),
+    }
+    if let Some(pc) = found.unknown_from {
+        println!("note: the loop at {} moves the pointer by an amount that depends on the data, so where it \
+                  points after that is unknown",
+                 at(pc));
+    }
+    for &pc in &found.endless_loops {
+        println!("warning: the loop at {} never ends once entered, it returns to a cell that is still nonzero",
+                 at(pc));
+    }
+}
+
+fn translate_command(args: &[String]) {
+    let mut from = Dialect::Brainfuck;
+    let mut to = Dialect::Brainfuck;
+    let mut out = None;
+
+    let parsed = parse_command(args, &[], &[], |name, value| match (name, value) {
+        ("--from", Some(value)) => parse_dialect(&value).map(|d| from = d),
+        ("--from-map", Some(value)) => load_dialect_map(&value).map(|d| from = d),
+        ("--to", Some(value)) => parse_dialect(&value).map(|d| to = d),
+        ("--to-map", Some(value)) => load_dialect_map(&value).map(|d| to = d),
+        ("-o", value) | ("--output", value) => {
+            out = value;
+            Ok(())
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+
+    let contents = read_or_exit(&filename);
+    let tokens: Vec<_> = match from.lex(&contents) {
+        Ok(tokens) => tokens.into_iter().map(|(_, token)| token).collect(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let translated = match to.spell(&tokens) {
+        Ok(translated) => translated,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match out {
+        Some(path) => write_or_exit(&path, translated),
+        None => print!("{}", translated),
+    }
+}
+
+fn generate_text_command(args: &[String]) {
+    let mut out = None;
+
+    let parsed = parse_command(args, &["--dialect"], &[], |name, value| match (name, value) {
+        ("-o", value) | ("--output", value) => {
+            out = value;
+            Ok(())
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let text = parsed.operand();
+
+    let program = parsed.dialect.spell(&textgen::generate(text.as_bytes())).expect("Could not spell a generated program.");
+    match out {
+        Some(path) => write_or_exit(&path, program),
+        None => print!("{}", program),
+    }
+}
+
+fn minimize_input_command(args: &[String]) {
+    let mut keep = None;
+    let mut prefix = None;
+    let mut max_memory = None;
+    let mut out = None;
+
+    let shared = ["--dialect", "--max-steps"];
+    let parsed = parse_command(args, &shared, &[], |name, value| match (name, value) {
+        ("--keep", Some(value)) => match value.as_str() {
+            "output" | "error" | "limit" | "coverage" => {
+                keep = Some(value);
+                Ok(())
+            }
+            _ => Err(format!("Unknown property '{}'.", value)),
+        },
+        ("--prefix", Some(value)) => value.parse::<usize>()
+            .map(|n| prefix = Some(n))
+            .map_err(|_| format!("Invalid count '{}'.", value)),
+        ("--max-memory", Some(value)) => parse_size(&value).map(|size| max_memory = Some(size)),
+        ("-o", value) | ("--output", value) => {
+            out = value;
+            Ok(())
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let limits = minimize::Limits { max_steps: parsed.max_steps.unwrap_or(10_000_000), max_memory };
+    let dialect = parsed.dialect;
+
+    let (filename, input_file) = match parsed.operands.as_slice() {
+        [filename, input_file] => (filename, input_file),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let contents = read_or_exit(filename);
+    let input = read_bytes_or_exit(input_file);
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    let program = ir::lower(&tree);
+
+    let original = minimize::observe(&program, &input, limits);
+    // Without --keep, keep whatever went wrong, or else the output.
+    let keep = keep.unwrap_or_else(|| match original {
+        minimize::Observation { limit_hit: true, .. } => "limit",
+        minimize::Observation { error: Some(_), .. } => "error",
+        _ => "output",
+    }.to_owned());
+    let property = match keep.as_str() {
+        "output" => {
+            let len = prefix.unwrap_or(original.output.len()).min(original.output.len());
+            minimize::Property::OutputPrefix(original.output[..len].to_vec())
+        }
+        "error" => match original.error {
+            Some(ref error) => minimize::Property::Error(error.clone()),
+            None => {
+                eprintln!("The program does not fail on '{}'.", input_file);
+                std::process::exit(1);
+            }
+        },
+        "limit" if !original.limit_hit => {
+            eprintln!("The program does not hit a limit on '{}'.", input_file);
+            std::process::exit(1);
+        }
+        "limit" => minimize::Property::Limit,
+        _ => minimize::Property::Coverage(original.coverage.clone()),
+    };
+
+    let minimized = minimize::minimize(&program, &input, &property, limits);
+    eprintln!("Kept the {} with {} of {} input bytes.", keep, minimized.len(), input.len());
+    match out {
+        Some(path) => write_or_exit(&path, minimized),
+        None => {
+            if let Err(e) = std::io::stdout().write_all(&minimized) {
+                eprintln!("Could not write output: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn fmt_command(args: &[String]) {
+    let mut minify = false;
+    let mut in_place = false;
+    let mut width = formatter::DEFAULT_WIDTH;
+    let mut out = None;
+
+    let parsed = parse_command(args, &[], &["--minify", "--in-place"], |name, value| match (name, value) {
+        ("--minify", None) => {
+            minify = true;
+            Ok(())
+        }
+        ("--in-place", None) => {
+            in_place = true;
+            Ok(())
+        }
+        ("--width", Some(value)) => match value.parse::<usize>() {
+            Ok(n) if n > 0 => {
+                width = n;
+                Ok(())
+            }
+            _ => Err(format!("Invalid width '{}'.", value)),
+        },
+        ("-o", value) | ("--output", value) => {
+            out = value;
+            Ok(())
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    if in_place {
+        out = Some(filename.clone());
+    }
+
+    let contents = read_or_exit(&filename);
+    let formatted = if minify {
+        formatter::minify(&contents)
+    } else {
+        match formatter::pretty(&contents, width) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}", e);
+                print_repairs(&repair::suggest(&contents), &contents);
+                std::process::exit(1);
+            }
+        }
+    };
+    match out {
+        Some(path) => write_or_exit(&path, formatted),
+        None => print!("{}", formatted),
+    }
+}
+
+fn bench_command(args: &[String]) {
+    let mut iterations = 10;
+    let mut compare = None;
+    let mut input = Vec::new();
+    let mut use_jit = false;
+    let mut backend = tape::Backend::default();
+
+    let shared = ["--opt-level", "--dialect"];
+    let parsed = parse_command(args, &shared, &["--jit"], |name, value| match (name, value) {
+        ("--jit", None) => {
+            use_jit = true;
+            Ok(())
+        }
+        ("--iterations", Some(value)) => match value.parse::<usize>() {
+            Ok(n) if n > 0 => {
+                iterations = n;
+                Ok(())
+            }
+            _ => Err(format!("Invalid count '{}'.", value)),
+        },
+        ("--compare", Some(value)) => parse_opt_level(&value).map(|level| compare = Some(level)),
+        ("--input-file", Some(value)) => std::fs::read(&value)
+            .map(|bytes| input = bytes)
+            .map_err(|e| format!("Could not read '{}': {}", value, e)),
+        ("--tape", Some(value)) => value.parse().map(|b| backend = b),
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let dialect = parsed.dialect;
+    let mut levels = vec![parsed.opt_level.unwrap_or(OptLevel::NONE)];
+    levels.extend(compare);
+
+    let contents = read_or_exit(&filename);
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    let program = ir::lower(&tree);
+
+    let mut results = Vec::new();
+    for &level in &levels {
+        let optimized = ir::optimize(&program, level);
+        let measured = if use_jit {
+            bench_jit(&optimized, &input, iterations, backend)
+        } else {
+            bench::measure(&optimized, &input, iterations, backend, run)
+        };
+        match measured {
+            Ok(measurement) => results.push((level, measurement)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    print_bench(&results, iterations);
+    if results.iter().any(|(_, m)| m.output != results[0].1.output) {
+        eprintln!("Warning: the optimization levels printed different output.");
+    }
+}
+
+/// Runs `bench::measure` on machine code compiled once up front.
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+fn bench_jit(program: &Program, input: &[u8], iterations: usize, tape: tape::Backend) -> Result<bench::Measurement, String> {
+    let compiled = jit::compile(program)?;
+    bench::measure(program, input, iterations, tape, |state| jit::run_compiled(&compiled, state))
+}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Token {
-    Increment,
-    Decrement,
-    ShiftLeft,
-    ShiftRight,
-    Input,
-    Output,
-    BeginLoop,
-    EndLoop,
+#[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+fn bench_jit(program: &Program, input: &[u8], iterations: usize, tape: tape::Backend) -> Result<bench::Measurement, String> {
+    bench::measure(program, input, iterations, tape, run)
 }
 
-#[derive(Clone, Debug)]
-pub enum SyntaxItem {
-    Single(Token),
-    Loop(Vec<SyntaxItem>),
+/// Prints a column of timings for each optimization level, and how much
+/// faster the second is than the first.
+fn print_bench(results: &[(OptLevel, bench::Measurement)], iterations: usize) {
+    let ms = |d: Duration| format!("{:.3} ms", d.as_secs_f64() * 1000.0);
+    let row = |label: &str, cell: &dyn Fn(&bench::Measurement) -> String| {
+        let cells: Vec<String> = results.iter().map(|(_, m)| format!("{:>16}", cell(m))).collect();
+        println!("{:<16}{}", label, cells.join(""));
+    };
+
+    let header: Vec<String> = results.iter().map(|&(level, _)| format!("{:>16}", format!("opt-level {}", level))).collect();
+    println!("{:<16}{}", format!("{} runs", iterations), header.join(""));
+    row("mean", &|m| ms(m.summary.mean));
+    row("median", &|m| ms(m.summary.median));
+    row("stddev", &|m| ms(m.summary.stddev));
+    row("min", &|m| ms(m.summary.min));
+    row("max", &|m| ms(m.summary.max));
+    row("instructions", &|m| m.instructions.to_string());
+    row("instructions/s", &|m| format!("{:.0}", m.instructions_per_second()));
+    if let [(_, ref first), (level, ref second)] = *results {
+        println!("opt-level {} is {:.2}x as fast (mean)",
+                 level,
+                 first.summary.mean.as_secs_f64() / second.summary.mean.as_secs_f64().max(1e-9));
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct State {
-    data: Vec<u8>,
-    pointer: usize,
+fn lib_command(args: &[String]) {
+    let parsed = parse_command(args, &["--dialect"], &[], |name, _| Err(format!("Unknown option '{}'.", name)));
+    let dialect = parsed.dialect;
+    let words: Vec<&str> = parsed.operands.iter().map(String::as_str).collect();
 
-    commands: Vec<SyntaxItem>,
+    let result = match *words.as_slice() {
+        ["add", name, filename] => library::check_name(name).and_then(|_| {
+            let contents = read_or_exit(filename);
+            let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+            library::add(name, &tree).map(|path| eprintln!("Added '{}' as {}, run it with 'bfinterpreter @{}'.", filename, path.display(), name))
+        }),
+        ["remove", name] => library::remove(name),
+        ["list"] => library::list().map(|names| {
+            for name in names {
+                println!("{}", name);
+            }
+        }),
+        ["path"] => library::dir().map(|dir| println!("{}", dir.display())),
+        ["path", name] => library::path(name).map(|path| println!("{}", path.display())),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 
-impl State {
-    pub fn new(commands: Vec<SyntaxItem>) -> Self {
-        let mut state = State {
-            data: Vec::new(),
-            pointer: 0,
+/// Runs programs like a shell pipeline, each reading what the one before it
+/// writes a byte at a time; the first reads stdin and the last writes to
+/// stdout.
+fn pipe_command(args: &[String]) {
+    let shared = ["--opt-level", "--dialect", "--max-steps"];
+    let parsed = parse_command(args, &shared, &[], |name, _| Err(format!("Unknown option '{}'.", name)));
+    let (filenames, dialect, max_steps) = (parsed.operands, parsed.dialect, parsed.max_steps);
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::NONE);
+    if filenames.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
 
-            commands: commands,
+    let states = filenames.iter().map(|filename| {
+        let path = match filename.strip_prefix('@') {
+            Some(name) => library::path(name).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+            None => std::path::PathBuf::from(filename),
         };
-        state.data.push(0);
+        let contents = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("Could not read '{}': {}", path.display(), e);
+            std::process::exit(1);
+        });
+        let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+        let mut state = State::new(ir::optimize(&ir::lower(&tree), opt_level));
+        state.max_steps = max_steps;
         state
+    });
+    let mut states: Vec<State> = states.collect();
+    states[0].use_stdio();
+    let mut last = pipeline::chain(states).expect("There is at least one program.");
+    last.output = Some(Box::new(Stdout::default()));
+
+    match run(&mut last) {
+        Ok(RunOutcome::OutputClosed) => std::process::exit(EXIT_OUTPUT_CLOSED),
+        Ok(_) => (),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn lex(input: &String) -> Vec<Token> {
-    input.chars()
-        .filter_map(|c| match c {
-            '+' => Some(Token::Increment),
-            '-' => Some(Token::Decrement),
-            '<' => Some(Token::ShiftLeft),
-            '>' => Some(Token::ShiftRight),
-            ',' => Some(Token::Input),
-            '.' => Some(Token::Output),
-            '[' => Some(Token::BeginLoop),
-            ']' => Some(Token::EndLoop),
-            _ => None,
-        })
-        .collect()
-}
-
-fn parse(input: &String) -> Result<Vec<SyntaxItem>, String> {
-    let tokens = lex(input);
-
-    let mut tree = Vec::new();
-    let mut it = tokens.iter().enumerate();
-    while let Some((i, token)) = it.next() {
-        match *token {
-            Token::BeginLoop => {
-                // Cut off already processed tokens.
-                let mut inner = &input[i + 1..];
-
-                let mut counter = 1;
-                let mut index = 0;
-                for c in inner.chars() {
-                    if c == '[' {
-                        counter = counter + 1;
-                    } else if c == ']' {
-                        counter = counter - 1;
-                    }
+/// Runs a language server for editors on stdin and stdout.
+fn lsp_command(args: &[String]) {
+    if let Some(arg) = args.first() {
+        eprintln!("Unexpected argument '{}'.", arg);
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    let stdin = std::io::stdin();
+    if let Err(e) = lsp::serve(stdin.lock(), std::io::stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-                    index += 1;
-                    if counter == 0 {
-                        break;
-                    }
-                }
+/// Runs programs sent over TCP: each connection sends a program ending with
+/// '!' and then its input, and receives the output as it is written.
+fn serve_command(args: &[String]) {
+    let mut host = "127.0.0.1".to_owned();
+    let mut port = 8080u16;
+    let mut limits = server::Limits::default();
+    let mut store = None;
+
+    let count = |value: &str| value.parse::<u64>().map_err(|_| format!("Invalid count '{}'.", value));
+    let shared = ["--opt-level", "--max-steps"];
+    let parsed = parse_command(args, &shared, &[], |name, value| match (name, value) {
+        ("--host", Some(value)) => {
+            host = value;
+            Ok(())
+        }
+        ("--port", Some(value)) => value.parse()
+            .map(|n| port = n)
+            .map_err(|_| format!("Invalid port '{}'.", value)),
+        ("--max-program", Some(value)) => parse_size(&value).map(|size| limits.program_size = size),
+        ("--max-memory", Some(value)) => parse_size(&value).map(|size| limits.max_memory = size),
+        ("--idle-timeout", Some(value)) => count(&value).map(|n| limits.idle_timeout = Duration::from_secs(n)),
+        ("--connections", Some(value)) => count(&value).map(|n| limits.connections = n as usize),
+        ("--audit-log", value) => {
+            limits.audit_log = value;
+            Ok(())
+        }
+        ("--store", Some(value)) => storage::open(&value).map(|opened| store = Some(opened)),
+        ("--output-rate", Some(value)) => parse_size(&value).map(|rate| limits.output_rate = Some(rate as u64)),
+        ("--output-rate-policy", Some(value)) => {
+            parse_rate_policy(&value).map(|policy| limits.output_rate_policy = policy)
+        }
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    if let Some(arg) = parsed.operands.first() {
+        eprintln!("Unexpected argument '{}'.", arg);
+        std::process::exit(2);
+    }
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::MAX);
+    limits.max_steps = parsed.max_steps.unwrap_or(limits.max_steps);
+
+    let listener = std::net::TcpListener::bind((host.as_str(), port)).unwrap_or_else(|e| {
+        eprintln!("Could not listen on {}:{}: {}", host, port, e);
+        std::process::exit(2);
+    });
+    eprintln!("Listening on {}:{}; send a program ending with '!', then its input.", host, port);
+    let report = |peer: &str, summary: &str| eprintln!("{}: {}", peer, summary);
+    if let Err(e) = server::serve(listener, limits, opt_level, store, report) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn test_command(args: &[String]) {
+    let shared = ["--opt-level", "--max-steps"];
+    let parsed = parse_command(args, &shared, &[], |name, _| Err(format!("Unknown option '{}'.", name)));
+    let dir = parsed.operand();
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::NONE);
+    let max_steps = parsed.max_steps.unwrap_or(100_000_000);
+    let cases = golden::discover(std::path::Path::new(&dir)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for case in &cases {
+        match golden::check(case, opt_level, max_steps) {
+            golden::Outcome::Pass => {
+                println!("PASS  {}", case.name);
+                passed += 1;
+            }
+            golden::Outcome::Fail(difference) => {
+                println!("FAIL  {}: {}", case.name, difference);
+                failed += 1;
+            }
+            golden::Outcome::Error(e) => {
+                println!("ERROR {}: {}", case.name, e);
+                failed += 1;
+            }
+            golden::Outcome::Skip => {
+                println!("SKIP  {}: no {}.out", case.name, case.name);
+                skipped += 1;
+            }
+        }
+    }
+    println!("\n{} passed, {} failed, {} skipped", passed, failed, skipped);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn verify_command(args: &[String]) {
+    let mut backends = (differential::Backend::Interpreter, differential::Backend::Optimized);
+    let mut input = Vec::new();
 
-                if counter != 0 {
-                    panic!("Unmatched parenthesis found.");
+    let shared = ["--opt-level", "--dialect"];
+    let parsed = parse_command(args, &shared, &[], |name, value| match (name, value) {
+        ("--backends", Some(value)) => {
+            let named: Option<Vec<_>> = value.split(',').map(differential::Backend::from_name).collect();
+            match named.as_deref() {
+                Some(&[a, b]) => {
+                    backends = (a, b);
+                    Ok(())
                 }
+                _ => Err(format!("Invalid backends '{}': give two of interp, opt, bytecode and jit.", value)),
+            }
+        }
+        ("--input-file", Some(value)) => std::fs::read(&value)
+            .map(|bytes| input = bytes)
+            .map_err(|e| format!("Could not read '{}': {}", value, e)),
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::MAX);
+    let dialect = parsed.dialect;
+    let contents = read_or_exit(&filename);
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    let program = ir::lower(&tree);
 
-                inner = &inner[..index];
+    let (a, b) = backends;
+    let runs = differential::run_on(a, &program, opt_level, &input)
+        .and_then(|first| differential::run_on(b, &program, opt_level, &input).map(|second| (first, second)));
+    let (first, second) = runs.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    match differential::divergence(&first, &second) {
+        None => println!("{} and {} agree: {} bytes of output, {} cells left non-zero.",
+                         a.name(),
+                         b.name(),
+                         first.output.len(),
+                         first.cells.len()),
+        Some(divergence) => {
+            println!("{} and {} diverge: {}.", a.name(), b.name(), divergence);
+            std::process::exit(1);
+        }
+    }
+}
 
-                // Parse inner tokens.
-                let item = parse(&inner.to_owned())
-                    .expect("Could not process inner structure of loop.");
+/// Runs two programs on the same input and reports the first byte where
+/// their output differs, or that they printed the same.
+fn diff_command(args: &[String]) {
+    let mut input = Vec::new();
 
-                tree.push(SyntaxItem::Loop(item));
+    let shared = ["--opt-level", "--dialect", "--max-steps"];
+    let parsed = parse_command(args, &shared, &[], |name, value| match (name, value) {
+        ("--input", Some(value)) => {
+            input = value.into_bytes();
+            Ok(())
+        }
+        ("--input-file", Some(value)) => std::fs::read(&value)
+            .map(|bytes| input = bytes)
+            .map_err(|e| format!("Could not read '{}': {}", value, e)),
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let (filenames, dialect, max_steps) = (parsed.operands, parsed.dialect, parsed.max_steps);
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::NONE);
+    if filenames.len() != 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
 
-                // This looks really weird.
-                for _ in 0..index {
-                    it.next();
+    let programs: Vec<Program> = filenames.iter()
+        .map(|filename| {
+            let contents = read_or_exit(filename);
+            let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+            ir::optimize(&ir::lower(&tree), opt_level)
+        })
+        .collect();
+    let (a, b) = (&filenames[0], &filenames[1]);
+    match differential::compare_programs(&programs[0], &programs[1], &input, max_steps) {
+        differential::Comparison::Same { bytes, a: first, b: second } => {
+            println!("{} and {} print the same {} bytes, in {} and {} steps.", a, b, bytes, first.steps, second.steps);
+            for (filename, side) in [(a, &first), (b, &second)] {
+                if let Some(ref e) = side.error {
+                    println!("{} failed: {}", filename, e);
+                }
+            }
+            if first.error.is_some() != second.error.is_some() {
+                std::process::exit(1);
+            }
+        }
+        differential::Comparison::Differs { at, a: first, b: second } => {
+            println!("Output differs at byte {}:", at);
+            for (filename, side) in [(a, &first), (b, &second)] {
+                match (side.byte, side.error.as_ref()) {
+                    (Some(byte), _) => println!("  {} printed {} ('{}') after {} steps",
+                                                filename,
+                                                byte,
+                                                std::ascii::escape_default(byte),
+                                                side.steps),
+                    (None, Some(e)) => println!("  {} failed after {} steps: {}", filename, side.steps, e),
+                    (None, None) => println!("  {} ended after {} steps", filename, side.steps),
                 }
             }
-            Token::EndLoop => continue,
-            _ => tree.push(SyntaxItem::Single(*token)),
+            std::process::exit(1);
         }
     }
+}
+
+/// Records a run and writes it as an HTML page that animates the tape.
+fn render_command(args: &[String]) {
+    let mut out = None;
+    let mut settings = animation::Settings::default();
+    let mut input = Vec::new();
+
+    let count = |value: &str| match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid count '{}'.", value)),
+    };
+    let shared = ["--opt-level", "--dialect"];
+    let parsed = parse_command(args, &shared, &[], |name, value| match (name, value) {
+        ("--out", value) | ("-o", value) => {
+            out = value;
+            Ok(())
+        }
+        ("--every", Some(value)) => count(&value).map(|n| settings.every = n as u64),
+        ("--max-frames", Some(value)) => count(&value).map(|n| settings.max_frames = n),
+        ("--cells", Some(value)) => count(&value).map(|n| settings.cells = n),
+        ("--input", Some(value)) => {
+            input = value.into_bytes();
+            Ok(())
+        }
+        ("--input-file", Some(value)) => std::fs::read(&value)
+            .map(|bytes| input = bytes)
+            .map_err(|e| format!("Could not read '{}': {}", value, e)),
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let out = out.unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    });
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::NONE);
+    let dialect = parsed.dialect;
+    let contents = read_or_exit(&filename);
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    let mut state = State::new(ir::optimize(&ir::lower(&tree), opt_level));
+    state.input = Some(Box::new(std::io::Cursor::new(input)));
 
-    Ok(tree)
+    let recording = animation::record(&mut state, &settings);
+    if let Err(e) = std::fs::write(&out, recording.to_html(&filename, &contents)) {
+        eprintln!("Could not write '{}': {}", out, e);
+        std::process::exit(1);
+    }
+    eprintln!("Wrote {} frames to '{}'. {}", recording.frames.len(), out, recording.end);
 }
 
-fn run(state: &mut State) {
-    let mut it = state.commands.iter();
-    while let Some(command) = it.next() {
-        // println!("Executing: {:?}", *command);
-        match *command {
-            SyntaxItem::Single(ref t) => {
-                match *t {
-                    Token::Increment => {
-                        if state.data[state.pointer] == 255 {
-                            state.data[state.pointer] = 0;
-                        } else {
-                            state.data[state.pointer] += 1
-                        }
-                    }
-                    Token::Decrement => {
-                        if state.data[state.pointer] == 0 {
-                            state.data[state.pointer] = 255;
-                        } else {
-                            state.data[state.pointer] -= 1
-                        }
-                    }
-                    Token::ShiftLeft => {
-                        if state.pointer > 0 {
-                            state.pointer = state.pointer - 1;
-                        } else {
-                            state.data.insert(0, 0);
-                        }
-                    }
-                    Token::ShiftRight => {
-                        state.data.push(0);
-                        state.pointer = state.pointer + 1;
-                    }
-                    Token::Input => {
-                        let mut s = String::new();
-                        std::io::stdin().read_line(&mut s).expect("Unable to read from STDIN.");
-
-                        let trim = s.trim();
-                        let result = trim.parse::<u8>();
-                        match result {
-                            Ok(i) => {
-                                state.data[state.pointer] = i;
-                                continue;
-                            }
-                            Err(_) => (),
-                        }
-
-                        let option = trim.chars().nth(0);
-                        match option {
-                            Some(c) => {
-                                state.data[state.pointer] = c as u8;
-                                continue;
-                            }
-                            None => panic!("Could not parse input."),
-                        }
-                    } 
-                    Token::Output => {
-                        print!("{}", state.data[state.pointer] as char);
-                        std::io::stdout().flush().expect("Could not flush.");
-                    }
-                    Token::BeginLoop => continue,
-                    Token::EndLoop => continue,
-                }
+/// Runs a program on random input, one line per seed, and fails if any
+/// run crashed.
+fn fuzz_command(args: &[String]) {
+    let mut seed = 0u64;
+    let mut runs = 100u64;
+    let mut limits = fuzz::Limits::default();
+
+    let count = |value: &str| value.parse::<u64>().map_err(|_| format!("Invalid count '{}'.", value));
+    let shared = ["--opt-level", "--dialect", "--max-steps"];
+    let parsed = parse_command(args, &shared, &[], |name, value| match (name, value) {
+        ("--seed", Some(value)) => value.parse()
+            .map(|n| seed = n)
+            .map_err(|_| format!("Invalid seed '{}'.", value)),
+        ("--runs", Some(value)) => count(&value).map(|n| runs = n),
+        ("--max-memory", Some(value)) => parse_size(&value).map(|n| limits.memory_limit = n),
+        ("--max-input", Some(value)) => count(&value).map(|n| limits.max_input = n as usize),
+        _ => Err(format!("Unknown option '{}'.", name)),
+    });
+    let filename = parsed.operand();
+    let opt_level = parsed.opt_level.unwrap_or(OptLevel::NONE);
+    let dialect = parsed.dialect;
+    limits.max_steps = parsed.max_steps.unwrap_or(limits.max_steps);
+    let contents = read_or_exit(&filename);
+    let tree = parse_or_exit(&contents, &dialect, &parser::ParseOptions::default(), &mut Timings::default());
+    let program = ir::optimize(&ir::lower(&tree), opt_level);
+
+    let mut counts = [0u64; 5];
+    for i in 0..runs {
+        let case = fuzz::run_seed(&program, seed.wrapping_add(i), &limits);
+        let (kind, verdict) = match case.verdict {
+            fuzz::Verdict::Finished => (0, "ok".to_owned()),
+            fuzz::Verdict::Crashed(ref e) => (1, format!("crash: {}", e)),
+            fuzz::Verdict::Hung => (2, format!("hang after {} steps", limits.max_steps)),
+            fuzz::Verdict::OutOfMemory => (3, "out of memory".to_owned()),
+            fuzz::Verdict::InputExhausted => (4, "input exhausted".to_owned()),
+        };
+        counts[kind] += 1;
+        println!("seed {}: {} input bytes, {} output bytes, digest {:016x}, {}",
+                 case.seed, case.input.len(), case.output_len, case.digest, verdict);
+    }
+    eprintln!("{} runs: {} ok, {} crashed, {} hung, {} out of memory, {} out of input.",
+              runs, counts[0], counts[1], counts[2], counts[3], counts[4]);
+    if counts[1] > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn extract_command(args: &[String]) {
+    let all = args.iter().any(|a| a == "--all");
+    let run_it = args.iter().any(|a| a == "--run");
+    let rest: Vec<String> = args.iter().filter(|a| *a != "--all" && *a != "--run").cloned().collect();
+    let options = parse_args_or_exit(&rest);
+
+    let contents = read_or_exit(&options.filename);
+    let candidates = extract::candidates(&contents);
+    if candidates.is_empty() {
+        eprintln!("No Brainfuck code found in '{}'.", options.filename);
+        std::process::exit(1);
+    }
+
+    let shown = if all { candidates.len() } else { 1 };
+    for (i, candidate) in candidates.iter().take(shown).enumerate() {
+        let (line, column) = line_col(&contents, candidate.start);
+        let (end_line, end_column) = line_col(&contents, candidate.end);
+        eprintln!("#{} confidence {:.2} at {}:{}-{}:{}",
+                  i + 1, candidate.confidence, line, column, end_line, end_column);
+        if !run_it {
+            println!("{}", candidate.code);
+        }
+    }
+
+    if run_it {
+        run_source(&candidates[0].code, &options, None);
+    }
+}
+
+/// Loads a saved state for `program`.
+fn resume(path: &str, program: &Program) -> State {
+    let state = std::fs::File::open(path)
+        .map_err(|e| format!("Could not open '{}': {}", path, e))
+        .and_then(|f| State::load(std::io::BufReader::new(f)))
+        .and_then(|state| if state.program() == program {
+            Ok(state)
+        } else {
+            Err(format!("'{}' was saved from a different program.", path))
+        });
+
+    let state = match state {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    state
+}
+
+/// Whether `run_jit` would run `state` as machine code.
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+fn jit_supports(state: &State) -> bool {
+    jit::supports(state)
+}
+
+#[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+fn jit_supports(_state: &State) -> bool {
+    false
+}
+
+/// Runs `state` as machine code if possible, timing compilation on its own.
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+fn run_jit(state: &mut State, timings: &mut Timings) -> Result<RunOutcome, String> {
+    if !jit::supports(state) {
+        return timings.measure("run", || run(state));
+    }
+    let compiled = timings.measure("jit", || jit::compile(state.program()))?;
+    timings.measure("run", || jit::run_compiled(&compiled, state))
+}
+
+#[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+fn run_jit(state: &mut State, timings: &mut Timings) -> Result<RunOutcome, String> {
+    timings.measure("run", || run(state))
+}
+
+/// Loads the plugins given with `--plugin` anywhere on the command line, so
+/// that every command sees what they register, and returns the remaining
+/// arguments.
+fn load_plugins(args: Vec<String>) -> Vec<String> {
+    let mut rest = Vec::new();
+    let mut it = args.into_iter();
+    while let Some(arg) = it.next() {
+        let path = if arg == "--plugin" {
+            it.next()
+        } else if let Some(path) = arg.strip_prefix("--plugin=") {
+            Some(path.to_owned())
+        } else {
+            rest.push(arg);
+            continue;
+        };
+        let result = path.ok_or_else(|| "Option '--plugin' requires a value.".to_owned())
+            .and_then(|path| load_plugin(&path));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+    rest
+}
+
+#[cfg(feature = "plugins")]
+fn load_plugin(path: &str) -> Result<(), String> {
+    plugin::load(path)
+}
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugin(_path: &str) -> Result<(), String> {
+    Err("Plugins need a build with the 'plugins' feature.".to_owned())
+}
+
+/// Runs the optimizer passes of plugins named in `passes` on `program`.
+#[cfg(feature = "plugins")]
+fn apply_passes(program: Program, passes: &[String]) -> Result<Program, String> {
+    passes.iter().try_fold(program, |program, name| {
+        plugin::pass(name)
+            .ok_or_else(|| format!("Unknown pass '{}'.", name))?
+            .apply(&program)
+    })
+}
+
+#[cfg(not(feature = "plugins"))]
+fn apply_passes(_program: Program, _passes: &[String]) -> Result<Program, String> {
+    Err("Passes need a build with the 'plugins' feature.".to_owned())
+}
+
+#[cfg(feature = "plugins")]
+fn open_sink(spec: &str) -> Result<Box<dyn Write>, String> {
+    plugin::open_sink(spec).map(|sink| Box::new(sink) as Box<dyn Write>)
+}
+
+#[cfg(not(feature = "plugins"))]
+fn open_sink(_spec: &str) -> Result<Box<dyn Write>, String> {
+    Err("Output sinks need a build with the 'plugins' feature.".to_owned())
+}
+
+/// Loads the bytecode next to the source file at `path`, if it was written
+/// after the source and optimized at `opt_level`.
+fn load_cache(path: &str, opt_level: OptLevel) -> Option<Program> {
+    let source = std::path::Path::new(path);
+    let cache = source.with_extension(bytecode::EXTENSION);
+    let modified = |p: &std::path::Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(source), modified(&cache)) {
+        (Some(source_time), Some(cache_time)) if cache_time >= source_time => (),
+        _ => return None,
+    }
+
+    match std::fs::read(&cache).map_err(|e| e.to_string()).and_then(|bytes| bytecode::decode(&bytes)) {
+        Ok(loaded) if loaded.opt_level == opt_level => Some(loaded.program),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("warning: ignoring '{}': {}", cache.display(), e);
+            None
+        }
+    }
+}
+
+/// Whether the run may use the bytecode cached next to the source instead of
+/// compiling it. The cache has no syntax tree and is compiled from Brainfuck,
+/// so it is not used when a tree is needed or the program is in another
+/// dialect. Nor is it used with included files, which it would not notice
+/// change, or with options that look at the compiled program, which would
+/// see how the cache was built rather than how this run builds it. Limits on
+/// the source and `--lenient` are checked by the parser, so the cache is not
+/// used with them either.
+fn uses_cache(options: &Options) -> bool {
+    options.dialect == Dialect::Brainfuck
+        && options.parse_options == parser::ParseOptions::default()
+        && !options.include
+        && !options.dump_ast
+        && !options.hints
+        && !options.dump_ir
+        && !options.stats
+        && !options.coverage
+        && !options.trace
+        && !options.profile
+        && !options.debug
+        && options.passes.is_empty()
+}
+
+/// Runs `contents` as configured by `options`. `input`, if given, is read
+/// instead of stdin.
+fn run_source(contents: &str, options: &Options, input: Option<Vec<u8>>) {
+    let mut timings = Timings::default();
+    let tree = match options.expansion {
+        Some(ref expansion) => parse_expanded_or_exit(expansion, options, &mut timings),
+        None => parse_or_exit(contents, &options.dialect, &options.parse_options, &mut timings),
+    };
+
+    if options.dump_ast {
+        dump_ast(&tree);
+        return;
+    }
+    if let Some(ref path) = options.export_derivation {
+        let mut state = State::new(ir::lower(&tree));
+        state.use_stdio();
+        if let Some(input) = input {
+            state.input = Some(Box::new(std::io::Cursor::new(input)));
+        }
+        let written = derivation::derive(&mut state, options.derivation_steps).and_then(|derivation| {
+            let text = derivation.write(derivation::Format::from_path(path));
+            std::fs::write(path, text).map_err(|e| format!("Could not write '{}': {}", path, e))
+        });
+        if let Err(e) = written {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let program = timings.measure("lower", || ir::lower(&tree));
+    let mut optimized = Program::default();
+    let dead_code = timings.measure("optimize", || ir::optimize_into(&mut optimized, &program, options.opt_level));
+    run_program(optimized, contents, Some(&tree), dead_code, options, input, timings);
+}
+
+/// Runs `program`, compiled from `contents`, as configured by `options`.
+/// Hints need the syntax tree and are only given if `tree` is set.
+/// `dead_code` is the number of instructions the optimizer removed.
+fn run_program(program: Program,
+               contents: &str,
+               tree: Option<&Ast>,
+               dead_code: usize,
+               options: &Options,
+               input: Option<Vec<u8>>,
+               mut timings: Timings) {
+    let program = if options.passes.is_empty() {
+        program
+    } else {
+        match timings.measure("passes", || apply_passes(program, &options.passes)) {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    };
+    if options.dump_ir {
+        if !options.interleave_source {
+            dump_ir(&program);
+        } else if contents.is_empty() {
+            eprintln!("--interleave-source needs the source, which a bytecode file does not have.");
+            std::process::exit(2);
+        } else {
+            dump_ir_interleaved(&program, contents, &options.dialect);
+        }
+        return;
+    }
+    if options.debug && program.instructions.contains(&ir::Instruction::Fork) {
+        eprintln!("The debugger cannot step through the threads of a Brainfork program.");
+        std::process::exit(2);
+    }
+
+    let mut state = match options.resume {
+        Some(ref path) => resume(path, &program),
+        None => State::new(program),
+    };
+    state.stats.dead_code = dead_code as u64;
+    state.memory_limit = options.max_memory;
+    state.max_steps = options.max_steps;
+    state.max_loop_iterations = options.max_loop_iterations;
+    state.timeout = options.timeout;
+    state.strict_tape = options.strict_tape;
+    if let Err(e) = state.use_tape(options.tape) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    state.byte_input = options.byte_input;
+    state.eof_unchanged = options.eof_unchanged;
+    state.newline = options.newline;
+    state.output_rate = options.output_rate.map(|rate| RateLimiter::new(rate, options.output_rate_policy));
+    if options.trace {
+        let out: Box<dyn Write> = match options.trace_file {
+            Some(ref path) => {
+                Box::new(std::io::BufWriter::new(create_or_exit(path)))
+            }
+            None => Box::new(std::io::BufWriter::new(std::io::stderr())),
+        };
+        state.trace = Some(Tracer::new(options.trace_format.unwrap_or(trace::Format::Text), out));
+    }
+    if options.profile {
+        state.profile = Some(HashMap::new());
+    }
+    if options.coverage {
+        state.coverage = Some(vec![0; state.program().instructions.len()]);
+    }
+    if options.heat_map.is_some() {
+        state.heat = Some(heat::HeatMap::default());
+    }
+    let input = input.or_else(|| options.input_text.as_ref().map(|text| text.clone().into_bytes()));
+    let reads_stdin = input.is_none() && options.input_file.is_none();
+    // The TUI prompts for input itself and shows the output on its screen.
+    if !options.tui {
+        state.use_stdio();
+    }
+    // Keys pressed one at a time in raw mode are not asked for.
+    if reads_stdin && !options.tui && !options.raw_tty && !options.no_prompt &&
+       std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        state.prompt = Some(match options.prompt {
+            Some(ref prompt) => prompt.clone(),
+            None if std::env::var_os("NO_COLOR").is_none() => DEFAULT_PROMPT_COLORED.to_owned(),
+            None => DEFAULT_PROMPT.to_owned(),
+        });
+    }
+    if let Some(input) = input {
+        state.input = Some(Box::new(std::io::Cursor::new(input)));
+    }
+    if let Some(ref path) = options.input_file {
+        let file = std::fs::File::open(path).unwrap_or_else(|e| {
+            eprintln!("Could not open '{}': {}", path, e);
+            std::process::exit(1);
+        });
+        state.input = Some(Box::new(std::io::BufReader::new(file)));
+    }
+    if let Some(ref spec) = options.output_sink {
+        state.output = match open_sink(spec) {
+            Ok(sink) => Some(sink),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        };
+    }
+    state.output_mode = options.output_mode;
+    // The debugger shows the output between its own, which it has to keep
+    // up with.
+    state.flush_policy = match options.flush_policy {
+        _ if options.debug => output::FlushPolicy::Always,
+        Some(policy) => policy,
+        None if options.output_file.is_none() && options.output_sink.is_none() && std::io::stdout().is_terminal() => {
+            output::FlushPolicy::Line
+        }
+        None => output::FlushPolicy::OnInput,
+    };
+    if let Some(ref path) = options.output_file {
+        let file = match std::fs::File::create(path) {
+            Ok(file) => std::io::BufWriter::new(file),
+            Err(e) => {
+                eprintln!("Could not create '{}': {}", path, e);
+                std::process::exit(2);
             }
-            SyntaxItem::Loop(ref v) => {
-                let mut s = state.clone();
-                s.commands = v.clone();
+        };
+        state.output = Some(if options.tee {
+            Box::new(output::Tee::new(file, Stdout::default()))
+        } else {
+            Box::new(file)
+        });
+    }
+    if let Some(ref path) = options.record_input {
+        state.input_record = Some(Box::new(std::io::BufWriter::new(create_or_exit(path))));
+    }
+    // A terminal only ever delivers new input, so there is nothing to skip.
+    if options.resume.is_some() && !(reads_stdin && std::io::stdin().is_terminal()) {
+        if let Err(e) = state.skip_consumed_input() {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let names = match CellNames::parse(contents) {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if !options.metrics.is_empty() {
+        let metrics = match options.metrics.iter().map(|metric| Metric::parse(metric, &names)).collect() {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        };
+        let log = options.metrics_file.as_ref().map(|path| {
+            Box::new(std::io::BufWriter::new(create_or_exit(path))) as Box<dyn Write>
+        });
+        state.metrics = Some(Metrics::new(metrics,
+                                          options.metrics_every.unwrap_or(100_000),
+                                          log));
+    }
+    if let Some(every) = options.checkpoint_every {
+        let path = options.checkpoint_file.clone().unwrap_or_else(|| format!("{}.checkpoint", options.filename));
+        state.checkpoint = Some(Checkpoint { every, path });
+    }
+
+    // Entered before Ctrl-C is caught, so that the terminal is restored
+    // before a second Ctrl-C quits.
+    #[cfg(unix)]
+    let raw_tty = if options.raw_tty && reads_stdin {
+        match bfinterpreter::tty::RawTerminal::enter() {
+            Ok(raw_tty) => raw_tty,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    {
+        if options.raw_tty {
+            eprintln!("warning: --raw-tty is only supported on Unix, reading lines instead");
+        }
+    }
+
+    // The debugger and the TUI handle Ctrl-C themselves, and machine code
+    // does not look at the flag. `--jit` falls back to the interpreter when
+    // the program cannot run as machine code, which does.
+    let machine_code = options.jit && jit_supports(&state);
+    if !options.debug && !options.tui && !machine_code {
+        match catch_interrupts() {
+            Ok(interrupt) => state.interrupt = Some(interrupt),
+            Err(e) => eprintln!("warning: {}", e),
+        }
+    }
+
+    let start = Instant::now();
+    let result = if options.debug {
+        timings.measure("run", || debug_session(&mut state, contents, &names, options.history)).map(|_| state.outcome())
+    } else if options.tui {
+        timings.measure("run", || tui::run(&mut state, contents, &names, &mut std::io::stdout())).map(|_| state.outcome())
+    } else if options.jit {
+        if !cfg!(all(feature = "jit", target_arch = "x86_64")) {
+            eprintln!("warning: built without the 'jit' feature, interpreting instead");
+        }
+        run_jit(&mut state, &mut timings)
+    } else {
+        timings.measure("run", || run(&mut state))
+    };
+    // Restored before anything is reported, since errors exit the process.
+    #[cfg(unix)]
+    drop(raw_tty);
+
+    if let Some(ref path) = options.audit_log {
+        let record = audit::Record::new(contents,
+                                        state.stats.input_bytes,
+                                        options.max_memory,
+                                        &result,
+                                        start.elapsed());
+        if let Err(e) = audit::append(path, &record) {
+            eprintln!("{}", e);
+        }
+    }
+
+    if options.stats_json {
+        eprintln!("{}", report::StatsReport::new(&state, &result).to_json());
+    } else if options.stats {
+        print_stats(&state, &options.num_format);
+    }
+    if options.dump_tape {
+        print_tape(&state, options.dump_tape_cells, &names);
+    }
+    if options.timings {
+        timings.print();
+    }
+    if let Some(ref metrics) = state.metrics {
+        print_metrics(metrics, &options.num_format);
+    }
+    if let Some(ref profile) = state.profile {
+        print_profile(profile, contents, options.profile_top, &options.num_format);
+    }
+    if let (Some(ref heat), Some(ref path)) = (&state.heat, &options.heat_map) {
+        if let Err(e) = std::fs::write(path, heat.to_csv(&names)) {
+            eprintln!("Could not write '{}': {}", path, e);
+        }
+    }
+    if let Some(ref counts) = state.coverage {
+        let hits = coverage::hits(state.program(), counts);
+        let report = if options.coverage_lcov {
+            coverage::lcov(&options.filename, contents, &hits)
+        } else {
+            coverage::annotate(contents, &hits)
+        };
+        match options.coverage_file {
+            Some(ref path) => if let Err(e) = std::fs::write(path, report) {
+                eprintln!("Could not write '{}': {}", path, e);
+            },
+            None => eprint!("{}", report),
+        }
+    }
 
-                while state.data[state.pointer] != 0 {
-                    run(&mut s);
+    let limit_hit = options.max_steps.is_some_and(|limit| state.stats.instructions >= limit) ||
+                    options.max_memory.is_some_and(|limit| state.stats.peak_memory > limit) ||
+                    options.timeout.is_some_and(|timeout| start.elapsed() >= timeout);
+    if let Some(tree) = tree.filter(|_| options.hints && (state.stats.output_bytes == 0 || limit_hit)) {
+        print_hints(&hints::analyze(tree), contents);
+    }
 
-                    state.data = s.data.clone();
-                    state.pointer = s.pointer;
+    match result {
+        // A cell is a byte, so the status is always one a shell can see.
+        Ok(RunOutcome::Finished) if options.exit_from_cell => {
+            std::process::exit(i32::from(state.tape().get(state.pointer())))
+        }
+        Ok(RunOutcome::Finished) => (),
+        Ok(RunOutcome::OutputClosed) => std::process::exit(EXIT_OUTPUT_CLOSED),
+        Ok(RunOutcome::Interrupted) => {
+            eprintln!("Interrupted after {} instructions.", state.stats.instructions);
+            print_position(&state, contents, &names);
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        Err(e) => {
+            match options.expansion {
+                // Point into the files as written rather than the expansion.
+                Some(ref expansion) => {
+                    eprintln!("{}", expansion.explain(&e));
+                    eprintln!("at {}", position(&state, contents));
+                    if let Some(span) = state.program().span_of(state.pc()) {
+                        eprintln!("in {}", expansion.locate(span.start));
+                    }
+                }
+                None => {
+                    // Programs loaded from bytecode come without their source,
+                    // which leaves nothing to show.
+                    let span = state.program().span_of(state.pc()).filter(|span| span.end <= contents.len());
+                    print_diagnostic(Severity::Error, &e, contents, span);
+                    eprintln!("at {}", position(&state, contents));
                 }
             }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Writes what the library logs to stderr, as `--verbose` asks for.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level().as_str().to_lowercase(), record.target(), record.args());
         }
     }
+
+    fn flush(&self) {}
 }
 
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Takes `-v`, `-vv` and `--verbose` off the arguments, which every command
+/// accepts, and logs at debug level for one of them and at trace level for
+/// more.
+fn init_logging(args: Vec<String>) -> Vec<String> {
+    let mut verbosity = 0;
+    let rest = args.into_iter()
+        .filter(|arg| {
+            let v = match arg.as_str() {
+                "-v" | "--verbose" => 1,
+                "-vv" => 2,
+                _ => return true,
+            };
+            verbosity += v;
+            false
+        })
+        .collect();
+    let level = match verbosity {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+    rest
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let args = load_plugins(init_logging(std::env::args().collect()));
+
+    match args.get(1).map(String::as_str) {
+        Some("examples") => return examples_command(&args[2..]),
+        Some("tutorial") => return tutorial_command(),
+        Some("split") => return split_command(&args[2..]),
+        Some("extract") => return extract_command(&args[2..]),
+        Some("compile") => return compile_command(&args[2..]),
+        Some("disasm") => return disasm_command(&args[2..]),
+        Some("outline") => return outline_command(&args[2..]),
+        Some("analyze") => return analyze_command(&args[2..]),
+        Some("translate") => return translate_command(&args[2..]),
+        Some("generate-text") => return generate_text_command(&args[2..]),
+        Some("minimize-input") => return minimize_input_command(&args[2..]),
+        Some("fmt") => return fmt_command(&args[2..]),
+        Some("bench") => return bench_command(&args[2..]),
+        Some("lib") => return lib_command(&args[2..]),
+        Some("test") => return test_command(&args[2..]),
+        Some("verify") => return verify_command(&args[2..]),
+        Some("diff") => return diff_command(&args[2..]),
+        Some("render") => return render_command(&args[2..]),
+        Some("fuzz") => return fuzz_command(&args[2..]),
+        Some("pipe") => return pipe_command(&args[2..]),
+        Some("serve") => return serve_command(&args[2..]),
+        Some("lsp") => return lsp_command(&args[2..]),
+        _ => (),
+    }
+
+    let mut options = parse_args_or_exit(&args[1..]);
+    if !options.batch.is_empty() {
+        return run_batch(&options);
+    }
+    if let Some(name) = options.filename.strip_prefix('@') {
+        match library::path(name) {
+            Ok(path) => options.filename = path.to_string_lossy().into_owned(),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let bytes = read_bytes_or_exit(&options.filename);
+    if bytecode::is_bytecode(&bytes) {
+        let mut timings = Timings::default();
+        let loaded = match timings.measure("load", || bytecode::decode(&bytes)) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        return run_program(loaded.program, "", None, 0, &options, None, timings);
+    }
+    let mut contents = String::from_utf8(bytes).unwrap_or_else(|e| {
+        eprintln!("Could not read '{}': {}", options.filename, e);
+        std::process::exit(1);
+    });
+
+    let manifest = split::parse_manifest(&contents);
+    let is_manifest = manifest.is_some();
+    if let Some(chunks) = manifest {
+        contents = match split::load_manifest(std::path::Path::new(&options.filename), &chunks) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+    } else if options.fix {
+        let edits = repair::suggest(&contents);
+        if !edits.is_empty() {
+            contents = repair::apply(&contents, &edits);
+            write_or_exit(&options.filename, &contents);
+            eprintln!("Applied {} bracket fix(es) to '{}'.", edits.len(), options.filename);
+        }
+    }
+    if options.include {
+        match preprocess::expand(std::path::Path::new(&options.filename), contents) {
+            Ok(expansion) => {
+                contents = expansion.source.clone();
+                options.expansion = Some(expansion);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let filename = &args[1];
+    if options.nested {
+        // A `!` in the file separates the program from its input, as dbfi
+        // expects; without one the input is read from stdin.
+        let (program, input) = match contents.find('!') {
+            Some(i) => (&contents[..i], contents.as_bytes()[i + 1..].to_vec()),
+            None => {
+                let mut input = Vec::new();
+                std::io::stdin().read_to_end(&mut input).expect("Could not read input.");
+                (&contents[..], input)
+            }
+        };
+        let input = nested::encode_input(program, &input);
+        return run_source(nested::SELF_INTERPRETER, &options, Some(input));
+    }
+    if options.split_input {
+        if let Some(i) = contents.find('!') {
+            let input = contents.as_bytes()[i + 1..].to_vec();
+            contents.truncate(i);
+            return run_source(&contents, &options, Some(input));
+        }
+    }
 
-    let mut f = std::fs::File::open(filename).expect("File not found.");
+    if !is_manifest && uses_cache(&options) {
+        let mut timings = Timings::default();
+        if let Some(program) = timings.measure("load", || load_cache(&options.filename, options.opt_level)) {
+            return run_program(program, &contents, None, 0, &options, None, timings);
+        }
+    }
 
-    let mut contents = String::new();
-    f.read_to_string(&mut contents).expect("Could not read file.");
+    run_source(&contents, &options, None);
+}
 
-    contents = contents.chars().filter(|c| !c.is_whitespace()).collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let result = parse(&contents).expect("Could not parse.");
+    #[test]
+    fn cache_of_another_level_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("bf-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("a.b");
+        std::fs::write(&source, "++++++++[>++++++++<-]>+.").unwrap();
+        let tree = parser::parse("++++++++[>++++++++<-]>+.").unwrap();
+        let program = ir::optimize(&ir::lower(&tree), OptLevel::MAX);
+        std::fs::write(dir.join("a.bfc"), bytecode::encode(&program, OptLevel::MAX)).unwrap();
 
-    let mut state = State::new(result);
-    run(&mut state);
+        let path = source.to_str().unwrap();
+        assert_eq!(load_cache(path, OptLevel::NONE), None);
+        assert_eq!(load_cache(path, OptLevel::MAX), Some(program));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,113 @@
+use std::io::Write;
+
+use interp::Stats;
+use names::CellNames;
+use tape::Tape;
+
+/// A value computed from the machine state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expr {
+    /// The value of a cell, or 0 if the tape does not reach it yet.
+    Cell(usize),
+    /// The position of the data pointer.
+    Pointer,
+    /// Bytes written so far.
+    Output,
+}
+
+impl Expr {
+    /// Parses `cell(N)`, `ptr` or `output`, where `N` is a cell index or one
+    /// of `names`.
+    pub fn parse(s: &str, names: &CellNames) -> Result<Expr, String> {
+        let s = s.trim();
+        match s {
+            "ptr" => return Ok(Expr::Pointer),
+            "output" => return Ok(Expr::Output),
+            _ => (),
+        }
+        s.strip_prefix("cell(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .and_then(|index| names.resolve(index.trim()).ok())
+            .map(Expr::Cell)
+            .ok_or_else(|| format!("Invalid expression '{}', expected cell(N), cell(NAME), ptr or output.", s))
+    }
+
+    pub fn eval<T: Tape>(&self, tape: &T, pointer: usize, stats: &Stats) -> u64 {
+        match *self {
+            Expr::Cell(index) => tape.get(index) as u64,
+            Expr::Pointer => pointer as u64,
+            Expr::Output => stats.output_bytes,
+        }
+    }
+}
+
+/// A named watch expression and the range of values it was seen to take.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metric {
+    pub name: String,
+    pub expr: Expr,
+    pub last: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Metric {
+    /// Parses a declaration of the form `name=expr`.
+    pub fn parse(s: &str, names: &CellNames) -> Result<Metric, String> {
+        let (name, expr) = s.split_once('=')
+            .ok_or_else(|| format!("Invalid metric '{}', expected name=expr.", s))?;
+        if name.is_empty() {
+            return Err(format!("Metric '{}' has no name.", s));
+        }
+        Ok(Metric {
+            name: name.to_owned(),
+            expr: Expr::parse(expr, names)?,
+            last: 0,
+            min: u64::MAX,
+            max: 0,
+        })
+    }
+}
+
+/// Watch expressions sampled every `every` instructions and once more when
+/// the program ends. Every sample can also be logged as a line of JSON.
+pub struct Metrics {
+    pub metrics: Vec<Metric>,
+    pub every: u64,
+    pub samples: u64,
+    log: Option<Box<dyn Write>>,
+}
+
+impl Metrics {
+    pub fn new(metrics: Vec<Metric>, every: u64, log: Option<Box<dyn Write>>) -> Self {
+        Metrics { metrics, every, samples: 0, log }
+    }
+
+    pub fn sample<T: Tape>(&mut self, tape: &T, pointer: usize, stats: &Stats) -> Result<(), String> {
+        self.samples += 1;
+        let mut values = ::serde_json::Map::new();
+        for metric in &mut self.metrics {
+            let value = metric.expr.eval(tape, pointer, stats);
+            metric.last = value;
+            metric.min = metric.min.min(value);
+            metric.max = metric.max.max(value);
+            values.insert(metric.name.clone(), value.into());
+        }
+
+        if let Some(ref mut log) = self.log {
+            let mut line = ::serde_json::Map::new();
+            line.insert("instructions".to_owned(), stats.instructions.into());
+            line.insert("metrics".to_owned(), values.into());
+            writeln!(log, "{}", ::serde_json::Value::Object(line))
+                .map_err(|e| format!("Could not write metrics: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        match self.log {
+            Some(ref mut log) => log.flush().map_err(|e| format!("Could not write metrics: {}", e)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Shrinking the input of a program while it keeps behaving the same way,
+//! for reducing bug reports.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::Cursor;
+
+use harness::SharedBuffer;
+use interp::{run, State};
+use ir::Program;
+
+/// What a single run of the program did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Observation {
+    pub output: Vec<u8>,
+    pub error: Option<String>,
+    /// Whether the run stopped at the step or memory limit.
+    pub limit_hit: bool,
+    /// Source offsets of the loops whose body was executed, as recorded by
+    /// the loop profile.
+    pub coverage: BTreeSet<usize>,
+}
+
+/// Limits for the runs made while minimizing.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_steps: u64,
+    pub max_memory: Option<usize>,
+}
+
+/// Runs `program` on `input` and records what it did.
+pub fn observe(program: &Program, input: &[u8], limits: Limits) -> Observation {
+    let output = SharedBuffer::default();
+    let mut state = State::new(program.clone());
+    state.input = Some(Box::new(Cursor::new(input.to_vec())));
+    state.output = Some(Box::new(output.clone()));
+    state.max_steps = Some(limits.max_steps);
+    state.memory_limit = limits.max_memory;
+    state.profile = Some(HashMap::new());
+
+    let error = run(&mut state).err();
+    let limit_hit = state.stats.instructions >= limits.max_steps ||
+                    limits.max_memory.is_some_and(|limit| state.stats.peak_memory > limit);
+    let coverage = state.profile
+        .take()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, entry)| entry.iterations > 0)
+        .map(|(offset, _)| offset)
+        .collect();
+    Observation { output: output.take(), error, limit_hit, coverage }
+}
+
+/// The behaviour a smaller input has to keep.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Property {
+    /// The output starts with these bytes.
+    OutputPrefix(Vec<u8>),
+    /// The run fails with this error.
+    Error(String),
+    /// The run stops at a limit.
+    Limit,
+    /// At least the loops of this coverage are executed.
+    Coverage(BTreeSet<usize>),
+}
+
+impl Property {
+    pub fn holds(&self, observation: &Observation) -> bool {
+        match *self {
+            Property::OutputPrefix(ref prefix) => observation.output.starts_with(prefix),
+            Property::Error(ref error) => observation.error.as_ref() == Some(error),
+            Property::Limit => observation.limit_hit,
+            Property::Coverage(ref coverage) => observation.coverage.is_superset(coverage),
+        }
+    }
+}
+
+/// Shrinks `input` by removing ever smaller chunks of it, keeping every
+/// removal after which `property` still holds (delta debugging). The result
+/// is as small as removing any single byte of it allows.
+///
+/// Candidates that execute fewer loops than the current input are tried
+/// last, since they are the likeliest to have lost the behaviour.
+pub fn minimize(program: &Program, input: &[u8], property: &Property, limits: Limits) -> Vec<u8> {
+    let mut input = input.to_vec();
+    if property.holds(&observe(program, &[], limits)) {
+        return Vec::new();
+    }
+
+    let mut coverage = observe(program, &input, limits).coverage;
+    let mut chunks = 2;
+    while input.len() >= 2 {
+        let size = input.len().div_ceil(chunks);
+        let mut deferred = Vec::new();
+        let mut reduced = None;
+        for start in (0..input.len()).step_by(size) {
+            let mut candidate = input[..start].to_vec();
+            candidate.extend_from_slice(&input[(start + size).min(input.len())..]);
+            let observation = observe(program, &candidate, limits);
+            if !property.holds(&observation) {
+                continue;
+            }
+            if observation.coverage.is_superset(&coverage) {
+                reduced = Some((candidate, observation.coverage));
+                break;
+            }
+            deferred.push((candidate, observation.coverage));
+        }
+
+        match reduced.or_else(|| deferred.into_iter().next()) {
+            Some((candidate, candidate_coverage)) => {
+                input = candidate;
+                coverage = candidate_coverage;
+                chunks = (chunks - 1).max(2);
+            }
+            None if chunks >= input.len() => break,
+            None => chunks = (chunks * 2).min(input.len()),
+        }
+    }
+
+    input
+}
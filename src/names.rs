@@ -0,0 +1,74 @@
+//! Names for cells, given in comments of the source by directives like
+//! `;@name 5 counter`, so that tools can show and accept `counter` instead
+//! of the cell index.
+
+use std::collections::BTreeMap;
+
+/// Starts a directive naming a cell.
+pub const DIRECTIVE: &str = ";@name";
+
+/// The cell names declared by a program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CellNames {
+    names: BTreeMap<usize, String>,
+}
+
+impl CellNames {
+    /// Reads every `;@name INDEX NAME` directive in `source`. Names consist
+    /// of letters, digits and `_` and do not start with a digit, so that they
+    /// contain no commands and cannot be mistaken for an index.
+    pub fn parse(source: &str) -> Result<CellNames, String> {
+        let mut names = CellNames::default();
+        for (i, line) in source.lines().enumerate() {
+            let mut rest = line;
+            while let Some(start) = rest.find(DIRECTIVE) {
+                rest = &rest[start + DIRECTIVE.len()..];
+                let mut words = rest.split_whitespace();
+                let (index, name) = match (words.next().and_then(|w| w.parse().ok()), words.next()) {
+                    (Some(index), Some(name)) if is_name(name) => (index, name),
+                    _ => return Err(format!("Invalid directive on line {}, expected '{} INDEX NAME'.", i + 1, DIRECTIVE)),
+                };
+                if names.cell(name).is_some() || names.names.contains_key(&index) {
+                    return Err(format!("Line {} names a cell or uses a name a second time.", i + 1));
+                }
+                names.names.insert(index, name.to_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name(&self, cell: usize) -> Option<&str> {
+        self.names.get(&cell).map(String::as_str)
+    }
+
+    pub fn cell(&self, name: &str) -> Option<usize> {
+        self.names.iter().find(|&(_, n)| n == name).map(|(&cell, _)| cell)
+    }
+
+    /// Reads a cell given by its index or name.
+    pub fn resolve(&self, s: &str) -> Result<usize, String> {
+        s.parse()
+            .ok()
+            .or_else(|| self.cell(s))
+            .ok_or_else(|| format!("'{}' is neither a cell index nor a cell name.", s))
+    }
+
+    /// The name of `cell` if it has one, or else its index.
+    pub fn label(&self, cell: usize) -> String {
+        self.name(cell).map_or_else(|| cell.to_string(), str::to_owned)
+    }
+
+    /// The named cells in order of their index.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names.iter().map(|(&cell, name)| (cell, name.as_str()))
+    }
+}
+
+fn is_name(word: &str) -> bool {
+    word.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+    word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
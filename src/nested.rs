@@ -0,0 +1,22 @@
+//! Support for running programs inside a Brainfuck self-interpreter.
+
+use lexer;
+
+/// The self-interpreter used by `--nested`, Daniel B. Cristofani's dbfi. It
+/// reads a program up to a `!` and then runs it on the rest of its input.
+pub const SELF_INTERPRETER: &str = include_str!("examples/dbfi.b");
+
+/// Builds the input the self-interpreter expects for running `program` on
+/// `input`.
+///
+/// Comments are dropped so that a `!` in them cannot end the program early.
+/// Since `,` reads a line at a time, every byte is written on a line of its
+/// own as a number.
+pub fn encode_input(program: &str, input: &[u8]) -> Vec<u8> {
+    let commands = lexer::lex(program).into_iter().map(|(offset, _)| program.as_bytes()[offset]);
+    let mut encoded = Vec::new();
+    for byte in commands.chain(Some(b'!')).chain(input.iter().cloned()) {
+        encoded.extend_from_slice(format!("{}\n", byte).as_bytes());
+    }
+    encoded
+}
@@ -0,0 +1,52 @@
+/// The line ending a program uses. Its output is translated to `\n`, and a
+/// `\n` it reads is translated to this line ending.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Newline {
+    #[default]
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl Newline {
+    pub fn from_name(name: &str) -> Option<Newline> {
+        match name {
+            "lf" => Some(Newline::Lf),
+            "crlf" => Some(Newline::Crlf),
+            "cr" => Some(Newline::Cr),
+            _ => None,
+        }
+    }
+
+    /// The bytes a program sees for a `\n` in its input.
+    pub fn input(self) -> &'static [u8] {
+        match self {
+            Newline::Lf => b"\n",
+            Newline::Crlf => b"\r\n",
+            Newline::Cr => b"\r",
+        }
+    }
+
+    /// Translates a byte written by the program into the bytes to write
+    /// instead, the first `n` of the returned buffer.
+    ///
+    /// With `Crlf` a `\r` is held back in `held_cr` until the next byte
+    /// shows whether it starts a line ending.
+    pub fn output(self, byte: u8, held_cr: &mut bool) -> ([u8; 2], usize) {
+        match self {
+            Newline::Lf => ([byte, 0], 1),
+            Newline::Cr if byte == b'\r' => ([b'\n', 0], 1),
+            Newline::Cr => ([byte, 0], 1),
+            Newline::Crlf => {
+                let held = ::std::mem::replace(held_cr, byte == b'\r');
+                match (held, byte) {
+                    (true, b'\n') => ([b'\n', 0], 1),
+                    (true, b'\r') => ([b'\r', 0], 1),
+                    (true, _) => ([b'\r', byte], 2),
+                    (false, b'\r') => ([0, 0], 0),
+                    (false, _) => ([byte, 0], 1),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+/// Base numbers are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Radix {
+    #[default]
+    Dec,
+    Hex,
+    Bin,
+}
+
+impl Radix {
+    pub fn from_name(name: &str) -> Option<Radix> {
+        match name {
+            "dec" => Some(Radix::Dec),
+            "hex" => Some(Radix::Hex),
+            "bin" => Some(Radix::Bin),
+            _ => None,
+        }
+    }
+}
+
+/// How numbers in reports are written. The output never depends on the
+/// locale: hex and binary numbers carry a `0x` or `0b` prefix, and digits
+/// are only grouped when a separator is given.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NumFormat {
+    pub radix: Radix,
+    /// Minimum number of digits, padded with zeros.
+    pub width: usize,
+    /// Inserted between groups of three decimal or four hex or binary digits.
+    pub separator: Option<char>,
+}
+
+impl NumFormat {
+    pub fn format(&self, n: u64) -> String {
+        let (prefix, digits, group) = match self.radix {
+            Radix::Dec => ("", n.to_string(), 3),
+            Radix::Hex => ("0x", format!("{:x}", n), 4),
+            Radix::Bin => ("0b", format!("{:b}", n), 4),
+        };
+        let digits = format!("{:0>width$}", digits, width = self.width);
+
+        let mut out = String::from(prefix);
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(group) {
+                if let Some(separator) = self.separator {
+                    out.push(separator);
+                }
+            }
+            out.push(c);
+        }
+        out
+    }
+}
@@ -0,0 +1,37 @@
+//! Callbacks from the interpreter for tools built on top of it, such as
+//! debuggers, profilers and visualizers. Set an `Observer` as
+//! `State::observer` and `interp::step` reports what each instruction does;
+//! unset, nothing is reported and nothing is paid for.
+
+use ir::Instruction;
+
+/// Receives what the program does. Every callback does nothing by default,
+/// so an observer only implements the ones it needs.
+#[allow(unused_variables)]
+pub trait Observer {
+    /// The instruction at `pc` is about to run, with the pointer at
+    /// `pointer`.
+    fn on_instruction(&mut self, pc: usize, instruction: Instruction, pointer: usize) {}
+
+    /// `cell` now holds `value`, which may be what it held before.
+    fn on_cell_write(&mut self, cell: usize, value: u8) {}
+
+    /// The program wrote `value`, before it is encoded for the output.
+    fn on_output(&mut self, value: u8) {}
+
+    /// The program read `value` into `cell`.
+    fn on_input(&mut self, cell: usize, value: u8) {}
+
+    /// The loop starting at `pc`, the index of its `JumpIfZero`, was
+    /// entered. Loops that are skipped are neither entered nor exited.
+    fn on_loop_enter(&mut self, pc: usize) {}
+
+    /// The loop starting at `pc` ended after at least one iteration.
+    fn on_loop_exit(&mut self, pc: usize) {}
+}
+
+/// An observer that ignores everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
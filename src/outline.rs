@@ -0,0 +1,167 @@
+//! A structural overview of a program: where its loops are, how they are
+//! nested and what the simple ones do.
+
+use std::collections::BTreeMap;
+use std::mem;
+
+use lexer::Token;
+
+/// A loop of the program, with the loops in its body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+    /// Source offset of the opening bracket.
+    pub start: usize,
+    /// Source offset of the closing bracket.
+    pub end: usize,
+    /// How far one iteration moves the pointer, unless that depends on a
+    /// nested loop.
+    pub net_move: Option<isize>,
+    /// Name of the common pattern the loop follows, e.g. `clear` for `[-]`.
+    pub idiom: Option<&'static str>,
+    pub body: Vec<Loop>,
+}
+
+impl Loop {
+    /// Number of loops this one consists of, itself included.
+    pub fn count(&self) -> usize {
+        let mut count = 0;
+        let mut pending = vec![self];
+        while let Some(l) = pending.pop() {
+            count += 1;
+            pending.extend(&l.body);
+        }
+        count
+    }
+}
+
+impl Drop for Loop {
+    /// Takes nested loops apart one at a time, like `SyntaxItem` does.
+    fn drop(&mut self) {
+        let mut bodies = vec![mem::take(&mut self.body)];
+        while let Some(mut loops) = bodies.pop() {
+            for l in &mut loops {
+                if !l.body.is_empty() {
+                    bodies.push(mem::take(&mut l.body));
+                }
+            }
+        }
+    }
+}
+
+/// A loop being read by `outline`.
+struct Frame {
+    start: usize,
+    pointer: isize,
+    known_move: bool,
+    /// Only loops without nested loops and I/O are matched against idioms.
+    simple: bool,
+    changes: BTreeMap<isize, i32>,
+    body: Vec<Loop>,
+}
+
+impl Frame {
+    fn new(start: usize) -> Self {
+        Frame {
+            start,
+            pointer: 0,
+            known_move: true,
+            simple: true,
+            changes: BTreeMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// The loop read, whose closing bracket is at `end`.
+    fn finish(mut self, end: usize) -> Loop {
+        self.changes.retain(|_, n| n.rem_euclid(256) != 0);
+        Loop {
+            start: self.start,
+            end,
+            net_move: if self.known_move { Some(self.pointer) } else { None },
+            idiom: if self.simple { idiom(self.pointer, &self.changes) } else { None },
+            body: self.body,
+        }
+    }
+}
+
+/// Outlines the loops of a program from its tokens, as produced by
+/// `lexer::lex` or a `Dialect`. The brackets should already have been
+/// checked by parsing; a stray `]` is ignored, as by the parser, and a loop
+/// never closed ends at its opening bracket.
+pub fn outline(tokens: &[(usize, Token)]) -> Vec<Loop> {
+    let mut loops = Vec::new();
+    // The loops being read, innermost last. Deep nesting needs no recursion.
+    let mut open: Vec<Frame> = Vec::new();
+    for &(offset, token) in tokens {
+        let frame = match open.last_mut() {
+            Some(frame) => frame,
+            None => {
+                if token == Token::BeginLoop {
+                    open.push(Frame::new(offset));
+                }
+                continue;
+            }
+        };
+        match token {
+            Token::ShiftLeft => frame.pointer -= 1,
+            Token::ShiftRight => frame.pointer += 1,
+            Token::Increment => *frame.changes.entry(frame.pointer).or_insert(0) += 1,
+            Token::Decrement => *frame.changes.entry(frame.pointer).or_insert(0) -= 1,
+            Token::Input | Token::Output | Token::Fork => frame.simple = false,
+            Token::BeginLoop => open.push(Frame::new(offset)),
+            Token::EndLoop => {
+                let inner = open.pop().expect("a loop is open").finish(offset);
+                close(&mut open, &mut loops, inner);
+            }
+        }
+    }
+    while let Some(frame) = open.pop() {
+        let start = frame.start;
+        close(&mut open, &mut loops, frame.finish(start));
+    }
+    loops
+}
+
+/// Adds the loop `inner`, just read, to the body of the innermost `open`
+/// one, or to `loops` if it is not nested.
+fn close(open: &mut [Frame], loops: &mut Vec<Loop>, inner: Loop) {
+    match open.last_mut() {
+        Some(outer) => {
+            outer.known_move &= inner.net_move == Some(0);
+            outer.simple = false;
+            outer.body.push(inner);
+        }
+        None => loops.push(inner),
+    }
+}
+
+/// Names a loop without nested loops and I/O by how it moves the pointer
+/// and which cells it changes, relative to the tested one.
+fn idiom(pointer: isize, changes: &BTreeMap<isize, i32>) -> Option<&'static str> {
+    if changes.is_empty() {
+        return match pointer {
+            0 => None,
+            p if p > 0 => Some("scan right"),
+            _ => Some("scan left"),
+        };
+    }
+    if pointer != 0 {
+        return None;
+    }
+
+    let others: Vec<i32> = changes.iter().filter(|&(&cell, _)| cell != 0).map(|(_, &n)| n).collect();
+    match changes.get(&0) {
+        // An odd step reaches zero from any value.
+        Some(&n) if others.is_empty() && n % 2 != 0 => Some("clear"),
+        Some(&n) if n.abs() == 1 && !others.is_empty() => {
+            Some(if others.iter().all(|&m| m == 1) {
+                if others.len() == 1 { "move" } else { "copy" }
+            } else if others.iter().all(|&m| m == -1) {
+                "subtract"
+            } else {
+                "multiply"
+            })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, Write};
+
+/// How the bytes a program writes with `.` reach its output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Each byte as it is.
+    #[default]
+    Raw,
+    /// Each byte as the character with that code, encoded in UTF-8, which
+    /// turns bytes from 128 on into two.
+    Utf8,
+    /// Each byte as a decimal number.
+    Decimal(Separator),
+    /// Each byte as two hexadecimal digits.
+    Hex(Separator),
+}
+
+/// What separates the numbers of `OutputMode::Decimal` and `OutputMode::Hex`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Separator {
+    /// Every number is on a line of its own.
+    #[default]
+    Newline,
+    /// The numbers are on one line, which ends when the output is flushed.
+    Space,
+}
+
+impl Separator {
+    pub fn from_name(name: &str) -> Option<Separator> {
+        match name {
+            "newline" => Some(Separator::Newline),
+            "space" => Some(Separator::Space),
+            _ => None,
+        }
+    }
+}
+
+impl OutputMode {
+    /// The mode called `name`, with numbers on lines of their own. `numeric`
+    /// is the old name of `decimal`.
+    pub fn from_name(name: &str) -> Option<OutputMode> {
+        match name {
+            "raw" => Some(OutputMode::Raw),
+            "utf8-lossy" => Some(OutputMode::Utf8),
+            "decimal" | "numeric" => Some(OutputMode::Decimal(Separator::Newline)),
+            "hex" => Some(OutputMode::Hex(Separator::Newline)),
+            _ => None,
+        }
+    }
+
+    /// This mode with its numbers separated by `separator`; modes that do
+    /// not write numbers stay as they are.
+    pub fn separated_by(self, separator: Separator) -> OutputMode {
+        match self {
+            OutputMode::Decimal(_) => OutputMode::Decimal(separator),
+            OutputMode::Hex(_) => OutputMode::Hex(separator),
+            mode => mode,
+        }
+    }
+
+    /// Whether bytes are written as numbers rather than text.
+    pub fn is_numeric(self) -> bool {
+        self.separator().is_some()
+    }
+
+    fn separator(self) -> Option<Separator> {
+        match self {
+            OutputMode::Decimal(separator) | OutputMode::Hex(separator) => Some(separator),
+            _ => None,
+        }
+    }
+
+    /// Appends what `byte` is written as to `out`. `first` says whether it
+    /// starts a line of numbers separated by spaces.
+    pub fn encode(self, byte: u8, first: bool, out: &mut Vec<u8>) {
+        let number = match self {
+            OutputMode::Raw => return out.push(byte),
+            OutputMode::Utf8 => {
+                let mut buf = [0; 2];
+                return out.extend_from_slice((byte as char).encode_utf8(&mut buf).as_bytes());
+            }
+            OutputMode::Decimal(_) => byte.to_string(),
+            OutputMode::Hex(_) => format!("{:02x}", byte),
+        };
+        match self.separator() {
+            Some(Separator::Space) if !first => out.push(b' '),
+            _ => {}
+        }
+        out.extend_from_slice(number.as_bytes());
+        if self.separator() == Some(Separator::Newline) {
+            out.push(b'\n');
+        }
+    }
+
+    /// Whether a line of numbers needs a newline to end it.
+    pub fn ends_lines(self) -> bool {
+        self.separator() == Some(Separator::Space)
+    }
+}
+
+/// When the output is flushed, which for stdout is when what was written
+/// shows up. Output is also flushed whenever its buffer is full, and once
+/// the program has ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// After every byte, which is slow for programs printing a lot.
+    Always,
+    /// After every line, and before `,` waits for input.
+    #[default]
+    Line,
+    /// Before `,` waits for input, so that a question asked without a
+    /// newline shows up before the answer is needed.
+    OnInput,
+    /// Only once the program has ended.
+    AtExit,
+}
+
+impl FlushPolicy {
+    pub fn from_name(name: &str) -> Option<FlushPolicy> {
+        match name {
+            "always" => Some(FlushPolicy::Always),
+            "line" => Some(FlushPolicy::Line),
+            "on-input" => Some(FlushPolicy::OnInput),
+            "at-exit" => Some(FlushPolicy::AtExit),
+            _ => None,
+        }
+    }
+}
+
+/// Writes everything to two writers, e.g. a file and stdout for `--tee`.
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Tee { first, second }
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
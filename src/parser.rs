@@ -0,0 +1,239 @@
+use std::fmt;
+use std::mem;
+
+use lexer::{lex, Token};
+
+#[derive(Clone, Debug)]
+pub enum SyntaxItem {
+    /// A single command, together with its source offset.
+    Single(usize, Token),
+    /// A loop body, together with the source offsets of its opening and
+    /// closing brackets.
+    Loop(usize, Vec<SyntaxItem>, usize),
+}
+
+/// The syntax tree of a program: its commands, with loops nested. It
+/// dereferences to its top-level items, and answers questions about the
+/// program as a whole.
+#[derive(Clone, Debug, Default)]
+pub struct Ast {
+    items: Vec<SyntaxItem>,
+}
+
+/// A loop of a program, as listed by `Ast::loops`.
+#[derive(Clone, Copy, Debug)]
+pub struct LoopRef<'a> {
+    /// Source offset of the opening bracket.
+    pub start: usize,
+    /// Source offset of the closing bracket.
+    pub end: usize,
+    /// How many loops this one is inside of.
+    pub depth: usize,
+    pub body: &'a [SyntaxItem],
+}
+
+impl Ast {
+    pub fn new(items: Vec<SyntaxItem>) -> Self {
+        Ast { items }
+    }
+
+    pub fn items(&self) -> &[SyntaxItem] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<SyntaxItem> {
+        self.items
+    }
+
+    /// Number of commands, counting both brackets of a loop, the same count
+    /// `ParseOptions::max_size` limits.
+    pub fn instruction_count(&self) -> usize {
+        self.loops().len() * 2 + self.singles().count()
+    }
+
+    /// How deep loops are nested, 0 for a program without loops.
+    pub fn max_nesting_depth(&self) -> usize {
+        self.loops().iter().map(|l| l.depth + 1).max().unwrap_or(0)
+    }
+
+    /// Every loop, outer ones before those in their bodies, in the order of
+    /// their opening brackets.
+    pub fn loops(&self) -> Vec<LoopRef<'_>> {
+        let mut loops = Vec::new();
+        // The item lists still to look through, with their depth, walked
+        // without recursion like the rest of the tree.
+        let mut pending = vec![(&self.items[..], 0)];
+        while let Some((items, depth)) = pending.pop() {
+            for item in items {
+                if let SyntaxItem::Loop(start, ref body, end) = *item {
+                    loops.push(LoopRef { start, end, depth, body });
+                    pending.push((body, depth + 1));
+                }
+            }
+        }
+        loops.sort_by_key(|l| l.start);
+        loops
+    }
+
+    /// Whether the program has a `,` anywhere, even where it never runs.
+    pub fn uses_input(&self) -> bool {
+        self.singles().any(|token| token == Token::Input)
+    }
+
+    /// The tokens of every single command, in no particular order.
+    fn singles(&self) -> impl Iterator<Item = Token> + '_ {
+        let loops = self.loops();
+        let bodies = ::std::iter::once(&self.items[..]).chain(loops.into_iter().map(|l| l.body));
+        bodies.flat_map(|items| items.iter()).filter_map(|item| match *item {
+            SyntaxItem::Single(_, token) => Some(token),
+            SyntaxItem::Loop(..) => None,
+        })
+    }
+}
+
+impl ::std::ops::Deref for Ast {
+    type Target = [SyntaxItem];
+
+    fn deref(&self) -> &[SyntaxItem] {
+        &self.items
+    }
+}
+
+impl From<Vec<SyntaxItem>> for Ast {
+    fn from(items: Vec<SyntaxItem>) -> Self {
+        Ast::new(items)
+    }
+}
+
+impl<'a> IntoIterator for &'a Ast {
+    type Item = &'a SyntaxItem;
+    type IntoIter = ::std::slice::Iter<'a, SyntaxItem>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+impl fmt::Display for SyntaxItem {
+    /// Writes the item as Brainfuck source, see `to_source`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The loops still to be closed, each with the items after it.
+        let mut open = Vec::new();
+        let mut items = ::std::slice::from_ref(self).iter();
+        loop {
+            match items.next() {
+                Some(&SyntaxItem::Single(_, token)) => write!(f, "{}", token)?,
+                Some(SyntaxItem::Loop(_, body, _)) => {
+                    write!(f, "[")?;
+                    open.push(mem::replace(&mut items, body.iter()));
+                }
+                None => match open.pop() {
+                    Some(rest) => {
+                        write!(f, "]")?;
+                        items = rest;
+                    }
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+}
+
+impl Drop for SyntaxItem {
+    /// Takes nested loops apart one at a time, since dropping them in turn
+    /// would need as much stack as they are deep.
+    fn drop(&mut self) {
+        if let SyntaxItem::Loop(_, ref mut body, _) = *self {
+            let mut bodies = vec![mem::take(body)];
+            while let Some(mut items) = bodies.pop() {
+                for item in &mut items {
+                    if let SyntaxItem::Loop(_, ref mut body, _) = *item {
+                        if !body.is_empty() {
+                            bodies.push(mem::take(body));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Writes a syntax tree back as Brainfuck source. Comments are not part of
+/// the tree, so only the commands of the original source remain; parsing
+/// the result gives the same tree, apart from the offsets.
+pub fn to_source(items: &[SyntaxItem]) -> String {
+    items.iter().map(SyntaxItem::to_string).collect()
+}
+
+/// Parses `input` into a syntax tree, the first stage of running it. The
+/// tree is lowered by `ir::lower` and then optionally `ir::optimize`d.
+pub fn parse(input: &str) -> Result<Ast, String> {
+    parse_tokens(&lex(input))
+}
+
+/// How `parse_with` reads programs. By default no limits are set, which
+/// programs from sources that are not trusted should have, and a `]` that
+/// closes no loop is an error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// How deep loops may be nested.
+    pub max_nesting: Option<usize>,
+    /// Commands the program may have.
+    pub max_size: Option<usize>,
+    /// Ignore a `]` that closes no loop, as older versions did.
+    pub lenient: bool,
+}
+
+/// Like `parse`, but as `options` say.
+pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Ast, String> {
+    parse_tokens_with(&lex(input), options)
+}
+
+/// Parses tokens produced by `lexer::lex`.
+pub fn parse_tokens(tokens: &[(usize, Token)]) -> Result<Ast, String> {
+    parse_tokens_with(tokens, &ParseOptions::default())
+}
+
+/// Like `parse_tokens`, but as `options` say. Loops are parsed without
+/// recursion, so any nesting the limits allow works.
+pub fn parse_tokens_with(tokens: &[(usize, Token)], options: &ParseOptions) -> Result<Ast, String> {
+    if let Some(max) = options.max_size {
+        if tokens.len() > max {
+            return Err(format!("The program has {} commands, more than the limit of {}.", tokens.len(), max));
+        }
+    }
+
+    let mut tree = Vec::new();
+    // The loops being parsed, innermost last, each with its opening offset
+    // and the items that come before it.
+    let mut open: Vec<(usize, Vec<SyntaxItem>)> = Vec::new();
+    let mut deepest = 0;
+    for &(offset, token) in tokens {
+        match token {
+            Token::BeginLoop => {
+                if let Some(max) = options.max_nesting {
+                    if open.len() >= max {
+                        return Err(format!("Loops are nested more than {} deep at offset {}.", max, offset));
+                    }
+                }
+                open.push((offset, mem::take(&mut tree)));
+                deepest = deepest.max(open.len());
+            }
+            Token::EndLoop => match open.pop() {
+                Some((start, outer)) => {
+                    let body = mem::replace(&mut tree, outer);
+                    tree.push(SyntaxItem::Loop(start, body, offset));
+                }
+                None if options.lenient => debug!("ignored the ']' at offset {}, which closes no loop", offset),
+                None => return Err(format!("Unmatched ']' at offset {}, it closes no loop.", offset)),
+            },
+            _ => tree.push(SyntaxItem::Single(offset, token)),
+        }
+    }
+
+    if let Some(&(offset, _)) = open.last() {
+        return Err(format!("Unmatched '[' at offset {}, it is never closed.", offset));
+    }
+    debug!("parsed {} commands with loops nested {} deep", tokens.len(), deepest);
+    Ok(Ast::new(tree))
+}
@@ -0,0 +1,224 @@
+//! Partial evaluation for `--opt-level=3`: many programs spend most of their
+//! time computing constants before they first read input. That start is run
+//! once at compile time and replaced by instructions that print what it
+//! printed and set the cells to what it left in them.
+
+use ir::{Instruction, Program};
+
+/// Most instructions run ahead of time, so that compiling a program that
+/// never reads input still ends.
+pub const MAX_STEPS: u64 = 10_000_000;
+
+/// Most cells the evaluated start may use.
+pub const MAX_CELLS: usize = 1 << 16;
+
+/// A point between two loops at the top level of the program, where the
+/// rest of it can be cut off and run on its own.
+#[derive(Clone, Copy, Debug)]
+struct Cut {
+    pc: usize,
+    steps: u64,
+    pointer: usize,
+    tape_len: usize,
+    output_len: usize,
+}
+
+/// Runs `program` from its start up to the first `,` or `Y`, and replaces
+/// what ran with `Set` and `Output` instructions of the same effect, if
+/// that is shorter.
+///
+/// Evaluation also stops before moving left of the first cell, before
+/// more than `MAX_STEPS` instructions or `MAX_CELLS` cells, and before a
+/// cell wraps around, since generated code may use wider cells. Stopping
+/// inside a loop rolls back to the start of its outermost loop.
+pub fn evaluate_prefix(program: &mut Program) {
+    let depths = depths(&program.instructions);
+    let mut tape = vec![0u8];
+    let mut pointer = 0;
+    let mut output = Vec::new();
+    // Old values of the cells written since `cut`, to roll back to it.
+    let mut undo: Vec<(usize, u8)> = Vec::new();
+    let mut cut = Cut { pc: 0, steps: 0, pointer: 0, tape_len: 1, output_len: 0 };
+    let mut pc = 0;
+    let mut steps = 0;
+
+    loop {
+        if pc < program.instructions.len() && depths[pc] == 0 {
+            cut = Cut { pc, steps, pointer, tape_len: tape.len(), output_len: output.len() };
+            undo.clear();
+        }
+        let instruction = match program.instructions.get(pc) {
+            Some(&instruction) if steps < MAX_STEPS => instruction,
+            Some(_) => break,
+            None => {
+                cut = Cut { pc, steps, pointer, tape_len: tape.len(), output_len: output.len() };
+                undo.clear();
+                break;
+            }
+        };
+        let cell = tape[pointer];
+        let written = match instruction {
+            Instruction::Add(n) => match add(cell, n) {
+                Some(sum) => Some((pointer, sum)),
+                None => break,
+            },
+            Instruction::Clear => Some((pointer, 0)),
+            Instruction::Set(n) => Some((pointer, n)),
+            Instruction::AddAt(at, n) => match reach(&mut tape, pointer, at) {
+                Some(target) => match add(tape[target], n) {
+                    Some(sum) => Some((target, sum)),
+                    None => break,
+                },
+                None => break,
+            },
+            Instruction::SetAt(at, n) => match reach(&mut tape, pointer, at) {
+                Some(target) => Some((target, n)),
+                None => break,
+            },
+            Instruction::Move(n) => {
+                match reach(&mut tape, pointer, n) {
+                    Some(target) => pointer = target,
+                    None => break,
+                }
+                None
+            }
+            Instruction::Output => {
+                output.push(cell);
+                None
+            }
+            Instruction::Input | Instruction::Fork => break,
+            Instruction::JumpIfZero(target) if cell == 0 => {
+                pc = target;
+                steps += 1;
+                continue;
+            }
+            Instruction::JumpIfNotZero(target) if cell != 0 => {
+                pc = target;
+                steps += 1;
+                continue;
+            }
+            Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_) => None,
+        };
+        if let Some((target, value)) = written {
+            undo.push((target, tape[target]));
+            tape[target] = value;
+        }
+        pc += 1;
+        steps += 1;
+    }
+
+    for &(cell, value) in undo.iter().rev() {
+        tape[cell] = value;
+    }
+    tape.truncate(cut.tape_len);
+    output.truncate(cut.output_len);
+
+    let prefix = residual(&output, &tape, cut.pointer);
+    if cut.pc == 0 || prefix.len() as u64 >= cut.steps {
+        debug!("kept the first {} instructions, evaluating them ahead of time saves nothing", cut.pc);
+        return;
+    }
+    debug!("evaluated {} steps ahead of time, the first {} instructions became {}",
+           cut.steps,
+           cut.pc,
+           prefix.len());
+    splice(program, prefix, cut.pc);
+}
+
+/// `cell + n as i8`, unless that wraps around.
+fn add(cell: u8, n: u8) -> Option<u8> {
+    match (cell as i16) + (n as i8 as i16) {
+        sum @ 0..=255 => Some(sum as u8),
+        _ => None,
+    }
+}
+
+/// Index of the cell `n` cells away from `pointer`, growing the tape to
+/// reach it, unless that is left of the first cell or past `MAX_CELLS`.
+fn reach(tape: &mut Vec<u8>, pointer: usize, n: isize) -> Option<usize> {
+    match (pointer as isize).checked_add(n) {
+        Some(target) if target >= 0 && (target as usize) < MAX_CELLS => {
+            let target = target as usize;
+            if target >= tape.len() {
+                tape.resize(target + 1, 0);
+            }
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+/// How deeply each instruction is nested in loops. The `JumpIfZero` of a
+/// loop counts as outside of it and the `JumpIfNotZero` as inside, so that
+/// the program can be cut before the first but not before the second.
+fn depths(instructions: &[Instruction]) -> Vec<usize> {
+    let mut depth = 0;
+    instructions.iter()
+        .map(|instruction| match *instruction {
+            Instruction::JumpIfZero(_) => {
+                depth += 1;
+                depth - 1
+            }
+            Instruction::JumpIfNotZero(_) => {
+                depth -= 1;
+                depth + 1
+            }
+            _ => depth,
+        })
+        .collect()
+}
+
+/// Instructions that print `output` and then leave the cells as in `tape`
+/// and the pointer at `pointer`, starting on a blank tape.
+fn residual(output: &[u8], tape: &[u8], pointer: usize) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut last = None;
+    for &byte in output {
+        if last != Some(byte) {
+            instructions.push(Instruction::Set(byte));
+            last = Some(byte);
+        }
+        instructions.push(Instruction::Output);
+    }
+
+    let mut at = 0;
+    for (cell, &value) in tape.iter().enumerate() {
+        let current = if cell == 0 { last.unwrap_or(0) } else { 0 };
+        if value == current {
+            continue;
+        }
+        if cell != at {
+            instructions.push(Instruction::Move(cell as isize - at as isize));
+            at = cell;
+        }
+        instructions.push(if value == 0 { Instruction::Clear } else { Instruction::Set(value) });
+    }
+    if pointer != at {
+        instructions.push(Instruction::Move(pointer as isize - at as isize));
+    }
+    instructions
+}
+
+/// Replaces the instructions of `program` before `cut` with `prefix`, which
+/// takes the source offset of the first of them and the span of all.
+fn splice(program: &mut Program, prefix: Vec<Instruction>, cut: usize) {
+    let shift = |target: usize| target - cut + prefix.len();
+    let rest = program.instructions[cut..].iter().map(|&instruction| match instruction {
+        Instruction::JumpIfZero(target) => Instruction::JumpIfZero(shift(target)),
+        Instruction::JumpIfNotZero(target) => Instruction::JumpIfNotZero(shift(target)),
+        instruction => instruction,
+    });
+    let instructions: Vec<Instruction> = prefix.iter().cloned().chain(rest).collect();
+
+    let offset = program.offsets[0];
+    let mut offsets = vec![offset; prefix.len()];
+    offsets.extend_from_slice(&program.offsets[cut..]);
+    // Each instruction of the prefix stands for all the ones it replaces.
+    let end = (0..cut).map(|i| program.end(i)).max().unwrap_or(offset + 1);
+    let mut ends = vec![end; prefix.len()];
+    ends.extend((cut..program.instructions.len()).map(|i| program.end(i)));
+
+    program.instructions = instructions;
+    program.offsets = offsets;
+    program.ends = ends;
+}
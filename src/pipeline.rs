@@ -0,0 +1,75 @@
+//! Chaining programs like a shell pipeline, each reading what the one before
+//! it writes. Programs run lazily: a program only runs when the next one
+//! reads, and only as far as that read needs.
+
+use std::io::{self, BufRead, Read};
+
+use harness::{output_bytes, OutputBytes};
+use interp::State;
+
+/// The output of a program as the input of another. `,` reads a line and
+/// takes a number as the byte with that value, so every byte is handed on
+/// as a line holding its value.
+struct PipeInput {
+    upstream: OutputBytes,
+    line: Vec<u8>,
+    /// Bytes of `line` already read.
+    read: usize,
+    /// Set once the 0 marking the end of the output was handed on.
+    ended: bool,
+}
+
+impl Read for PipeInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for PipeInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.read == self.line.len() {
+            self.line.clear();
+            self.read = 0;
+            match self.upstream.next() {
+                Some(Ok(byte)) => self.line.extend_from_slice(format!("{}\n", byte).as_bytes()),
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                None if !self.ended => {
+                    self.ended = true;
+                    self.line.extend_from_slice(b"0\n");
+                }
+                None => {}
+            }
+        }
+        Ok(&self.line[self.read..])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.read = (self.read + n).min(self.line.len());
+    }
+}
+
+/// Makes `downstream` read what `upstream` writes, byte for byte, in place
+/// of its own input. Once `upstream` has finished, `downstream` reads a 0,
+/// which is how most filters expect their input to end, and then nothing.
+/// An error of `upstream` fails the read of `downstream`.
+pub fn connect(upstream: State, downstream: &mut State) {
+    downstream.input = Some(Box::new(PipeInput {
+        upstream: output_bytes(upstream),
+        line: Vec::new(),
+        read: 0,
+        ended: false,
+    }));
+}
+
+/// Connects `states` in order, the first reading its input as set up, and
+/// returns the last, which runs them all. Returns `None` for no states.
+pub fn chain<I: IntoIterator<Item = State>>(states: I) -> Option<State> {
+    states.into_iter().fold(None, |upstream, mut state| {
+        if let Some(upstream) = upstream {
+            connect(upstream, &mut state);
+        }
+        Some(state)
+    })
+}
@@ -0,0 +1,409 @@
+//! Plugins, built with the 'plugins' feature: shared libraries loaded at
+//! runtime that add dialects, optimizer passes and output sinks.
+//! `include/bfinterpreter_plugin.h` declares the interface.
+//!
+//! What a plugin registers is shared by the whole process, and plugins stay
+//! loaded until it exits.
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::Mutex;
+
+use ir::{Instruction, Program};
+use lexer::{self, Token};
+
+/// `BF_PLUGIN_ABI_VERSION` of the header this module implements.
+pub const ABI_VERSION: u32 = 3;
+
+const ADD: u8 = 0;
+const CLEAR: u8 = 1;
+const MOVE: u8 = 2;
+const INPUT: u8 = 3;
+const OUTPUT: u8 = 4;
+const JUMP_IF_ZERO: u8 = 5;
+const JUMP_IF_NOT_ZERO: u8 = 6;
+const FORK: u8 = 7;
+const SET: u8 = 8;
+const ADD_AT: u8 = 9;
+const SET_AT: u8 = 10;
+
+pub type EmitCommand = unsafe extern "C" fn(ctx: *mut c_void, offset: usize, command: c_char);
+pub type LexFn = unsafe extern "C" fn(source: *const c_char, len: usize, emit: EmitCommand, ctx: *mut c_void) -> c_int;
+pub type EmitInstruction = unsafe extern "C" fn(ctx: *mut c_void, instruction: RawInstruction);
+pub type PassFn = unsafe extern "C" fn(program: *const RawInstruction,
+                                       len: usize,
+                                       emit: EmitInstruction,
+                                       ctx: *mut c_void)
+                                       -> c_int;
+pub type SinkOpenFn = unsafe extern "C" fn(argument: *const c_char) -> *mut c_void;
+pub type SinkWriteFn = unsafe extern "C" fn(sink: *mut c_void, bytes: *const u8, len: usize) -> c_int;
+pub type SinkCloseFn = unsafe extern "C" fn(sink: *mut c_void);
+type InitFn = unsafe extern "C" fn(host: *const Host) -> c_int;
+
+/// An instruction as plugins see it, `bf_instruction` in C.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawInstruction {
+    pub op: u8,
+    pub arg: i64,
+    pub at: i64,
+    pub offset: usize,
+}
+
+#[repr(C)]
+struct Host {
+    abi_version: u32,
+    registry: *mut c_void,
+    register_dialect: unsafe extern "C" fn(*mut c_void, *const c_char, LexFn),
+    register_pass: unsafe extern "C" fn(*mut c_void, *const c_char, PassFn),
+    register_sink: unsafe extern "C" fn(*mut c_void, *const c_char, SinkOpenFn, SinkWriteFn, SinkCloseFn),
+}
+
+/// A dialect added by a plugin.
+#[derive(Clone, Debug)]
+pub struct Frontend {
+    pub name: String,
+    lex: LexFn,
+}
+
+// Names are unique among plugins, and comparing functions is unreliable.
+impl PartialEq for Frontend {
+    fn eq(&self, other: &Frontend) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Frontend {}
+
+struct Commands {
+    len: usize,
+    tokens: Vec<(usize, Token)>,
+    invalid: Option<String>,
+}
+
+unsafe extern "C" fn emit_command(ctx: *mut c_void, offset: usize, command: c_char) {
+    let commands = &mut *(ctx as *mut Commands);
+    match lexer::token(command as u8 as char) {
+        Some(token) if offset <= commands.len => commands.tokens.push((offset, token)),
+        Some(_) => {
+            commands.invalid.get_or_insert_with(|| format!("offset {} is past the end of the source", offset));
+        }
+        None => {
+            commands.invalid.get_or_insert_with(|| format!("'{}' is not a command", command as u8 as char));
+        }
+    }
+}
+
+impl Frontend {
+    /// Splits `input` into tokens, like `Dialect::lex`.
+    pub fn lex(&self, input: &str) -> Result<Vec<(usize, Token)>, String> {
+        let mut commands = Commands { len: input.len(), tokens: Vec::new(), invalid: None };
+        let status = unsafe {
+            (self.lex)(input.as_ptr() as *const c_char,
+                       input.len(),
+                       emit_command,
+                       &mut commands as *mut Commands as *mut c_void)
+        };
+        if status != 0 {
+            return Err(format!("The '{}' dialect could not read the program.", self.name));
+        }
+        match commands.invalid {
+            Some(e) => Err(format!("The '{}' dialect is broken: {}.", self.name, e)),
+            None => Ok(commands.tokens),
+        }
+    }
+}
+
+/// An optimizer pass added by a plugin.
+#[derive(Clone, Debug)]
+pub struct Pass {
+    pub name: String,
+    run: PassFn,
+}
+
+unsafe extern "C" fn emit_instruction(ctx: *mut c_void, instruction: RawInstruction) {
+    (*(ctx as *mut Vec<RawInstruction>)).push(instruction);
+}
+
+impl Pass {
+    /// Runs the pass on `program`, checking what it hands back.
+    pub fn apply(&self, program: &Program) -> Result<Program, String> {
+        let raw: Vec<RawInstruction> = program.instructions
+            .iter()
+            .zip(&program.offsets)
+            .map(|(&instruction, &offset)| to_raw(instruction, offset))
+            .collect();
+        let mut out: Vec<RawInstruction> = Vec::new();
+        let status = unsafe {
+            (self.run)(raw.as_ptr(),
+                       raw.len(),
+                       emit_instruction,
+                       &mut out as *mut Vec<RawInstruction> as *mut c_void)
+        };
+        if status != 0 {
+            return Err(format!("The '{}' pass failed.", self.name));
+        }
+        from_raw(&out).map_err(|e| format!("The '{}' pass is broken: {}.", self.name, e))
+    }
+}
+
+fn to_raw(instruction: Instruction, offset: usize) -> RawInstruction {
+    let (op, arg, at) = match instruction {
+        Instruction::Add(n) => (ADD, n as i8 as i64, 0),
+        Instruction::Clear => (CLEAR, 0, 0),
+        Instruction::Set(n) => (SET, n as i64, 0),
+        Instruction::AddAt(at, n) => (ADD_AT, n as i8 as i64, at as i64),
+        Instruction::SetAt(at, n) => (SET_AT, n as i64, at as i64),
+        Instruction::Move(n) => (MOVE, n as i64, 0),
+        Instruction::Input => (INPUT, 0, 0),
+        Instruction::Output => (OUTPUT, 0, 0),
+        Instruction::JumpIfZero(target) => (JUMP_IF_ZERO, target as i64, 0),
+        Instruction::JumpIfNotZero(target) => (JUMP_IF_NOT_ZERO, target as i64, 0),
+        Instruction::Fork => (FORK, 0, 0),
+    };
+    RawInstruction { op, arg, at, offset }
+}
+
+/// Reads instructions back, pointing each jump just past its partner as
+/// `ir::lower` does.
+fn from_raw(raw: &[RawInstruction]) -> Result<Program, String> {
+    let mut program = Program::default();
+    let mut open = Vec::new();
+    for (i, r) in raw.iter().enumerate() {
+        let instruction = match r.op {
+            ADD => Instruction::Add(r.arg.rem_euclid(256) as u8),
+            CLEAR => Instruction::Clear,
+            SET => Instruction::Set(r.arg.rem_euclid(256) as u8),
+            ADD_AT => Instruction::AddAt(r.at as isize, r.arg.rem_euclid(256) as u8),
+            SET_AT => Instruction::SetAt(r.at as isize, r.arg.rem_euclid(256) as u8),
+            MOVE => Instruction::Move(r.arg as isize),
+            INPUT => Instruction::Input,
+            OUTPUT => Instruction::Output,
+            JUMP_IF_ZERO => {
+                open.push(i);
+                Instruction::JumpIfZero(0)
+            }
+            JUMP_IF_NOT_ZERO => {
+                let start = open.pop().ok_or_else(|| format!("instruction {} closes no loop", i))?;
+                program.instructions[start] = Instruction::JumpIfZero(i + 1);
+                Instruction::JumpIfNotZero(start + 1)
+            }
+            FORK => Instruction::Fork,
+            op => return Err(format!("instruction {} has the unknown operation {}", i, op)),
+        };
+        program.instructions.push(instruction);
+        program.offsets.push(r.offset);
+    }
+    match open.pop() {
+        Some(start) => Err(format!("the loop at instruction {} is never closed", start)),
+        None => Ok(program),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SinkKind {
+    open: SinkOpenFn,
+    write: SinkWriteFn,
+    close: SinkCloseFn,
+}
+
+/// An open output sink of a plugin, closed when dropped.
+pub struct Sink {
+    name: String,
+    handle: *mut c_void,
+    kind: SinkKind,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match unsafe { (self.kind.write)(self.handle, buf.as_ptr(), buf.len()) } {
+            0 => Ok(buf.len()),
+            _ => Err(io::Error::other(format!("The '{}' sink failed.", self.name))),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        unsafe { (self.kind.close)(self.handle) }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    dialects: Vec<Frontend>,
+    passes: Vec<Pass>,
+    sinks: Vec<(String, SinkKind)>,
+    error: Option<String>,
+}
+
+impl Registry {
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.dialects.iter().map(|d| d.name.as_str())
+            .chain(self.passes.iter().map(|p| p.name.as_str()))
+            .chain(self.sinks.iter().map(|s| s.0.as_str()))
+    }
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    dialects: Vec::new(),
+    passes: Vec::new(),
+    sinks: Vec::new(),
+    error: None,
+});
+
+/// Copies a name handed to the host, noting an error in `registry` if
+/// there is none.
+unsafe fn name(registry: &mut Registry, name: *const c_char) -> Option<String> {
+    if name.is_null() {
+        registry.error.get_or_insert_with(|| "it registered something without a name".to_owned());
+        return None;
+    }
+    Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+}
+
+unsafe extern "C" fn register_dialect(registry: *mut c_void, name_ptr: *const c_char, lex: LexFn) {
+    let registry = &mut *(registry as *mut Registry);
+    if let Some(name) = name(registry, name_ptr) {
+        registry.dialects.push(Frontend { name, lex });
+    }
+}
+
+unsafe extern "C" fn register_pass(registry: *mut c_void, name_ptr: *const c_char, run: PassFn) {
+    let registry = &mut *(registry as *mut Registry);
+    if let Some(name) = name(registry, name_ptr) {
+        registry.passes.push(Pass { name, run });
+    }
+}
+
+unsafe extern "C" fn register_sink(registry: *mut c_void,
+                                   name_ptr: *const c_char,
+                                   open: SinkOpenFn,
+                                   write: SinkWriteFn,
+                                   close: SinkCloseFn) {
+    let registry = &mut *(registry as *mut Registry);
+    if let Some(name) = name(registry, name_ptr) {
+        registry.sinks.push((name, SinkKind { open, write, close }));
+    }
+}
+
+/// Loads the plugin at `path` and adds what it registers. Every name has to
+/// be new, among all kinds of registrations.
+pub fn load(path: &str) -> Result<(), String> {
+    let init: InitFn = unsafe { dl::open_init(path)? };
+    let mut loaded = Registry::default();
+    let host = Host {
+        abi_version: ABI_VERSION,
+        registry: &mut loaded as *mut Registry as *mut c_void,
+        register_dialect,
+        register_pass,
+        register_sink,
+    };
+    if unsafe { init(&host) } != 0 {
+        return Err(format!("Plugin '{}' failed to start; it may be built for another version.", path));
+    }
+    if let Some(e) = loaded.error {
+        return Err(format!("Plugin '{}' is broken: {}.", path, e));
+    }
+
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut seen: Vec<&str> = registry.names().collect();
+    for name in loaded.names() {
+        if seen.contains(&name) {
+            return Err(format!("Plugin '{}' registers '{}', which is already taken.", path, name));
+        }
+        seen.push(name);
+    }
+    registry.dialects.extend(loaded.dialects);
+    registry.passes.extend(loaded.passes);
+    registry.sinks.extend(loaded.sinks);
+    Ok(())
+}
+
+/// The dialect a plugin registered as `name`.
+pub fn dialect(name: &str) -> Option<Frontend> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.dialects.iter().find(|d| d.name == name).cloned()
+}
+
+/// The optimizer pass a plugin registered as `name`.
+pub fn pass(name: &str) -> Option<Pass> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.passes.iter().find(|p| p.name == name).cloned()
+}
+
+/// Opens the sink given as `NAME` or `NAME:ARGUMENT`.
+pub fn open_sink(spec: &str) -> Result<Sink, String> {
+    let (name, argument) = match spec.find(':') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => (spec, ""),
+    };
+    let kind = {
+        let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        match registry.sinks.iter().find(|s| s.0 == name) {
+            Some(&(_, kind)) => kind,
+            None => return Err(format!("Unknown output sink '{}'.", name)),
+        }
+    };
+    let argument = CString::new(argument).map_err(|_| "The sink argument contains a NUL byte.".to_owned())?;
+    let handle = unsafe { (kind.open)(argument.as_ptr()) };
+    if handle.is_null() {
+        return Err(format!("Could not open the '{}' sink.", name));
+    }
+    Ok(Sink { name: name.to_owned(), handle, kind })
+}
+
+#[cfg(unix)]
+mod dl {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+
+    use super::InitFn;
+
+    const RTLD_NOW: c_int = 2;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    extern "C" {
+        fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *mut c_char;
+    }
+
+    unsafe fn error() -> String {
+        let message = dlerror();
+        if message.is_null() {
+            "unknown error".to_owned()
+        } else {
+            CStr::from_ptr(message).to_string_lossy().into_owned()
+        }
+    }
+
+    /// Opens the library at `path`, which is never closed again, and finds
+    /// its `bf_plugin_init`.
+    pub unsafe fn open_init(path: &str) -> Result<InitFn, String> {
+        let filename = CString::new(path).map_err(|_| format!("Invalid plugin path '{}'.", path))?;
+        let library = dlopen(filename.as_ptr(), RTLD_NOW);
+        if library.is_null() {
+            return Err(format!("Could not load plugin '{}': {}", path, error()));
+        }
+        let symbol = dlsym(library, b"bf_plugin_init\0".as_ptr() as *const c_char);
+        if symbol.is_null() {
+            return Err(format!("'{}' is not a plugin: {}", path, error()));
+        }
+        Ok(::std::mem::transmute::<*mut c_void, InitFn>(symbol))
+    }
+}
+
+#[cfg(not(unix))]
+mod dl {
+    use super::InitFn;
+
+    pub unsafe fn open_init(_path: &str) -> Result<InitFn, String> {
+        Err("Plugins can only be loaded on Unix.".to_owned())
+    }
+}
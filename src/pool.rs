@@ -0,0 +1,65 @@
+//! Buffers left over from finished runs, so that processes running many
+//! programs (the tutorial, test runners, servers) reuse their allocations
+//! instead of making new ones for every run.
+
+use interp::State;
+use ir::{self, OptLevel, Program};
+use parser::Ast;
+
+/// Most buffers of each kind a pool keeps; any beyond are freed.
+const MAX_POOLED: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct Pool {
+    tapes: Vec<Vec<u8>>,
+    programs: Vec<Program>,
+    outputs: Vec<Vec<u8>>,
+}
+
+fn put<T>(pool: &mut Vec<T>, item: T) {
+    if pool.len() < MAX_POOLED {
+        pool.push(item);
+    }
+}
+
+impl Pool {
+    /// Lowers and optimizes `ast` into pooled program buffers.
+    pub fn compile(&mut self, ast: &Ast, level: OptLevel) -> Program {
+        let mut lowered = self.programs.pop().unwrap_or_default();
+        ir::lower_into(&mut lowered, ast);
+        if level == OptLevel::NONE {
+            return lowered;
+        }
+        let mut optimized = self.programs.pop().unwrap_or_default();
+        ir::optimize_into(&mut optimized, &lowered, level);
+        self.put_program(lowered);
+        optimized
+    }
+
+    /// A new state for `program` on a pooled tape.
+    pub fn state(&mut self, program: Program) -> State {
+        State::with_tape(program, self.tapes.pop().unwrap_or_default())
+    }
+
+    /// An empty output buffer.
+    pub fn output(&mut self) -> Vec<u8> {
+        let mut output = self.outputs.pop().unwrap_or_default();
+        output.clear();
+        output
+    }
+
+    /// Keeps the tape and program of a state that is done.
+    pub fn recycle(&mut self, state: State) {
+        let (tape, program) = state.into_buffers();
+        put(&mut self.tapes, tape);
+        self.put_program(program);
+    }
+
+    pub fn put_program(&mut self, program: Program) {
+        put(&mut self.programs, program);
+    }
+
+    pub fn put_output(&mut self, output: Vec<u8>) {
+        put(&mut self.outputs, output);
+    }
+}
@@ -0,0 +1,211 @@
+//! The `include` extension, a preprocessor run on the source before it is
+//! lexed. A line `@include "file.b"` is replaced by that file, found
+//! relative to the one including it, and a line `@define NAME commands`
+//! defines a macro that `@NAME` expands to further on, except after a `;`. The expansion
+//! remembers where each part of it came from, so errors can point into the
+//! files as written.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A program with its includes and macros expanded.
+#[derive(Clone, Debug, Default)]
+pub struct Expansion {
+    pub source: String,
+    /// Path and contents of every file read.
+    files: Vec<(PathBuf, String)>,
+    /// The parts of `source` in order, each copied from a file.
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    /// Offset in the expanded source.
+    start: usize,
+    file: usize,
+    /// Offset in the file.
+    offset: usize,
+}
+
+impl Expansion {
+    /// The file, line and column, from 1, that the expanded source's
+    /// `offset` came from, as `file:line:column`.
+    pub fn locate(&self, offset: usize) -> String {
+        let i = self.segments.partition_point(|segment| segment.start <= offset);
+        match i.checked_sub(1).map(|i| self.segments[i]) {
+            Some(segment) => {
+                let (path, contents) = &self.files[segment.file];
+                at(path, contents, segment.offset + offset - segment.start)
+            }
+            None => format!("offset {}", offset),
+        }
+    }
+
+    /// `message` with the location of every offset it mentions added, as in
+    /// `at offset 12 (lib.b:3:4)`.
+    pub fn explain(&self, message: &str) -> String {
+        let mut explained = String::new();
+        let mut rest = message;
+        while let Some(i) = rest.find("offset ") {
+            let digits = rest[i + 7..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - i - 7);
+            let end = i + 7 + digits;
+            explained.push_str(&rest[..end]);
+            if let Ok(offset) = rest[i + 7..end].parse() {
+                explained.push_str(&format!(" ({})", self.locate(offset)));
+            }
+            rest = &rest[end..];
+        }
+        explained.push_str(rest);
+        explained
+    }
+}
+
+/// `path:line:column` of `offset` in `contents`.
+fn at(path: &Path, contents: &str, offset: usize) -> String {
+    let before = &contents[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    format!("{}:{}:{}", path.display(), line, column)
+}
+
+/// A macro's commands and where they are defined.
+struct Macro {
+    file: usize,
+    offset: usize,
+    body: String,
+}
+
+struct Expander {
+    expansion: Expansion,
+    macros: HashMap<String, Macro>,
+    /// The files and macros being expanded, to catch them including or
+    /// using themselves.
+    active: Vec<String>,
+}
+
+/// Expands `source`, read from `path`.
+pub fn expand(path: &Path, source: String) -> Result<Expansion, String> {
+    let mut expander = Expander {
+        expansion: Expansion::default(),
+        macros: HashMap::new(),
+        active: Vec::new(),
+    };
+    expander.file(path.to_owned(), source)?;
+    Ok(expander.expansion)
+}
+
+impl Expander {
+    fn file(&mut self, path: PathBuf, contents: String) -> Result<(), String> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone()).display().to_string();
+        if self.active.contains(&key) {
+            return Err(format!("'{}' includes itself.", path.display()));
+        }
+        self.active.push(key);
+        let file = self.expansion.files.len();
+        self.expansion.files.push((path.clone(), contents.clone()));
+
+        let mut start = 0;
+        for line in contents.split_inclusive('\n') {
+            let offset = start;
+            start += line.len();
+            let directive = line.trim_start();
+            let indent = line.len() - directive.len();
+            if let Some(rest) = strip_directive(directive, "@include") {
+                let name = rest.trim();
+                let name = match name.strip_prefix('"').and_then(|name| name.strip_suffix('"')) {
+                    Some(name) => name,
+                    None => {
+                        return Err(format!("Expected a quoted file name after '@include' at {}.",
+                                           at(&path, &contents, offset + indent)))
+                    }
+                };
+                let included = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+                let text = ::std::fs::read_to_string(&included)
+                    .map_err(|e| format!("Could not read '{}': {}", included.display(), e))?;
+                self.file(included, text)?;
+            } else if let Some(rest) = strip_directive(directive, "@define") {
+                let rest = rest.trim_start();
+                let name_len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+                let name = &rest[..name_len];
+                if name.is_empty() {
+                    return Err(format!("Expected a macro name after '@define' at {}.",
+                                       at(&path, &contents, offset + indent)));
+                }
+                if self.macros.contains_key(name) {
+                    return Err(format!("Macro '{}' is defined again at {}.", name, at(&path, &contents, offset + indent)));
+                }
+                let body = rest[name_len..].trim_start();
+                self.macros.insert(name.to_owned(),
+                                   Macro {
+                                       file,
+                                       offset: offset + line.len() - body.len(),
+                                       body: body.to_owned(),
+                                   });
+            } else {
+                self.text(file, offset, line)?;
+            }
+        }
+        self.active.pop();
+        Ok(())
+    }
+
+    /// Copies `text`, found at `offset` in `file`, expanding the macros it
+    /// uses.
+    fn text(&mut self, file: usize, offset: usize, text: &str) -> Result<(), String> {
+        let mut copied = 0;
+        let mut rest = text;
+        while let Some(i) = rest.find('@') {
+            let name_len = rest[i + 1..].find(|c: char| !is_name_char(c)).unwrap_or(rest.len() - i - 1);
+            let name = &rest[i + 1..i + 1 + name_len];
+            // A lone `@` is a comment like any other character, and `;@`
+            // starts a directive for other tools, like `names::DIRECTIVE`.
+            if name.is_empty() || rest[..i].ends_with(';') {
+                self.copy(file, offset + copied, &rest[..i + 1]);
+                copied += i + 1;
+                rest = &rest[i + 1..];
+                continue;
+            }
+            self.copy(file, offset + copied, &rest[..i]);
+            let used_at = offset + copied + i;
+            copied += i + 1 + name_len;
+            rest = &rest[i + 1 + name_len..];
+
+            let (path, contents) = &self.expansion.files[file];
+            let (body_file, body_offset, body) = match self.macros.get(name) {
+                Some(m) => (m.file, m.offset, m.body.clone()),
+                None => return Err(format!("Unknown macro '@{}' at {}.", name, at(path, contents, used_at))),
+            };
+            let key = format!("@{}", name);
+            if self.active.contains(&key) {
+                return Err(format!("Macro '{}' uses itself at {}.", name, at(path, contents, used_at)));
+            }
+            self.active.push(key);
+            self.text(body_file, body_offset, &body)?;
+            self.active.pop();
+        }
+        self.copy(file, offset + copied, rest);
+        Ok(())
+    }
+
+    fn copy(&mut self, file: usize, offset: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.expansion.segments.push(Segment {
+            start: self.expansion.source.len(),
+            file,
+            offset,
+        });
+        self.expansion.source.push_str(text);
+    }
+}
+
+/// The rest of `line` if it starts with the directive `name`, rather than
+/// with a macro whose name merely begins like it.
+fn strip_directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.strip_prefix(name).filter(|rest| !rest.starts_with(is_name_char))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
@@ -0,0 +1,242 @@
+//! Bindings for using the interpreter from Python, in notebooks for
+//! example, built with the 'python' feature as an extension module named
+//! `bfinterpreter`, with maturin or another PyO3 build tool.
+//!
+//! `compile` turns source into a `Program`, `run` runs one to the end and
+//! returns what it printed, and a `Machine` runs one a step at a time so
+//! that the tape can be looked at in between. Input is given as a whole,
+//! read a value per line unless `byte_input` is set, the same as with
+//! `Config`, and the limits are keyword options.
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use config::Config;
+use dialect::Dialect;
+use harness::SharedBuffer;
+use interp::{self, step_threads, State};
+use ir::{OptLevel, Program};
+use tape::Tape;
+
+/// A compiled program, made by `compile`.
+#[pyclass(name = "Program", frozen)]
+pub struct PyProgram(Program);
+
+#[pymethods]
+impl PyProgram {
+    /// Number of instructions.
+    fn __len__(&self) -> usize {
+        self.0.instructions.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Program of {} instructions>", self.0.instructions.len())
+    }
+
+    /// The instructions, one per line, as `--dump-ir` prints them.
+    fn instructions(&self) -> Vec<String> {
+        self.0.instructions.iter().map(|instruction| instruction.to_string()).collect()
+    }
+}
+
+/// Reads, parses and optimizes `source`, written in `dialect`, at
+/// `opt_level`.
+#[pyfunction]
+#[pyo3(signature = (source, opt_level = 0, dialect = "bf"))]
+fn compile(source: &str, opt_level: u32, dialect: &str) -> PyResult<PyProgram> {
+    let dialect = Dialect::from_name(dialect).ok_or_else(|| PyValueError::new_err(format!("Unknown dialect '{}'.", dialect)))?;
+    let level = OptLevel::new(opt_level).ok_or_else(|| {
+        PyValueError::new_err(format!("Invalid optimization level {}, expected 0 to {}.", opt_level, OptLevel::MAX))
+    })?;
+    Config::new()
+        .dialect(dialect)
+        .opt_level(level)
+        .compile(source)
+        .map(PyProgram)
+        .map_err(PyValueError::new_err)
+}
+
+/// The keyword options of `run` and `Machine`, which are those of `Config`.
+#[derive(Clone, Default)]
+struct Options {
+    max_steps: Option<u64>,
+    /// In seconds.
+    timeout: Option<f64>,
+    memory_limit: Option<usize>,
+    tape: Option<String>,
+    strict_tape: bool,
+    byte_input: bool,
+    eof_unchanged: bool,
+}
+
+impl Options {
+    /// A state running `program` as the options say, reading `input` and
+    /// printing into `output`.
+    fn state(&self, program: Program, input: Vec<u8>, output: SharedBuffer) -> PyResult<State> {
+        let mut config = Config::new().input(Cursor::new(input)).output(output);
+        if let Some(steps) = self.max_steps {
+            config = config.max_steps(steps);
+        }
+        if let Some(seconds) = self.timeout {
+            let timeout = Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(format!("Invalid timeout: {}", e)))?;
+            config = config.timeout(timeout);
+        }
+        if let Some(bytes) = self.memory_limit {
+            config = config.memory_limit(bytes);
+        }
+        if let Some(ref tape) = self.tape {
+            config = config.tape(tape.parse().map_err(PyValueError::new_err)?);
+        }
+        if self.strict_tape {
+            config = config.strict_tape();
+        }
+        if self.byte_input {
+            config = config.byte_input();
+        }
+        if self.eof_unchanged {
+            config = config.eof_unchanged();
+        }
+        config.state(program).map_err(PyRuntimeError::new_err)
+    }
+}
+
+/// The program `program` stands for, which is a `Program` or source to
+/// compile without optimizations.
+fn program_of(program: &Bound<PyAny>) -> PyResult<Program> {
+    if let Ok(compiled) = program.cast::<PyProgram>() {
+        return Ok(compiled.get().0.clone());
+    }
+    let source: String = program.extract()?;
+    Config::new().compile(&source).map_err(PyValueError::new_err)
+}
+
+/// Runs `program` on `input` and returns everything it printed. Raises
+/// `RuntimeError` if the program fails or a limit is exceeded.
+#[pyfunction]
+#[pyo3(signature = (program, input = None, *, max_steps = None, timeout = None, memory_limit = None, tape = None,
+                    strict_tape = false, byte_input = false, eof_unchanged = false))]
+#[allow(clippy::too_many_arguments)]
+fn run<'py>(py: Python<'py>,
+            program: &Bound<'py, PyAny>,
+            input: Option<Vec<u8>>,
+            max_steps: Option<u64>,
+            timeout: Option<f64>,
+            memory_limit: Option<usize>,
+            tape: Option<String>,
+            strict_tape: bool,
+            byte_input: bool,
+            eof_unchanged: bool)
+            -> PyResult<Bound<'py, PyBytes>> {
+    let options = Options { max_steps, timeout, memory_limit, tape, strict_tape, byte_input, eof_unchanged };
+    let output = SharedBuffer::default();
+    let mut state = options.state(program_of(program)?, input.unwrap_or_default(), output.clone())?;
+    interp::run(&mut state).map_err(PyRuntimeError::new_err)?;
+    Ok(PyBytes::new(py, &output.take()))
+}
+
+/// A program that runs a step at a time, for watching what it does.
+#[pyclass(unsendable)]
+pub struct Machine {
+    state: State,
+    output: SharedBuffer,
+    finished: bool,
+}
+
+#[pymethods]
+impl Machine {
+    /// Sets up `program` to run on `input`, with the options of `run`.
+    #[new]
+    #[pyo3(signature = (program, input = None, *, max_steps = None, timeout = None, memory_limit = None, tape = None,
+                        strict_tape = false, byte_input = false, eof_unchanged = false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(program: &Bound<PyAny>,
+           input: Option<Vec<u8>>,
+           max_steps: Option<u64>,
+           timeout: Option<f64>,
+           memory_limit: Option<usize>,
+           tape: Option<String>,
+           strict_tape: bool,
+           byte_input: bool,
+           eof_unchanged: bool)
+           -> PyResult<Self> {
+        let options = Options { max_steps, timeout, memory_limit, tape, strict_tape, byte_input, eof_unchanged };
+        let output = SharedBuffer::default();
+        let state = options.state(program_of(program)?, input.unwrap_or_default(), output.clone())?;
+        Ok(Machine { state, output, finished: false })
+    }
+
+    /// Executes the next instruction. Returns `False`, without doing
+    /// anything, once the program has finished. Raises `RuntimeError` if
+    /// the instruction fails.
+    fn step(&mut self) -> PyResult<bool> {
+        if self.finished {
+            return Ok(false);
+        }
+        if step_threads(&mut self.state).map_err(PyRuntimeError::new_err)? {
+            return Ok(true);
+        }
+        self.finished = true;
+        self.state.flush().map_err(PyRuntimeError::new_err)?;
+        Ok(false)
+    }
+
+    /// Runs the program to its end and returns everything it printed,
+    /// including what it printed before.
+    fn run<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        while self.step()? {}
+        Ok(self.output(py))
+    }
+
+    /// Everything the program printed so far.
+    #[getter]
+    fn output<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.output.contents())
+    }
+
+    /// The cells of the tape, up to the rightmost the program reached.
+    #[getter]
+    fn tape<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.state.tape().to_vec())
+    }
+
+    #[getter]
+    fn pointer(&self) -> usize {
+        self.state.pointer()
+    }
+
+    /// Index of the next instruction.
+    #[getter]
+    fn pc(&self) -> usize {
+        self.state.pc()
+    }
+
+    /// The next instruction, as `--dump-ir` prints it, or `None` at the end.
+    #[getter]
+    fn instruction(&self) -> Option<String> {
+        self.state.program().instructions.get(self.state.pc()).map(|instruction| instruction.to_string())
+    }
+
+    /// Instructions executed so far.
+    #[getter]
+    fn steps(&self) -> u64 {
+        self.state.stats.instructions
+    }
+
+    #[getter]
+    fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[pymodule]
+fn bfinterpreter(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyProgram>()?;
+    m.add_class::<Machine>()?;
+    m.add_function(wrap_pyfunction!(python::compile, m)?)?;
+    m.add_function(wrap_pyfunction!(python::run, m)?)?;
+    Ok(())
+}
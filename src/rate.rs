@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Delay output until the rate allows it.
+    #[default]
+    Throttle,
+    /// Stop the program as soon as it exceeds the rate.
+    Abort,
+}
+
+/// Token bucket limiting output to a number of bytes per second, allowing
+/// bursts of up to one second's worth of output.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    policy: Policy,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64, policy: Policy) -> Self {
+        RateLimiter {
+            bytes_per_sec,
+            policy,
+            tokens: bytes_per_sec as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Accounts for one output byte, returning how long the caller was made
+    /// to wait, or an error if the policy is to abort.
+    pub fn acquire(&mut self) -> Result<Duration, String> {
+        let rate = self.bytes_per_sec as f64;
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * rate).min(rate);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(Duration::default());
+        }
+
+        match self.policy {
+            Policy::Abort => {
+                Err(format!("Output aborted: rate limit of {} bytes/s exceeded.", self.bytes_per_sec))
+            }
+            Policy::Throttle => {
+                let wait = Duration::from_secs_f64((1.0 - self.tokens) / rate);
+                ::std::thread::sleep(wait);
+                self.tokens = 0.0;
+                self.last = Instant::now();
+                Ok(wait)
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use lexer::{lex, Token};
+
+/// A single edit that helps balance the brackets of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Delete the `]` at this offset, which closes no loop.
+    Delete(usize),
+    /// Insert a `]` at `at` to close the loop opened at `opened`.
+    Insert { at: usize, opened: usize },
+}
+
+/// Computes a minimal set of edits that balances all brackets in `source`:
+/// every `]` without a matching `[` is deleted, and every `[` left open is
+/// closed after the last command of the program.
+pub fn suggest(source: &str) -> Vec<Edit> {
+    let tokens = lex(source);
+    let end = tokens.last().map_or(0, |&(offset, _)| offset + 1);
+
+    let mut edits = Vec::new();
+    let mut open = Vec::new();
+    for &(offset, token) in &tokens {
+        match token {
+            Token::BeginLoop => open.push(offset),
+            Token::EndLoop if open.pop().is_none() => edits.push(Edit::Delete(offset)),
+            _ => (),
+        }
+    }
+
+    // Close the innermost loops first.
+    edits.extend(open.into_iter().rev().map(|opened| Edit::Insert { at: end, opened }));
+    edits
+}
+
+/// Applies edits produced by `suggest` to `source`.
+pub fn apply(source: &str, edits: &[Edit]) -> String {
+    let mut result = String::with_capacity(source.len() + edits.len());
+    let mut rest = 0;
+
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|edit| match *edit {
+        Edit::Delete(offset) => offset,
+        Edit::Insert { at, .. } => at,
+    });
+
+    for edit in edits {
+        match edit {
+            Edit::Delete(offset) => {
+                result.push_str(&source[rest..offset]);
+                rest = offset + 1;
+            }
+            Edit::Insert { at, .. } => {
+                result.push_str(&source[rest..at]);
+                result.push(']');
+                rest = at;
+            }
+        }
+    }
+
+    result.push_str(&source[rest..]);
+    result
+}
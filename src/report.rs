@@ -0,0 +1,76 @@
+//! The statistics printed by `--stats-format=json`, for tools that read them.
+//!
+//! Within a schema version fields are only ever added, never renamed,
+//! removed or given another meaning, so readers should ignore fields they do
+//! not know. Any other change bumps `SCHEMA_VERSION`.
+
+use interp::{RunOutcome, State};
+use tape::Tape;
+
+/// Version of the schema of `StatsReport`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How a run ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    OutputClosed,
+    Interrupted,
+    Error,
+}
+
+/// Statistics of a single run, printed as one line of JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    /// `SCHEMA_VERSION` of the interpreter that wrote the report.
+    pub schema_version: u32,
+    pub outcome: Outcome,
+    /// The error the run stopped with, if `outcome` is `error`.
+    pub error: Option<String>,
+    pub instructions: u64,
+    pub tape_cells: usize,
+    /// Bytes held by the interpreter when the run ended.
+    pub memory_bytes: usize,
+    pub peak_memory_bytes: usize,
+    pub memory_limit_bytes: Option<usize>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// Instructions the optimizer removed as dead code.
+    pub dead_code_instructions: u64,
+    /// Time spent waiting on the output rate limit, if one was set.
+    pub throttled_ms: Option<f64>,
+}
+
+impl StatsReport {
+    pub fn new(state: &State, result: &Result<RunOutcome, String>) -> StatsReport {
+        StatsReport {
+            schema_version: SCHEMA_VERSION,
+            outcome: match *result {
+                Ok(RunOutcome::Finished) => Outcome::Ok,
+                Ok(RunOutcome::OutputClosed) => Outcome::OutputClosed,
+                Ok(RunOutcome::Interrupted) => Outcome::Interrupted,
+                Err(_) => Outcome::Error,
+            },
+            error: result.clone().err(),
+            instructions: state.stats.instructions,
+            tape_cells: state.tape().len(),
+            memory_bytes: state.memory_usage(),
+            peak_memory_bytes: state.stats.peak_memory,
+            memory_limit_bytes: state.memory_limit,
+            input_bytes: state.stats.input_bytes,
+            output_bytes: state.stats.output_bytes,
+            dead_code_instructions: state.stats.dead_code,
+            throttled_ms: state.output_rate.as_ref().map(|_| state.stats.throttled.as_secs_f64() * 1000.0),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        ::serde_json::to_string(self).expect("Could not serialize statistics.")
+    }
+
+    /// Reads a report written by `to_json`.
+    pub fn from_json(json: &str) -> Result<StatsReport, String> {
+        ::serde_json::from_str(json).map_err(|e| format!("Invalid statistics: {}", e))
+    }
+}
@@ -0,0 +1,242 @@
+//! A playground server for the `serve` command: every connection sends a
+//! program ending with `!`, followed by the program's input, and gets back
+//! the output as the program writes it. Programs run under limits on their
+//! size, instructions, memory and time waiting for input, each connection
+//! on a thread of its own.
+//!
+//! With a `Store`, programs can also be kept to be run again later: a
+//! connection sending `!put!`, then a program ending with `!`, gets back the
+//! program's ID on a line, and one sending `!ID!`, then input, runs the
+//! program kept under that ID on it.
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use audit;
+use interp::{run, RunOutcome, State};
+use ir::{self, OptLevel};
+use parser::parse;
+use rate::{self, RateLimiter};
+use storage::{self, Store};
+
+/// Longest request after a leading `!`: an ID and the `!` after it.
+const REQUEST_LEN: u64 = 17;
+
+/// What every connection is allowed to use, and what is kept of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes of source a program may have.
+    pub program_size: usize,
+    pub max_steps: u64,
+    /// Bytes of memory the interpreter may use for a program.
+    pub max_memory: usize,
+    /// How long a connection may send nothing while the program waits for
+    /// its input.
+    pub idle_timeout: Duration,
+    /// Connections handled at once; any more are turned away.
+    pub connections: usize,
+    /// Bytes of output a program may write per second, see `rate`.
+    pub output_rate: Option<u64>,
+    pub output_rate_policy: rate::Policy,
+    /// Where to append a record of every program run, see `audit`.
+    pub audit_log: Option<String>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            program_size: 64 * 1024,
+            max_steps: 100_000_000,
+            max_memory: 1 << 20,
+            idle_timeout: Duration::from_secs(30),
+            connections: 16,
+            output_rate: None,
+            output_rate_policy: rate::Policy::default(),
+            audit_log: None,
+        }
+    }
+}
+
+/// Handles connections to `listener` until accepting one fails, calling
+/// `report` with the address of each peer and how its connection went.
+/// Programs are only kept if there is a `store`.
+pub fn serve<F>(listener: TcpListener,
+                limits: Limits,
+                opt_level: OptLevel,
+                store: Option<Box<dyn Store>>,
+                report: F)
+                -> Result<(), String>
+    where F: Fn(&str, &str) + Send + Sync + 'static
+{
+    let store: Option<Arc<dyn Store>> = store.map(Arc::from);
+    let report = Arc::new(report);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = stream.map_err(|e| format!("Could not accept a connection: {}", e))?;
+        let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| "unknown".to_owned());
+        if active.fetch_add(1, Ordering::SeqCst) >= limits.connections {
+            active.fetch_sub(1, Ordering::SeqCst);
+            let _ = stream.write_all(b"error: Too many connections, try again later.\n");
+            report(&peer, "turned away");
+            continue;
+        }
+        let active = Arc::clone(&active);
+        let report = Arc::clone(&report);
+        let limits = limits.clone();
+        let store = store.clone();
+        thread::spawn(move || {
+            report(&peer, &handle(stream, &limits, opt_level, store.as_deref()));
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+/// Runs the program sent over `stream`, and returns how that went. A
+/// program that was read is recorded in the audit log, if there is one,
+/// whether or not it ran to the end.
+fn handle(mut stream: TcpStream, limits: &Limits, opt_level: OptLevel, store: Option<&dyn Store>) -> String {
+    let start = Instant::now();
+    let mut source = None;
+    let mut state = None;
+    let served = run_connection(&stream, limits, opt_level, store, &mut source, &mut state);
+    let result = match served {
+        Ok(Served::Stored(id)) => {
+            let _ = writeln!(stream, "{}", id);
+            return format!("stored {}", id);
+        }
+        Ok(Served::Ran(outcome)) => Ok(outcome),
+        Err(e) => Err(e),
+    };
+    let (instructions, input_bytes, wrote, throttled) = state.map_or((0, 0, false, None), |state| {
+        let throttled = state.output_rate.as_ref().map(|_| state.stats.throttled);
+        (state.stats.instructions, state.stats.input_bytes, state.stats.output_bytes > 0, throttled)
+    });
+
+    let logged = match (&limits.audit_log, source) {
+        (Some(path), Some(source)) => {
+            let record = audit::Record::new(&source, input_bytes, Some(limits.max_memory), &result, start.elapsed());
+            audit::append(path, &record).err()
+        }
+        _ => None,
+    };
+    let summary = match result {
+        Ok(RunOutcome::Finished) => format!("finished after {} instructions", instructions),
+        Ok(_) => format!("stopped after {} instructions, the connection was closed", instructions),
+        Err(e) => {
+            // The error goes on a line of its own after any output.
+            let _ = writeln!(stream, "{}error: {}", if wrote { "\n" } else { "" }, e);
+            format!("failed after {} instructions: {}", instructions, e)
+        }
+    };
+    let summary = match throttled {
+        Some(throttled) if throttled > Duration::default() => {
+            format!("{}, throttled for {:.3} ms", summary, throttled.as_secs_f64() * 1000.0)
+        }
+        _ => summary,
+    };
+    match logged {
+        Some(e) => format!("{}; {}", summary, e),
+        None => summary,
+    }
+}
+
+/// What a connection asked for, once it is done.
+enum Served {
+    /// The program ran and ended like this.
+    Ran(RunOutcome),
+    /// The program was kept under this ID.
+    Stored(String),
+}
+
+/// Reads the request from `stream`, and either keeps the program sent in
+/// `store` or runs it, or the program kept under the ID sent, with its
+/// source in `source` and the run left in `state`.
+fn run_connection(stream: &TcpStream,
+                  limits: &Limits,
+                  opt_level: OptLevel,
+                  store: Option<&dyn Store>,
+                  source: &mut Option<String>,
+                  state: &mut Option<State>)
+                  -> Result<Served, String> {
+    let io_error = |e| format!("Connection failed: {}", e);
+    stream.set_read_timeout(Some(limits.idle_timeout)).map_err(io_error)?;
+    let mut reader = BufReader::new(Idle {
+        stream: stream.try_clone().map_err(io_error)?,
+        timeout: limits.idle_timeout,
+    });
+
+    let bytes = if reader.fill_buf().map_err(io_error)?.first() == Some(&b'!') {
+        reader.consume(1);
+        let store = store.ok_or_else(|| "This server does not keep programs.".to_owned())?;
+        let mut request = Vec::new();
+        reader.by_ref().take(REQUEST_LEN).read_until(b'!', &mut request).map_err(io_error)?;
+        match String::from_utf8_lossy(&request).strip_suffix('!') {
+            Some("put") => {
+                let bytes = read_program(&mut reader, limits)?;
+                parse(&String::from_utf8_lossy(&bytes))?;
+                let id = storage::id_of(&bytes);
+                match store.get(&id)? {
+                    Some(ref kept) if *kept != bytes => return Err(format!("Another program has the ID {}.", id)),
+                    Some(_) => (),
+                    None => store.put(&id, &bytes)?,
+                }
+                return Ok(Served::Stored(id));
+            }
+            Some(id) if storage::is_id(id) => {
+                store.get(id)?.ok_or_else(|| format!("There is no program with the ID {}.", id))?
+            }
+            _ => return Err("Send '!put!' followed by a program to keep, or '!ID!' followed by input.".to_owned()),
+        }
+    } else {
+        read_program(&mut reader, limits)?
+    };
+    let source = source.get_or_insert(String::from_utf8_lossy(&bytes).into_owned());
+    let tree = parse(source)?;
+
+    let state = state.get_or_insert(State::new(ir::optimize(&ir::lower(&tree), opt_level)));
+    state.input = Some(Box::new(reader));
+    state.output = Some(Box::new(stream.try_clone().map_err(io_error)?));
+    state.max_steps = Some(limits.max_steps);
+    state.memory_limit = Some(limits.max_memory);
+    state.output_rate = limits.output_rate.map(|rate| RateLimiter::new(rate, limits.output_rate_policy));
+    run(state).map(Served::Ran)
+}
+
+/// Reads a program ending with `!` from `reader`, without the `!`.
+fn read_program<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    reader.by_ref()
+        .take(limits.program_size as u64 + 1)
+        .read_until(b'!', &mut bytes)
+        .map_err(|e| format!("Connection failed: {}", e))?;
+    if bytes.pop() != Some(b'!') {
+        return Err(if bytes.len() >= limits.program_size {
+            format!("The program is longer than {} bytes.", limits.program_size)
+        } else {
+            "The program has to end with '!', followed by its input.".to_owned()
+        });
+    }
+    Ok(bytes)
+}
+
+/// A connection whose read timeout says what happened when it runs out.
+struct Idle {
+    stream: TcpStream,
+    timeout: Duration,
+}
+
+impl Read for Idle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                io::Error::new(ErrorKind::TimedOut, format!("nothing was sent for {} s", self.timeout.as_secs()))
+            }
+            _ => e,
+        })
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+/// First line of a manifest written by `bfinterpreter split`.
+pub const MANIFEST_HEADER: &str = "# bf manifest v1";
+
+/// Splits `source` into consecutive chunks of at most `max_len` bytes.
+///
+/// Chunks only end where no loop is open, so each one parses on its own and
+/// running them one after the other on the same tape is the same as running
+/// the whole program. A single top-level loop longer than `max_len` becomes
+/// a chunk of its own.
+pub fn split(source: &str, max_len: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut last_cut = None;
+    let mut depth = 0usize;
+
+    for (i, c) in source.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+        if depth > 0 {
+            continue;
+        }
+
+        let end = i + c.len_utf8();
+        if end - start > max_len {
+            if let Some(cut) = last_cut.filter(|&cut| cut > start) {
+                chunks.push(&source[start..cut]);
+                start = cut;
+            }
+            if end - start > max_len {
+                chunks.push(&source[start..end]);
+                start = end;
+            }
+        }
+        last_cut = Some(end);
+    }
+
+    if start < source.len() {
+        chunks.push(&source[start..]);
+    }
+    chunks
+}
+
+/// Returns the chunk file names listed in a manifest, or `None` if `contents`
+/// is not a manifest.
+pub fn parse_manifest(contents: &str) -> Option<Vec<&str>> {
+    let mut lines = contents.lines();
+    if lines.next().map(str::trim_end) != Some(MANIFEST_HEADER) {
+        return None;
+    }
+    Some(lines.map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).collect())
+}
+
+/// Reads the chunks listed in a manifest, resolving them relative to the
+/// manifest's directory, and joins them back into one program.
+pub fn load_manifest(path: &Path, chunks: &[&str]) -> Result<String, String> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut source = String::new();
+    for chunk in chunks {
+        let chunk_path = dir.join(chunk);
+        let contents = ::std::fs::read_to_string(&chunk_path)
+            .map_err(|e| format!("Could not read '{}': {}", chunk_path.display(), e))?;
+        source.push_str(&contents);
+    }
+    Ok(source)
+}
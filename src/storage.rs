@@ -0,0 +1,151 @@
+//! Where the `serve` command keeps programs uploaded to be run again later
+//! by their ID, which is what a playground needs for links that share a
+//! program. A program's ID is a hash of its source, so uploading the same
+//! program twice gives the same ID. `Memory` keeps programs until it is full
+//! and then forgets the one used least recently, `Dir` keeps a file for
+//! each in a directory.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use audit;
+
+/// Programs kept by `Memory` unless told otherwise.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// Keeps the source of programs by their ID. Implementations are shared by
+/// the threads of every connection.
+pub trait Store: Send + Sync {
+    /// Keeps `source` under `id`, replacing what was kept there before.
+    fn put(&self, id: &str, source: &[u8]) -> Result<(), String>;
+
+    /// The source kept under `id`, or `None` if there is none.
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, String>;
+}
+
+/// The ID of `source`.
+pub fn id_of(source: &[u8]) -> String {
+    format!("{:016x}", audit::fnv1a(source))
+}
+
+/// Whether `id` is one `id_of` could have made, which is checked before it
+/// names a file.
+pub fn is_id(id: &str) -> bool {
+    id.len() == 16 && id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Opens the store `spec` names: `memory`, or `memory:N` to keep at most N
+/// programs, or `dir:PATH`, which is created if it does not exist.
+pub fn open(spec: &str) -> Result<Box<dyn Store>, String> {
+    let (kind, argument) = match spec.find(':') {
+        Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+        None => (spec, None),
+    };
+    match (kind, argument) {
+        ("memory", None) => Ok(Box::new(Memory::new(DEFAULT_CAPACITY))),
+        ("memory", Some(capacity)) => match capacity.parse() {
+            Ok(capacity) if capacity > 0 => Ok(Box::new(Memory::new(capacity))),
+            _ => Err(format!("Invalid capacity '{}'.", capacity)),
+        },
+        ("dir", Some(path)) if !path.is_empty() => Ok(Box::new(Dir::open(path)?)),
+        ("dir", _) => Err("A 'dir' store needs a path, as in 'dir:PATH'.".to_owned()),
+        _ => Err(format!("Unknown store '{}', expected memory[:N] or dir:PATH.", spec)),
+    }
+}
+
+/// Programs kept in memory, at most `capacity` of them.
+pub struct Memory {
+    capacity: usize,
+    programs: Mutex<Recent>,
+}
+
+/// The programs of `Memory`, and their IDs from the least to the most
+/// recently used.
+#[derive(Default)]
+struct Recent {
+    sources: HashMap<String, Vec<u8>>,
+    order: VecDeque<String>,
+}
+
+impl Recent {
+    fn touch(&mut self, id: &str) {
+        if let Some(i) = self.order.iter().position(|other| other == id) {
+            let id = self.order.remove(i).expect("the position is in the queue");
+            self.order.push_back(id);
+        }
+    }
+}
+
+impl Memory {
+    pub fn new(capacity: usize) -> Self {
+        Memory {
+            capacity,
+            programs: Mutex::new(Recent::default()),
+        }
+    }
+}
+
+impl Store for Memory {
+    fn put(&self, id: &str, source: &[u8]) -> Result<(), String> {
+        let mut programs = self.programs.lock().map_err(|_| "The store is unusable after a crash.".to_owned())?;
+        if programs.sources.insert(id.to_owned(), source.to_vec()).is_some() {
+            programs.touch(id);
+            return Ok(());
+        }
+        programs.order.push_back(id.to_owned());
+        while programs.order.len() > self.capacity {
+            if let Some(oldest) = programs.order.pop_front() {
+                programs.sources.remove(&oldest);
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut programs = self.programs.lock().map_err(|_| "The store is unusable after a crash.".to_owned())?;
+        let source = programs.sources.get(id).cloned();
+        if source.is_some() {
+            programs.touch(id);
+        }
+        Ok(source)
+    }
+}
+
+/// Programs kept in a directory, as `ID.b` files of their source.
+pub struct Dir {
+    path: PathBuf,
+}
+
+impl Dir {
+    /// Keeps programs in `path`, creating it if needed.
+    pub fn open(path: &str) -> Result<Self, String> {
+        fs::create_dir_all(path).map_err(|e| format!("Could not create '{}': {}", path, e))?;
+        Ok(Dir { path: PathBuf::from(path) })
+    }
+
+    fn file(&self, id: &str) -> Result<PathBuf, String> {
+        if !is_id(id) {
+            return Err(format!("Invalid program ID '{}'.", id));
+        }
+        Ok(self.path.join(format!("{}.b", id)))
+    }
+}
+
+impl Store for Dir {
+    fn put(&self, id: &str, source: &[u8]) -> Result<(), String> {
+        let file = self.file(id)?;
+        fs::write(&file, source).map_err(|e| format!("Could not write '{}': {}", file.display(), e))
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, String> {
+        let file = self.file(id)?;
+        match fs::read(&file) {
+            Ok(source) => Ok(Some(source)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Could not read '{}': {}", file.display(), e)),
+        }
+    }
+}
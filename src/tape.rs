@@ -0,0 +1,559 @@
+//! Where the cells of the tape are kept. A `Vec` suits most programs; with
+//! the `mmap` feature, a tape can instead live in a large anonymous memory
+//! mapping, whose pages the system only provides once they are touched, so
+//! that a program using tens of millions of cells does not pay for them up
+//! front or for copying them every time the tape grows. A sparse tape only
+//! keeps the regions a program has written to, for programs that hop
+//! between cells far apart.
+
+use std::collections::HashMap;
+#[cfg(all(feature = "mmap", unix))]
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The operations the interpreter needs from a tape. Cells are numbered
+/// from the leftmost one the program has reached.
+pub trait Tape {
+    /// Number of cells, up to the rightmost one the program has reached.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The value of `cell`, or 0 past the end of the tape, where the
+    /// program has not been yet.
+    fn get(&self, cell: usize) -> u8;
+
+    fn set(&mut self, cell: usize, value: u8);
+
+    /// Makes the tape `len` cells long, adding zeros at the right or
+    /// dropping the cells past it.
+    fn resize(&mut self, len: usize) -> Result<(), String>;
+
+    /// Adds `n` zeros at the left, which shifts the index of every cell.
+    fn grow_left(&mut self, n: usize) -> Result<(), String>;
+
+    /// Drops the `n` leftmost cells, undoing `grow_left`.
+    fn shrink_left(&mut self, n: usize);
+
+    /// Moves a pointer at `pointer` by `n` cells, growing the tape to reach
+    /// the cell it ends up at. Returns that cell, and how many cells were
+    /// added at the left, by which the index of every cell shifted.
+    fn move_by(&mut self, pointer: usize, n: isize) -> Result<(usize, usize), String> {
+        if n < 0 {
+            let n = n.unsigned_abs();
+            if n > pointer {
+                let missing = n - pointer;
+                self.grow_left(missing)?;
+                return Ok((0, missing));
+            }
+            return Ok((pointer - n, 0));
+        }
+        let pointer = pointer + n as usize;
+        if pointer >= self.len() {
+            self.resize(pointer + 1)?;
+        }
+        Ok((pointer, 0))
+    }
+
+    /// The first of the cells `from`, `from + step`, `from + 2 * step` and
+    /// so on that holds zero, which is where a loop like `[>]` or `[<<]`
+    /// stops. Going right there always is one, past the end of the tape if
+    /// not before; going left there is none if the cells reached before the
+    /// start of the tape all hold a value. `step` must not be zero.
+    fn scan_zero(&self, from: usize, step: isize) -> Option<usize> {
+        let mut cell = from;
+        while self.get(cell) != 0 {
+            cell = next_cell(cell, step)?;
+        }
+        Some(cell)
+    }
+
+    /// Bytes held for the cells.
+    fn memory_usage(&self) -> usize;
+
+    /// The cells as a single slice, for tapes that are kept that way, which
+    /// machine code needs.
+    fn as_mut_slice(&mut self) -> Option<&mut [u8]>;
+
+    fn to_vec(&self) -> Vec<u8> {
+        (0..self.len()).map(|cell| self.get(cell)).collect()
+    }
+}
+
+/// The cell `step` cells away from `cell`, or `None` left of the tape.
+#[inline]
+fn next_cell(cell: usize, step: isize) -> Option<usize> {
+    if step < 0 {
+        cell.checked_sub(step.unsigned_abs())
+    } else {
+        Some(cell + step as usize)
+    }
+}
+
+/// `Tape::scan_zero` on cells kept in a single slice, which is much faster
+/// than looking at them one at a time.
+fn scan_slice(cells: &[u8], from: usize, step: isize) -> Option<usize> {
+    if from >= cells.len() {
+        return Some(from);
+    }
+    let stride = step.unsigned_abs();
+    if step > 0 {
+        let found = cells[from..].iter().step_by(stride).position(|&value| value == 0);
+        // Past the end, the first cell reached holds zero.
+        Some(from + found.unwrap_or_else(|| (cells.len() - from).div_ceil(stride)) * stride)
+    } else {
+        cells[..=from].iter().rev().step_by(stride).position(|&value| value == 0).map(|i| from - i * stride)
+    }
+}
+
+impl Tape for Vec<u8> {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn get(&self, cell: usize) -> u8 {
+        <[u8]>::get(self, cell).cloned().unwrap_or(0)
+    }
+
+    fn set(&mut self, cell: usize, value: u8) {
+        self[cell] = value;
+    }
+
+    fn resize(&mut self, len: usize) -> Result<(), String> {
+        Vec::resize(self, len, 0);
+        Ok(())
+    }
+
+    fn grow_left(&mut self, n: usize) -> Result<(), String> {
+        self.splice(0..0, std::iter::repeat_n(0, n));
+        Ok(())
+    }
+
+    fn shrink_left(&mut self, n: usize) {
+        self.drain(..n);
+    }
+
+    fn scan_zero(&self, from: usize, step: isize) -> Option<usize> {
+        scan_slice(self, from, step)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.capacity()
+    }
+
+    fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        Some(self)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+/// The kinds of tape, chosen with `State::use_tape` or `Config::tape`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Vec,
+    #[cfg(all(feature = "mmap", unix))]
+    Mmap,
+    Sparse,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vec" => Ok(Backend::Vec),
+            "sparse" => Ok(Backend::Sparse),
+            #[cfg(all(feature = "mmap", unix))]
+            "mmap" => Ok(Backend::Mmap),
+            #[cfg(not(all(feature = "mmap", unix)))]
+            "mmap" => Err("The mmap tape needs the 'mmap' feature and a Unix system.".to_owned()),
+            _ => Err(format!("Unknown tape '{}'.", s)),
+        }
+    }
+}
+
+/// The tape of a `State`, of any kind. Snapshots hold its cells as a list,
+/// whatever the kind, and load into a `Vec`.
+#[derive(Clone, Debug)]
+pub enum Cells {
+    Vec(Vec<u8>),
+    #[cfg(all(feature = "mmap", unix))]
+    Mmap(MmapTape),
+    Sparse(SparseTape),
+}
+
+impl Cells {
+    /// An empty tape of the kind `backend`.
+    pub fn new(backend: Backend) -> Result<Self, String> {
+        match backend {
+            Backend::Vec => Ok(Cells::Vec(Vec::new())),
+            #[cfg(all(feature = "mmap", unix))]
+            Backend::Mmap => MmapTape::new().map(Cells::Mmap),
+            Backend::Sparse => Ok(Cells::Sparse(SparseTape::default())),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match *self {
+            Cells::Vec(_) => Backend::Vec,
+            #[cfg(all(feature = "mmap", unix))]
+            Cells::Mmap(_) => Backend::Mmap,
+            Cells::Sparse(_) => Backend::Sparse,
+        }
+    }
+}
+
+impl Default for Cells {
+    fn default() -> Self {
+        Cells::Vec(Vec::new())
+    }
+}
+
+/// Evaluates `$call` with `$tape` bound to whichever tape `$cells` holds.
+macro_rules! dispatch {
+    ($cells:expr, $tape:ident => $call:expr) => {
+        match $cells {
+            Cells::Vec($tape) => $call,
+            #[cfg(all(feature = "mmap", unix))]
+            Cells::Mmap($tape) => $call,
+            Cells::Sparse($tape) => $call,
+        }
+    };
+}
+
+impl Tape for Cells {
+    #[inline]
+    fn len(&self) -> usize {
+        dispatch!(self, tape => tape.len())
+    }
+
+    #[inline]
+    fn get(&self, cell: usize) -> u8 {
+        dispatch!(self, tape => tape.get(cell))
+    }
+
+    #[inline]
+    fn set(&mut self, cell: usize, value: u8) {
+        dispatch!(self, tape => tape.set(cell, value))
+    }
+
+    fn resize(&mut self, len: usize) -> Result<(), String> {
+        dispatch!(self, tape => Tape::resize(tape, len))
+    }
+
+    fn grow_left(&mut self, n: usize) -> Result<(), String> {
+        dispatch!(self, tape => tape.grow_left(n))
+    }
+
+    fn shrink_left(&mut self, n: usize) {
+        dispatch!(self, tape => tape.shrink_left(n))
+    }
+
+    #[inline]
+    fn move_by(&mut self, pointer: usize, n: isize) -> Result<(usize, usize), String> {
+        dispatch!(self, tape => tape.move_by(pointer, n))
+    }
+
+    fn scan_zero(&self, from: usize, step: isize) -> Option<usize> {
+        dispatch!(self, tape => tape.scan_zero(from, step))
+    }
+
+    fn memory_usage(&self) -> usize {
+        dispatch!(self, tape => tape.memory_usage())
+    }
+
+    fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        dispatch!(self, tape => Tape::as_mut_slice(tape))
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        dispatch!(self, tape => Tape::to_vec(tape))
+    }
+}
+
+impl Serialize for Cells {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Cells::Vec(ref cells) => cells.serialize(serializer),
+            #[cfg(all(feature = "mmap", unix))]
+            Cells::Mmap(ref tape) => tape.cells().serialize(serializer),
+            Cells::Sparse(ref tape) => tape.to_vec().serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cells {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Cells::Vec)
+    }
+}
+
+/// Cells in each region of a sparse tape.
+const CHUNK: usize = 4096;
+
+/// A tape that only keeps the regions of `CHUNK` cells that were written
+/// to, so that its memory use follows how many regions a program touches
+/// rather than how far it goes.
+#[derive(Clone, Debug, Default)]
+pub struct SparseTape {
+    /// Regions by the index of their first cell, divided by `CHUNK`, with
+    /// cells counted from where the tape started, so growing to the left
+    /// leaves them where they are.
+    chunks: HashMap<isize, Box<[u8; CHUNK]>>,
+    /// Cells the tape has grown by to the left.
+    left: usize,
+    len: usize,
+}
+
+impl SparseTape {
+    /// The region holding `cell`, and where in it.
+    #[inline]
+    fn locate(&self, cell: usize) -> (isize, usize) {
+        let at = cell as isize - self.left as isize;
+        (at.div_euclid(CHUNK as isize), at.rem_euclid(CHUNK as isize) as usize)
+    }
+}
+
+impl Tape for SparseTape {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn get(&self, cell: usize) -> u8 {
+        let (chunk, at) = self.locate(cell);
+        self.chunks.get(&chunk).map_or(0, |chunk| chunk[at])
+    }
+
+    #[inline]
+    fn set(&mut self, cell: usize, value: u8) {
+        let (chunk, at) = self.locate(cell);
+        match self.chunks.get_mut(&chunk) {
+            Some(chunk) => chunk[at] = value,
+            None if value == 0 => (),
+            None => {
+                let mut cells = Box::new([0; CHUNK]);
+                cells[at] = value;
+                self.chunks.insert(chunk, cells);
+            }
+        }
+    }
+
+    fn resize(&mut self, len: usize) -> Result<(), String> {
+        // Cells dropped are zeroed, so that they read as new ones if the
+        // tape grows again.
+        if len < self.len {
+            let (last, end) = self.locate(len);
+            self.chunks.retain(|&chunk, _| chunk <= last);
+            if let Some(chunk) = self.chunks.get_mut(&last) {
+                chunk[end..].fill(0);
+            }
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    fn grow_left(&mut self, n: usize) -> Result<(), String> {
+        self.left += n;
+        self.len += n;
+        Ok(())
+    }
+
+    fn shrink_left(&mut self, n: usize) {
+        for cell in 0..n {
+            self.set(cell, 0);
+        }
+        self.left -= n;
+        self.len -= n;
+    }
+
+    /// Looks at the cells of each region in turn, and stops at the first
+    /// region that was never written to, all of whose cells hold zero.
+    fn scan_zero(&self, from: usize, step: isize) -> Option<usize> {
+        let mut cell = from;
+        loop {
+            let (chunk, mut at) = self.locate(cell);
+            let cells = match self.chunks.get(&chunk) {
+                Some(cells) => cells,
+                None => return Some(cell),
+            };
+            while at < CHUNK {
+                if cells[at] == 0 {
+                    return Some(cell);
+                }
+                cell = next_cell(cell, step)?;
+                match next_cell(at, step) {
+                    Some(next) => at = next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.chunks.capacity() * std::mem::size_of::<(isize, Box<[u8; CHUNK]>)>() + self.chunks.len() * CHUNK
+    }
+
+    fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// Address space reserved for a mapped tape. Only the pages that are
+/// touched take up memory.
+#[cfg(all(feature = "mmap", unix))]
+const MMAP_RESERVE: usize = 1 << 40;
+
+/// The size of the pages memory usage is counted in, which is what most
+/// systems use.
+#[cfg(all(feature = "mmap", unix))]
+const PAGE_SIZE: usize = 4096;
+
+/// A tape in an anonymous memory mapping of `MMAP_RESERVE` bytes, which
+/// never moves, so it only has to be copied when it grows to the left.
+#[cfg(all(feature = "mmap", unix))]
+pub struct MmapTape {
+    cells: *mut u8,
+    len: usize,
+}
+
+// The mapping is owned by the tape alone, like the buffer of a `Vec`.
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Send for MmapTape {}
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Sync for MmapTape {}
+
+#[cfg(all(feature = "mmap", unix))]
+impl MmapTape {
+    pub fn new() -> Result<Self, String> {
+        let cells = unsafe {
+            libc::mmap(std::ptr::null_mut(),
+                       MMAP_RESERVE,
+                       libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                       -1,
+                       0)
+        };
+        if cells == libc::MAP_FAILED {
+            return Err(format!("Could not map the tape: {}", std::io::Error::last_os_error()));
+        }
+        Ok(MmapTape {
+            cells: cells as *mut u8,
+            len: 0,
+        })
+    }
+
+    pub fn cells(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.cells, self.len) }
+    }
+
+    fn cells_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.cells, self.len) }
+    }
+
+    fn reserve(&self, len: usize) -> Result<(), String> {
+        if len > MMAP_RESERVE {
+            return Err(format!("The mapped tape cannot hold more than {} cells.", MMAP_RESERVE));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Tape for MmapTape {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn get(&self, cell: usize) -> u8 {
+        self.cells().get(cell).cloned().unwrap_or(0)
+    }
+
+    #[inline]
+    fn set(&mut self, cell: usize, value: u8) {
+        self.cells_mut()[cell] = value;
+    }
+
+    fn resize(&mut self, len: usize) -> Result<(), String> {
+        self.reserve(len)?;
+        // Cells dropped are zeroed, so that they read as new ones if the
+        // tape grows again; the pages past the end are zero already.
+        if len < self.len {
+            self.cells_mut()[len..].fill(0);
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    fn grow_left(&mut self, n: usize) -> Result<(), String> {
+        self.reserve(self.len + n)?;
+        let old = self.len;
+        self.len += n;
+        let cells = self.cells_mut();
+        cells.copy_within(..old, n);
+        cells[..n].fill(0);
+        Ok(())
+    }
+
+    fn shrink_left(&mut self, n: usize) {
+        let cells = self.cells_mut();
+        cells.copy_within(n.., 0);
+        let len = cells.len();
+        cells[len - n..].fill(0);
+        self.len -= n;
+    }
+
+    fn scan_zero(&self, from: usize, step: isize) -> Option<usize> {
+        scan_slice(self.cells(), from, step)
+    }
+
+    /// Counts every page up to the end of the tape, though the system only
+    /// provides those that were touched.
+    fn memory_usage(&self) -> usize {
+        self.len.div_ceil(PAGE_SIZE) * PAGE_SIZE
+    }
+
+    fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        Some(self.cells_mut())
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.cells().to_vec()
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Clone for MmapTape {
+    /// Aborts like a `Vec` would if the copy cannot be mapped.
+    fn clone(&self) -> Self {
+        let mut copy = MmapTape::new().unwrap_or_else(|e| panic!("{}", e));
+        copy.len = self.len;
+        copy.cells_mut().copy_from_slice(self.cells());
+        copy
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl fmt::Debug for MmapTape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapTape").field("len", &self.len).finish()
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for MmapTape {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.cells as *mut libc::c_void, MMAP_RESERVE);
+        }
+    }
+}
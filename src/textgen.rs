@@ -0,0 +1,58 @@
+//! Writes programs that print a given text.
+
+use std::iter;
+
+use lexer::Token;
+
+/// Builds a program that prints `text`.
+///
+/// Each byte is made from the previous one in the same cell, either with a
+/// run of `+` or `-`, or, where that is shorter, with a loop on the cell to
+/// its right that adds a multiple of some step, e.g. `>++++[<++++++>-]<`.
+/// The helper cell is zero again after every loop.
+pub fn generate(text: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current = 0u8;
+    for &byte in text {
+        adjust(&mut tokens, byte.wrapping_sub(current) as i8 as i32);
+        tokens.push(Token::Output);
+        current = byte;
+    }
+    tokens
+}
+
+/// Appends the shortest code found that adds `delta` to the current cell.
+fn adjust(tokens: &mut Vec<Token>, delta: i32) {
+    let mut best = None;
+    let mut best_len = delta.unsigned_abs() as usize;
+    for times in 2..=16 {
+        let step = delta / times;
+        let rest = delta - step * times;
+        if step == 0 {
+            continue;
+        }
+        // `>`, `[`, `<`, `>`, `-`, `]` and `<` around the counts.
+        let len = 7 + times as usize + step.unsigned_abs() as usize + rest.unsigned_abs() as usize;
+        if len < best_len {
+            best = Some((times, step, rest));
+            best_len = len;
+        }
+    }
+
+    match best {
+        Some((times, step, rest)) => {
+            tokens.push(Token::ShiftRight);
+            add(tokens, times);
+            tokens.extend_from_slice(&[Token::BeginLoop, Token::ShiftLeft]);
+            add(tokens, step);
+            tokens.extend_from_slice(&[Token::ShiftRight, Token::Decrement, Token::EndLoop, Token::ShiftLeft]);
+            add(tokens, rest);
+        }
+        None => add(tokens, delta),
+    }
+}
+
+fn add(tokens: &mut Vec<Token>, n: i32) {
+    let token = if n < 0 { Token::Decrement } else { Token::Increment };
+    tokens.extend(iter::repeat_n(token, n.unsigned_abs() as usize));
+}
@@ -0,0 +1,87 @@
+use std::io::Write;
+
+use ir::Instruction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One line per executed instruction: `<pc> <instruction> ptr=<n> cell=<n>`.
+    Text,
+    /// Newline-delimited JSON, one object per `Event`.
+    Json,
+}
+
+/// Execution events emitted in the JSON trace format.
+///
+/// Each line is an object whose `event` field names the variant in
+/// snake case; the remaining fields are those listed here. New event kinds
+/// and fields may be added, but existing ones keep their meaning.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// An instruction is about to execute. `cell` is the value under the
+    /// pointer before it runs.
+    Instruction { pc: usize, op: String, ptr: usize, cell: u8 },
+    /// The cell at `ptr` now holds `value`.
+    CellWrite { ptr: usize, value: u8 },
+    /// The program wrote `value` to its output.
+    Output { value: u8 },
+    /// The program is waiting for input to store at `ptr`.
+    InputRequested { ptr: usize },
+}
+
+pub struct Tracer {
+    format: Format,
+    out: Box<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(format: Format, out: Box<dyn Write>) -> Self {
+        Tracer { format, out }
+    }
+
+    pub fn instruction(&mut self, pc: usize, instruction: Instruction, ptr: usize, cell: u8) -> Result<(), String> {
+        match self.format {
+            Format::Text => {
+                writeln!(self.out, "{} {} ptr={} cell={}", pc, instruction, ptr, cell).map_err(write_error)
+            }
+            Format::Json => {
+                self.event(&Event::Instruction {
+                    pc,
+                    op: instruction.to_string(),
+                    ptr,
+                    cell,
+                })
+            }
+        }
+    }
+
+    pub fn cell_write(&mut self, ptr: usize, value: u8) -> Result<(), String> {
+        self.event(&Event::CellWrite { ptr, value })
+    }
+
+    pub fn output(&mut self, value: u8) -> Result<(), String> {
+        self.event(&Event::Output { value })
+    }
+
+    pub fn input_requested(&mut self, ptr: usize) -> Result<(), String> {
+        self.event(&Event::InputRequested { ptr })
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(write_error)
+    }
+
+    /// Writes `event` in the JSON format; the text format only records instructions.
+    fn event(&mut self, event: &Event) -> Result<(), String> {
+        if self.format != Format::Json {
+            return Ok(());
+        }
+
+        ::serde_json::to_writer(&mut self.out, event).map_err(|e| write_error(e.into()))?;
+        self.out.write_all(b"\n").map_err(write_error)
+    }
+}
+
+fn write_error(e: ::std::io::Error) -> String {
+    format!("Could not write trace: {}", e)
+}
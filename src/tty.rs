@@ -0,0 +1,56 @@
+//! Unbuffered terminal input for `--raw-tty`. The terminal stops waiting
+//! for Enter and echoing what is typed, so that `,` gets every key the
+//! moment it is pressed, as games need. Ctrl-C still interrupts and output
+//! is left as it is.
+
+use std::io;
+
+use signal_hook::consts::SIGINT;
+use signal_hook::SigId;
+
+/// The terminal on stdin in raw mode, which is restored when this is
+/// dropped, including while a panic unwinds, and when Ctrl-C is pressed, in
+/// case the process is about to be killed by it.
+pub struct RawTerminal {
+    saved: libc::termios,
+    restore_on_interrupt: SigId,
+}
+
+impl RawTerminal {
+    /// Puts the terminal into raw mode, or returns `None` if stdin is not a
+    /// terminal, which leaves nothing to do.
+    pub fn enter() -> Result<Option<Self>, String> {
+        let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Ok(None);
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        // Only async-signal-safe calls are allowed here, which tcsetattr is.
+        let restore_on_interrupt = unsafe {
+            signal_hook::low_level::register(SIGINT, move || {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+            })
+        }.map_err(|e| format!("Could not set up the terminal: {}", e))?;
+        let terminal = RawTerminal {
+            saved,
+            restore_on_interrupt,
+        };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(format!("Could not set up the terminal: {}", io::Error::last_os_error()));
+        }
+        Ok(Some(terminal))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        signal_hook::low_level::unregister(self.restore_on_interrupt);
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::io::{self, Cursor, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::QueueableCommand;
+
+use harness::SharedBuffer;
+use interp::{self, State};
+use ir::Instruction;
+use names::CellNames;
+use tape::Tape;
+
+/// Pauses between two instructions that the speed keys step through.
+const DELAYS: [u64; 9] = [0, 1, 5, 20, 50, 100, 250, 500, 1000];
+
+/// Index into `DELAYS` of the speed a session starts with.
+const DEFAULT_SPEED: usize = 5;
+
+/// How often the screen is redrawn when running without a pause.
+const FRAME: Duration = Duration::from_millis(33);
+
+/// Width of one cell in the tape row.
+const CELL_WIDTH: usize = 4;
+
+const KEYS: &str = "space pause  s step  +/- speed  q quit";
+
+/// The source of the program being run and the cells it names.
+struct Listing<'a> {
+    source: &'a str,
+    names: &'a CellNames,
+}
+
+fn io_error(e: io::Error) -> String {
+    format!("Terminal error: {}", e)
+}
+
+/// Puts the terminal into raw mode on an alternate screen and restores it
+/// when dropped, even if the session ends with an error.
+struct Screen {
+    out: io::Stdout,
+}
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        out.queue(EnterAlternateScreen)?.queue(Hide)?.flush()?;
+        Ok(Screen { out })
+    }
+
+    /// Reads a line of input with the terminal temporarily back in cooked
+    /// mode, so that it is echoed and can be edited.
+    fn read_line(&mut self, row: u16) -> io::Result<String> {
+        self.out.queue(MoveTo(0, row))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(Print("input> "))?
+            .queue(Show)?
+            .flush()?;
+        terminal::disable_raw_mode()?;
+        let mut line = String::new();
+        let result = io::stdin().read_line(&mut line);
+        terminal::enable_raw_mode()?;
+        self.out.queue(Hide)?.flush()?;
+        result.map(|_| line)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.out.queue(Show).and_then(|o| o.queue(LeaveAlternateScreen)).and_then(|o| o.flush());
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// What the session is currently doing, shown in the status line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Running,
+    Paused,
+    Finished,
+    Failed,
+}
+
+/// Runs the program in a full-screen view of the tape, the source and the
+/// output, until it finishes and a key is pressed or the user quits.
+///
+/// The view is drawn on the terminal, which is read for keys. Output is
+/// captured while the view is open and written to `out` once the terminal
+/// has been restored. Without `state.input` set, input is prompted for at
+/// the bottom of the screen. The cell under the pointer is labelled with its
+/// name from `names`, if it has one.
+pub fn run(state: &mut State, source: &str, names: &CellNames, out: &mut dyn Write) -> Result<(), String> {
+    let output = SharedBuffer::default();
+    state.output = Some(Box::new(output.clone()));
+    let prompt_for_input = state.input.is_none();
+
+    let result = {
+        let mut screen = Screen::enter().map_err(io_error)?;
+        session(&mut screen, state, &Listing { source, names }, &output, prompt_for_input)
+    };
+
+    let printed = output.take();
+    out.write_all(&printed).and_then(|_| out.flush()).map_err(io_error)?;
+    result
+}
+
+fn session(screen: &mut Screen,
+           state: &mut State,
+           listing: &Listing,
+           output: &SharedBuffer,
+           prompt_for_input: bool)
+           -> Result<(), String> {
+    let mut speed = DEFAULT_SPEED;
+    let mut mode = Mode::Running;
+    let mut error = None;
+    let mut last_frame = Instant::now() - FRAME;
+
+    loop {
+        let mut single_step = false;
+        let wait = match mode {
+            Mode::Running => Duration::from_millis(DELAYS[speed]),
+            _ => Duration::from_millis(100),
+        };
+        let deadline = Instant::now() + wait;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if !event::poll(timeout).map_err(io_error)? {
+                break;
+            }
+            let key = match event::read().map_err(io_error)? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                Event::Resize(..) => {
+                    last_frame = Instant::now() - FRAME;
+                    continue;
+                }
+                _ => continue,
+            };
+            match (mode, key.code) {
+                (_, KeyCode::Char('q')) | (_, KeyCode::Esc) => return error.map_or(Ok(()), Err),
+                (Mode::Finished, _) | (Mode::Failed, _) => return error.map_or(Ok(()), Err),
+                (Mode::Running, KeyCode::Char(' ')) => mode = Mode::Paused,
+                (Mode::Paused, KeyCode::Char(' ')) => mode = Mode::Running,
+                (_, KeyCode::Char('s')) => {
+                    mode = Mode::Paused;
+                    single_step = true;
+                }
+                (_, KeyCode::Char('+')) | (_, KeyCode::Char('=')) => speed = speed.saturating_sub(1),
+                (_, KeyCode::Char('-')) => speed = (speed + 1).min(DELAYS.len() - 1),
+                _ => (),
+            }
+            last_frame = Instant::now() - FRAME;
+            if single_step {
+                break;
+            }
+        }
+
+        if mode == Mode::Running || single_step {
+            let reads = state.program().instructions.get(state.pc()) == Some(&Instruction::Input);
+            if reads && prompt_for_input {
+                draw(screen, state, listing, output, mode, speed, None).map_err(io_error)?;
+                let (_, rows) = terminal::size().map_err(io_error)?;
+                let line = screen.read_line(rows.saturating_sub(1)).map_err(io_error)?;
+                state.input = Some(Box::new(Cursor::new(line.into_bytes())));
+            }
+            match interp::step_threads(state) {
+                Ok(true) => (),
+                Ok(false) => mode = Mode::Finished,
+                Err(e) => {
+                    mode = Mode::Failed;
+                    error = Some(e);
+                }
+            }
+            if reads && prompt_for_input {
+                state.input = None;
+            }
+            if mode == Mode::Finished || mode == Mode::Failed {
+                state.flush()?;
+                last_frame = Instant::now() - FRAME;
+            }
+        }
+
+        if last_frame.elapsed() >= FRAME || DELAYS[speed] >= FRAME.as_millis() as u64 {
+            draw(screen, state, listing, output, mode, speed, error.as_ref()).map_err(io_error)?;
+            last_frame = Instant::now();
+        }
+    }
+}
+
+fn draw(screen: &mut Screen,
+        state: &State,
+        listing: &Listing,
+        output: &SharedBuffer,
+        mode: Mode,
+        speed: usize,
+        error: Option<&String>)
+        -> io::Result<()> {
+    let (columns, rows) = terminal::size()?;
+    let (width, height) = (columns as usize, rows as usize);
+    let out = &mut screen.out;
+    out.queue(Clear(ClearType::All))?;
+
+    let status = match mode {
+        Mode::Running => "running",
+        Mode::Paused => "paused",
+        Mode::Finished => "finished, press any key",
+        Mode::Failed => "failed, press any key",
+    };
+    let header = format!("{}  steps {}  delay {} ms  {}",
+                         status,
+                         state.stats.instructions,
+                         DELAYS[speed],
+                         KEYS);
+    out.queue(MoveTo(0, 0))?.queue(Print(clip(&header, width)))?;
+
+    // The tape, scrolled so that the pointer stays in view.
+    let tape = state.tape();
+    let visible = (width / CELL_WIDTH).max(1);
+    let first = state.pointer()
+        .saturating_sub(visible / 2)
+        .min(tape.len().saturating_sub(visible));
+    out.queue(MoveTo(0, 2))?;
+    for i in (first..tape.len()).take(visible) {
+        let cell = tape.get(i);
+        if i == state.pointer() {
+            out.queue(SetAttribute(Attribute::Reverse))?;
+        }
+        out.queue(Print(format!("{:>width$}", cell, width = CELL_WIDTH)))?;
+        if i == state.pointer() {
+            out.queue(SetAttribute(Attribute::Reset))?;
+        }
+    }
+    let marker = (state.pointer() - first) * CELL_WIDTH + CELL_WIDTH - 1;
+    let cell = match listing.names.name(state.pointer()) {
+        Some(name) => format!("cell {} ({})", state.pointer(), name),
+        None => format!("cell {}", state.pointer()),
+    };
+    let label = if marker + CELL_WIDTH * 3 <= width {
+        format!("{}^ {}", " ".repeat(marker), cell)
+    } else {
+        let text = format!("{} ^", cell);
+        format!("{}{}", " ".repeat((marker + 1).saturating_sub(text.len())), text)
+    };
+    out.queue(MoveTo(0, 3))?.queue(Print(clip(&label, width)))?;
+
+    // The source around the instruction about to run, which is highlighted.
+    let output_rows = (height.saturating_sub(6) / 3).max(1);
+    let source_rows = height.saturating_sub(output_rows + 6);
+    let current = state.program().span_of(state.pc());
+    let lines: Vec<(usize, &str)> = line_starts(listing.source);
+    let current_line = current.as_ref().map_or(0, |span| lines.iter().rposition(|&(start, _)| start <= span.start).unwrap_or(0));
+    let top = current_line.saturating_sub(source_rows / 2);
+    // Long lines scroll sideways, all together, to keep the instruction in view.
+    let left = match current {
+        Some(ref span) if span.start - lines[current_line].0 >= width => span.start - lines[current_line].0 - width / 2,
+        _ => 0,
+    };
+    for (row, &(start, line)) in lines.iter().skip(top).take(source_rows).enumerate() {
+        out.queue(MoveTo(0, (5 + row) as u16))?;
+        let (start, line) = match (left..line.len()).find(|&i| line.is_char_boundary(i)) {
+            Some(i) => (start + i, clip(&line[i..], width)),
+            None => (start + line.len(), ""),
+        };
+        match current {
+            // The commands the instruction stands for, as far as they are on
+            // the line it starts on.
+            Some(ref span) if span.start >= start && span.start < start + line.len() => {
+                let column = span.start - start;
+                let first = column + line[column..].chars().next().map_or(1, char::len_utf8);
+                let mut next = (span.end - start).clamp(first, line.len());
+                while !line.is_char_boundary(next) {
+                    next += 1;
+                }
+                out.queue(Print(&line[..column]))?
+                    .queue(SetAttribute(Attribute::Reverse))?
+                    .queue(Print(&line[column..next]))?
+                    .queue(SetAttribute(Attribute::Reset))?
+                    .queue(Print(&line[next..]))?;
+            }
+            _ => {
+                out.queue(Print(line))?;
+            }
+        }
+    }
+
+    // The last lines of output, or the error that stopped the program.
+    let separator_row = height.saturating_sub(output_rows + 1);
+    out.queue(MoveTo(0, separator_row as u16))?.queue(Print(clip(&format!("{:-<width$}", "-- output ", width = width), width)))?;
+    let printed = String::from_utf8_lossy(&output.contents()).into_owned();
+    let mut shown: Vec<&str> = printed.split('\n').collect();
+    if let Some(e) = error {
+        shown.push(e);
+    }
+    let skip = shown.len().saturating_sub(output_rows);
+    for (row, line) in shown.iter().skip(skip).enumerate() {
+        out.queue(MoveTo(0, (separator_row + 1 + row) as u16))?.queue(Print(clip(line, width)))?;
+    }
+
+    out.flush()
+}
+
+/// Splits `source` into lines, each with the byte offset it starts at. Tabs
+/// are not expanded, so lines have the same byte layout as the source.
+fn line_starts(source: &str) -> Vec<(usize, &str)> {
+    let mut start = 0;
+    source.split('\n')
+        .map(|line| {
+            let entry = (start, line.trim_end_matches('\r'));
+            start += line.len() + 1;
+            entry
+        })
+        .collect()
+}
+
+/// Cuts `s` to at most `width` bytes without splitting a character.
+fn clip(s: &str, width: usize) -> &str {
+    if s.len() <= width {
+        return s;
+    }
+    let mut end = width;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
@@ -0,0 +1,91 @@
+use harness::run_pooled;
+use pool::Pool;
+
+/// Instructions a submission may execute per test before it is considered stuck.
+const MAX_STEPS: u64 = 10_000_000;
+
+pub struct Test {
+    pub input: &'static str,
+    pub expected: &'static str,
+}
+
+pub struct Exercise {
+    pub title: &'static str,
+    pub task: &'static str,
+    pub hint: &'static str,
+    pub tests: &'static [Test],
+}
+
+pub const EXERCISES: &[Exercise] = &[
+    Exercise {
+        title: "A single letter",
+        task: "Print the letter A (character code 65).",
+        hint: "`+` adds one to the current cell and `.` prints it. A loop like `++++[>++++<-]` \
+               multiplies.",
+        tests: &[Test { input: "", expected: "A" }],
+    },
+    Exercise {
+        title: "Two letters",
+        task: "Print Hi (character codes 72 and 105).",
+        hint: "Keep one cell per letter and move between them with `>` and `<`.",
+        tests: &[Test { input: "", expected: "Hi" }],
+    },
+    Exercise {
+        title: "Echo",
+        task: "Read a character and print it back.",
+        hint: "`,` reads one line of input and stores its first character in the current cell.",
+        tests: &[Test { input: "x\n", expected: "x" }, Test { input: "Q\n", expected: "Q" }],
+    },
+    Exercise {
+        title: "Next letter",
+        task: "Read a letter and print the one that follows it in the alphabet.",
+        hint: "Read, add one, print.",
+        tests: &[Test { input: "a\n", expected: "b" }, Test { input: "M\n", expected: "N" }],
+    },
+    Exercise {
+        title: "Adding numbers",
+        task: "Read two numbers whose sum is below 10 and print the sum as a digit.",
+        hint: "A line holding a number is read as that number, not as its digit characters. \
+               Move one cell into the other with `[->+<]`, then add 48 to get a digit.",
+        tests: &[Test { input: "3\n4\n", expected: "7" },
+                 Test { input: "0\n9\n", expected: "9" },
+                 Test { input: "5\n0\n", expected: "5" }],
+    },
+    Exercise {
+        title: "Countdown",
+        task: "Print the digits 9876543210.",
+        hint: "Set up a counter of 10 next to the character 9 and decrement both in a loop.",
+        tests: &[Test { input: "", expected: "9876543210" }],
+    },
+];
+
+/// Why a submission did not pass an exercise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The program could not be parsed or failed while running.
+    Error(String),
+    /// The program printed `actual` instead of `expected` for `input`.
+    WrongOutput {
+        input: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Runs `source` against all tests of `exercise`, stopping at the first failure.
+pub fn check(exercise: &Exercise, source: &str) -> Result<(), Failure> {
+    let mut pool = Pool::default();
+    for test in exercise.tests {
+        let output = run_pooled(&mut pool, source, test.input.as_bytes(), MAX_STEPS).map_err(Failure::Error)?;
+        let actual = String::from_utf8_lossy(&output).into_owned();
+        pool.put_output(output);
+        if actual != test.expected {
+            return Err(Failure::WrongOutput {
+                input: test.input.to_owned(),
+                expected: test.expected.to_owned(),
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! Bindings for running programs from JavaScript, built with the 'wasm'
+//! feature for `wasm32-unknown-unknown`.
+//!
+//! There is no stdin or stdout in the browser, so input is passed in as a
+//! whole and output is handed back once the program has finished. Profiling
+//! and output rate limits need a clock and are not available here.
+
+use std::io::Cursor;
+
+use wasm_bindgen::prelude::*;
+
+use harness::SharedBuffer;
+use interp::{run as run_state, State};
+use ir;
+use parser::parse;
+
+/// Runs `source` on `input` and returns everything it printed.
+#[wasm_bindgen]
+pub fn run(source: &str, input: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let tree = parse(source).map_err(|e| JsValue::from_str(&e))?;
+
+    let output = SharedBuffer::default();
+    let mut state = State::new(ir::lower(&tree));
+    state.input = Some(Box::new(Cursor::new(input.to_vec())));
+    state.output = Some(Box::new(output.clone()));
+
+    run_state(&mut state).map_err(|e| JsValue::from_str(&e))?;
+    Ok(output.take())
+}